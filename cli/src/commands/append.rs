@@ -0,0 +1,32 @@
+use dzip_core::{CompressionMethod, NewFile, Result};
+use log::info;
+use std::path::Path;
+
+/// Adds `files` to an existing archive, storing each under its path relative to `base_dir`.
+pub fn append_to_archive(
+    archive_path: &str,
+    files: &[String],
+    base_dir: &str,
+    compression: CompressionMethod,
+) -> Result<()> {
+    let new_files = files
+        .iter()
+        .map(|file| {
+            let relative = dzip_core::path::resolve_relative_path(file)?;
+            let data = std::fs::read(Path::new(base_dir).join(&relative)).map_err(|e| {
+                dzip_core::DzipError::Io(std::io::Error::other(format!(
+                    "Failed to read {}: {}",
+                    file, e
+                )))
+            })?;
+            Ok(NewFile {
+                path: dzip_core::path::to_archive_format(&relative),
+                data,
+                compression,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    info!("Appending {} file(s) to {}", new_files.len(), archive_path);
+    dzip_core::append_files(Path::new(archive_path), &new_files)
+}
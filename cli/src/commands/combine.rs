@@ -0,0 +1,15 @@
+use dzip_core::Result;
+use log::info;
+use std::path::Path;
+
+/// Merges the volumes of a split archive into the single file `output_path`.
+pub fn combine_archive(input_path: &str, output_path: &str) -> Result<()> {
+    info!("Combining {} into {}", input_path, output_path);
+    let metadata = dzip_core::combine_volumes(Path::new(input_path), Path::new(output_path))?;
+    info!(
+        "Wrote {} chunk(s), {} bytes",
+        metadata.chunks.len(),
+        metadata.main_data_end
+    );
+    Ok(())
+}
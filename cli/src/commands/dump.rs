@@ -0,0 +1,118 @@
+use dzip_core::reader::DzipReader;
+use dzip_core::volume::FileSystemVolumeManager;
+use dzip_core::{ArchiveMetadata, Chunk, CompressionMethod, DzipError, Result};
+use log::info;
+use std::fs::File;
+use std::path::Path;
+
+/// Writes the stored bytes of chunk `chunk_id` to `output_path` as they are, without
+/// decrypting or decompressing them, and prints where the chunk lives.
+///
+/// Placeholder lengths are corrected first, so exactly the bytes a reader would decode are
+/// written. Returns the chunk with its corrected length.
+pub fn dump_chunk(input_path: &str, chunk_id: usize, output_path: &str) -> Result<Chunk> {
+    let input = Path::new(input_path);
+    let mut reader = DzipReader::new(File::open(input)?);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+    let listed = *metadata.chunks.get(chunk_id).ok_or_else(|| {
+        DzipError::Config(format!(
+            "Chunk {} does not exist, the archive has {} chunks",
+            chunk_id,
+            metadata.chunks.len()
+        ))
+    })?;
+
+    let mut volumes = FileSystemVolumeManager::new(
+        input.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    if let Some(name) = input.file_name() {
+        volumes.set_numbered_fallback(&name.to_string_lossy());
+    }
+    metadata.correct_chunk_sizes(&mut volumes)?;
+    let chunk = metadata.chunks[chunk_id];
+    let data = reader.read_stored_chunk_with_volumes(&chunk, &mut volumes)?;
+    std::fs::write(output_path, &data)?;
+
+    let volume = match chunk.file {
+        0 => input
+            .file_name()
+            .map_or(input_path.into(), |n| n.to_string_lossy()),
+        id => metadata
+            .volume_files
+            .get(id as usize - 1)
+            .map_or("?".into(), |name| name.as_str().into()),
+    };
+    println!("Chunk {}", chunk_id);
+    println!("  Volume:       {} ({})", chunk.file, volume);
+    println!("  Offset:       {} ({:#x})", chunk.offset, chunk.offset);
+    println!(
+        "  Flags:        {:#06x} ({})",
+        chunk.flags,
+        CompressionMethod::from_flags(chunk.flags).map_or("unknown", |m| m.name())
+    );
+    if listed.compressed_length == chunk.compressed_length {
+        println!("  Stored:       {} bytes", chunk.compressed_length);
+    } else {
+        println!(
+            "  Stored:       {} bytes ({} listed)",
+            chunk.compressed_length, listed.compressed_length
+        );
+    }
+    println!("  Decompressed: {} bytes", chunk.decompressed_length);
+    info!("Wrote {} bytes to {}", data.len(), output_path);
+    Ok(chunk)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::pack::{PackOptions, pack_archive};
+    use crate::commands::testutil::write_pack_config;
+
+    #[test]
+    fn test_dump_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.bin", &[1u8; 300], "Copy"),
+            ("b.txt", &[b'b'; 2000], "Zlib"),
+            ("c.bin", &[3u8; 300], "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                max_volume_size: Some(400),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        for chunk_id in 0..files.len() {
+            let raw = dir.path().join(format!("raw{}.bin", chunk_id));
+            let chunk = dump_chunk(
+                packed.join("test.dz").to_str().unwrap(),
+                chunk_id,
+                raw.to_str().unwrap(),
+            )
+            .unwrap();
+
+            let volume = match chunk.file {
+                0 => "test.dz".to_string(),
+                id => format!("test.d{:02}", id),
+            };
+            let bytes = std::fs::read(packed.join(volume)).unwrap();
+            let start = chunk.offset as usize;
+            let expected = &bytes[start..start + chunk.compressed_length as usize];
+            assert_eq!(std::fs::read(&raw).unwrap(), expected);
+        }
+
+        let raw = dir.path().join("missing.bin");
+        let packed = packed.join("test.dz");
+        assert!(dump_chunk(packed.to_str().unwrap(), 3, raw.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,200 @@
+use dzip_core::reader::DzipReader;
+use dzip_core::volume::FileSystemVolumeManager;
+use dzip_core::{ArchiveMetadata, DzipError, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// What [`export_chunks`] writes next to the chunk files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkManifest {
+    /// The chunk files hold the stored bytes instead of the decompressed data
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub raw: bool,
+    /// Hashes of the chunks of each file, in order, by logical path with `/` separators
+    pub files: BTreeMap<String, Vec<String>>,
+    /// How to decode each raw chunk, by hash; only written with `raw`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty", default)]
+    pub chunks: BTreeMap<String, RawChunk>,
+}
+
+/// Flags and size of a chunk exported as stored, needed to decompress it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RawChunk {
+    /// Chunk flags from the archive header
+    pub flags: u16,
+    /// Size of the chunk once decompressed
+    pub decompressed_length: u32,
+}
+
+/// Path of the manifest [`export_chunks`] writes for the archive at `input_path`:
+/// `<stem>.manifest.json` in `output_dir`, or `<stem>.raw.manifest.json` with `raw`, so
+/// that the manifests of several archives, and of both modes, can share the directory.
+pub fn manifest_path(output_dir: &Path, input_path: &str, raw: bool) -> PathBuf {
+    let stem = Path::new(input_path)
+        .file_stem()
+        .map_or_else(|| "archive".into(), |s| s.to_string_lossy());
+    let suffix = if raw {
+        ".raw.manifest.json"
+    } else {
+        ".manifest.json"
+    };
+    output_dir.join(format!("{}{}", stem, suffix))
+}
+
+/// Writes every chunk of the archive at `input_path` to `output_dir` as `<hash>.chunk`,
+/// named by the SHA-256 of its contents in hex, together with a [`ChunkManifest`] listing
+/// the chunks of each file at [`manifest_path`].
+///
+/// Chunks are decompressed, so a file is the concatenation of its chunks; with `raw` they
+/// are written as stored instead, and the manifest records the flags and decompressed
+/// length of each one. Chunk files already in `output_dir` are kept, so the chunks of
+/// several archives can share a directory. Encrypted archives are rejected.
+pub fn export_chunks(input_path: &str, output_dir: &str, raw: bool) -> Result<ChunkManifest> {
+    let input = Path::new(input_path);
+    let mut reader = DzipReader::new(File::open(input)?);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+    if metadata.is_encrypted() {
+        return Err(DzipError::Unsupported(
+            "Exporting the chunks of encrypted archives is not supported".to_string(),
+        ));
+    }
+    let mut volumes = FileSystemVolumeManager::new(
+        input.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    if let Some(name) = input.file_name() {
+        volumes.set_numbered_fallback(&name.to_string_lossy());
+    }
+    metadata.correct_chunk_sizes(&mut volumes)?;
+
+    let output_dir = Path::new(output_dir);
+    std::fs::create_dir_all(output_dir)?;
+    let mut hashes = Vec::with_capacity(metadata.chunks.len());
+    let mut raw_chunks = BTreeMap::new();
+    let mut written = 0;
+    for (id, chunk) in (0..).zip(&metadata.chunks) {
+        let data = if raw {
+            reader.read_stored_chunk_with_volumes(chunk, &mut volumes)?
+        } else {
+            reader.read_chunk_data_with_volumes(id, chunk, &mut volumes)?
+        };
+        let hash = format!("{:x}", Sha256::digest(&data));
+        let path = output_dir.join(format!("{}.chunk", hash));
+        if !path.exists() {
+            std::fs::write(&path, &data)?;
+            written += 1;
+        }
+        if raw {
+            raw_chunks.insert(
+                hash.clone(),
+                RawChunk {
+                    flags: chunk.flags,
+                    decompressed_length: chunk.decompressed_length,
+                },
+            );
+        }
+        hashes.push(hash);
+    }
+
+    let files = metadata
+        .file_map
+        .iter()
+        .enumerate()
+        .map(|(i, (_, chunk_ids))| {
+            let chunks = chunk_ids.iter().map(|&id| hashes[id as usize].clone());
+            Ok((metadata.resolve_path(i)?, chunks.collect()))
+        })
+        .collect::<Result<_>>()?;
+    let manifest = ChunkManifest {
+        raw,
+        files,
+        chunks: raw_chunks,
+    };
+    let json = serde_json::to_string_pretty(&manifest).map_err(std::io::Error::other)?;
+    std::fs::write(manifest_path(output_dir, input_path, raw), json)?;
+    info!(
+        "Exported {} chunks of {} files ({} new) to {}",
+        hashes.len(),
+        manifest.files.len(),
+        written,
+        output_dir.display()
+    );
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testutil::{fixture_or_skip, test_data};
+
+    #[test]
+    fn test_export_chunks() {
+        let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+            return;
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("cas");
+        let manifest = export_chunks(path.to_str().unwrap(), out.to_str().unwrap(), false).unwrap();
+
+        let mut reader = DzipReader::new(File::open(&path).unwrap());
+        let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(manifest.files.len(), metadata.file_map.len());
+        for i in 0..metadata.file_map.len() {
+            assert!(
+                manifest
+                    .files
+                    .contains_key(&metadata.resolve_path(i).unwrap())
+            );
+        }
+        let read_manifest = |raw| -> ChunkManifest {
+            let path = manifest_path(&out, path.to_str().unwrap(), raw);
+            serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+        };
+        assert!(out.join("testnew.manifest.json").exists());
+        assert_eq!(read_manifest(false), manifest);
+        assert!(manifest.chunks.is_empty());
+
+        // Files are rebuilt by concatenating their chunks
+        for name in ["BMP/Image8.bmp", "TXT/Text1.txt"] {
+            let mut data = Vec::new();
+            for hash in &manifest.files[name] {
+                data.extend(std::fs::read(out.join(format!("{}.chunk", hash))).unwrap());
+            }
+            let expected =
+                std::fs::read(test_data(&format!("DerbhExampleFiles/{}", name))).unwrap();
+            assert_eq!(data, expected);
+        }
+
+        let raw = export_chunks(path.to_str().unwrap(), out.to_str().unwrap(), true).unwrap();
+        assert!(raw.raw);
+        assert_eq!(raw.files.len(), manifest.files.len());
+        assert_eq!(read_manifest(true), raw);
+        // The manifest of the decompressed chunks is kept
+        assert_eq!(read_manifest(false), manifest);
+
+        // Raw chunks decompress with the flags and length in the manifest
+        let registry = dzip_core::CodecRegistry::new();
+        for name in ["BMP/Image8.bmp", "TXT/Text1.txt"] {
+            let mut data = Vec::new();
+            for (id, hash) in (0..).zip(&raw.files[name]) {
+                let stored = std::fs::read(out.join(format!("{}.chunk", hash))).unwrap();
+                let info = raw.chunks[hash];
+                let chunk = dzip_core::format::Chunk {
+                    offset: 0,
+                    compressed_length: stored.len() as u32,
+                    decompressed_length: info.decompressed_length,
+                    flags: info.flags,
+                    file: 0,
+                };
+                data.extend(registry.decompress_chunk(id, stored, &chunk).unwrap());
+            }
+            let expected =
+                std::fs::read(test_data(&format!("DerbhExampleFiles/{}", name))).unwrap();
+            assert_eq!(data, expected);
+        }
+    }
+}
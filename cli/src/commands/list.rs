@@ -0,0 +1,385 @@
+use dzip_core::{ArchiveMetadata, CompressionMethod, NameEncoding, Result, StringLayout};
+use log::warn;
+use serde::Serialize;
+
+/// Output format of the `list` command.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ListFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Contents of an archive, as printed by `list --format json`.
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub version: u8,
+    pub num_files: usize,
+    pub num_directories: usize,
+    pub num_chunks: usize,
+    pub num_volumes: usize,
+    /// Order of the names in the string table
+    pub string_layout: StringLayout,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub files: Vec<ListEntry>,
+}
+
+/// A single user file of the listing.
+#[derive(Debug, Serialize)]
+pub struct ListEntry {
+    /// Logical path with `/` separators
+    pub path: String,
+    /// Decompressed size in bytes
+    pub size: u64,
+    /// Stored size in bytes
+    pub compressed_size: u64,
+    pub chunk_count: usize,
+    /// Method of the file's first chunk, if it has one dzip-rs knows
+    pub compression: Option<CompressionMethod>,
+    /// Volume holding the file's first chunk (0 = main file)
+    pub archive_file_index: Option<u16>,
+}
+
+impl ListEntry {
+    /// Stored size as a fraction of the decompressed size, `None` for empty files.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.size > 0).then(|| self.compressed_size as f64 / self.size as f64)
+    }
+}
+
+impl Listing {
+    pub fn new(metadata: &ArchiveMetadata) -> Result<Self> {
+        let files = metadata
+            .file_map
+            .iter()
+            .enumerate()
+            .map(|(i, (_, chunk_ids))| {
+                let chunks: Vec<_> = chunk_ids
+                    .iter()
+                    .filter_map(|&id| metadata.chunks.get(id as usize))
+                    .collect();
+                Ok(ListEntry {
+                    path: metadata.resolve_path(i)?,
+                    size: chunks.iter().map(|c| c.decompressed_length as u64).sum(),
+                    compressed_size: chunks.iter().map(|c| c.compressed_length as u64).sum(),
+                    chunk_count: chunk_ids.len(),
+                    compression: chunks
+                        .first()
+                        .and_then(|c| CompressionMethod::from_flags(c.flags)),
+                    archive_file_index: chunks.first().map(|c| c.file),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            version: metadata.settings.version,
+            num_files: metadata.user_files.len(),
+            num_directories: metadata.directories.len(),
+            num_chunks: metadata.chunks.len(),
+            num_volumes: metadata.volume_files.len() + 1,
+            string_layout: metadata.string_layout,
+            comment: metadata.comment().map(str::to_string),
+            files,
+        })
+    }
+}
+
+/// A volume of an archive, as printed by `list --volumes`.
+#[derive(Debug, Serialize)]
+pub struct VolumeEntry {
+    /// Volume ID (0 = main file)
+    pub index: u16,
+    /// File name stored in the archive, or of the main file
+    pub name: String,
+    /// Size of the file in bytes, `None` if it cannot be opened
+    pub size: Option<u64>,
+    /// Number of chunks stored in the volume
+    pub chunk_count: usize,
+}
+
+/// Lists the main file and the split volumes of the archive at `input_path`, with the
+/// number of chunks each one holds. Volumes that cannot be opened have no size.
+pub fn volumes(input_path: &str, name_encoding: NameEncoding) -> Result<Vec<VolumeEntry>> {
+    let input = std::path::Path::new(input_path);
+    let main = std::fs::File::open(input)?;
+    let main_size = main.metadata()?.len();
+    let mut reader = dzip_core::reader::DzipReader::new(main);
+    reader.set_name_encoding(name_encoding);
+    let metadata = ArchiveMetadata::load(&mut reader)?;
+
+    let base_dir = input.parent().unwrap_or(std::path::Path::new("."));
+    let mut volume_manager = dzip_core::volume::FileSystemVolumeManager::new(
+        base_dir.to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    // Like unpack, accept volumes found under their numbered name
+    if let Some(name) = input.file_name() {
+        volume_manager.set_numbered_fallback(&name.to_string_lossy());
+    }
+    let mut sizes = metadata.volume_sizes(&mut volume_manager);
+    sizes.insert(0, main_size);
+
+    let main_name = input
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    Ok(std::iter::once(main_name)
+        .chain(metadata.volume_files.iter().cloned())
+        .enumerate()
+        .map(|(i, name)| {
+            let index = i as u16;
+            VolumeEntry {
+                index,
+                name,
+                size: sizes.get(&index).copied(),
+                chunk_count: metadata.chunks.iter().filter(|c| c.file == index).count(),
+            }
+        })
+        .collect())
+}
+
+/// Lists the archive whose main file is `input_path`.
+///
+/// Only the main file is needed. Stored sizes are corrected like for extraction where the
+/// volumes are present; chunks in missing volumes keep the sizes the header declares,
+/// with a warning.
+pub fn listing(input_path: &str, name_encoding: NameEncoding) -> Result<Listing> {
+    let input = std::path::Path::new(input_path);
+    let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(input)?);
+    reader.set_name_encoding(name_encoding);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+
+    let base_dir = input.parent().unwrap_or(std::path::Path::new("."));
+    let mut volume_manager = dzip_core::volume::FileSystemVolumeManager::new(
+        base_dir.to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    if let Some(name) = input.file_name() {
+        volume_manager.set_numbered_fallback(&name.to_string_lossy());
+    }
+    let sizes = metadata.volume_sizes(&mut volume_manager);
+    for (i, name) in metadata.volume_files.iter().enumerate() {
+        if !sizes.contains_key(&(i as u16 + 1)) {
+            warn!(
+                "Volume {} ({}) is missing, showing the stored sizes its header declares",
+                i + 1,
+                name
+            );
+        }
+    }
+    let mut chunks = metadata.chunks.clone();
+    match dzip_core::reader::correct_chunk_sizes(&mut chunks, &sizes) {
+        Ok(()) => metadata.chunks = chunks,
+        Err(e) => warn!("{}, showing the stored sizes the header declares", e),
+    }
+    Listing::new(&metadata)
+}
+
+/// Prints the volumes of an archive, flagging the ones that are missing.
+pub fn list_volumes(
+    input_path: &str,
+    name_encoding: NameEncoding,
+    format: ListFormat,
+) -> Result<()> {
+    let volumes = volumes(input_path, name_encoding)?;
+    match format {
+        ListFormat::Json => {
+            let json = serde_json::to_string_pretty(&volumes).map_err(std::io::Error::other)?;
+            println!("{}", json);
+        }
+        ListFormat::Table => {
+            println!("{:<3} | {:<10} | {:<6} | Name", "Vol", "Size", "Chunks");
+            println!("{:-<3}-+-{:-<10}-+-{:-<6}-+-{:-<20}", "", "", "", "");
+            for volume in &volumes {
+                println!(
+                    "{:<3} | {:<10} | {:<6} | {}",
+                    volume.index,
+                    volume.size.map_or("MISSING".to_string(), |s| s.to_string()),
+                    volume.chunk_count,
+                    volume.name
+                );
+            }
+            let missing = volumes.iter().filter(|v| v.size.is_none()).count();
+            println!("{} volume(s), {} missing", volumes.len(), missing);
+        }
+    }
+    Ok(())
+}
+
+/// Prints the files of an archive without extracting anything.
+pub fn list_archive(
+    input_path: &str,
+    name_encoding: NameEncoding,
+    format: ListFormat,
+) -> Result<()> {
+    let listing = listing(input_path, name_encoding)?;
+
+    match format {
+        ListFormat::Json => {
+            let json = serde_json::to_string_pretty(&listing).map_err(std::io::Error::other)?;
+            println!("{}", json);
+        }
+        ListFormat::Table => {
+            println!(
+                "{:<10} | {:<10} | {:<6} | {:<6} | {:<12} | {:<3} | Path",
+                "Size", "Packed", "Ratio", "Chunks", "Method", "Vol"
+            );
+            println!(
+                "{:-<10}-+-{:-<10}-+-{:-<6}-+-{:-<6}-+-{:-<12}-+-{:-<3}-+-{:-<20}",
+                "", "", "", "", "", "", ""
+            );
+            for entry in &listing.files {
+                println!(
+                    "{:<10} | {:<10} | {:<6} | {:<6} | {:<12} | {:<3} | {}",
+                    entry.size,
+                    entry.compressed_size,
+                    entry
+                        .ratio()
+                        .map_or("-".to_string(), |r| format!("{:.1}%", r * 100.0)),
+                    entry.chunk_count,
+                    entry.compression.map_or("-", CompressionMethod::name),
+                    entry
+                        .archive_file_index
+                        .map_or("-".to_string(), |v| v.to_string()),
+                    entry.path
+                );
+            }
+            println!(
+                "{} file(s), {} directories, {} chunk(s) in {} volume(s)",
+                listing.num_files, listing.num_directories, listing.num_chunks, listing.num_volumes
+            );
+            if listing.string_layout == StringLayout::DirectoriesFirst {
+                println!("String table lists the directories first");
+            }
+            if let Some(comment) = &listing.comment {
+                println!("Comment: {}", comment);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testutil::{fixture_or_skip, pack_with_defaults, write_pack_config};
+
+    #[test]
+    fn test_listing_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] = [
+            ("a.txt", b"alpha", "Copy"),
+            ("img/b.bmp", &[0u8; 300], "Zlib"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let mut reader = dzip_core::reader::DzipReader::new(
+            std::fs::File::open(packed.join("test.dz")).unwrap(),
+        );
+        let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+        let json = serde_json::to_value(Listing::new(&metadata).unwrap()).unwrap();
+
+        assert_eq!(json["version"], 0);
+        assert_eq!(json["num_files"], 2);
+        assert_eq!(json["num_directories"], 1);
+        assert_eq!(json["num_chunks"], 2);
+        assert_eq!(json["num_volumes"], 1);
+        assert_eq!(json["string_layout"], "FilesFirst");
+        assert_eq!(
+            json["files"][1],
+            serde_json::json!({
+                "path": "img/b.bmp",
+                "size": 300,
+                "compressed_size": metadata.chunks[1].compressed_length,
+                "chunk_count": 1,
+                "compression": "Zlib",
+                "archive_file_index": 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_listing_sums_chunks_across_volumes() {
+        let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+            return;
+        };
+        let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(&path).unwrap());
+        let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+        metadata
+            .correct_chunk_sizes(&mut dzip_core::volume::FileSystemVolumeManager::new(
+                path.parent().unwrap().to_path_buf(),
+                metadata.volume_files.clone(),
+            ))
+            .unwrap();
+        let listing = Listing::new(&metadata).unwrap();
+
+        for (i, entry) in listing.files.iter().enumerate() {
+            let chunks = metadata.file_chunks(i).unwrap();
+            let packed: u64 = chunks.iter().map(|c| c.compressed_length as u64).sum();
+            assert_eq!(entry.compressed_size, packed, "{}", entry.path);
+            assert_eq!(entry.chunk_count, chunks.len());
+        }
+        let image = listing
+            .files
+            .iter()
+            .find(|e| e.path == "BMP/Image16.bmp")
+            .unwrap();
+        assert_eq!(image.chunk_count, 2);
+        assert_eq!(image.ratio(), Some(2266.0 / 24684.0));
+    }
+
+    #[test]
+    fn test_listing_without_split_volumes() {
+        let Some(dir) = fixture_or_skip("ExampleSplitArchive") else {
+            return;
+        };
+        let full = listing(dir.join("testnew.dz").to_str().unwrap(), NameEncoding::Utf8).unwrap();
+
+        let copy = tempfile::tempdir().unwrap();
+        let main = copy.path().join("testnew.dz");
+        std::fs::copy(dir.join("testnew.dz"), &main).unwrap();
+        let partial = listing(main.to_str().unwrap(), NameEncoding::Utf8).unwrap();
+
+        assert_eq!(partial.num_volumes, full.num_volumes);
+        assert_eq!(partial.files.len(), full.files.len());
+        for (partial, full) in partial.files.iter().zip(&full.files) {
+            assert_eq!(partial.path, full.path);
+            assert_eq!(partial.size, full.size);
+            assert_eq!(partial.chunk_count, full.chunk_count);
+            if partial.archive_file_index == Some(0) {
+                assert_eq!(partial.compressed_size, full.compressed_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_archive_volumes() {
+        let Some(dir) = fixture_or_skip("ExampleSplitArchive") else {
+            return;
+        };
+        let split = volumes(dir.join("testnew.dz").to_str().unwrap(), NameEncoding::Utf8).unwrap();
+        assert_eq!(split.len(), 4);
+        assert_eq!(split[0].name, "testnew.dz");
+        assert!(split.iter().all(|v| v.size.is_some()));
+        assert_eq!(split.iter().map(|v| v.chunk_count).sum::<usize>(), 8);
+
+        // A missing volume is reported, not an error
+        let copy = tempfile::tempdir().unwrap();
+        for name in ["testnew.dz", "testnew1.dz", "testnew3.dz"] {
+            std::fs::copy(dir.join(name), copy.path().join(name)).unwrap();
+        }
+        let partial = volumes(
+            copy.path().join("testnew.dz").to_str().unwrap(),
+            NameEncoding::Utf8,
+        )
+        .unwrap();
+        assert_eq!(partial.len(), 4);
+        assert_eq!(partial[2].size, None);
+        assert_eq!(partial[2].chunk_count, split[2].chunk_count);
+        assert_eq!(partial[3].size, split[3].size);
+    }
+}
@@ -1,3 +1,59 @@
+pub mod append;
+pub mod combine;
+pub mod dump;
+pub mod export;
+pub mod list;
 pub mod pack;
+pub mod transcode;
 pub mod unpack;
 pub mod verify;
+
+use dzip_core::{DzipError, ProgressEvent, Result};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Returns a progress callback drawing an indicatif bar, finished with `done_message`.
+/// With `quiet` the bar is never drawn.
+pub fn progress_bar(done_message: &'static str, quiet: bool) -> impl Fn(ProgressEvent) + Sync {
+    let pb = ProgressBar::hidden();
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} {msg}")
+            .unwrap()
+            .progress_chars("=>-"),
+    );
+    move |event| match event {
+        ProgressEvent::Start(total) => {
+            pb.set_length(total);
+            if !quiet {
+                pb.set_draw_target(ProgressDrawTarget::stderr());
+            }
+        }
+        ProgressEvent::Inc(n) => pb.inc(n),
+        ProgressEvent::FileDone(path) => pb.set_message(path),
+        ProgressEvent::Finish => pb.finish_with_message(done_message),
+    }
+}
+
+/// Runs `f` on its own pool of `max_threads` threads, so that the parallel work inside
+/// uses no more than that. Without a limit `f` runs on rayon's global pool.
+pub fn with_thread_limit<T: Send>(
+    max_threads: Option<usize>,
+    f: impl FnOnce() -> Result<T> + Send,
+) -> Result<T> {
+    let Some(max_threads) = max_threads else {
+        return f();
+    };
+    if max_threads == 0 {
+        return Err(DzipError::Config(
+            "Number of jobs must be at least 1".to_string(),
+        ));
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(max_threads)
+        .build()
+        .map_err(std::io::Error::other)?;
+    pool.install(f)
+}
+
+#[cfg(test)]
+pub(crate) mod testutil;
@@ -1,16 +1,60 @@
 use crate::config;
-use dzip_core::format::{ArchiveSettings, CHUNK_DZ, Chunk, ChunkSettings, RangeSettings};
-use dzip_core::{Result, compress_data};
-use indicatif::{ProgressBar, ProgressStyle};
-use log::{debug, info};
-use rayon::prelude::*;
-use std::io::{Seek, SeekFrom, Write};
-
-pub fn pack_archive(input_path: &str, output_dir: &str) -> Result<()> {
+use dzip_core::format::VERSION_WIDE;
+use dzip_core::pack::{FileSystemPackSource, PackSink, PackSource};
+use dzip_core::volume::FileSystemPackSink;
+use dzip_core::{
+    ArchiveMetadata, CompressionMethod, EncryptionKey, FormatVersion, PackBuilder, PackEstimate,
+    PackReport, ProgressEvent, Result,
+};
+use log::{error, info};
+use std::sync::atomic::AtomicBool;
+
+/// Options controlling packing, on top of the config file.
+#[derive(Debug, Default, Clone)]
+pub struct PackOptions<'a> {
+    /// Split output into volumes of at most this many bytes, overriding the config
+    pub max_volume_size: Option<u64>,
+    /// Store byte-identical files only once, in addition to the config setting
+    pub dedup: bool,
+    /// Capacity of the write buffer of each volume
+    pub io_buffer_size: Option<usize>,
+    /// Only validate the config and the source files, without writing anything
+    pub dry_run: bool,
+    /// Compress on at most this many threads (all cores if unset)
+    pub max_threads: Option<usize>,
+    /// Stop with `DzipError::Cancelled` once this is set
+    pub cancel: Option<&'a AtomicBool>,
+    /// Encrypt the chunk data with this key
+    pub key: Option<EncryptionKey>,
+    /// Store a checksum of the header, in addition to the config setting
+    pub header_checksum: bool,
+    /// Store the Unix permission bits of each file, in addition to the config setting
+    pub permissions: bool,
+    /// Pack files even if several of them have the same path in the archive
+    pub allow_duplicates: bool,
+    /// Name of the main file, instead of the first archive of the config
+    pub archive_name: Option<String>,
+    /// Directory to read the files of a config from, instead of its `base_dir`
+    pub base_dir: Option<std::path::PathBuf>,
+}
+
+/// Packs the files listed in the config at `input_path` into `output_dir`.
+///
+/// The main file is named after the first archive of the config and the files are read
+/// from its `base_dir`, taken relative to the config file, unless `options` names others.
+///
+/// `on_progress` is told the number of files to pack, then receives one `Inc(1)` per file
+/// written and `Finish` once the archive is written.
+pub fn pack_archive(
+    input_path: &str,
+    output_dir: &str,
+    options: &PackOptions,
+    on_progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<()> {
     let config_path = std::path::Path::new(input_path);
     info!("Parsing config file: {}", config_path.display());
     let mut config = config::parse_config(config_path)
-        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        .map_err(|e| dzip_core::DzipError::Config(format!("{:#}", e)))?;
 
     // If base_dir is "." (default), make it relative to the config file's directory
     #[allow(clippy::collapsible_if)]
@@ -19,275 +63,1013 @@ pub fn pack_archive(input_path: &str, output_dir: &str) -> Result<()> {
             config.base_dir = parent.to_path_buf();
         }
     }
+    if let Some(base_dir) = &options.base_dir {
+        config.base_dir = base_dir.clone();
+    }
+    if let Some(name) = &options.archive_name {
+        match config.archives.first_mut() {
+            Some(main) => *main = name.clone(),
+            None => config.archives.push(name.clone()),
+        }
+    }
+
+    pack_config(
+        &config,
+        &config_path.display().to_string(),
+        output_dir,
+        options,
+        on_progress,
+    )
+}
+
+/// Packs every file below the directory `input_dir` into the archive `archive_name` in
+/// `output_dir`, without a config (see [`config::scan_directory`]).
+///
+/// The archive itself is left out if it is written below `input_dir`.
+pub fn pack_directory(
+    input_dir: &str,
+    output_dir: &str,
+    archive_name: &str,
+    compression: CompressionMethod,
+    options: &PackOptions,
+    on_progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<()> {
+    info!("Scanning directory: {}", input_dir);
+    let mut config =
+        config::scan_directory(std::path::Path::new(input_dir), archive_name, compression)
+            .map_err(|e| dzip_core::DzipError::Config(format!("{:#}", e)))?;
+    let output = std::path::Path::new(output_dir).join(archive_name);
+    if let Ok(output) = output.canonicalize() {
+        config.files.retain(|entry| {
+            config
+                .base_dir
+                .join(&entry.path)
+                .canonicalize()
+                .is_ok_and(|path| path != output)
+        });
+    }
+    info!("Found {} files", config.files.len());
+    pack_config(&config, input_dir, output_dir, options, on_progress)
+}
+
+/// Packs the files listed in `config`, loaded from `origin`, into `output_dir`.
+fn pack_config(
+    config: &config::DzipConfig,
+    origin: &str,
+    output_dir: &str,
+    options: &PackOptions,
+    on_progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<()> {
+    let source = FileSystemPackSource::new(config.base_dir.clone());
+    if options.dry_run {
+        let problems = validate_config(config, &source, options);
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        if !problems.is_empty() {
+            return Err(dzip_core::DzipError::Config(format!(
+                "{} problem(s) found in {}",
+                problems.len(),
+                origin
+            )));
+        }
+        info!(
+            "Config is valid: {} files in {} archive(s)",
+            config.files.len(),
+            config.archives.len()
+        );
+        let estimate = estimate_pack_size(config, &source, options)?;
+        info!(
+            "Estimated archive size: about {} bytes ({} to {}) for {} bytes of input",
+            estimate.expected, estimate.min, estimate.max, estimate.raw
+        );
+        return Ok(());
+    }
 
     std::fs::create_dir_all(output_dir)?;
 
-    // --- Prepare Metadata ---
-    // 1. Strings: User Files + Unique Directories
-    // Note: Dzip strings table contains filenames (basename) and directory paths.
-    // The exact structure is: [List of User Filenames], [List of Directory Paths].
-    // Wait, the format in unpacking:
-    // strings = reader.read_strings(settings.num_user_files + settings.num_directories - 1)?
-    // And map points to dir_index.
-    // So strings table is: [file1_name, file2_name, ..., dir1_path, dir2_path, ...].
-    // Note root dir is implicit/empty and usually not in strings table?
-    // Unpacker: `dir_index = num_user_files + (dir_id - 1)`.
-    // If dir_id=1, index = num_user_files.
-    // So yes, strings list is [Files..., Dir1, Dir2...].
-
-    // Collect File Names
-    let mut file_names = Vec::new();
+    let mut sink = FileSystemPackSink::new(std::path::PathBuf::from(output_dir));
+    if let Some(size) = options.io_buffer_size {
+        sink.set_buffer_size(size);
+    }
+    let metadata = super::with_thread_limit(options.max_threads, || {
+        pack_with_source(config, &source, &mut sink, options, on_progress)
+    })?;
+    print_report(&PackReport::new(&metadata.chunks));
+    Ok(())
+}
+
+/// Prints the stored and decompressed sizes per compression method.
+fn print_report(report: &PackReport) {
+    let mut methods: Vec<_> = report.per_method.iter().collect();
+    methods.sort_by_key(|(method, _)| format!("{:?}", method));
+    println!(
+        "{:<12} | {:<10} | {:<10} | Ratio",
+        "Method", "Size", "Packed"
+    );
+    println!("{:-<12}-+-{:-<10}-+-{:-<10}-+-{:-<6}", "", "", "", "");
+    for (method, &(raw, compressed)) in methods {
+        println!(
+            "{:<12} | {:<10} | {:<10} | {}",
+            format!("{:?}", method),
+            raw,
+            compressed,
+            format_ratio((raw > 0).then(|| compressed as f64 / raw as f64))
+        );
+    }
+    println!(
+        "{} chunk(s), {} -> {} bytes ({})",
+        report.chunk_count,
+        report.total_raw,
+        report.total_compressed,
+        format_ratio(report.ratio())
+    );
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    ratio.map_or("-".to_string(), |r| format!("{:.1}%", r * 100.0))
+}
+
+/// Checks `config` against `source` without reading any file contents and returns every
+/// problem found, so that all of them can be reported at once.
+pub fn validate_config(
+    config: &config::DzipConfig,
+    source: &dyn PackSource,
+    options: &PackOptions,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    if config.archives.is_empty() {
+        problems.push("No archives specified".to_string());
+    }
+    let volume_limit = options.max_volume_size.or(config.max_volume_size);
+    if volume_limit == Some(0) {
+        problems.push("max_volume_size must be greater than zero".to_string());
+    }
+    if options.max_threads == Some(0) {
+        problems.push("Number of jobs must be at least 1".to_string());
+    }
+    if let Some(version) = config.version.filter(|&v| v > VERSION_WIDE) {
+        problems.push(format!("Unsupported format version {}", version));
+    }
     for entry in &config.files {
-        // Use filename component
-        if let Some(name) = entry.path.file_name() {
-            file_names.push(name.to_string_lossy().to_string());
-        } else {
-            return Err(
-                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid file path").into(),
-            );
+        if !source.exists(&entry.path) {
+            problems.push(format!("Source file not found: {}", entry.path.display()));
+        }
+        if let Some(level) = entry.bzip_level.filter(|l| !(1..=9).contains(l)) {
+            problems.push(format!(
+                "{}: bzip_level {} is not between 1 and 9",
+                entry.path.display(),
+                level
+            ));
+        }
+        if let Err(e) = entry.parse_modifiers() {
+            problems.push(format!("{}: {:#}", entry.path.display(), e));
+        }
+        // With a volume limit the volumes are assigned while packing.
+        if volume_limit.is_none() && entry.archive_file_index as usize >= config.archives.len() {
+            problems.push(format!(
+                "{}: archive_file_index {} is out of range ({} archive(s))",
+                entry.path.display(),
+                entry.archive_file_index,
+                config.archives.len()
+            ));
         }
     }
+    if !options.allow_duplicates {
+        let paths: Vec<String> = config.files.iter().map(archive_path).collect();
+        for (path, count) in dzip_core::pack::duplicate_paths(paths.iter().map(String::as_str)) {
+            problems.push(format!("{} files have the path {}", count, path));
+        }
+    }
+    problems
+}
+
+/// Path of a file entry inside the archive.
+fn archive_path(entry: &config::FileEntry) -> String {
+    match &entry.archive_path {
+        Some(path) => dzip_core::path::to_archive_format(std::path::Path::new(path)),
+        None => dzip_core::path::to_archive_format(&entry.path),
+    }
+}
 
-    // Collect Unique Directories and assign IDs
-    let mut directories = Vec::new();
-    let mut dir_map = std::collections::HashMap::new(); // path -> dir_id (1-based)
+/// Packs the files listed in `config`, reading them from `source` and writing the volumes
+/// to `sink`. `base_dir` is not used; `source` resolves the paths of the file entries.
+///
+/// Files are added to the [`PackBuilder`] in config order, so the chunk table is the one
+/// the builder gives for the same files added in that order.
+///
+/// Returns the metadata of the written archive.
+pub fn pack_with_source(
+    config: &config::DzipConfig,
+    source: &dyn PackSource,
+    sink: &mut dyn PackSink,
+    options: &PackOptions,
+    on_progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<ArchiveMetadata> {
+    pack_builder(config, source, sink, options, on_progress)?.build()
+}
 
-    // Directory ID 0 is Root.
-    // We need to map each file to a dir_id.
-    let mut file_dir_ids = Vec::new();
+/// Estimates the size of the archive [`pack_with_source`] would write, without writing
+/// anything. See [`PackBuilder::estimate`].
+pub fn estimate_pack_size(
+    config: &config::DzipConfig,
+    source: &dyn PackSource,
+    options: &PackOptions,
+) -> Result<PackEstimate> {
+    let mut sink = dzip_core::volume::MemoryPackSink::new();
+    let on_progress = |_| {};
+    pack_builder(config, source, &mut sink, options, &on_progress)?.estimate()
+}
 
+fn pack_builder<'a>(
+    config: &config::DzipConfig,
+    source: &'a dyn PackSource,
+    sink: &'a mut dyn PackSink,
+    options: &PackOptions<'a>,
+    on_progress: &'a (dyn Fn(ProgressEvent) + Sync),
+) -> Result<PackBuilder<'a>> {
+    if let Some(entry) = config.files.iter().find(|e| !source.exists(&e.path)) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Source file not found: {}", entry.path.display()),
+        )
+        .into());
+    }
+
+    let mut builder = PackBuilder::new(sink)
+        .archive_names(config.archives.clone())
+        .dedup(options.dedup || config.dedup)
+        .header_checksum(options.header_checksum || config.header_checksum)
+        .allow_duplicates(options.allow_duplicates)
+        .name_encoding(config.encoding.unwrap_or_default())
+        .string_layout(config.string_layout.unwrap_or_default())
+        .on_progress(on_progress);
+    if let Some(cancel) = options.cancel {
+        builder = builder.cancel_flag(cancel);
+    }
+    if let Some(key) = options.key {
+        builder = builder.encryption_key(key);
+    }
+    // A command-line limit takes precedence over the one in the config file.
+    if let Some(limit) = options.max_volume_size.or(config.max_volume_size) {
+        builder = builder.volume_limit(limit);
+    }
+    if let Some(version) = config.version {
+        builder = builder.version(FormatVersion::try_from(version)?);
+    }
+    if let Some(comment) = &config.comment {
+        builder = builder.comment(comment.clone());
+    }
+    // Files are streamed through the compressors instead of being read into memory first.
     for entry in &config.files {
-        let parent = entry.path.parent().unwrap_or(std::path::Path::new(""));
-        // Force Windows-style backslashes as requested using core utility
-        let parent_str = dzip_core::path::to_archive_format(parent);
-
-        if parent_str.is_empty() || parent_str == "." {
-            file_dir_ids.push(0u16);
-        } else {
-            // Check if known
-            if let Some(&id) = dir_map.get(&parent_str) {
-                file_dir_ids.push(id);
-            } else {
-                // New directory
-                // Directories list stores paths.
-                directories.push(parent_str.clone());
-                let id = directories.len() as u16; // 1-based
-                dir_map.insert(parent_str, id);
-                file_dir_ids.push(id);
-            }
+        builder = builder.add_source_file_to_volume(
+            &archive_path(entry),
+            source,
+            &entry.path,
+            entry.compression,
+            entry.archive_file_index,
+        );
+        if entry.no_chunks {
+            builder = builder.no_chunks();
+        }
+        if !entry.flags.is_empty() {
+            builder = builder.extra_flags(entry.flags.iter().fold(0, |flags, m| flags | m.flag()));
+        }
+        if let Some(mtime) = source.modified(&entry.path) {
+            builder = builder.mtime(mtime);
+        }
+        if let Some(mode) = (options.permissions || config.permissions)
+            .then(|| source.mode(&entry.path))
+            .flatten()
+        {
+            builder = builder.mode(mode);
+        }
+        // The dedicated fields take precedence over the modifiers string.
+        let modifiers = entry.parse_modifiers().map_err(|e| {
+            dzip_core::DzipError::Config(format!("{}: {:#}", entry.path.display(), e))
+        })?;
+        if let Some(variant) = entry.lzma_variant.or(modifiers.lzma_variant) {
+            builder = builder.lzma_variant(variant);
+        }
+        if let Some(level) = entry.bzip_level.or(modifiers.level) {
+            builder = builder.bzip_level(level);
         }
     }
+    Ok(builder)
+}
 
-    let num_user_files = file_names.len() as u16;
-    let num_directories = (directories.len() + 1) as u16; // +1 for Root?
-    // Unpacker: `strings_count = num_user_files + num_directories - 1`.
-    // So strings count = files + dirs.
-    // Strings array = [Files..., Dirs...].
-    // Root dir is NOT in strings.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::testutil::{
+        assert_roundtrip, pack_with_defaults, unpack_with_defaults, write_pack_config,
+    };
 
-    let mut all_strings = file_names;
-    all_strings.extend(directories);
+    #[test]
+    fn test_pack_with_volume_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.bin", &[1u8; 300], "Copy"),
+            ("b.bin", &[2u8; 300], "Copy"),
+            ("sub/c.bin", &[3u8; 300], "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
 
-    // --- Open Volumes ---
-    if config.archives.is_empty() {
-        return Err(
-            std::io::Error::new(std::io::ErrorKind::InvalidInput, "No archives specified").into(),
-        );
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                max_volume_size: Some(500),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        for name in ["test.dz", "test.d01", "test.d02"] {
+            let len = std::fs::metadata(packed.join(name)).unwrap().len();
+            assert!(len <= 500, "{} is {} bytes", name, len);
+        }
+
+        let unpacked = dir.path().join("unpacked");
+        unpack_with_defaults(&packed.join("test.dz"), &unpacked);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(unpacked.join(name)).unwrap(), data);
+        }
     }
 
-    let mut writers = std::collections::HashMap::new();
-    for (i, name) in config.archives.iter().enumerate() {
-        let path = std::path::Path::new(output_dir).join(name);
-        info!("Opening volume {}: {}", i, path.display());
-        let f = std::fs::File::create(&path)?;
-        writers.insert(i as u16, f);
+    #[test]
+    fn test_pack_dedup() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let texture = vec![7u8; 1000];
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a/tex.bin", &texture, "Zlib"),
+            ("b/tex.bin", &texture, "Zlib"),
+            ("c.bin", b"unique", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                dedup: true,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        let archive = packed.join("test.dz");
+        let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(&archive).unwrap());
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.chunks.len(), 2);
+        assert_eq!(metadata.file_map[0].1, metadata.file_map[1].1);
+
+        let unpacked = dir.path().join("unpacked");
+        unpack_with_defaults(&archive, &unpacked);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(unpacked.join(name)).unwrap(), data);
+        }
     }
 
-    // --- Pre-calculate Header Size (Volume 0) ---
-    // Header (ArchiveSettings) = 4+2+2+1 = 9
-    // Strings = Sum(len+1)
-    // FileMap (ChunkMap) = NumFiles * (2 + NumChunksInFile*2 + 2)
-    // ChunkSettings = 2+2=4
-    // ChunkTable = NumChunks * 16
-    // Auxiliary File List = Sum(len+1) of archives[1..]
+    #[test]
+    fn test_pack_progress_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.bin", b"alpha", "Zlib"),
+            ("b.bin", b"bravo", "Bzip"),
+            ("c.bin", b"charlie", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
 
-    // Assuming 1 chunk per file
-    let num_chunks = num_user_files;
+        let events = std::sync::Mutex::new(Vec::new());
+        pack_archive(
+            config_path.to_str().unwrap(),
+            dir.path().join("packed").to_str().unwrap(),
+            &PackOptions::default(),
+            &|event| events.lock().unwrap().push(event),
+        )
+        .unwrap();
 
-    let mut header_size = 9;
-    for s in &all_strings {
-        header_size += s.len() as u64 + 1;
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.first(), Some(&ProgressEvent::Start(3)));
+        assert_eq!(events.last(), Some(&ProgressEvent::Finish));
+        assert_eq!(events.len(), 5);
+        assert!(events[1..4].iter().all(|e| *e == ProgressEvent::Inc(1)));
     }
-    let file_map_size = (num_user_files as u64) * 6; // DirID(2) + ChunkID(2) + Term(2)
-    header_size += file_map_size;
 
-    header_size += 4; // ChunkSettings
-    let chunk_table_size = (num_chunks as u64) * 16;
-    header_size += chunk_table_size;
+    #[test]
+    fn test_pack_is_reproducible() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 4] = [
+            ("z/a.txt", b"alpha", "Zlib"),
+            ("b/c.txt", b"charlie", "Bzip"),
+            ("b/a/d.txt", b"delta", "Lzma"),
+            ("e.txt", b"echo", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let mut reversed = files;
+        reversed.reverse();
+        let reversed_path = write_pack_config(&dir.path().join("reversed"), "test.dz", &reversed);
+
+        let pack = |config_path: &std::path::Path, max_threads, out: &str| {
+            let packed = dir.path().join(out);
+            pack_archive(
+                config_path.to_str().unwrap(),
+                packed.to_str().unwrap(),
+                &PackOptions {
+                    max_threads,
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+            .unwrap();
+            std::fs::read(packed.join("test.dz")).unwrap()
+        };
+        // Files finishing compression in another order change nothing
+        let single = pack(&config_path, Some(1), "single");
+        assert_eq!(pack(&config_path, Some(4), "parallel"), single);
 
-    // Add Volume List Size
-    if config.archives.len() > 1 {
-        for name in &config.archives[1..] {
-            header_size += name.len() as u64 + 1;
+        // Neither does the order in which the files use the directories: only the file
+        // table follows the config
+        let load = |data: Vec<u8>| {
+            let mut reader = dzip_core::reader::DzipReader::new(std::io::Cursor::new(data));
+            ArchiveMetadata::load(&mut reader).unwrap()
+        };
+        let forward = load(single);
+        let backward = load(pack(&reversed_path, Some(4), "reversed_out"));
+        assert_eq!(forward.directories, ["b", "b\\a", "z"]);
+        assert_eq!(backward.directories, forward.directories);
+        for i in 0..files.len() {
+            let j = files.len() - 1 - i;
+            assert_eq!(
+                forward.file_entry(i).unwrap().0,
+                backward.file_entry(j).unwrap().0
+            );
         }
     }
 
-    // Should we add GlobalSettings size? Only if we use DZ compression.
-    // Config options might specify usage. For now assume minimal header.
-    // We will update this offset if needed.
+    #[test]
+    fn test_single_job_matches_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let text = b"All work and no play makes Jack a dull boy. ".repeat(200);
+        let files: [(&str, &[u8], &str); 4] = [
+            ("a.txt", &text, "Zlib"),
+            ("b/c.txt", &text[..3000], "Bzip"),
+            ("b/d.bin", &[9u8; 2000], "Lzma"),
+            ("e.bin", b"echo", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+
+        let mut outputs = Vec::new();
+        for max_threads in [Some(1), None] {
+            let packed = dir.path().join(format!("packed-{:?}", max_threads));
+            pack_archive(
+                config_path.to_str().unwrap(),
+                packed.to_str().unwrap(),
+                &PackOptions {
+                    max_volume_size: Some(400),
+                    max_threads,
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+            .unwrap();
+            let unpacked = dir.path().join(format!("unpacked-{:?}", max_threads));
+            crate::commands::unpack::unpack_archive(
+                packed.join("test.dz").to_str().unwrap(),
+                unpacked.to_str().unwrap(),
+                &crate::commands::unpack::UnpackOptions {
+                    max_threads,
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+            .unwrap();
+
+            let mut volumes: Vec<_> = std::fs::read_dir(&packed)
+                .unwrap()
+                .map(|e| e.unwrap().path())
+                .collect();
+            volumes.sort();
+            let volumes: Vec<_> = volumes
+                .iter()
+                .map(|p| (p.file_name().unwrap().to_owned(), std::fs::read(p).unwrap()))
+                .collect();
+            let extracted: Vec<_> = files
+                .iter()
+                .map(|(name, _, _)| std::fs::read(unpacked.join(name)).unwrap())
+                .collect();
+            outputs.push((volumes, extracted));
+        }
+        assert!(outputs[0].0.len() > 1);
+        assert_eq!(outputs[0], outputs[1]);
 
-    // Seek Volume 0
-    if let Some(w) = writers.get_mut(&0) {
-        w.seek(SeekFrom::Start(header_size))?;
+        let result = pack_archive(
+            config_path.to_str().unwrap(),
+            dir.path().join("none").to_str().unwrap(),
+            &PackOptions {
+                max_threads: Some(0),
+                ..Default::default()
+            },
+            &|_| {},
+        );
+        assert!(matches!(result, Err(dzip_core::DzipError::Config(_))));
     }
 
-    // --- Process Files and Write Chunks ---
-    let mut chunks = Vec::new();
-    let mut chunk_map = Vec::new(); // (dir_id, vec![chunk_id])
+    #[test]
+    fn test_cli_and_builder_chunk_tables_match() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 4] = [
+            ("z.bin", &[9u8; 700], "Bzip"),
+            ("a/b.txt", &[b'b'; 500], "Zlib"),
+            ("m.bin", &[5u8; 300], "Copy"),
+            ("a/empty.bin", b"", "Lzma"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                max_volume_size: Some(400),
+                max_threads: Some(4),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        let file = std::fs::File::open(packed.join("test.dz")).unwrap();
+        let cli = ArchiveMetadata::load(&mut dzip_core::reader::DzipReader::new(file)).unwrap();
 
-    // Parallel Compression Phase
-    info!("Compressing chunks in parallel...");
-    let pb = ProgressBar::new(config.files.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} {msg}")
-            .unwrap()
-            .progress_chars("=>-"),
-    );
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        let mut builder = PackBuilder::new(&mut sink)
+            .archive_names(vec!["test.dz".to_string()])
+            .volume_limit(400);
+        for (name, data, method) in &files {
+            builder = builder.add_file(name, data.to_vec(), method.parse().unwrap());
+        }
+        let built = builder.build().unwrap();
 
-    let processed_files: Vec<(u16, Vec<u8>, usize, u16)> = config
-        .files
-        .par_iter()
-        .enumerate()
-        .map(|(i, entry)| {
-            let full_path = config.base_dir.join(&entry.path);
-            debug!("Processing file {}: {}", i, full_path.display());
-            pb.set_message(format!("Compressing {}", entry.path.display()));
-
-            let raw_data = std::fs::read(&full_path).map_err(|e| {
-                dzip_core::DzipError::Io(std::io::Error::other(format!(
-                    "Failed to read {}: {}",
-                    full_path.display(),
-                    e
-                )))
-            })?;
-            let original_len = raw_data.len();
-
-            let method = entry.compression;
-            let (flags, compressed_data) = compress_data(&raw_data, method)?;
-
-            pb.inc(1);
-            Ok((
-                entry.archive_file_index,
-                compressed_data,
-                original_len,
-                flags,
-            ))
-        })
-        .collect::<Result<Vec<_>>>()?;
-    pb.finish_with_message("Compression complete");
-
-    // Sequential Write Phase
-    info!("Writing compressed chunks to volumes...");
-    for (i, (archive_id, compressed_data, original_len, flags)) in
-        processed_files.into_iter().enumerate()
-    {
-        let chunk_id = chunks.len() as u16;
-
-        let writer = writers.get_mut(&archive_id).ok_or_else(|| {
-            std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                format!("Archive volume {} not found in config", archive_id),
-            )
-        })?;
+        // Chunk IDs follow the file order, whichever way the files were given
+        assert_eq!(cli.chunks, built.chunks);
+        assert_eq!(cli.file_map, built.file_map);
+        assert_eq!(cli.user_files, built.user_files);
+        assert_eq!(cli.directories, built.directories);
+        assert_eq!(cli.volume_files, built.volume_files);
+        assert!(!built.volume_files.is_empty());
+        let volumes = sink.into_volumes();
+        for name in &built.volume_files {
+            assert_eq!(std::fs::read(packed.join(name)).unwrap(), volumes[name]);
+        }
+    }
 
-        let offset = writer.stream_position()? as u32;
-        writer.write_all(&compressed_data)?;
+    #[test]
+    fn test_pack_wide_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] =
+            [("a/one.bin", b"one", "Zlib"), ("b/two.bin", b"two", "Copy")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(&config_path, format!("version = 1\n{}", config)).unwrap();
 
-        chunks.push(Chunk {
-            offset,
-            compressed_length: compressed_data.len() as u32,
-            decompressed_length: original_len as u32,
-            flags,
-            file: archive_id,
-        });
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let archive = packed.join("test.dz");
+        let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(&archive).unwrap());
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.settings.version, VERSION_WIDE);
+        // Chunk data starts right after the header
+        assert_eq!(
+            metadata.chunks[0].offset as u64,
+            metadata.header_len().unwrap()
+        );
 
-        chunk_map.push((file_dir_ids[i], vec![chunk_id]));
+        let unpacked = dir.path().join("unpacked");
+        let config = unpack_with_defaults(&archive, &unpacked);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(unpacked.join(name)).unwrap(), data);
+        }
+        assert_eq!(config.version, Some(VERSION_WIDE));
     }
 
-    // --- Write Header ---
-    info!("Writing header to Volume 0...");
-    let main_writer = writers
-        .get_mut(&0)
-        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Volume 0 missing"))?;
+    #[test]
+    fn test_pack_from_memory_source() {
+        let config: config::DzipConfig = toml::from_str(
+            r#"
+            archives = ["test.dz"]
+            base_dir = "does/not/exist"
+
+            [[files]]
+            path = "a.txt"
+            archive_file_index = 0
+            compression = "Zlib"
 
-    main_writer.seek(SeekFrom::Start(0))?;
+            [[files]]
+            path = "sub/b.txt"
+            archive_file_index = 0
+            compression = "Copy"
+            "#,
+        )
+        .unwrap();
+        let mut source = dzip_core::pack::MemoryPackSource::new();
+        source.insert("a.txt", b"alpha".to_vec());
+        source.insert("sub/b.txt", b"bravo".to_vec());
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        pack_with_source(
+            &config,
+            &source,
+            &mut sink,
+            &PackOptions::default(),
+            &|_| {},
+        )
+        .unwrap();
 
-    // We need DzipWriter
-    struct SimpleWriter<'a, W: Write + Seek>(&'a mut W);
-    impl<'a, W: Write + Seek> Write for SimpleWriter<'a, W> {
-        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-            self.0.write(buf)
+        let mut volumes = sink.into_volumes();
+        let main = volumes.remove("test.dz").unwrap();
+        let mut reader = dzip_core::reader::DzipReader::new(std::io::Cursor::new(main));
+        let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+        let mut volume_manager = dzip_core::volume::MemoryVolumeManager::new(Vec::new(), volumes);
+        for (path, data) in [("a.txt", b"alpha"), ("sub/b.txt", b"bravo")] {
+            let extracted = metadata
+                .extract_one(&mut reader, &mut volume_manager, path)
+                .unwrap();
+            assert_eq!(&extracted, data);
         }
-        fn flush(&mut self) -> std::io::Result<()> {
-            self.0.flush()
+
+        let mut source = dzip_core::pack::MemoryPackSource::new();
+        source.insert("a.txt", b"alpha".to_vec());
+        let result = pack_with_source(
+            &config,
+            &source,
+            &mut dzip_core::volume::MemoryPackSink::new(),
+            &PackOptions::default(),
+            &|_| {},
+        );
+        assert!(
+            matches!(result, Err(dzip_core::DzipError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound)
+        );
+    }
+
+    #[test]
+    fn test_pack_modifiers() {
+        let config: config::DzipConfig = toml::from_str(
+            r#"
+            archives = ["test.dz"]
+            base_dir = "."
+
+            [[files]]
+            path = "zlib.bin"
+            archive_file_index = 0
+            compression = "Zlib"
+
+            [[files]]
+            path = "raw.bin"
+            archive_file_index = 0
+            compression = "Lzma"
+            modifiers = "lzma:raw"
+
+            [[files]]
+            path = "fast.bin"
+            archive_file_index = 0
+            compression = "Bzip"
+            modifiers = "to 25%, level=1"
+            "#,
+        )
+        .unwrap();
+        let data = b"modifiers ".repeat(50);
+        let mut source = dzip_core::pack::MemoryPackSource::new();
+        for entry in &config.files {
+            source.insert(entry.path.to_str().unwrap(), data.clone());
         }
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        let metadata = pack_with_source(
+            &config,
+            &source,
+            &mut sink,
+            &PackOptions::default(),
+            &|_| {},
+        )
+        .unwrap();
+
+        let flags: Vec<_> = metadata.chunks.iter().map(|c| c.flags).collect();
+        assert_eq!(
+            flags,
+            [
+                dzip_core::format::CHUNK_ZLIB,
+                dzip_core::format::CHUNK_LZMA,
+                dzip_core::format::CHUNK_BZIP
+            ]
+        );
+        let main = sink.into_volumes().remove("test.dz").unwrap();
+        let bzip = &metadata.chunks[2];
+        assert_eq!(&main[bzip.offset as usize..][..4], b"BZh1");
+        let mut reader = dzip_core::reader::DzipReader::new(std::io::Cursor::new(main));
+        let mut volumes =
+            dzip_core::volume::MemoryVolumeManager::new(Vec::new(), Default::default());
+        let variant = reader
+            .lzma_variant_with_volumes(&metadata.chunks[1], &mut volumes)
+            .unwrap();
+        assert_eq!(variant, Some(dzip_core::LzmaVariant::Raw));
+
+        let mut entry = config.files[0].clone();
+        entry.modifiers = "level=9".to_string();
+        assert!(entry.parse_modifiers().is_err());
+        entry.modifiers = "levle=9".to_string();
+        assert!(entry.parse_modifiers().is_err());
     }
-    impl<'a, W: Write + Seek> Seek for SimpleWriter<'a, W> {
-        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
-            self.0.seek(pos)
+
+    #[test]
+    fn test_estimate_brackets_packed_size() {
+        let config: config::DzipConfig = toml::from_str(
+            r#"
+            archives = ["test.dz"]
+            base_dir = "."
+            dedup = true
+            max_volume_size = 100000
+            comment = "estimate"
+
+            [[files]]
+            path = "text.txt"
+            archive_file_index = 0
+            compression = "Zlib"
+
+            [[files]]
+            path = "noise.bin"
+            archive_file_index = 0
+            compression = "Lzma"
+
+            [[files]]
+            path = "small.txt"
+            archive_file_index = 0
+            compression = "Bzip"
+
+            [[files]]
+            path = "dir/copy.txt"
+            archive_file_index = 0
+            compression = "Zlib"
+
+            [[files]]
+            path = "dir/raw.bin"
+            archive_file_index = 0
+            compression = "Copy"
+
+            [[files]]
+            path = "empty.bin"
+            archive_file_index = 0
+            compression = "Lzma"
+            "#,
+        )
+        .unwrap();
+        // Compressible text whose start compresses better than the rest, and noise
+        let mut text = b"aaaa".repeat(20_000);
+        for i in 0..50_000u32 {
+            text.extend(format!("{} ", i).as_bytes());
         }
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..150_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 24) as u8
+            })
+            .collect();
+        let mut source = dzip_core::pack::MemoryPackSource::new();
+        source.insert("text.txt", text.clone());
+        source.insert("noise.bin", noise);
+        source.insert("small.txt", b"small ".repeat(100));
+        source.insert("dir/copy.txt", text);
+        source.insert("dir/raw.bin", vec![7u8; 5000]);
+        source.insert("empty.bin", Vec::new());
+
+        let estimate = estimate_pack_size(&config, &source, &PackOptions::default()).unwrap();
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        pack_with_source(
+            &config,
+            &source,
+            &mut sink,
+            &PackOptions::default(),
+            &|_| {},
+        )
+        .unwrap();
+        let volumes = sink.into_volumes();
+        assert!(volumes.len() > 1);
+        let packed: u64 = volumes.values().map(|v| v.len() as u64).sum();
+
+        assert!(estimate.min <= packed, "{:?} for {}", estimate, packed);
+        assert!(packed <= estimate.max, "{:?} for {}", estimate, packed);
+        assert!(estimate.min <= estimate.expected && estimate.expected <= estimate.max);
+        assert_eq!(
+            estimate.raw,
+            config
+                .files
+                .iter()
+                .map(|f| source.file_len(&f.path).unwrap())
+                .sum::<u64>()
+        );
     }
 
-    let mut dzip_writer = dzip_core::writer::DzipWriter::new(SimpleWriter(main_writer));
+    #[test]
+    fn test_pack_dry_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 1] = [("a.bin", b"alpha", "Zlib")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(
+            &config_path,
+            format!(
+                "{}{}",
+                config,
+                "[[files]]\npath = \"missing1.bin\"\narchive_file_index = 0\ncompression = \"Copy\"\n\
+                 [[files]]\npath = \"missing2.bin\"\narchive_file_index = 0\ncompression = \"Copy\"\n"
+            ),
+        )
+        .unwrap();
 
-    // ... rest of header writing ...
+        let packed = dir.path().join("packed");
+        let options = PackOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let result = pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &options,
+            &|_| {},
+        );
+        assert!(matches!(result, Err(dzip_core::DzipError::Config(_))));
+        assert!(!packed.exists());
 
-    dzip_writer.write_archive_settings(&ArchiveSettings {
-        header: 0x5A525444, // DTRZ
-        num_user_files,
-        num_directories,
-        version: 0,
-    })?;
+        let config = crate::config::parse_config(&config_path).unwrap();
+        let source = FileSystemPackSource::new(src);
+        let problems = validate_config(&config, &source, &options);
+        assert_eq!(
+            problems,
+            [
+                "Source file not found: missing1.bin",
+                "Source file not found: missing2.bin"
+            ]
+        );
+    }
 
-    // ...
+    #[test]
+    fn test_pack_duplicate_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] =
+            [("a.bin", b"alpha", "Zlib"), ("sub/b.bin", b"bravo", "Copy")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        // The same file again, and another one stored under its path
+        std::fs::write(
+            &config_path,
+            format!(
+                "{}{}",
+                config,
+                "[[files]]\npath = \"a.bin\"\narchive_file_index = 0\ncompression = \"Copy\"\n\
+                 [[files]]\npath = \"a.bin\"\narchive_path = \"sub\\\\b.bin\"\narchive_file_index = 0\ncompression = \"Copy\"\n"
+            ),
+        )
+        .unwrap();
 
-    dzip_writer.write_strings(&all_strings)?;
-    dzip_writer.write_file_chunk_map(&chunk_map)?;
+        let packed = dir.path().join("packed");
+        let result = pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions::default(),
+            &|_| {},
+        );
+        let Err(dzip_core::DzipError::Config(message)) = result else {
+            panic!("expected a config error, got {:?}", result);
+        };
+        assert_eq!(
+            message,
+            "Duplicate paths: a.bin (2 files), sub/b.bin (2 files)"
+        );
+        assert!(!packed.join("test.dz").exists());
 
-    // ...
+        let config = crate::config::parse_config(&config_path).unwrap();
+        let source = FileSystemPackSource::new(src);
+        let problems = validate_config(&config, &source, &PackOptions::default());
+        assert_eq!(
+            problems,
+            [
+                "2 files have the path a.bin",
+                "2 files have the path sub/b.bin"
+            ]
+        );
 
-    let num_archive_files = config.archives.len() as u16;
+        let options = PackOptions {
+            allow_duplicates: true,
+            ..Default::default()
+        };
+        assert!(validate_config(&config, &source, &options).is_empty());
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &options,
+            &|_| {},
+        )
+        .unwrap();
+        assert!(packed.join("test.dz").exists());
+    }
 
-    dzip_writer.write_chunk_settings(&ChunkSettings {
-        num_archive_files,
-        num_chunks: chunks.len() as u16,
-    })?;
+    #[test]
+    fn test_pack_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8]); 3] = [
+            ("a.bin", &[1u8; 300]),
+            ("sub/b.txt", b"hello"),
+            ("sub/deeper/c.bin", &[3u8; 1000]),
+        ];
+        for (name, data) in &files {
+            let path = src.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, data).unwrap();
+        }
 
-    dzip_writer.write_chunks(&chunks)?;
-
-    // Write Auxiliary File List
-    if config.archives.len() > 1 {
-        let aux_files = &config.archives[1..];
-        dzip_writer.write_strings(aux_files)?;
-    }
-
-    let has_dz = chunks.iter().any(|c| (c.flags & CHUNK_DZ) != 0);
-    if has_dz {
-        dzip_writer.write_global_settings(&RangeSettings {
-            win_size: 0,
-            flags: 0,
-            offset_table_size: 0,
-            offset_tables: 0,
-            offset_contexts: 0,
-            ref_length_table_size: 0,
-            ref_length_tables: 0,
-            ref_offset_table_size: 0,
-            ref_offset_tables: 0,
-            big_min_match: 0,
-        })?;
+        // Written inside the scanned directory, which must not pack the archive into itself
+        pack_directory(
+            src.to_str().unwrap(),
+            src.to_str().unwrap(),
+            "dir.dz",
+            CompressionMethod::Zlib,
+            &PackOptions::default(),
+            &|_| {},
+        )
+        .unwrap();
+
+        let unpacked = dir.path().join("unpacked");
+        unpack_with_defaults(&src.join("dir.dz"), &unpacked);
+        for (name, data) in &files {
+            assert_eq!(&std::fs::read(unpacked.join(name)).unwrap(), data);
+        }
+        assert!(!unpacked.join("dir.dz").exists());
     }
 
-    info!("Pack complete.");
-    Ok(())
+    #[test]
+    fn test_pack_with_archive_name_and_base_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] =
+            [("a.txt", b"alpha", "Copy"), ("sub/b.txt", b"bravo", "Zlib")];
+        write_pack_config(&src, "test.dz", &files);
+        // The config no longer sits next to the files
+        let config_path = dir.path().join("configs/game.toml");
+        std::fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+        std::fs::rename(src.join("pack.toml"), &config_path).unwrap();
+
+        let out = dir.path().join("out");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &PackOptions {
+                archive_name: Some("build.dz".to_string()),
+                base_dir: Some(src.clone()),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        assert!(!out.join("test.dz").exists());
+
+        let extracted = dir.path().join("extracted");
+        let config = unpack_with_defaults(&out.join("build.dz"), &extracted);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(extracted.join(name)).unwrap(), data);
+        }
+        assert_eq!(config.archives, ["build.dz"]);
+
+        // Without the override the files are looked for next to the config
+        let result = pack_archive(
+            config_path.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &PackOptions::default(),
+            &|_| {},
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_repack_single_archive_fixtures() {
+        assert_roundtrip("ExampleSingleArchive/test1.dz");
+        assert_roundtrip("ExampleSingleArchive/test2.dz");
+    }
+
+    #[test]
+    fn test_repack_split_archive_fixture() {
+        assert_roundtrip("ExampleSplitArchive/testnew.dz");
+    }
 }
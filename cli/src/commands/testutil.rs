@@ -0,0 +1,101 @@
+//! Helpers shared by the command tests.
+
+use super::pack::{PackOptions, pack_archive};
+use super::unpack::{UnpackOptions, unpack_archive};
+use crate::config::{self, DzipConfig};
+use std::path::{Path, PathBuf};
+
+/// Writes `files` below `dir` together with a TOML pack config listing them,
+/// and returns the path of the config.
+pub fn write_pack_config(dir: &Path, archive: &str, files: &[(&str, &[u8], &str)]) -> PathBuf {
+    let mut config = format!("archives = [\"{}\"]\nbase_dir = \".\"\n", archive);
+    for (name, data, method) in files {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+        config.push_str(&format!(
+            "[[files]]\npath = \"{}\"\narchive_file_index = 0\ncompression = \"{}\"\n",
+            name, method
+        ));
+    }
+    let config_path = dir.join("pack.toml");
+    std::fs::write(&config_path, config).unwrap();
+    config_path
+}
+
+/// Path of a fixture below the `test_data` directory of the repository.
+pub fn test_data(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("../test_data")
+        .join(path)
+}
+
+/// Path of a fixture below `test_data`, or `None` if it is missing, in which case the
+/// calling test should return early.
+pub fn fixture_or_skip(path: &str) -> Option<PathBuf> {
+    let path = test_data(path);
+    if path.exists() {
+        Some(path)
+    } else {
+        eprintln!("Test file not found at {:?}, skipping.", path);
+        None
+    }
+}
+
+/// Packs the config at `config_path` into `output_dir` with the default options, and
+/// returns the path of the main file.
+pub fn pack_with_defaults(config_path: &Path, output_dir: &Path) -> PathBuf {
+    pack_archive(
+        config_path.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        &PackOptions::default(),
+        &|_| {},
+    )
+    .unwrap();
+    let config = config::parse_config(config_path).unwrap();
+    output_dir.join(&config.archives[0])
+}
+
+/// Unpacks the archive at `archive` into `output_dir` with the default options, and
+/// returns the config written next to the files.
+pub fn unpack_with_defaults(archive: &Path, output_dir: &Path) -> DzipConfig {
+    unpack_archive(
+        archive.to_str().unwrap(),
+        output_dir.to_str().unwrap(),
+        &UnpackOptions::default(),
+        &|_| {},
+    )
+    .unwrap();
+    let stem = archive.file_stem().unwrap().to_str().unwrap();
+    config::parse_config(&output_dir.join(format!("{}.toml", stem))).unwrap()
+}
+
+/// Unpacks the fixture `path`, packs the extracted files again with the generated config
+/// and unpacks the result, then checks that both extractions hold the same bytes for
+/// every file of the config. Skips fixtures that are not there.
+pub fn assert_roundtrip(path: &str) {
+    let Some(path) = fixture_or_skip(path) else {
+        return;
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let stem = path.file_stem().unwrap().to_str().unwrap();
+    let out = dir.path().join("out");
+    let config = unpack_with_defaults(&path, &out);
+    let config_path = out.join(format!("{}.toml", stem));
+
+    let packed = pack_with_defaults(&config_path, &dir.path().join("packed"));
+    let out2 = dir.path().join("out2");
+    unpack_with_defaults(&packed, &out2);
+
+    assert!(!config.files.is_empty());
+    for entry in &config.files {
+        let first = out.join(&config.base_dir).join(&entry.path);
+        let second = out2.join(&config.base_dir).join(&entry.path);
+        assert!(
+            std::fs::read(&first).unwrap() == std::fs::read(&second).unwrap(),
+            "{:?} differs after repacking {:?}",
+            entry.path,
+            path
+        );
+    }
+}
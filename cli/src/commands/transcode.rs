@@ -0,0 +1,32 @@
+use dzip_core::{CompressionMethod, Result};
+use log::info;
+use std::path::Path;
+
+/// Recompresses the files of an archive matching `include` (all files if empty) with
+/// `method`, writing the result to `output_path`.
+pub fn transcode_archive(
+    input_path: &str,
+    output_path: &str,
+    method: CompressionMethod,
+    include: &[String],
+) -> Result<()> {
+    info!(
+        "Transcoding {} into {} with {}",
+        input_path,
+        output_path,
+        method.name()
+    );
+    let filter = super::unpack::include_filter(include)?;
+    let metadata = dzip_core::transcode(
+        Path::new(input_path),
+        Path::new(output_path),
+        method,
+        &|path| filter.as_ref().is_none_or(|f| f(path)),
+    )?;
+    info!(
+        "Wrote {} chunk(s), {} bytes",
+        metadata.chunks.len(),
+        metadata.main_data_end
+    );
+    Ok(())
+}
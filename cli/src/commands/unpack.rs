@@ -1,40 +1,265 @@
 use crate::config;
-use dzip_core::Result;
-use indicatif::{ProgressBar, ProgressStyle};
+use dzip_core::format::{FILE_MODE_BITS, VERSION_LEGACY};
+use dzip_core::{ChunkCipher, EncryptionKey, NameEncoding, ProgressEvent, Result, StringLayout};
 use log::{debug, error, info, warn};
 use rayon::prelude::*;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-pub fn unpack_archive(input_path: &str, output_dir: &str) -> Result<()> {
-    let file = std::fs::File::open(input_path)?;
-    let mut reader = dzip_core::reader::DzipReader::new(file);
+/// Predicate deciding which files to extract, given their logical path with `/` separators.
+pub type UnpackFilter<'a> = &'a (dyn Fn(&str) -> bool + Sync);
 
-    info!("Reading archive metadata...");
-    let settings = reader.read_archive_settings()?;
+/// Builds a filter matching any of the given glob patterns, or `None` if there are none.
+pub fn include_filter(patterns: &[String]) -> Result<Option<impl Fn(&str) -> bool + Sync + use<>>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let patterns = patterns
+        .iter()
+        .map(|p| {
+            glob::Pattern::new(p).map_err(|e| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("Invalid include pattern '{}': {}", p, e),
+                )
+            })
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    Ok(Some(move |path: &str| {
+        patterns.iter().any(|p| p.matches(path))
+    }))
+}
 
-    // Determine string count (handling implicit root directory)
-    let strings_count = (settings.num_user_files + settings.num_directories - 1) as usize;
-    let strings = reader.read_strings(strings_count)?;
+/// What to do when an extracted file already exists.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverwritePolicy {
+    /// Replace the existing file
+    #[default]
+    Always,
+    /// Keep the existing file and don't read its chunks. Its config entry only holds what
+    /// the header tells, so the LZMA framing of the file is not recorded.
+    Skip,
+    /// Stop with an error
+    Error,
+}
 
-    let map = reader.read_file_chunk_map(settings.num_user_files as usize)?;
-    let chunk_settings = reader.read_chunk_settings()?;
-    let mut chunks = reader.read_chunks(chunk_settings.num_chunks as usize)?;
+impl OverwritePolicy {
+    /// Creates the output file at `path`, or returns `None` if an existing file is kept.
+    fn create(self, path: &std::path::Path) -> std::io::Result<Option<std::fs::File>> {
+        match self {
+            OverwritePolicy::Always => std::fs::File::create(path).map(Some),
+            OverwritePolicy::Skip if path.exists() => {
+                debug!("Skipping existing file {}", path.display());
+                Ok(None)
+            }
+            OverwritePolicy::Skip => std::fs::File::create(path).map(Some),
+            OverwritePolicy::Error => std::fs::File::create_new(path).map(Some).map_err(|e| {
+                if e.kind() == std::io::ErrorKind::AlreadyExists {
+                    std::io::Error::new(e.kind(), format!("{} already exists", path.display()))
+                } else {
+                    e
+                }
+            }),
+        }
+    }
+}
 
-    // Read file list (if multi-volume)
-    let num_other_volumes = if chunk_settings.num_archive_files > 0 {
-        chunk_settings.num_archive_files as usize - 1
-    } else {
-        0
+/// Options controlling extraction.
+#[derive(Default, Clone, Copy)]
+pub struct UnpackOptions<'a> {
+    /// Only extract files for which this returns true
+    pub filter: Option<UnpackFilter<'a>>,
+    /// Encoding of the names stored in the archive
+    pub name_encoding: NameEncoding,
+    /// Capacity of the buffers around the archive files and the extracted files
+    pub io_buffer_size: Option<usize>,
+    /// Number of leading directories to drop from each path, like `tar --strip-components`
+    pub strip_components: usize,
+    /// Extract every file into the output directory itself, renaming clashing names
+    pub flatten: bool,
+    /// What to do with files that already exist in the output directory
+    pub overwrite: OverwritePolicy,
+    /// Extract on at most this many threads (all cores if unset)
+    pub max_threads: Option<usize>,
+    /// Stop with `DzipError::Cancelled` once this is set. Files extracted so far, including
+    /// a partially written one, are left in place.
+    pub cancel: Option<&'a AtomicBool>,
+    /// Key of an encrypted archive
+    pub key: Option<EncryptionKey>,
+    /// Rename files Windows cannot create and use extended-length paths for long ones
+    pub sanitize_names: bool,
+    /// Extract files only while the decompressed sizes of those in progress add up to at
+    /// most this many bytes. Larger files are extracted on their own.
+    pub memory_budget: Option<u64>,
+    /// Keep files that already exist with their full decompressed size, and extract the
+    /// others again, to continue an interrupted extraction
+    pub resume: bool,
+}
+
+impl UnpackOptions<'_> {
+    /// Path relative to the output directory that the file with the (stripped or
+    /// flattened) archive path `path` is extracted to, and whether it had to be renamed.
+    /// Paths escaping the output directory are rejected.
+    fn relative_output_path(&self, path: &str) -> Result<(std::path::PathBuf, bool)> {
+        if !self.sanitize_names {
+            return Ok((dzip_core::path::resolve_relative_path(path)?, false));
+        }
+        let safe = dzip_core::path::windows_safe_path(path);
+        let renamed = safe != path;
+        if renamed {
+            info!("Extracting {} as {}", path, safe);
+        }
+        Ok((dzip_core::path::resolve_relative_path(&safe)?, renamed))
+    }
+
+    fn full_output_path(
+        &self,
+        output_dir: &str,
+        relative: &std::path::Path,
+    ) -> Result<std::path::PathBuf> {
+        let path = std::path::Path::new(output_dir).join(relative);
+        if self.sanitize_names {
+            dzip_core::path::extended_length_path(&path)
+        } else {
+            Ok(path)
+        }
+    }
+
+    /// Cipher for the chunks of `metadata`. Encrypted archives need a key, others ignore it.
+    fn cipher(&self, metadata: &dzip_core::ArchiveMetadata) -> Result<Option<ChunkCipher>> {
+        match &self.key {
+            Some(key) => metadata.cipher(key),
+            None if metadata.is_encrypted() => Err(dzip_core::DzipError::Encryption(
+                "The archive is encrypted, a key is required".to_string(),
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates the output file at `path` for a file of `size` bytes, or returns `None` if an
+    /// existing file is kept.
+    ///
+    /// When resuming, an existing file of the right size counts as extracted, and one of any
+    /// other size as cut short by the interruption, so it is replaced whatever the
+    /// overwrite policy.
+    fn create_output(
+        &self,
+        path: &std::path::Path,
+        size: u64,
+    ) -> std::io::Result<Option<std::fs::File>> {
+        if self.resume {
+            match std::fs::metadata(path) {
+                Ok(existing) if existing.is_file() && existing.len() == size => {
+                    debug!("Already extracted: {}", path.display());
+                    return Ok(None);
+                }
+                Ok(_) => return std::fs::File::create(path).map(Some),
+                Err(_) => {}
+            }
+        }
+        self.overwrite.create(path)
+    }
+
+    fn check_cancel(&self) -> Result<()> {
+        match self.cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => Err(dzip_core::DzipError::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    fn buffer_size(&self) -> usize {
+        self.io_buffer_size
+            .unwrap_or(dzip_core::reader::DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Volume manager for the split volumes of the archive at `input`. Volumes that are
+    /// missing under their listed name are looked for as `name.d01`, `name.d02`, ...
+    fn volume_manager(
+        &self,
+        input: &std::path::Path,
+        file_list: Vec<String>,
+    ) -> dzip_core::volume::FileSystemVolumeManager {
+        let base_dir = input.parent().unwrap_or(std::path::Path::new("."));
+        let mut volumes =
+            dzip_core::volume::FileSystemVolumeManager::new(base_dir.to_path_buf(), file_list);
+        volumes.set_buffer_size(self.buffer_size());
+        if let Some(name) = input.file_name() {
+            volumes.set_numbered_fallback(&name.to_string_lossy());
+        }
+        volumes
+    }
+}
+
+/// Extracts the single file `logical_path`, writing it to stdout if `output` is `-`
+/// or below the directory `output` otherwise.
+pub fn unpack_file(
+    input_path: &str,
+    logical_path: &str,
+    output: &str,
+    options: &UnpackOptions,
+) -> Result<()> {
+    let input = std::path::Path::new(input_path);
+    let mut reader = dzip_core::reader::DzipReader::with_buffer_size(
+        std::fs::File::open(input)?,
+        options.buffer_size(),
+    );
+    reader.set_name_encoding(options.name_encoding);
+    let mut metadata = dzip_core::ArchiveMetadata::load(&mut reader)?;
+    reader.set_cipher(options.cipher(&metadata)?);
+    let mut volumes = options.volume_manager(input, metadata.volume_files.clone());
+    let index = metadata.find_unique_file(logical_path)?;
+    metadata.correct_file_chunk_sizes(&[index], &mut volumes)?;
+
+    if output == "-" {
+        let mut stdout = std::io::stdout().lock();
+        metadata.extract_file_to(&mut reader, &mut volumes, logical_path, &mut stdout)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    let (relative, _) = options.relative_output_path(logical_path)?;
+    let path = options.full_output_path(output, &relative)?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let Some(file) = options.overwrite.create(&path)? else {
+        info!("Kept existing {}", path.display());
+        return Ok(());
     };
-    let volume_files = reader.read_file_list(num_other_volumes)?;
+    let mut file = std::io::BufWriter::with_capacity(options.buffer_size(), file);
+    let written = metadata.extract_file_to(&mut reader, &mut volumes, logical_path, &mut file)?;
+    file.flush()?;
+    set_mtime(file.get_ref(), metadata.file_mtime(index))?;
+    set_permissions(file.get_ref(), metadata.file_mode(index))?;
+    info!("Extracted {} ({} bytes)", path.display(), written);
+    Ok(())
+}
+
+/// Extracts the files of the archive at `input_path` below `output_dir` and writes a
+/// config to repack them.
+///
+/// `on_progress` is told the number of files to extract, then receives a `FileDone` with
+/// the logical path and an `Inc(1)` per extracted file (from the worker threads) and
+/// `Finish` once the config is written.
+pub fn unpack_archive(
+    input_path: &str,
+    output_dir: &str,
+    options: &UnpackOptions,
+    on_progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<()> {
+    let file = std::fs::File::open(input_path)?;
+    let mut reader = dzip_core::reader::DzipReader::with_buffer_size(file, options.buffer_size());
+    reader.set_name_encoding(options.name_encoding);
+
+    info!("Reading archive metadata...");
+    let mut metadata = dzip_core::ArchiveMetadata::load(&mut reader)?;
+    let cipher = options.cipher(&metadata)?;
+    let volume_files = metadata.volume_files.clone();
     debug!(
         "Num archive files: {}, Volume List: {:?}",
-        chunk_settings.num_archive_files, volume_files
+        metadata.chunk_settings.num_archive_files, volume_files
     );
 
-    info!(
-        "Extracting {} files to '{}'...",
-        settings.num_user_files, output_dir
-    );
     std::fs::create_dir_all(output_dir)?;
 
     let mut archives_names = vec![
@@ -46,12 +271,9 @@ pub fn unpack_archive(input_path: &str, output_dir: &str) -> Result<()> {
     ];
     archives_names.extend(volume_files.clone());
 
-    use dzip_core::format::CHUNK_DZ;
-    let has_dz_chunks = chunks.iter().any(|c| (c.flags & CHUNK_DZ) != 0);
-
-    let global_options = if has_dz_chunks {
-        let settings = reader.read_global_settings()?;
-        Some(config::GlobalOptions {
+    let global_options = metadata
+        .range_settings
+        .map(|settings| config::GlobalOptions {
             win_size: settings.win_size,
             offset_table_size: settings.offset_table_size,
             offset_tables: settings.offset_tables,
@@ -62,198 +284,221 @@ pub fn unpack_archive(input_path: &str, output_dir: &str) -> Result<()> {
             ref_offset_tables: settings.ref_offset_tables,
             big_min_match: settings.big_min_match,
             ..config::GlobalOptions::default()
-        })
-    } else {
-        None
-    };
+        });
 
     let mut pack_config = config::DzipConfig {
         archives: archives_names,
         base_dir: std::path::PathBuf::from("."),
         files: Vec::new(),
         options: global_options,
+        max_volume_size: None,
+        // Files sharing a chunk are only stored once again when repacked.
+        dedup: shares_chunks(&metadata.file_map),
+        encoding: Some(options.name_encoding).filter(|e| *e != NameEncoding::Utf8),
+        string_layout: Some(metadata.string_layout).filter(|l| *l != StringLayout::FilesFirst),
+        version: Some(metadata.settings.version).filter(|&v| v != VERSION_LEGACY),
+        comment: metadata.comment().map(str::to_string),
+        header_checksum: metadata
+            .trailer
+            .as_ref()
+            .is_some_and(|t| t.header_crc.is_some()),
+        permissions: metadata
+            .trailer
+            .as_ref()
+            .is_some_and(|t| t.file_modes.is_some()),
     };
 
     // Prepare shared data for parallel execution
-    let settings_num_user_files = settings.num_user_files;
     let volume_files_shared = volume_files.clone(); // Clone vec from manager
-    let input_base_dir = std::path::Path::new(input_path)
-        .parent()
-        .unwrap_or_else(|| std::path::Path::new("."));
-    let input_base_dir_shared = input_base_dir.to_path_buf();
+    let input = std::path::Path::new(input_path);
+
+    let selected = select_files(&metadata, options)?;
+    if selected.len() < metadata.file_map.len() {
+        info!(
+            "Selected {} of {} files",
+            selected.len(),
+            metadata.file_map.len()
+        );
+    }
 
     // --- Chunk Size Correction ---
     // Some archives (like testnew.dz) have incorrect compressed_length headers (listing uncompressed size).
     // Validity check: compressed_length cannot exceed distance to next chunk or EOF.
-    let mut file_sizes = std::collections::HashMap::new();
-    if let Ok(meta) = std::fs::metadata(input_path) {
-        file_sizes.insert(0u16, meta.len());
-    }
-    for (i, vol_name) in volume_files.iter().enumerate() {
-        let path = input_base_dir.join(vol_name);
-        if let Ok(meta) = std::fs::metadata(&path) {
-            file_sizes.insert((i + 1) as u16, meta.len());
-        }
-    }
-    dzip_core::reader::correct_chunk_sizes(&mut chunks, &file_sizes);
+    // Only the volumes holding the selected files are opened.
+    let indices: Vec<usize> = selected.iter().map(|(i, _)| *i).collect();
+    metadata.correct_file_chunk_sizes(
+        &indices,
+        &mut options.volume_manager(input, volume_files.clone()),
+    )?;
+    let map = &metadata.file_map;
+    let chunks = &metadata.chunks;
     // -----------------------------
 
-    info!("Extracting {} files to '{}'...", map.len(), output_dir);
-    let pb = ProgressBar::new(map.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:40.cyan/blue} {pos}/{len} {msg}")
-            .unwrap()
-            .progress_chars("=>-"),
-    );
+    if options.memory_budget == Some(0) {
+        return Err(dzip_core::DzipError::Config(
+            "Memory budget must be greater than zero".to_string(),
+        ));
+    }
+    let budget = options.memory_budget.map(MemoryBudget::new);
+
+    info!("Extracting {} files to '{}'...", selected.len(), output_dir);
+    on_progress(ProgressEvent::Start(selected.len() as u64));
 
     // We need to collect file entries for config *after* parallel execution or use a mutex.
     // Collecting results is better.
     // Result type: (FileEntry, Vec<String>) where Vec<String> are log messages? No, just log directly or return errors.
     // Actually, we need to generate `pack_config.files`.
 
-    let results: Vec<config::FileEntry> = map
-        .par_iter()
-        .enumerate()
-        .map(|(i, (dir_id, chunk_ids))| -> Result<config::FileEntry> {
-            pb.inc(1);
-            let file_name = &strings[i];
-
-            // Actually, we should construct the full path string first, then resolve it.
-            // But we have `relative_path_buf` which is built using `push`.
-            // If `dir_name` contains `\`, `push` treats it as a filename on Unix.
-            // So `relative_path_buf` might be "dir\subdir/filename" on Unix.
-
-            // We should append components carefully?
-            // Or just use string builder for the "archive path" and then resolve.
-
-            // Best approach:
-            // 1. Reconstruct the full "archive path string" (using / or \ as per archive, likely mixed)
-            // 2. Pass that string to `resolve_relative_path`
-
-            let mut full_archive_path = String::new();
-            if *dir_id > 0 {
-                // dir_id 0 is root.
-                let dir_index = settings_num_user_files as usize + (*dir_id as usize) - 1;
-                if dir_index < strings.len() {
-                    let dir_name = &strings[dir_index];
-                    full_archive_path.push_str(dir_name);
-                    // Ensure separator if missing
-                    if !full_archive_path.ends_with('/') && !full_archive_path.ends_with('\\') {
-                        full_archive_path.push('\\'); // Use archive default separator
-                    }
-                }
-            }
-            full_archive_path.push_str(file_name);
-
-            // Normalize path using dzip-core path handling (Platform Aware)
-            let sanitized_path = dzip_core::path::resolve_relative_path(&full_archive_path)?;
-            let full_out_path = std::path::Path::new(output_dir).join(&sanitized_path);
+    // The main file and the volume manager are opened once per rayon work split instead of
+    // once per file, so each worker reuses its handles across the files it extracts.
+    let open_worker = || -> std::io::Result<_> {
+        let mut reader = dzip_core::reader::DzipReader::with_buffer_size(
+            std::fs::File::open(input_path)?,
+            options.buffer_size(),
+        );
+        reader.set_cipher(cipher.clone());
+        let volume_manager = options.volume_manager(input, volume_files_shared.clone());
+        Ok((reader, volume_manager))
+    };
 
-            // Sanity check: ensure it is still within output_dir?
-            // sanitize_path returns a relative path without `..` so joining it to output_dir is safe.
+    let extract_selected = || {
+        selected
+        .par_iter()
+        .map_init(
+            open_worker,
+            |worker, (i, path)| -> Result<config::FileEntry> {
+                options.check_cancel()?;
+                let i = *i;
+                let logical_path = metadata.resolve_path(i)?;
+                let (reader, volume_manager) = worker
+                    .as_mut()
+                    .map_err(|e| std::io::Error::new(e.kind(), e.to_string()))?;
+                let (_, chunk_ids) = &map[i];
+                // Normalize path using dzip-core path handling (Platform Aware). This also
+                // rejects paths that would escape the output directory.
+                let (sanitized_path, renamed) = options.relative_output_path(path)?;
+                let full_out_path = options.full_output_path(output_dir, &sanitized_path)?;
 
-            // Relative path for config
-            let relative_path = sanitized_path.clone();
+                // Sanity check: ensure it is still within output_dir?
+                // sanitize_path returns a relative path without `..` so joining it to output_dir is safe.
 
-            // Use sanitized path for creation
-            if let Some(parent) = full_out_path.parent() {
-                std::fs::create_dir_all(parent)?;
-            }
+                // Relative path for config
+                let relative_path = sanitized_path.clone();
 
-            // info!("Extracting: {}", file_name); // Valid input, but too detailed for parallel log? PB shows progress.
+                // Use sanitized path for creation
+                if let Some(parent) = full_out_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
 
-            let mut out_file = std::fs::File::create(&full_out_path)?;
+                // info!("Extracting: {}", file_name); // Valid input, but too detailed for parallel log? PB shows progress.
 
-            // Thread-local VolumeManager
-            let mut volume_manager = dzip_core::volume::FileSystemVolumeManager::new(
-                input_base_dir_shared.clone(),
-                volume_files_shared.clone(),
-            );
+                // Determine compression from the first chunk. Files without chunks are empty
+                // and are stored as-is.
+                use dzip_core::CompressionMethod;
+                let mut compression = CompressionMethod::Copy;
+                let mut archive_index = 0;
+                let mut flags = Vec::new();
+                if let Some(&first_chunk_id) = chunk_ids.first() {
+                    let chunk = &chunks[first_chunk_id as usize];
+                    archive_index = chunk.file;
 
-            // Also need local DzipReader for Main Volume (ID 0)
-            // But VolumeManager handles ID > 0.
-            // ID 0 chunks must be read from MAIN file.
-            // DzipReader::read_chunk_data_with_volumes handles this?
-            // "if chunk.file == 0 { self.read_chunk_data(chunk) }"
-            // So we need a DzipReader for `self`.
-            let main_file = std::fs::File::open(input_path).map_err(dzip_core::DzipError::Io)?;
-            let mut reader = dzip_core::reader::DzipReader::new(main_file);
-
-            // Determine compression from the first chunk
-            use dzip_core::CompressionMethod;
-            let mut compression = CompressionMethod::Dz; // Default
-            let mut archive_index = 0;
-            if let Some(&first_chunk_id) = chunk_ids.first() {
-                let chunk = &chunks[first_chunk_id as usize];
-                archive_index = chunk.file;
-
-                use dzip_core::format::*;
-                if (chunk.flags & CHUNK_ZLIB) != 0 {
-                    compression = CompressionMethod::Zlib;
-                } else if (chunk.flags & CHUNK_BZIP) != 0 {
-                    compression = CompressionMethod::Bzip;
-                } else if (chunk.flags & CHUNK_COPYCOMP) != 0 {
-                    compression = CompressionMethod::Copy;
-                } else if (chunk.flags & CHUNK_ZERO) != 0 {
-                    compression = CompressionMethod::Zero;
-                } else if (chunk.flags & CHUNK_MP3) != 0 {
-                    compression = CompressionMethod::Mp3;
-                } else if (chunk.flags & CHUNK_JPEG) != 0 {
-                    compression = CompressionMethod::Jpeg;
-                } else if (chunk.flags & CHUNK_LZMA) != 0 {
-                    compression = CompressionMethod::Lzma;
-                } else if (chunk.flags & CHUNK_DZ) != 0 {
-                    compression = CompressionMethod::Dz;
-                } else if (chunk.flags & CHUNK_COMBUF) != 0 {
-                    compression = CompressionMethod::Combuf;
-                } else if (chunk.flags & CHUNK_RANDOMACCESS) != 0 {
-                    compression = CompressionMethod::RandomAccess;
+                    // Chunks without a method flag fail to extract below
+                    compression = CompressionMethod::from_flags(chunk.flags).unwrap_or(compression);
+                    // Kept so that repacking sets the same flags
+                    let others = chunk.flags & !compression.flag() & !dzip_core::format::CHUNK_ENCRYPTED;
+                    flags = (0..16)
+                        .filter_map(|bit| CompressionMethod::from_flags(others & (1 << bit)))
+                        .collect();
                 }
-            }
 
-            for &chunk_id in chunk_ids {
-                let chunk = &chunks[chunk_id as usize];
-                /*
-                debug!(
-                    "Chunk {} - Offset: {}, CompLen: {}, DecompLen: {}, File: {}, Flags: {:#x}",
-                    chunk_id,
-                    chunk.offset,
-                    chunk.compressed_length,
-                    chunk.decompressed_length,
-                    chunk.file,
-                    chunk.flags
-                );
-                */
-                match reader.read_chunk_data_with_volumes(chunk, &mut volume_manager) {
-                    Ok(data) => {
-                        use std::io::Write;
-                        out_file.write_all(&data)?;
-                    }
-                    Err(dzip_core::DzipError::UnsupportedCompression(flags)) => {
-                        warn!(
-                            "Skipping chunk {} due to unsupported compression (flags: {:#x})",
-                            chunk_id, flags
-                        );
-                    }
-                    Err(_e) => {
-                        error!("Error extracting chunk {}: {}", chunk_id, _e);
-                        // Continue? Or fail? Currently continue.
-                        continue;
+                // Skipped files still get a config entry, from the header alone: none of
+                // their chunks is read.
+                let size = chunk_ids
+                    .iter()
+                    .filter_map(|&id| chunks.get(id as usize))
+                    .map(|c| c.decompressed_length as u64)
+                    .sum();
+                let mut lzma_variant = None;
+                if let Some(file) = options.create_output(&full_out_path, size)? {
+                    // Read errors are reported when the chunk is extracted below.
+                    lzma_variant = chunk_ids
+                        .first()
+                        .and_then(|&id| chunks.get(id as usize))
+                        .and_then(|chunk| reader.lzma_variant_with_volumes(chunk, volume_manager).ok())
+                        .flatten()
+                        .filter(|v| *v != dzip_core::LzmaVariant::default());
+                    let mut out_file = std::io::BufWriter::with_capacity(options.buffer_size(), file);
+                    let file_chunks = metadata.file_chunks(i)?;
+                    let mut write_chunk = |n: usize, data: Result<Vec<u8>>| -> Result<()> {
+                        options.check_cancel()?;
+                        let chunk_id = chunk_ids[n];
+                        match data {
+                            Ok(data) => {
+                                // The file is written as it is decoded, so only its start is checked
+                                if n == 0
+                                    && let Some(problem) = dzip_core::reader::check_media_data(
+                                        file_chunks[0].flags,
+                                        &data,
+                                        file_chunks.len() == 1,
+                                    )
+                                {
+                                    warn!("{}: {}", logical_path, problem);
+                                }
+                                out_file.write_all(&data)?
+                            }
+                            Err(dzip_core::DzipError::UnsupportedCompression(flags)) => {
+                                warn!(
+                                    "Skipping chunk {} due to unsupported compression (flags: {:#x})",
+                                    chunk_id, flags
+                                );
+                            }
+                            Err(e) => {
+                                // Continue with the next chunk
+                                error!("Error extracting chunk {}: {}", chunk_id, e);
+                            }
+                        }
+                        Ok(())
+                    };
+                    match &budget {
+                        // Files with many chunks are decompressed in parallel, but still
+                        // written in order.
+                        None => reader.for_each_chunk_data(chunk_ids, &file_chunks, volume_manager, write_chunk)?,
+                        // Decompressed one chunk at a time: a worker waiting inside a nested
+                        // parallel loop could pick up another file and block on the budget
+                        // it holds itself.
+                        Some(budget) => {
+                            let size = file_chunks.iter().map(|c| c.decompressed_length as u64).sum();
+                            let _reserved = budget.reserve(size);
+                            for (n, chunk) in file_chunks.iter().enumerate() {
+                                write_chunk(n, reader.read_chunk_data_with_volumes(chunk_ids[n], chunk, volume_manager))?;
+                            }
+                        }
                     }
+                    out_file.flush()?;
+                    set_mtime(out_file.get_ref(), metadata.file_mtime(i))?;
+                    set_permissions(out_file.get_ref(), metadata.file_mode(i))?;
                 }
-            }
+                on_progress(ProgressEvent::FileDone(logical_path.clone()));
+                on_progress(ProgressEvent::Inc(1));
 
-            Ok(config::FileEntry {
-                path: relative_path,
-                archive_file_index: archive_index,
-                compression,
-                modifiers: String::new(),
-            })
-        })
-        .collect::<Result<Vec<config::FileEntry>>>()?;
-
-    pack_config.files = results;
+                Ok(config::FileEntry {
+                    path: relative_path,
+                    // Flattened and renamed files keep their place in the archive when
+                    // repacked.
+                    archive_path: (options.flatten || renamed).then_some(logical_path),
+                    archive_file_index: archive_index,
+                    compression,
+                    lzma_variant,
+                    bzip_level: None,
+                    modifiers: String::new(),
+                    no_chunks: chunk_ids.is_empty(),
+                    flags,
+                })
+            },
+        )
+        .collect::<Result<Vec<config::FileEntry>>>()
+    };
+    pack_config.files = super::with_thread_limit(options.max_threads, extract_selected)?;
 
     // Write config file
     let input_name = std::path::Path::new(input_path)
@@ -265,7 +510,1419 @@ pub fn unpack_archive(input_path: &str, output_dir: &str) -> Result<()> {
     let toml_string = toml::to_string_pretty(&pack_config).expect("Failed to serialize config");
     std::fs::write(config_path, toml_string)?;
 
-    pb.finish_with_message("Unpack complete");
+    on_progress(ProgressEvent::Finish);
+    info!("Unpack complete.");
+    Ok(())
+}
+
+/// The files of the archive `options` selects, each paired with the path it is extracted
+/// to. Files excluded by the filter are skipped entirely, including their chunks.
+fn select_files(
+    metadata: &dzip_core::ArchiveMetadata,
+    options: &UnpackOptions,
+) -> Result<Vec<(usize, String)>> {
+    let mut selected = Vec::new();
+    for i in 0..metadata.file_map.len() {
+        let path = metadata.resolve_path(i)?;
+        if options.filter.is_some_and(|f| !f(&path)) {
+            continue;
+        }
+        match strip_components(&path, options.strip_components) {
+            Some(stripped) => selected.push((i, stripped)),
+            None => warn!(
+                "Skipping {}: fewer than {} leading components to strip",
+                path, options.strip_components
+            ),
+        }
+    }
+    let selected = if options.flatten {
+        flatten_paths(selected)
+    } else {
+        selected
+    };
+    Ok(if options.sanitize_names {
+        separate_sanitized_paths(selected)
+    } else {
+        selected
+    })
+}
+
+/// Extracts the files of the archive at `input_path` into a single tar archive at
+/// `tar_path` instead of a directory (`tar` feature).
+///
+/// Files keep the paths they would be extracted to, with their recorded modification
+/// time and permissions, and are written in file order. Each file is decompressed into
+/// memory before it is appended, as tar needs its size up front. No config is written.
+/// `on_progress` receives the same events as for [`unpack_archive`].
+#[cfg(feature = "tar")]
+pub fn unpack_to_tar(
+    input_path: &str,
+    tar_path: &str,
+    options: &UnpackOptions,
+    on_progress: &(dyn Fn(ProgressEvent) + Sync),
+) -> Result<()> {
+    let input = std::path::Path::new(input_path);
+    let mut reader = dzip_core::reader::DzipReader::with_buffer_size(
+        std::fs::File::open(input)?,
+        options.buffer_size(),
+    );
+    reader.set_name_encoding(options.name_encoding);
+    info!("Reading archive metadata...");
+    let mut metadata = dzip_core::ArchiveMetadata::load(&mut reader)?;
+    reader.set_cipher(options.cipher(&metadata)?);
+    let mut volumes = options.volume_manager(input, metadata.volume_files.clone());
+    let selected = select_files(&metadata, options)?;
+    let indices: Vec<usize> = selected.iter().map(|(i, _)| *i).collect();
+    metadata.correct_file_chunk_sizes(&indices, &mut volumes)?;
+    info!("Writing {} files to '{}'...", selected.len(), tar_path);
+    on_progress(ProgressEvent::Start(selected.len() as u64));
+
+    let file = std::fs::File::create(tar_path)?;
+    let mut tar = tar::Builder::new(std::io::BufWriter::with_capacity(
+        options.buffer_size(),
+        file,
+    ));
+    let mut directories = std::collections::HashSet::new();
+    for (i, path) in selected {
+        options.check_cancel()?;
+        let (relative, _) = options.relative_output_path(&path)?;
+        // Parent directories get an entry of their own the first time they are needed
+        let parents: Vec<&std::path::Path> = relative
+            .ancestors()
+            .skip(1)
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .collect();
+        for dir in parents.into_iter().rev() {
+            if directories.insert(dir.to_path_buf()) {
+                let mut header = tar::Header::new_gnu();
+                header.set_entry_type(tar::EntryType::Directory);
+                header.set_mode(0o755);
+                header.set_size(0);
+                tar.append_data(&mut header, dir, std::io::empty())?;
+            }
+        }
+
+        let data = metadata.read_file(&mut reader, &mut volumes, i)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Regular);
+        header.set_mode(metadata.file_mode(i).unwrap_or(0o644) & FILE_MODE_BITS);
+        header.set_mtime(metadata.file_mtime(i).unwrap_or(0));
+        header.set_size(data.len() as u64);
+        tar.append_data(&mut header, &relative, data.as_slice())?;
+        on_progress(ProgressEvent::FileDone(metadata.resolve_path(i)?));
+        on_progress(ProgressEvent::Inc(1));
+    }
+    tar.into_inner()?.flush()?;
+
+    on_progress(ProgressEvent::Finish);
     info!("Unpack complete.");
     Ok(())
 }
+
+/// Applies a modification time recorded in the archive to an extracted file.
+fn set_mtime(file: &std::fs::File, mtime: Option<u64>) -> std::io::Result<()> {
+    match mtime {
+        Some(secs) => {
+            file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+        }
+        None => Ok(()),
+    }
+}
+
+/// Applies permission bits recorded in the archive to an extracted file, without the
+/// setuid, setgid and sticky bits. Ignored on platforms without Unix permissions.
+fn set_permissions(file: &std::fs::File, mode: Option<u32>) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        return file.set_permissions(std::fs::Permissions::from_mode(mode & FILE_MODE_BITS));
+    }
+    let _ = (file, mode);
+    Ok(())
+}
+
+/// Drops the first `n` components of a `/`-separated path, or returns `None` if nothing
+/// would be left.
+fn strip_components(path: &str, n: usize) -> Option<String> {
+    if n == 0 {
+        return Some(path.to_string());
+    }
+    let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    (components.len() > n).then(|| components[n..].join("/"))
+}
+
+/// Replaces each path by its file name, appending `_1`, `_2`, ... to the stem of names
+/// that are already taken.
+fn flatten_paths(selected: Vec<(usize, String)>) -> Vec<(usize, String)> {
+    let mut taken = std::collections::HashSet::new();
+    selected
+        .into_iter()
+        .map(|(i, path)| {
+            let name = path.rsplit('/').next().unwrap_or_default().to_string();
+            let mut flat = name.clone();
+            let mut n = 1;
+            while !taken.insert(flat.clone()) {
+                flat = with_suffix(&name, n);
+                n += 1;
+            }
+            (i, flat)
+        })
+        .collect()
+}
+
+/// Appends `_1`, `_2`, ... to the stem of the file name of the paths that
+/// [`dzip_core::path::windows_safe_path`] would rewrite into a path already taken, e.g.
+/// `a?` and `a*`, which both become `a_`. Paths that need no rewriting keep their name.
+fn separate_sanitized_paths(selected: Vec<(usize, String)>) -> Vec<(usize, String)> {
+    use dzip_core::path::windows_safe_path;
+    let mut taken: std::collections::HashSet<String> = selected
+        .iter()
+        .filter(|(_, path)| windows_safe_path(path) == path.as_str())
+        .map(|(_, path)| path.clone())
+        .collect();
+    selected
+        .into_iter()
+        .map(|(i, path)| {
+            if windows_safe_path(&path) == path.as_str() {
+                return (i, path);
+            }
+            let (dir, name) = match path.rsplit_once('/') {
+                Some((dir, name)) => (format!("{}/", dir), name),
+                None => (String::new(), path.as_str()),
+            };
+            let mut separate = path.clone();
+            let mut n = 1;
+            while !taken.insert(windows_safe_path(&separate).into_owned()) {
+                separate = format!("{}{}", dir, with_suffix(name, n));
+                n += 1;
+            }
+            (i, separate)
+        })
+        .collect()
+}
+
+/// `name` with `_n` appended to its stem.
+fn with_suffix(name: &str, n: usize) -> String {
+    let (stem, ext) = match name.rfind('.').filter(|&dot| dot > 0) {
+        Some(dot) => name.split_at(dot),
+        None => (name, ""),
+    };
+    format!("{}_{}{}", stem, n, ext)
+}
+
+/// Counting semaphore over bytes, bounding the size of the files extracted at once.
+struct MemoryBudget {
+    limit: u64,
+    used: std::sync::Mutex<u64>,
+    released: std::sync::Condvar,
+}
+
+impl MemoryBudget {
+    fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            used: std::sync::Mutex::new(0),
+            released: std::sync::Condvar::new(),
+        }
+    }
+
+    /// Blocks until `bytes` (at most the whole budget) are free and holds them until the
+    /// returned guard is dropped.
+    fn reserve(&self, bytes: u64) -> BudgetReservation<'_> {
+        let bytes = bytes.min(self.limit);
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        while *used + bytes > self.limit {
+            used = self.released.wait(used).unwrap_or_else(|e| e.into_inner());
+        }
+        *used += bytes;
+        BudgetReservation {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+struct BudgetReservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl Drop for BudgetReservation<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+/// Whether any chunk is referenced by more than one file.
+fn shares_chunks(file_map: &[(u32, Vec<u32>)]) -> bool {
+    let mut seen = std::collections::HashSet::new();
+    file_map
+        .iter()
+        .flat_map(|(_, chunk_ids)| chunk_ids)
+        .any(|id| !seen.insert(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::pack::{PackOptions, pack_archive};
+    use crate::commands::testutil::{
+        fixture_or_skip, pack_with_defaults, test_data, unpack_with_defaults, write_pack_config,
+    };
+    use dzip_core::format::*;
+    use dzip_core::writer::DzipWriter;
+    use std::io::Cursor;
+
+    /// Builds a single-file archive storing `data` uncompressed under `dir\name`.
+    fn build_archive(dir: &str, name: &str, data: &[u8]) -> Vec<u8> {
+        let write_header = |offset: u32| {
+            let mut buffer = Vec::new();
+            let mut writer = DzipWriter::new(Cursor::new(&mut buffer));
+            writer
+                .write_archive_settings(&ArchiveSettings {
+                    header: 0x5A525444,
+                    num_user_files: 1,
+                    num_directories: 2,
+                    version: 0,
+                })
+                .unwrap();
+            writer
+                .write_strings(&[name.to_string(), dir.to_string()])
+                .unwrap();
+            writer.write_file_chunk_map(&[(1, vec![0])]).unwrap();
+            writer
+                .write_chunk_settings(&ChunkSettings {
+                    num_archive_files: 1,
+                    num_chunks: 1,
+                })
+                .unwrap();
+            writer
+                .write_chunks(&[Chunk {
+                    offset,
+                    compressed_length: data.len() as u32,
+                    decompressed_length: data.len() as u32,
+                    flags: CHUNK_COPYCOMP,
+                    file: 0,
+                }])
+                .unwrap();
+            buffer
+        };
+        let header_len = write_header(0).len() as u32;
+        let mut archive = write_header(header_len);
+        archive.extend_from_slice(data);
+        archive
+    }
+
+    #[test]
+    fn test_unpack_rejects_parent_dir_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("evil.dz");
+        std::fs::write(
+            &archive,
+            build_archive("..\\..\\escape", "evil.txt", b"pwned"),
+        )
+        .unwrap();
+
+        let out = dir.path().join("a").join("b").join("out");
+        let result = unpack_archive(
+            archive.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions::default(),
+            &|_| {},
+        );
+        assert!(matches!(result, Err(dzip_core::DzipError::Security(_))));
+        assert!(!dir.path().join("a").join("escape").exists());
+    }
+
+    #[test]
+    fn test_unpack_with_include_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.txt", b"alpha", "Copy"),
+            ("img/b.bmp", b"bravo", "Zlib"),
+            ("img/c.txt", b"charlie", "Bzip"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let filter = include_filter(&["img/*".to_string(), "*.bmp".to_string()])
+            .unwrap()
+            .unwrap();
+        let out = dir.path().join("out");
+        unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                filter: Some(&filter),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        assert!(!out.join("a.txt").exists());
+        assert_eq!(std::fs::read(out.join("img/b.bmp")).unwrap(), b"bravo");
+        assert_eq!(std::fs::read(out.join("img/c.txt")).unwrap(), b"charlie");
+
+        let config = crate::config::parse_config(&out.join("test.toml")).unwrap();
+        assert_eq!(config.files.len(), 2);
+    }
+
+    #[test]
+    fn test_unpack_selected_file_without_other_volumes() {
+        let Some(source) = fixture_or_skip("ExampleSplitArchive") else {
+            return;
+        };
+        let dir = tempfile::tempdir().unwrap();
+        // BMP/Image8.bmp is stored in the last volume only
+        for name in ["testnew.dz", "testnew3.dz"] {
+            std::fs::copy(source.join(name), dir.path().join(name)).unwrap();
+        }
+        let archive = dir.path().join("testnew.dz");
+        let expected = std::fs::read(test_data("DerbhExampleFiles/BMP/Image8.bmp")).unwrap();
+
+        let filter = include_filter(&["BMP/Image8.bmp".to_string()])
+            .unwrap()
+            .unwrap();
+        let out = dir.path().join("out");
+        unpack_archive(
+            archive.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                filter: Some(&filter),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(out.join("BMP/Image8.bmp")).unwrap(), expected);
+
+        let single = dir.path().join("single");
+        unpack_file(
+            archive.to_str().unwrap(),
+            "BMP/Image8.bmp",
+            single.to_str().unwrap(),
+            &UnpackOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            std::fs::read(single.join("BMP/Image8.bmp")).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_unpack_progress_events() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.txt", b"alpha", "Copy"),
+            ("img/b.bmp", b"bravo", "Zlib"),
+            ("img/c.txt", b"charlie", "Bzip"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let events = std::sync::Mutex::new(Vec::new());
+        unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            dir.path().join("out").to_str().unwrap(),
+            &UnpackOptions::default(),
+            &|event| events.lock().unwrap().push(event),
+        )
+        .unwrap();
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.first(), Some(&ProgressEvent::Start(3)));
+        assert_eq!(events.last(), Some(&ProgressEvent::Finish));
+        assert_eq!(events.len(), 8);
+        let incs = events
+            .iter()
+            .filter(|e| **e == ProgressEvent::Inc(1))
+            .count();
+        assert_eq!(incs, 3);
+        let mut done: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                ProgressEvent::FileDone(path) => Some(path.as_str()),
+                _ => None,
+            })
+            .collect();
+        done.sort_unstable();
+        assert_eq!(done, ["a.txt", "img/b.bmp", "img/c.txt"]);
+    }
+
+    #[test]
+    fn test_unpack_cancel_after_first_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.txt", b"alpha", "Copy"),
+            ("b.txt", b"bravo", "Zlib"),
+            ("c.txt", b"charlie", "Bzip"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let cancel = AtomicBool::new(false);
+        let done = std::sync::atomic::AtomicUsize::new(0);
+        let out = dir.path().join("out");
+        let result = unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                max_threads: Some(1),
+                cancel: Some(&cancel),
+                ..Default::default()
+            },
+            &|event| {
+                if let ProgressEvent::FileDone(_) = event {
+                    done.fetch_add(1, Ordering::Relaxed);
+                    cancel.store(true, Ordering::Relaxed);
+                }
+            },
+        );
+        assert!(matches!(result, Err(dzip_core::DzipError::Cancelled)));
+        assert_eq!(done.load(Ordering::Relaxed), 1);
+        let extracted = ["a.txt", "b.txt", "c.txt"]
+            .iter()
+            .filter(|name| out.join(name).exists())
+            .count();
+        assert_eq!(extracted, 1);
+        assert!(!out.join("test.toml").exists());
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn test_unpack_encrypted_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] =
+            [("a.txt", b"alpha", "Copy"), ("img/b.bmp", b"bravo", "Zlib")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        let key = [0x5a; 32];
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                key: Some(key),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        let archive = packed.join("test.dz");
+
+        let unpack = |key, out: &str| {
+            unpack_archive(
+                archive.to_str().unwrap(),
+                dir.path().join(out).to_str().unwrap(),
+                &UnpackOptions {
+                    key,
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+        };
+        assert!(matches!(
+            unpack(None, "none"),
+            Err(dzip_core::DzipError::Encryption(_))
+        ));
+        assert!(matches!(
+            unpack(Some([0x5b; 32]), "wrong"),
+            Err(dzip_core::DzipError::Encryption(_))
+        ));
+        assert!(!dir.path().join("wrong/a.txt").exists());
+
+        unpack(Some(key), "out").unwrap();
+        let out = dir.path().join("out");
+        assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(std::fs::read(out.join("img/b.bmp")).unwrap(), b"bravo");
+    }
+
+    #[test]
+    fn test_unpack_zero_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let zeros = vec![0u8; 100_000];
+        let files: [(&str, &[u8], &str); 2] = [
+            ("zero.bin", &zeros, "Zero"),
+            ("after.txt", b"after", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        unpack_with_defaults(&packed.join("test.dz"), &out);
+        assert_eq!(std::fs::read(out.join("zero.bin")).unwrap(), zeros);
+        assert_eq!(std::fs::read(out.join("after.txt")).unwrap(), b"after");
+    }
+
+    #[test]
+    fn test_unpack_empty_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("empty.z", b"", "Zlib"),
+            ("empty.lzma", b"", "Lzma"),
+            ("after.txt", b"after", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        unpack_with_defaults(&packed.join("test.dz"), &out);
+        assert_eq!(std::fs::metadata(out.join("empty.z")).unwrap().len(), 0);
+        assert_eq!(std::fs::metadata(out.join("empty.lzma")).unwrap().len(), 0);
+        assert_eq!(std::fs::read(out.join("after.txt")).unwrap(), b"after");
+    }
+
+    #[test]
+    fn test_files_without_chunks_round_trip() {
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        dzip_core::PackBuilder::new(&mut sink)
+            .archive_names(vec!["test.dz".to_string()])
+            .add_file("empty.bin", Vec::new(), dzip_core::CompressionMethod::Copy)
+            .no_chunks()
+            .add_file(
+                "a.txt",
+                b"alpha".to_vec(),
+                dzip_core::CompressionMethod::Copy,
+            )
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("test.dz");
+        std::fs::write(&archive, sink.into_volumes().remove("test.dz").unwrap()).unwrap();
+
+        let out = dir.path().join("out");
+        let config = unpack_with_defaults(&archive, &out);
+        assert_eq!(std::fs::metadata(out.join("empty.bin")).unwrap().len(), 0);
+        let no_chunks: Vec<bool> = config.files.iter().map(|f| f.no_chunks).collect();
+        assert_eq!(no_chunks, [true, false]);
+
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&out.join("test.toml"), &packed);
+        let file = std::fs::File::open(packed.join("test.dz")).unwrap();
+        let mut reader = dzip_core::reader::DzipReader::new(file);
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.file_map[0].1, Vec::<u32>::new());
+        assert_eq!(metadata.file_map[1].1, [0]);
+        assert_eq!(metadata.chunks.len(), 1);
+    }
+
+    #[test]
+    fn test_extra_chunk_flags_round_trip() {
+        use dzip_core::CompressionMethod;
+        use dzip_core::format::{CHUNK_COMBUF, CHUNK_RANDOMACCESS, CHUNK_ZLIB};
+
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        dzip_core::PackBuilder::new(&mut sink)
+            .archive_names(vec!["test.dz".to_string()])
+            .add_file("a.bin", vec![7u8; 300], CompressionMethod::Zlib)
+            .extra_flags(CHUNK_COMBUF | CHUNK_RANDOMACCESS)
+            .add_file("b.txt", b"bravo".to_vec(), CompressionMethod::Copy)
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let archive = dir.path().join("test.dz");
+        std::fs::write(&archive, sink.into_volumes().remove("test.dz").unwrap()).unwrap();
+
+        let out = dir.path().join("out");
+        let config = unpack_with_defaults(&archive, &out);
+        assert_eq!(std::fs::read(out.join("a.bin")).unwrap(), [7u8; 300]);
+        assert_eq!(config.files[0].compression, CompressionMethod::Zlib);
+        assert_eq!(
+            config.files[0].flags,
+            [CompressionMethod::Combuf, CompressionMethod::RandomAccess]
+        );
+        assert!(config.files[1].flags.is_empty());
+
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&out.join("test.toml"), &packed);
+        let repacked = std::fs::read(packed.join("test.dz")).unwrap();
+        let mut reader = dzip_core::reader::DzipReader::new(std::io::Cursor::new(repacked));
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(
+            metadata.chunks[0].flags,
+            CHUNK_ZLIB | CHUNK_COMBUF | CHUNK_RANDOMACCESS
+        );
+        assert_eq!(
+            reader.read_chunk_data(0, &metadata.chunks[0]).unwrap(),
+            [7u8; 300]
+        );
+    }
+
+    #[test]
+    fn test_unpack_overwrite_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] =
+            [("a.txt", b"alpha", "Zlib"), ("sub/b.txt", b"bravo", "Copy")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+        let archive = packed.join("test.dz");
+
+        let unpack_into = |name: &str, overwrite| {
+            let out = dir.path().join(name);
+            std::fs::create_dir_all(&out).unwrap();
+            std::fs::write(out.join("a.txt"), b"local edit").unwrap();
+            let result = unpack_archive(
+                archive.to_str().unwrap(),
+                out.to_str().unwrap(),
+                &UnpackOptions {
+                    overwrite,
+                    ..Default::default()
+                },
+                &|_| {},
+            );
+            (out, result)
+        };
+
+        let (out, result) = unpack_into("always", OverwritePolicy::Always);
+        result.unwrap();
+        assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"alpha");
+
+        let (out, result) = unpack_into("skip", OverwritePolicy::Skip);
+        result.unwrap();
+        assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"local edit");
+        assert_eq!(std::fs::read(out.join("sub/b.txt")).unwrap(), b"bravo");
+        let config = crate::config::parse_config(&out.join("test.toml")).unwrap();
+        assert_eq!(config.files.len(), 2);
+
+        let (out, result) = unpack_into("error", OverwritePolicy::Error);
+        assert!(
+            matches!(result, Err(dzip_core::DzipError::Io(e)) if e.kind() == std::io::ErrorKind::AlreadyExists)
+        );
+        assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"local edit");
+    }
+
+    #[test]
+    fn test_unpack_resume() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.txt", b"alpha", "Zlib"),
+            ("b.txt", b"bravo", "Copy"),
+            ("c.txt", b"charlie", "Lzma"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        // a.txt looks complete, b.txt was cut short and c.txt was never written
+        let out = dir.path().join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        std::fs::write(out.join("a.txt"), b"ALPHA").unwrap();
+        std::fs::write(out.join("b.txt"), b"br").unwrap();
+        unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                resume: true,
+                overwrite: OverwritePolicy::Error,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"ALPHA");
+        assert_eq!(std::fs::read(out.join("b.txt")).unwrap(), b"bravo");
+        assert_eq!(std::fs::read(out.join("c.txt")).unwrap(), b"charlie");
+    }
+
+    #[test]
+    fn test_extract_all_to_memory_matches_unpack() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let big = b"0123456789".repeat(10_000);
+        let files: [(&str, &[u8], &str); 4] = [
+            ("a.txt", b"alpha", "Zlib"),
+            ("sub/b.bin", &big, "Lzma"),
+            ("sub/c.txt", b"", "Copy"),
+            ("d.txt", b"delta", "Bzip"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+        let archive = packed.join("test.dz");
+        let out = dir.path().join("out");
+        unpack_with_defaults(&archive, &out);
+
+        let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(&archive).unwrap());
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        let mut volumes =
+            dzip_core::volume::FileSystemVolumeManager::new(packed.clone(), Vec::new());
+        let extracted = metadata
+            .extract_all_to_memory(&mut reader, &mut volumes)
+            .unwrap();
+        assert_eq!(extracted.len(), files.len());
+        for (path, data) in extracted {
+            assert_eq!(data, std::fs::read(out.join(&path)).unwrap(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_unpack_flatten() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a/tex.bin", b"first", "Zlib"),
+            ("b/tex.bin", b"second", "Copy"),
+            ("b/c/other.txt", b"other", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                flatten: true,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        assert_eq!(std::fs::read(out.join("tex.bin")).unwrap(), b"first");
+        assert_eq!(std::fs::read(out.join("tex_1.bin")).unwrap(), b"second");
+        assert_eq!(std::fs::read(out.join("other.txt")).unwrap(), b"other");
+        assert!(!out.join("a").exists());
+
+        // Repacking the flat directory restores the original structure.
+        let repacked = dir.path().join("repacked");
+        pack_with_defaults(&out.join("test.toml"), &repacked);
+        let unpacked = dir.path().join("unpacked");
+        unpack_with_defaults(&repacked.join("test.dz"), &unpacked);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(unpacked.join(name)).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_unpack_strip_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 3] = [
+            ("top.txt", b"top", "Copy"),
+            ("root/a.txt", b"alpha", "Zlib"),
+            ("root/sub/b.txt", b"bravo", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                strip_components: 1,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        assert!(!out.join("top.txt").exists());
+        assert!(!out.join("root").exists());
+        assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(std::fs::read(out.join("sub/b.txt")).unwrap(), b"bravo");
+        let config = crate::config::parse_config(&out.join("test.toml")).unwrap();
+        assert_eq!(config.files.len(), 2);
+    }
+
+    #[test]
+    fn test_repack_generated_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 5] = [
+            ("a.bin", &[1u8; 300], "Copy"),
+            ("sub/b.bin", &[2u8; 300], "Zlib"),
+            ("sub/copy_of_a.bin", &[1u8; 300], "Copy"),
+            ("other/empty.bin", b"", "Copy"),
+            ("sub/deeper/c.bin", &[3u8; 300], "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                max_volume_size: Some(500),
+                dedup: true,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        let out = dir.path().join("out");
+        unpack_with_defaults(&packed.join("test.dz"), &out);
+        let repacked = dir.path().join("repacked");
+        pack_with_defaults(&out.join("test.toml"), &repacked);
+
+        // Same files in the same order produce the same layout.
+        let volumes: Vec<_> = std::fs::read_dir(&packed)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert!(volumes.len() > 1);
+        for name in &volumes {
+            assert_eq!(
+                std::fs::read(packed.join(name)).unwrap(),
+                std::fs::read(repacked.join(name)).unwrap(),
+                "{:?} differs",
+                name
+            );
+        }
+
+        let out2 = dir.path().join("out2");
+        unpack_with_defaults(&repacked.join("test.dz"), &out2);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(out2.join(name)).unwrap(), data);
+        }
+        assert_eq!(
+            std::fs::read_to_string(out.join("test.toml")).unwrap(),
+            std::fs::read_to_string(out2.join("test.toml")).unwrap()
+        );
+    }
+
+    /// Archive `names.dz` in `dir` holding `names` with the contents `data0`, `data1`, ...
+    fn write_named_archive(dir: &std::path::Path, names: &[&str]) -> std::path::PathBuf {
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        let mut builder =
+            dzip_core::PackBuilder::new(&mut sink).archive_names(vec!["names.dz".to_string()]);
+        for (i, name) in names.iter().enumerate() {
+            builder = builder.add_file(
+                name,
+                format!("data{}", i).into_bytes(),
+                dzip_core::CompressionMethod::Copy,
+            );
+        }
+        builder.build().unwrap();
+        let archive = dir.join("names.dz");
+        std::fs::write(&archive, sink.into_volumes().remove("names.dz").unwrap()).unwrap();
+        archive
+    }
+
+    #[test]
+    fn test_unpack_memory_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let data: Vec<Vec<u8>> = (0..6u8).map(|i| vec![i; 600]).collect();
+        let names: Vec<String> = (0..6).map(|i| format!("file{i}.bin")).collect();
+        let files: Vec<(&str, &[u8], &str)> = names
+            .iter()
+            .zip(&data)
+            .map(|(name, data)| (name.as_str(), data.as_slice(), "Zlib"))
+            .collect();
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        // Room for one file at a time, and smaller than a single file
+        for budget in [1000, 100] {
+            let out = dir.path().join(format!("out{budget}"));
+            unpack_archive(
+                packed.join("test.dz").to_str().unwrap(),
+                out.to_str().unwrap(),
+                &UnpackOptions {
+                    max_threads: Some(4),
+                    memory_budget: Some(budget),
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+            .unwrap();
+            for (name, data) in names.iter().zip(&data) {
+                assert_eq!(&std::fs::read(out.join(name)).unwrap(), data);
+            }
+        }
+
+        let result = unpack_archive(
+            packed.join("test.dz").to_str().unwrap(),
+            dir.path().join("out0").to_str().unwrap(),
+            &UnpackOptions {
+                memory_budget: Some(0),
+                ..Default::default()
+            },
+            &|_| {},
+        );
+        assert!(matches!(result, Err(dzip_core::DzipError::Config(_))));
+    }
+
+    #[test]
+    fn test_unpack_sanitize_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = write_named_archive(dir.path(), &["what?.txt", "sub\\aux.txt", "ok.txt"]);
+
+        let out = dir.path().join("out");
+        unpack_archive(
+            archive.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                sanitize_names: true,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(out.join("what_.txt")).unwrap(), b"data0");
+        assert_eq!(std::fs::read(out.join("sub/_aux.txt")).unwrap(), b"data1");
+        assert_eq!(std::fs::read(out.join("ok.txt")).unwrap(), b"data2");
+
+        // Repacking restores the original names
+        let repacked = dir.path().join("repacked");
+        pack_with_defaults(&out.join("names.toml"), &repacked);
+        let mut reader = dzip_core::reader::DzipReader::new(
+            std::fs::File::open(repacked.join("names.dz")).unwrap(),
+        );
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        let mut paths: Vec<_> = (0..3).map(|i| metadata.resolve_path(i).unwrap()).collect();
+        paths.sort_unstable();
+        assert_eq!(paths, ["ok.txt", "sub/aux.txt", "what?.txt"]);
+    }
+
+    #[test]
+    fn test_unpack_sanitize_names_collision() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = write_named_archive(dir.path(), &["a?.txt", "a*.txt", "a_.txt"]);
+
+        let out = dir.path().join("out");
+        unpack_archive(
+            archive.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                sanitize_names: true,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        // The name that needs no rewriting keeps it, the others get a suffix
+        assert_eq!(std::fs::read(out.join("a_.txt")).unwrap(), b"data2");
+        assert_eq!(std::fs::read(out.join("a__1.txt")).unwrap(), b"data0");
+        assert_eq!(std::fs::read(out.join("a__2.txt")).unwrap(), b"data1");
+
+        let repacked = dir.path().join("repacked");
+        pack_with_defaults(&out.join("names.toml"), &repacked);
+        let mut reader = dzip_core::reader::DzipReader::new(
+            std::fs::File::open(repacked.join("names.dz")).unwrap(),
+        );
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        let paths: Vec<_> = (0..3).map(|i| metadata.resolve_path(i).unwrap()).collect();
+        assert_eq!(paths, ["a?.txt", "a*.txt", "a_.txt"]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_unpack_invalid_windows_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let archive = write_named_archive(dir.path(), &["what?.txt"]);
+        let unpack = |sanitize_names, out: &str| {
+            unpack_archive(
+                archive.to_str().unwrap(),
+                dir.path().join(out).to_str().unwrap(),
+                &UnpackOptions {
+                    sanitize_names,
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+        };
+        assert!(unpack(false, "plain").is_err());
+        unpack(true, "sanitized").unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("sanitized/what_.txt")).unwrap(),
+            b"data0"
+        );
+    }
+
+    #[test]
+    fn test_unpack_records_comment() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 1] = [("a.txt", b"alpha", "Copy")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(&config_path, format!("comment = \"Patch 1.2\"\n{}", config)).unwrap();
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        let config = unpack_with_defaults(&packed.join("test.dz"), &out);
+        assert_eq!(config.comment.as_deref(), Some("Patch 1.2"));
+
+        // Repacking the extracted files keeps the comment.
+        let repacked = dir.path().join("repacked");
+        pack_with_defaults(&out.join("test.toml"), &repacked);
+        let mut reader = dzip_core::reader::DzipReader::new(
+            std::fs::File::open(repacked.join("test.dz")).unwrap(),
+        );
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.comment(), Some("Patch 1.2"));
+    }
+
+    #[test]
+    fn test_repack_keeps_string_layout() {
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        dzip_core::PackBuilder::new(&mut sink)
+            .archive_names(vec!["test.dz".to_string()])
+            .add_file(
+                "tex/ui/a.png",
+                b"png".to_vec(),
+                dzip_core::CompressionMethod::Copy,
+            )
+            .add_file(
+                "readme.txt",
+                b"hello".to_vec(),
+                dzip_core::CompressionMethod::Copy,
+            )
+            .string_layout(StringLayout::DirectoriesFirst)
+            .build()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.dz");
+        std::fs::write(&path, sink.into_volumes().remove("test.dz").unwrap()).unwrap();
+
+        let out = dir.path().join("out");
+        let config = unpack_with_defaults(&path, &out);
+        assert_eq!(std::fs::read(out.join("readme.txt")).unwrap(), b"hello");
+        assert_eq!(config.string_layout, Some(StringLayout::DirectoriesFirst));
+
+        let repacked = dir.path().join("repacked");
+        pack_with_defaults(&out.join("test.toml"), &repacked);
+        let mut reader = dzip_core::reader::DzipReader::new(
+            std::fs::File::open(repacked.join("test.dz")).unwrap(),
+        );
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.string_layout, StringLayout::DirectoriesFirst);
+        assert_eq!(metadata.resolve_path(1).unwrap(), "readme.txt");
+    }
+
+    #[test]
+    fn test_unpack_restores_mtime() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] =
+            [("a.txt", b"alpha", "Copy"), ("sub/b.txt", b"bravo", "Zlib")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_234_567_890);
+        std::fs::File::options()
+            .write(true)
+            .open(src.join("sub/b.txt"))
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        unpack_with_defaults(&packed.join("test.dz"), &out);
+        let modified =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().modified().unwrap();
+        assert_eq!(modified(&out.join("sub/b.txt")), mtime);
+
+        let single = dir.path().join("single");
+        unpack_file(
+            packed.join("test.dz").to_str().unwrap(),
+            "sub/b.txt",
+            single.to_str().unwrap(),
+            &UnpackOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(modified(&single.join("sub/b.txt")), mtime);
+    }
+
+    #[cfg(feature = "tar")]
+    #[test]
+    fn test_unpack_to_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 4] = [
+            ("a.txt", b"alpha", "Copy"),
+            ("sub/b.txt", b"bravo", "Zlib"),
+            ("sub/deeper/c.bin", &[3u8; 3000], "Lzma"),
+            ("empty.bin", b"", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+        let archive = packed.join("test.dz");
+
+        let out = dir.path().join("out");
+        unpack_with_defaults(&archive, &out);
+        let tar_path = dir.path().join("out.tar");
+        unpack_to_tar(
+            archive.to_str().unwrap(),
+            tar_path.to_str().unwrap(),
+            &UnpackOptions::default(),
+            &|_| {},
+        )
+        .unwrap();
+
+        let mut tar = tar::Archive::new(std::fs::File::open(&tar_path).unwrap());
+        let mut entries = Vec::new();
+        for entry in tar.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            if entry.header().entry_type() != tar::EntryType::Regular {
+                continue;
+            }
+            let path = entry.path().unwrap().into_owned();
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut entry, &mut data).unwrap();
+            entries.push((path, data));
+        }
+        assert_eq!(entries.len(), files.len());
+        for (path, data) in entries {
+            assert_eq!(std::fs::read(out.join(&path)).unwrap(), data, "{:?}", path);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_restores_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] = [
+            ("run.sh", b"#!/bin/sh\necho hi\n", "Zlib"),
+            ("data.txt", b"data", "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let set_mode = |path: &std::path::Path, mode: u32| {
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+        set_mode(&src.join("run.sh"), 0o755);
+        set_mode(&src.join("data.txt"), 0o640);
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        // Without the flag, no permissions are stored
+        let plain = dir.path().join("plain");
+        pack_with_defaults(&config_path, &plain);
+        let mut reader =
+            dzip_core::reader::DzipReader::new(std::fs::File::open(plain.join("test.dz")).unwrap());
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.file_mode(0), None);
+
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                permissions: true,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+        let out = dir.path().join("out");
+        unpack_with_defaults(&packed.join("test.dz"), &out);
+        assert_eq!(mode(&out.join("run.sh")), 0o755);
+        assert_eq!(mode(&out.join("data.txt")), 0o640);
+        let config = std::fs::read_to_string(out.join("test.toml")).unwrap();
+        assert!(config.contains("permissions = true"));
+
+        let single = dir.path().join("single");
+        unpack_file(
+            packed.join("test.dz").to_str().unwrap(),
+            "run.sh",
+            single.to_str().unwrap(),
+            &UnpackOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(mode(&single.join("run.sh")), 0o755);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_drops_setuid_bit() {
+        use std::os::unix::fs::PermissionsExt;
+        let mut sink = dzip_core::volume::MemoryPackSink::new();
+        dzip_core::PackBuilder::new(&mut sink)
+            .archive_names(vec!["test.dz".to_string()])
+            .add_file(
+                "run",
+                b"#!/bin/sh\n".to_vec(),
+                dzip_core::CompressionMethod::Copy,
+            )
+            .mode(0o755)
+            .build()
+            .unwrap();
+        let main = sink.into_volumes().remove("test.dz").unwrap();
+
+        // The archive lists the file as setuid
+        let mut reader = dzip_core::reader::DzipReader::new(std::io::Cursor::new(&main));
+        let mut metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        let mut trailer = metadata.trailer.take().unwrap();
+        trailer.file_modes = Some(vec![0o4755]);
+        let mut archive = main[..metadata.main_data_end as usize].to_vec();
+        let mut cursor = std::io::Cursor::new(&mut archive);
+        cursor.set_position(metadata.main_data_end);
+        let mut writer = dzip_core::writer::DzipWriter::new(cursor);
+        writer.write_trailer(&trailer).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("test.dz");
+        std::fs::write(&path, &archive).unwrap();
+        let mut reader = dzip_core::reader::DzipReader::new(std::io::Cursor::new(&archive));
+        let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(metadata.file_mode(0), Some(0o4755));
+
+        let out = dir.path().join("out");
+        unpack_with_defaults(&path, &out);
+        let mode = std::fs::metadata(out.join("run"))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o7777, 0o755);
+
+        #[cfg(feature = "tar")]
+        {
+            let tar_path = dir.path().join("out.tar");
+            unpack_to_tar(
+                path.to_str().unwrap(),
+                tar_path.to_str().unwrap(),
+                &UnpackOptions::default(),
+                &|_| {},
+            )
+            .unwrap();
+            let mut tar = tar::Archive::new(std::fs::File::open(&tar_path).unwrap());
+            let entry = tar.entries().unwrap().next().unwrap().unwrap();
+            assert_eq!(entry.header().mode().unwrap(), 0o755);
+        }
+    }
+
+    #[test]
+    fn test_unpack_with_io_buffer_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] = [
+            ("a.bin", &[1u8; 300], "Zlib"),
+            ("sub/b.bin", &[2u8; 300], "Copy"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                io_buffer_size: Some(7),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        // Zero falls back to the default size
+        for size in [1, 0, 1 << 20] {
+            let out = dir.path().join(format!("out{}", size));
+            unpack_archive(
+                packed.join("test.dz").to_str().unwrap(),
+                out.to_str().unwrap(),
+                &UnpackOptions {
+                    io_buffer_size: Some(size),
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+            .unwrap();
+            for (name, data, _) in &files {
+                assert_eq!(&std::fs::read(out.join(name)).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unpack_records_lzma_variant() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 2] = [
+            ("raw.bin", b"raw lzma", "Lzma"),
+            ("xz.bin", b"xz lzma", "Lzma"),
+        ];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let mut config = crate::config::parse_config(&config_path).unwrap();
+        config.files[0].lzma_variant = Some(dzip_core::LzmaVariant::Raw);
+        config.files[1].lzma_variant = Some(dzip_core::LzmaVariant::Xz);
+        std::fs::write(&config_path, toml::to_string(&config).unwrap()).unwrap();
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let out = dir.path().join("out");
+        let generated = unpack_with_defaults(&packed.join("test.dz"), &out);
+        for (name, data, _) in &files {
+            assert_eq!(&std::fs::read(out.join(name)).unwrap(), data);
+        }
+        let variants: Vec<_> = generated.files.iter().map(|f| f.lzma_variant).collect();
+        assert_eq!(
+            variants,
+            [
+                Some(dzip_core::LzmaVariant::Raw),
+                Some(dzip_core::LzmaVariant::Xz)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unpack_shift_jis_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        let files: [(&str, &[u8], &str); 1] = [("データ/セーブ.bin", b"save", "Copy")];
+        let config_path = write_pack_config(&src, "test.dz", &files);
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        std::fs::write(&config_path, format!("encoding = \"ShiftJis\"\n{}", config)).unwrap();
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
+
+        let archive = packed.join("test.dz");
+        let out = dir.path().join("out");
+        assert!(
+            unpack_archive(
+                archive.to_str().unwrap(),
+                out.to_str().unwrap(),
+                &UnpackOptions::default(),
+                &|_| {}
+            )
+            .is_err()
+        );
+        unpack_archive(
+            archive.to_str().unwrap(),
+            out.to_str().unwrap(),
+            &UnpackOptions {
+                name_encoding: NameEncoding::ShiftJis,
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        assert_eq!(
+            std::fs::read(out.join("データ/セーブ.bin")).unwrap(),
+            b"save"
+        );
+        let config = crate::config::parse_config(&out.join("test.toml")).unwrap();
+        assert_eq!(config.encoding, Some(NameEncoding::ShiftJis));
+    }
+
+    #[test]
+    fn test_include_filter_rejects_invalid_pattern() {
+        assert!(include_filter(&["[".to_string()]).is_err());
+        assert!(include_filter(&[]).unwrap().is_none());
+    }
+}
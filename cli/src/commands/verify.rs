@@ -1,63 +1,152 @@
-use dzip_core::Result;
-use log::error;
+use dzip_core::{CompressionMethod, NameEncoding, Result};
+use log::{error, info};
 use rayon::prelude::*;
+use std::fmt;
 
-pub fn verify_archive(input_path: &str) -> Result<()> {
-    // use dzip_core::format::*; // don't import everything, be explicit if needed, but here symbols are used
+/// Outcome of verifying one file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyStatus {
+    Ok,
+    /// The reason the first bad chunk of the file failed.
+    Failed(String),
+}
+
+impl fmt::Display for VerifyStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerifyStatus::Ok => f.write_str("OK"),
+            VerifyStatus::Failed(_) => f.write_str("FAIL"),
+        }
+    }
+}
+
+/// Verification result of one file of the archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyEntry {
+    /// Index of the file in the archive.
+    pub index: usize,
+    /// Logical path of the file.
+    pub path: String,
+    pub status: VerifyStatus,
+    /// Decompressed size of all chunks of the file.
+    pub size: u64,
+    /// Stored size of all chunks of the file.
+    pub packed: u64,
+    /// Compression method of the first chunk, e.g. "Zlib".
+    pub method: &'static str,
+}
 
+/// Verifies every chunk of the archive and returns one entry per file, in archive order.
+///
+/// A chunk fails if it cannot be decompressed, if its decompressed size differs from
+/// the header, or if its CRC32 differs from the one stored in the archive trailer.
+/// Archives without a trailer are verified by size only.
+pub fn verify(input_path: &str, name_encoding: NameEncoding) -> Result<Vec<VerifyEntry>> {
     let mut reader = dzip_core::reader::DzipReader::new(
         std::fs::File::open(input_path).map_err(dzip_core::DzipError::Io)?,
     );
-
-    let settings = reader.read_archive_settings()?;
-
-    // Read strings (filenames + dirnames)
-    // Formula: num_user_files + num_directories - 1
-    let strings_count = (settings.num_user_files + settings.num_directories - 1) as usize;
-    let strings = reader.read_strings(strings_count)?;
-
-    // Read FileChunkMap
-    let map = reader.read_file_chunk_map(settings.num_user_files as usize)?;
-
-    // We need chunk headers to get sizes
-    let chunk_settings = reader.read_chunk_settings()?;
-    let mut chunks = reader.read_chunks(chunk_settings.num_chunks as usize)?;
-
-    // Read Auxiliary Files (Volumes)
-    let num_volumes_expected = chunk_settings.num_archive_files.saturating_sub(1);
-    let volume_files = if num_volumes_expected > 0 {
-        reader.read_strings(num_volumes_expected as usize)?
-    } else {
-        Vec::new()
-    };
+    reader.set_name_encoding(name_encoding);
+    let mut metadata = dzip_core::ArchiveMetadata::load(&mut reader)?;
 
     // Prepare shared data for VolumeManager
     let input_base_dir = std::path::Path::new(input_path)
         .parent()
         .unwrap_or_else(|| std::path::Path::new("."));
     let input_base_dir_shared = input_base_dir.to_path_buf();
-    let volume_files_shared = volume_files.clone();
-
-    // Use FileSystemVolumeManager
-    // Note: Creating one here just to read file sizes.
-    // Actually we don't need VolumeManager logic for size calc, can just iterate strings.
-    // But we need it for verification loop later.
+    let volume_files_shared = metadata.volume_files.clone();
 
     // --- Chunk Size Correction ---
-    let mut file_sizes = std::collections::HashMap::new();
-    if let Ok(meta) = std::fs::metadata(input_path) {
-        file_sizes.insert(0u16, meta.len());
-    }
-    for (i, vol_name) in volume_files.iter().enumerate() {
-        let path = input_base_dir.join(vol_name);
-        if let Ok(meta) = std::fs::metadata(&path) {
-            file_sizes.insert((i + 1) as u16, meta.len());
-        }
-    }
+    metadata.correct_chunk_sizes(&mut dzip_core::volume::FileSystemVolumeManager::new(
+        input_base_dir_shared.clone(),
+        volume_files_shared.clone(),
+    ))?;
+    let map = &metadata.file_map;
+    let chunks = &metadata.chunks;
+    let chunk_crcs = metadata
+        .trailer
+        .as_ref()
+        .and_then(|trailer| trailer.chunk_crcs.as_ref());
 
-    dzip_core::reader::correct_chunk_sizes(&mut chunks, &file_sizes);
+    map.par_iter()
+        .enumerate()
+        .map(|(i, (_, chunk_ids))| -> Result<VerifyEntry> {
+            // We need a local DzipReader and VolumeManager
+            let main_file = std::fs::File::open(input_path).map_err(dzip_core::DzipError::Io)?;
+            let mut local_reader = dzip_core::reader::DzipReader::new(main_file);
 
-    println!("Verifying archive integrity...");
+            let mut volume_manager = dzip_core::volume::FileSystemVolumeManager::new(
+                input_base_dir_shared.clone(),
+                volume_files_shared.clone(),
+            );
+
+            let mut status = VerifyStatus::Ok;
+            let (mut size, mut packed) = (0u64, 0u64);
+            for &chunk_id in chunk_ids {
+                let Some(chunk) = chunks.get(chunk_id as usize) else {
+                    status = VerifyStatus::Failed(format!("Chunk {} does not exist", chunk_id));
+                    break;
+                };
+                size += chunk.decompressed_length as u64;
+                packed += chunk.compressed_length as u64;
+                if status != VerifyStatus::Ok {
+                    continue;
+                }
+                match local_reader.read_chunk_data_with_volumes(
+                    chunk_id,
+                    chunk,
+                    &mut volume_manager,
+                ) {
+                    Ok(data) => {
+                        if data.len() != chunk.decompressed_length as usize {
+                            status = VerifyStatus::Failed(format!(
+                                "Chunk {} decompressed to {} bytes, expected {}",
+                                chunk_id,
+                                data.len(),
+                                chunk.decompressed_length
+                            ));
+                        } else if let Some(&expected) =
+                            chunk_crcs.and_then(|crcs| crcs.get(chunk_id as usize))
+                        {
+                            let actual = dzip_core::checksum(&data);
+                            if actual != expected {
+                                status = VerifyStatus::Failed(format!(
+                                    "Chunk {} CRC mismatch: {:08x}, expected {:08x}",
+                                    chunk_id, actual, expected
+                                ));
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        status = VerifyStatus::Failed(format!(
+                            "Chunk {} failed verification: {}",
+                            chunk_id, e
+                        ));
+                    }
+                }
+            }
+
+            Ok(VerifyEntry {
+                index: i,
+                path: metadata.file_path(i)?,
+                status,
+                size,
+                packed,
+                method: chunk_ids
+                    .first()
+                    .and_then(|&id| chunks.get(id as usize))
+                    .and_then(|chunk| CompressionMethod::from_flags(chunk.flags))
+                    .map_or("Unknown", CompressionMethod::name),
+            })
+        })
+        .collect()
+}
+
+/// Verifies the archive and prints a table of the results.
+///
+/// Returns the number of files that failed verification.
+pub fn verify_archive(input_path: &str, name_encoding: NameEncoding) -> Result<usize> {
+    info!("Verifying archive integrity...");
+    let entries = verify(input_path, name_encoding)?;
 
     println!(
         "{:<5} | {:<7} | {:<10} | {:<10} | {:<8} | Path",
@@ -68,95 +157,277 @@ pub fn verify_archive(input_path: &str) -> Result<()> {
         "", "", "", "", "", ""
     );
 
-    // Use parallel iterator to verify
-    // We need to collect results to print them in order (or we could print as we go if we didn't care about order, but table looks best ordered)
-    // Order is important for "Idx".
+    let mut failed = 0;
+    for entry in &entries {
+        if let VerifyStatus::Failed(reason) = &entry.status {
+            error!("{}: {}", entry.path, reason);
+            failed += 1;
+        }
+        println!(
+            "{:<5} | {:<7} | {:<10} | {:<10} | {:<8} | {}",
+            entry.index,
+            entry.status.to_string(),
+            entry.size,
+            entry.packed,
+            entry.method,
+            entry.path
+        );
+    }
 
-    let results: Vec<String> = map
-        .par_iter()
-        .enumerate()
-        .map(|(i, (dir_id, chunk_ids))| -> Result<String> {
-            let file_name = &strings[i];
-
-            // Reconstruct path
-            let mut full_path = String::new();
-            if *dir_id > 0 {
-                let dir_index = settings.num_user_files as usize + (*dir_id as usize) - 1;
-                if let Some(dir_name) = strings.get(dir_index) {
-                    full_path.push_str(dir_name);
-                    if !full_path.ends_with('/') && !full_path.ends_with('\\') {
-                        full_path.push('/');
-                    }
-                }
-            }
-            full_path.push_str(file_name);
-
-            // Calculate sizes
-            let mut size = 0;
-            let mut packed = 0;
-            let mut method_str = "Unknown";
-
-            use dzip_core::format::*;
-            if let Some(&first_chunk_id) = chunk_ids.first() {
-                let chunk = &chunks[first_chunk_id as usize];
-                // Determine method from first chunk
-                if (chunk.flags & CHUNK_ZLIB) != 0 {
-                    method_str = "Zlib";
-                } else if (chunk.flags & CHUNK_BZIP) != 0 {
-                    method_str = "Bzip";
-                } else if (chunk.flags & CHUNK_LZMA) != 0 {
-                    method_str = "LZMA";
-                } else if (chunk.flags & CHUNK_COPYCOMP) != 0 {
-                    method_str = "Copy";
-                } else if (chunk.flags & CHUNK_ZERO) != 0 {
-                    method_str = "Zero";
-                } else if (chunk.flags & CHUNK_DZ) != 0 {
-                    method_str = "Dz";
-                }
-            }
+    Ok(failed)
+}
 
-            // Verify integrity
-            // We need a local DzipReader and VolumeManager
-            let main_file = std::fs::File::open(input_path).map_err(dzip_core::DzipError::Io)?;
-            let mut local_reader = dzip_core::reader::DzipReader::new(main_file);
+/// Compares the header of the archive with the checksum stored in its trailer, without
+/// reading any chunk data, and prints the result.
+///
+/// Returns `false` on a mismatch. Archives without a stored checksum pass with a note.
+pub fn verify_header(input_path: &str, name_encoding: NameEncoding) -> Result<bool> {
+    let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(input_path)?);
+    reader.set_name_encoding(name_encoding);
+    let metadata = dzip_core::ArchiveMetadata::load(&mut reader)?;
+    match metadata.check_header_crc(&mut reader)? {
+        None => {
+            println!("Header checksum: not available");
+            Ok(true)
+        }
+        Some((stored, actual)) if stored == actual => {
+            println!("Header checksum: OK ({:08x})", actual);
+            Ok(true)
+        }
+        Some((stored, actual)) => {
+            error!(
+                "Header checksum mismatch: stored {:08x}, computed {:08x}",
+                stored, actual
+            );
+            Ok(false)
+        }
+    }
+}
 
-            let mut volume_manager = dzip_core::volume::FileSystemVolumeManager::new(
-                input_base_dir_shared.clone(),
-                volume_files_shared.clone(),
+/// Checks the structure of the archive without decompressing anything: the volume count
+/// matches the volume list and every chunk lies within its volume, judged by the same size
+/// correction as extraction uses. Files referencing missing chunks fail to load.
+///
+/// Prints each problem and a summary. Returns the number of problems found.
+pub fn verify_structure(input_path: &str, name_encoding: NameEncoding) -> Result<usize> {
+    let mut reader = dzip_core::reader::DzipReader::new(std::fs::File::open(input_path)?);
+    reader.set_name_encoding(name_encoding);
+    let metadata = dzip_core::ArchiveMetadata::load(&mut reader)?;
+    let base_dir = std::path::Path::new(input_path)
+        .parent()
+        .unwrap_or(std::path::Path::new("."));
+    let sizes = metadata.volume_sizes(&mut dzip_core::volume::FileSystemVolumeManager::new(
+        base_dir.to_path_buf(),
+        metadata.volume_files.clone(),
+    ));
+
+    info!("Checking archive structure...");
+    let mut problems = 0;
+    let mut report = |problem: String| {
+        error!("{}", problem);
+        problems += 1;
+    };
+
+    let num_volumes = metadata.volume_files.len() + 1;
+    if metadata.chunk_settings.num_archive_files as usize != num_volumes {
+        report(format!(
+            "Header declares {} volumes, the volume list has {}",
+            metadata.chunk_settings.num_archive_files, num_volumes
+        ));
+    }
+    if let Some(crcs) = metadata
+        .trailer
+        .as_ref()
+        .and_then(|t| t.chunk_crcs.as_ref())
+        && crcs.len() != metadata.chunks.len()
+    {
+        report(format!(
+            "Trailer has {} chunk checksums for {} chunks",
+            crcs.len(),
+            metadata.chunks.len()
+        ));
+    }
+    let mut failed_chunks = std::collections::HashSet::new();
+    for (id, chunk) in metadata.chunks.iter().enumerate() {
+        // Zero chunks store no data
+        if chunk.flags & dzip_core::format::CHUNK_ZERO != 0 {
+            continue;
+        }
+        match sizes.get(&chunk.file) {
+            None => report(format!("Chunk {}: volume {} is missing", id, chunk.file)),
+            Some(&size) if chunk.offset as u64 > size => report(format!(
+                "Chunk {}: offset {} is past the end of volume {} ({} bytes)",
+                id, chunk.offset, chunk.file, size
+            )),
+            Some(_) => continue,
+        }
+        failed_chunks.insert(id);
+    }
+
+    // Chunks that still have to be shortened after skipping placeholder lengths are cut off
+    // by the next chunk or the end of their volume.
+    let mut corrected = metadata.chunks.clone();
+    if failed_chunks.is_empty() {
+        dzip_core::reader::correct_chunk_sizes(&mut corrected, &sizes)?;
+    }
+    for (id, (chunk, fixed)) in metadata.chunks.iter().zip(&corrected).enumerate() {
+        let truncated = if dzip_core::reader::has_placeholder_length(chunk) {
+            fixed.compressed_length == 0 && chunk.decompressed_length > 0
+        } else {
+            fixed.compressed_length < chunk.compressed_length
+        };
+        if truncated && failed_chunks.insert(id) {
+            report(format!(
+                "Chunk {}: only {} of {} bytes present in volume {}",
+                id, fixed.compressed_length, chunk.compressed_length, chunk.file
+            ));
+        }
+    }
+
+    println!(
+        "{} of {} chunk(s) pass the structural check",
+        metadata.chunks.len() - failed_chunks.len(),
+        metadata.chunks.len()
+    );
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::pack::{PackOptions, pack_archive};
+    use crate::commands::testutil::{fixture_or_skip, pack_with_defaults, write_pack_config};
+
+    #[test]
+    fn test_verify_known_good_archive() {
+        let Some(archive) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+            return;
+        };
+        let entries = verify(archive.to_str().unwrap(), NameEncoding::Utf8).unwrap();
+
+        assert!(!entries.is_empty());
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.index, i);
+            assert_eq!(entry.status, VerifyStatus::Ok, "{}", entry.path);
+        }
+        assert!(entries.iter().any(|e| e.method == "Zlib" && e.size > 0));
+    }
+
+    #[test]
+    fn test_verify_header_checksum() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8], &str); 2] =
+            [("a.txt", b"alpha", "Copy"), ("b.txt", b"bravo", "Zlib")];
+        let config_path = write_pack_config(dir.path(), "test.dz", &files);
+        for header_checksum in [false, true] {
+            let packed = dir.path().join(format!("packed_{}", header_checksum));
+            pack_archive(
+                config_path.to_str().unwrap(),
+                packed.to_str().unwrap(),
+                &PackOptions {
+                    header_checksum,
+                    ..Default::default()
+                },
+                &|_| {},
+            )
+            .unwrap();
+            let archive = packed.join("test.dz");
+            let archive = archive.to_str().unwrap();
+            // Without a stored checksum there is nothing to compare
+            assert!(verify_header(archive, NameEncoding::Utf8).unwrap());
+
+            // Rename a.txt to c.txt: the header still parses, only the checksum notices
+            let mut data = std::fs::read(archive).unwrap();
+            let name = data.windows(5).position(|w| w == b"a.txt").unwrap();
+            data[name] = b'c';
+            std::fs::write(archive, &data).unwrap();
+            assert_eq!(
+                verify_header(archive, NameEncoding::Utf8).unwrap(),
+                !header_checksum
             );
+        }
+    }
 
-            let mut chunk_status = "OK";
-            for &chunk_id in chunk_ids {
-                if let Some(chunk) = chunks.get(chunk_id as usize) {
-                    if let Err(_e) =
-                        local_reader.read_chunk_data_with_volumes(chunk, &mut volume_manager)
-                    {
-                        // Log error but return FAIL string
-                        error!("Chunk {} failed verification: {}", chunk_id, _e);
-                        chunk_status = "FAIL";
-                    }
-                } else {
-                    chunk_status = "FAIL";
-                }
-            }
-            let status = chunk_status;
+    #[test]
+    fn test_verify_detects_corrupted_chunk() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8], &str); 2] = [
+            (
+                "a.txt",
+                b"The quick brown fox jumps over the lazy dog",
+                "Copy",
+            ),
+            ("b.txt", b"Pack my box with five dozen liquor jugs", "Zlib"),
+        ];
+        let config_path = write_pack_config(dir.path(), "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_with_defaults(&config_path, &packed);
 
-            for &cid in chunk_ids {
-                let chunk = &chunks[cid as usize];
-                size += chunk.decompressed_length;
-                packed += chunk.compressed_length;
-            }
+        let archive = packed.join("test.dz");
+        assert_eq!(
+            verify_archive(archive.to_str().unwrap(), NameEncoding::Utf8).unwrap(),
+            0
+        );
 
-            Ok(format!(
-                "{:<5} | {:<7} | {:<10} | {:<10} | {:<8} | {}",
-                i, status, size, packed, method_str, full_path
-            ))
-        })
-        .collect::<Result<Vec<String>>>()?;
+        // Flip a byte inside the stored (uncompressed) data; it still "decompresses".
+        let mut bytes = std::fs::read(&archive).unwrap();
+        let pos = bytes
+            .windows(files[0].1.len())
+            .position(|w| w == files[0].1)
+            .unwrap();
+        bytes[pos + 4] ^= 0xFF;
+        std::fs::write(&archive, bytes).unwrap();
 
-    for line in results {
-        println!("{}", line);
+        assert_eq!(
+            verify_archive(archive.to_str().unwrap(), NameEncoding::Utf8).unwrap(),
+            1
+        );
     }
 
-    Ok(())
+    #[test]
+    fn test_verify_structure_detects_truncated_volume() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: [(&str, &[u8], &str); 3] = [
+            ("a.bin", &[1u8; 300], "Copy"),
+            ("b.bin", &[2u8; 300], "Copy"),
+            ("c.bin", &[3u8; 300], "Copy"),
+        ];
+        let config_path = write_pack_config(dir.path(), "test.dz", &files);
+        let packed = dir.path().join("packed");
+        pack_archive(
+            config_path.to_str().unwrap(),
+            packed.to_str().unwrap(),
+            &PackOptions {
+                max_volume_size: Some(500),
+                ..Default::default()
+            },
+            &|_| {},
+        )
+        .unwrap();
+
+        let archive = packed.join("test.dz");
+        assert_eq!(
+            verify_structure(archive.to_str().unwrap(), NameEncoding::Utf8).unwrap(),
+            0
+        );
+
+        let volume = packed.join("test.d02");
+        let len = std::fs::metadata(&volume).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&volume)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+        assert_eq!(
+            verify_structure(archive.to_str().unwrap(), NameEncoding::Utf8).unwrap(),
+            1
+        );
+
+        std::fs::remove_file(&volume).unwrap();
+        assert_eq!(
+            verify_structure(archive.to_str().unwrap(), NameEncoding::Utf8).unwrap(),
+            1
+        );
+    }
 }
@@ -1,5 +1,5 @@
 use anyhow::{Context, Result};
-use dzip_core::CompressionMethod;
+use dzip_core::{CompressionMethod, LzmaVariant, NameEncoding, StringLayout};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
@@ -10,15 +10,104 @@ pub struct DzipConfig {
     pub files: Vec<FileEntry>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub options: Option<GlobalOptions>,
+    /// Split output into volumes of at most this many bytes.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub max_volume_size: Option<u64>,
+    /// Store byte-identical files only once.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub dedup: bool,
+    /// Encoding of the names stored in the archive (UTF-8 if unset).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub encoding: Option<NameEncoding>,
+    /// Order of the names in the string table (files first if unset).
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub string_layout: Option<StringLayout>,
+    /// Archive format version, picked from the archive size if unset.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub version: Option<u8>,
+    /// Free-form comment stored in the archive.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub comment: Option<String>,
+    /// Store a checksum of the header in the archive.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub header_checksum: bool,
+    /// Store the Unix permission bits of each file in the archive.
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub permissions: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileEntry {
     pub path: PathBuf,
+    /// Path inside the archive, if it differs from `path`
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub archive_path: Option<String>,
     pub archive_file_index: u16,
     pub compression: CompressionMethod,
+    /// Framing of LZMA data (the `.lzma` format if unset)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub lzma_variant: Option<LzmaVariant>,
+    /// Bzip2 level from 1 to 9 (6 if unset)
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub bzip_level: Option<u32>,
+    /// Codec settings as a string, see [`FileEntry::parse_modifiers`]
     #[serde(skip_serializing_if = "String::is_empty", default)]
-    pub modifiers: String, // e.g., "to 25%"
+    pub modifiers: String,
+    /// The file is empty and listed without any chunk, rather than with an empty Zero chunk
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub no_chunks: bool,
+    /// Flags of other methods also set on the chunks, e.g. `RandomAccess` on Zlib chunks
+    #[serde(skip_serializing_if = "Vec::is_empty", default)]
+    pub flags: Vec<CompressionMethod>,
+}
+
+/// Codec settings given in [`FileEntry::modifiers`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub lzma_variant: Option<LzmaVariant>,
+    pub level: Option<u32>,
+}
+
+impl FileEntry {
+    /// Parses `modifiers`, words separated by whitespace or commas:
+    ///
+    /// * `level=<1-9>`: Bzip2 level, for Bzip files
+    /// * `lzma:<alone|raw|xz>`: framing of LZMA data, for Lzma files
+    ///
+    /// Other words without `=` or `:`, such as the `to 25%` of dzip configs, are ignored.
+    pub fn parse_modifiers(&self) -> Result<Modifiers> {
+        let mut modifiers = Modifiers::default();
+        let words = self
+            .modifiers
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|w| !w.is_empty());
+        for word in words {
+            if let Some(level) = word.strip_prefix("level=") {
+                if self.compression != CompressionMethod::Bzip {
+                    anyhow::bail!("`{}` only applies to Bzip files", word);
+                }
+                let level = level
+                    .parse()
+                    .ok()
+                    .filter(|l| (1..=9).contains(l))
+                    .with_context(|| format!("`{}`: level must be 1 to 9", word))?;
+                modifiers.level = Some(level);
+            } else if let Some(variant) = word.strip_prefix("lzma:") {
+                if self.compression != CompressionMethod::Lzma {
+                    anyhow::bail!("`{}` only applies to Lzma files", word);
+                }
+                modifiers.lzma_variant = Some(match variant.to_ascii_lowercase().as_str() {
+                    "alone" => LzmaVariant::Alone,
+                    "raw" => LzmaVariant::Raw,
+                    "xz" => LzmaVariant::Xz,
+                    _ => anyhow::bail!("`{}`: LZMA framing must be alone, raw or xz", word),
+                });
+            } else if word.contains(['=', ':']) {
+                anyhow::bail!("Unknown modifier `{}`", word);
+            }
+        }
+        Ok(modifiers)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -58,6 +147,70 @@ impl Default for GlobalOptions {
     }
 }
 
+/// Builds a config packing every file below `root` into the archive `archive`, each
+/// compressed with `compression` and stored under its path relative to `root`.
+///
+/// Files are listed in path order, so scanning the same tree always gives the same
+/// archive. Symbolic links and other special files are skipped.
+pub fn scan_directory(
+    root: &Path,
+    archive: &str,
+    compression: CompressionMethod,
+) -> Result<DzipConfig> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(dir) = pending.pop() {
+        let full = root.join(&dir);
+        let mut entries = std::fs::read_dir(&full)
+            .with_context(|| format!("Failed to read directory {}", full.display()))?
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        // Subdirectories are pushed in reverse to be visited in order
+        let mut subdirs = Vec::new();
+        for entry in entries {
+            let path = dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                subdirs.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            } else {
+                log::debug!("Skipping {}: not a regular file", path.display());
+            }
+        }
+        pending.extend(subdirs.into_iter().rev());
+    }
+    files.sort();
+
+    Ok(DzipConfig {
+        archives: vec![archive.to_string()],
+        base_dir: root.to_path_buf(),
+        files: files
+            .into_iter()
+            .map(|path| FileEntry {
+                path,
+                archive_path: None,
+                archive_file_index: 0,
+                compression,
+                lzma_variant: None,
+                bzip_level: None,
+                modifiers: String::new(),
+                no_chunks: false,
+                flags: Vec::new(),
+            })
+            .collect(),
+        options: None,
+        max_volume_size: None,
+        dedup: false,
+        encoding: None,
+        string_layout: None,
+        version: None,
+        comment: None,
+        header_checksum: false,
+        permissions: false,
+    })
+}
+
 pub fn parse_config(path: &Path) -> Result<DzipConfig> {
     let content = std::fs::read_to_string(path)?;
 
@@ -70,6 +223,14 @@ pub fn parse_config(path: &Path) -> Result<DzipConfig> {
         base_dir: PathBuf::from("."),
         files: Vec::new(),
         options: Some(GlobalOptions::default()),
+        max_volume_size: None,
+        dedup: false,
+        encoding: None,
+        string_layout: None,
+        version: None,
+        comment: None,
+        header_checksum: false,
+        permissions: false,
     };
 
     for line in content.lines() {
@@ -94,6 +255,31 @@ pub fn parse_config(path: &Path) -> Result<DzipConfig> {
                     config.base_dir = PathBuf::from(parts[1]);
                 }
             }
+            "version" => {
+                if parts.len() > 1 {
+                    config.version = Some(parts[1].parse().context("Failed to parse version")?);
+                }
+            }
+            "encoding" => {
+                if parts.len() > 1 {
+                    config.encoding =
+                        Some(parts[1].parse().context("Failed to parse name encoding")?);
+                }
+            }
+            "dedup" => {
+                if parts.len() > 1 {
+                    config.dedup = parts[1] == "1";
+                }
+            }
+            "max_volume_size" => {
+                if parts.len() > 1 {
+                    config.max_volume_size = Some(
+                        parts[1]
+                            .parse()
+                            .context("Failed to parse max_volume_size")?,
+                    );
+                }
+            }
             "file" => {
                 // file <path> <index> <algo> [modifiers...]
                 if parts.len() >= 4 {
@@ -114,9 +300,14 @@ pub fn parse_config(path: &Path) -> Result<DzipConfig> {
 
                     config.files.push(FileEntry {
                         path,
+                        archive_path: None,
                         archive_file_index: idx,
                         compression: algo,
+                        lzma_variant: None,
+                        bzip_level: None,
                         modifiers,
+                        no_chunks: false,
+                        flags: Vec::new(),
                     });
                 }
             }
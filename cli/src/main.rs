@@ -1,5 +1,5 @@
 use clap::{Parser, Subcommand};
-use dzip_core::Result;
+use dzip_core::{CompressionMethod, NameEncoding, Result};
 use log::info;
 
 mod commands;
@@ -12,6 +12,10 @@ struct Cli {
     #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// Only log errors, without progress bars
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -22,9 +26,48 @@ enum Commands {
     Unpack {
         /// The dzip file to unpack
         input: String,
-        /// The output directory
+        /// Extract only this file (logical path inside the archive)
+        file: Option<String>,
+        /// The output directory, or `-` to write a single file to stdout
         #[arg(short, long, default_value = ".")]
         output: String,
+        /// Only extract files whose path matches this glob (repeatable)
+        #[arg(long, conflicts_with = "file")]
+        include: Vec<String>,
+        /// Drop this many leading directories from each extracted path
+        #[arg(long, default_value_t = 0, conflicts_with = "file")]
+        strip_components: usize,
+        /// Extract all files into the output directory, renaming clashing names
+        #[arg(long, conflicts_with = "file")]
+        flatten: bool,
+        /// Encoding of the names stored in the archive (utf8, shift-jis, gbk, cp1252, utf16le)
+        #[arg(long, default_value = "utf8")]
+        encoding: NameEncoding,
+        /// Size in bytes of the read and write buffers
+        #[arg(long)]
+        io_buffer_size: Option<usize>,
+        /// What to do with files that already exist
+        #[arg(long, value_enum, default_value_t)]
+        overwrite: commands::unpack::OverwritePolicy,
+        /// Number of threads to extract on (all cores by default)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Key of an encrypted archive, as 64 hexadecimal digits
+        #[arg(long, value_parser = dzip_core::crypto::parse_key)]
+        key: Option<dzip_core::EncryptionKey>,
+        /// Rename files whose names Windows cannot store, and allow paths beyond MAX_PATH
+        #[arg(long)]
+        sanitize_names: bool,
+        /// Limit the combined size in bytes of the files being extracted at once
+        #[arg(long, conflicts_with = "file")]
+        memory_budget: Option<u64>,
+        /// Continue an interrupted extraction: keep files that already have their full size
+        #[arg(long, conflicts_with = "file")]
+        resume: bool,
+        /// Write the files into this tar archive instead of the output directory
+        #[cfg(feature = "tar")]
+        #[arg(long, conflicts_with_all = ["file", "resume"])]
+        tar: Option<String>,
     },
     /// Pack a directory into a dzip file
     Pack {
@@ -33,30 +76,386 @@ enum Commands {
         /// The output directory
         #[arg(short, long, default_value = ".")]
         output: String,
+        /// Split the archive into volumes of at most this many bytes
+        #[arg(long)]
+        max_volume_size: Option<u64>,
+        /// Store byte-identical files only once
+        #[arg(long)]
+        dedup: bool,
+        /// Size in bytes of the write buffer of each volume
+        #[arg(long)]
+        io_buffer_size: Option<usize>,
+        /// Check the config and the source files without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Number of threads to compress on (all cores by default)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+        /// Encrypt the archive with this key, given as 64 hexadecimal digits
+        #[arg(long, value_parser = dzip_core::crypto::parse_key)]
+        key: Option<dzip_core::EncryptionKey>,
+        /// Store a checksum of the header to detect edits to it (see `verify --header`)
+        #[arg(long)]
+        header_checksum: bool,
+        /// Store the Unix permission bits of each file, restored by `unpack`
+        #[arg(long)]
+        permissions: bool,
+        /// Pack files even if several of them have the same path in the archive
+        #[arg(long)]
+        allow_duplicates: bool,
+        /// Name of the dzip file (default: the first archive of the config)
+        #[arg(long)]
+        name: Option<String>,
+        /// Directory holding the files to pack (default: the base_dir of the config)
+        #[arg(long)]
+        base_dir: Option<std::path::PathBuf>,
+    },
+    /// Pack every file below a directory into a dzip file, without a config
+    PackDir {
+        /// The directory to pack
+        input: String,
+        /// The output directory
+        #[arg(short, long, default_value = ".")]
+        output: String,
+        /// Name of the dzip file (default: the directory name with `.dz`)
+        #[arg(long)]
+        name: Option<String>,
+        /// Compression method for all files
+        #[arg(short, long, default_value = "zlib")]
+        compression: CompressionMethod,
+        /// Split the archive into volumes of at most this many bytes
+        #[arg(long)]
+        max_volume_size: Option<u64>,
+        /// Store byte-identical files only once
+        #[arg(long)]
+        dedup: bool,
+        /// Check the source files without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Number of threads to compress on (all cores by default)
+        #[arg(short, long)]
+        jobs: Option<usize>,
+    },
+    /// Add files to an existing dzip file without repacking it
+    Append {
+        /// The dzip file to add to
+        archive: String,
+        /// Files to add, stored under their path relative to the base directory
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// Directory the file paths are relative to
+        #[arg(short, long, default_value = ".")]
+        base_dir: String,
+        /// Compression method for the new files
+        #[arg(short, long, default_value = "zlib")]
+        compression: CompressionMethod,
+    },
+    /// Merge the volumes of a split dzip file into a single file
+    Combine {
+        /// The main file of the split archive
+        input: String,
+        /// The combined dzip file to write
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Write the stored bytes of a chunk to a file, without decompressing them
+    DumpChunk {
+        /// The dzip file holding the chunk
+        input: String,
+        /// ID of the chunk, its index in the chunk table
+        chunk_id: usize,
+        /// The file to write the bytes to
+        #[arg(short, long)]
+        output: String,
+    },
+    /// Write every chunk of a dzip file to a file named by its hash, with a manifest
+    ExportChunks {
+        /// The dzip file to export
+        input: String,
+        /// The directory to write the chunks and the manifest to
+        #[arg(short, long, default_value = ".")]
+        output: String,
+        /// Write the chunks as stored instead of decompressing them
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Recompress the files of a dzip file with another method
+    Transcode {
+        /// The dzip file to recompress
+        input: String,
+        /// The dzip file to write
+        #[arg(short, long)]
+        output: String,
+        /// Compression method for the recompressed files
+        #[arg(short, long)]
+        compression: CompressionMethod,
+        /// Only recompress files whose path matches this glob (repeatable)
+        #[arg(long)]
+        include: Vec<String>,
+    },
+    /// List archive contents without extracting
+    List {
+        /// Input archive file
+        input: String,
+        /// Output format
+        #[arg(long, value_enum, default_value_t)]
+        format: commands::list::ListFormat,
+        /// Encoding of the names stored in the archive (utf8, shift-jis, gbk, cp1252, utf16le)
+        #[arg(long, default_value = "utf8")]
+        encoding: NameEncoding,
+        /// List the volumes of the archive, their sizes and chunk counts instead of its files
+        #[arg(long)]
+        volumes: bool,
     },
     /// Verify and list archive contents
     Verify {
         /// Input archive file
         input: String,
+        /// Encoding of the names stored in the archive (utf8, shift-jis, gbk, cp1252, utf16le)
+        #[arg(long, default_value = "utf8")]
+        encoding: NameEncoding,
+        /// Only check that all chunks lie within their volumes, without decompressing
+        #[arg(long)]
+        fast: bool,
+        /// Only compare the header with the checksum stored in the archive
+        #[arg(long, conflicts_with = "fast")]
+        header: bool,
     },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    let log_level = if cli.verbose { "debug" } else { "info" };
+    let log_level = match (cli.verbose, cli.quiet) {
+        (true, _) => "debug",
+        (_, true) => "error",
+        _ => "info",
+    };
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(log_level)).init();
 
     match &cli.command {
-        Commands::Unpack { input, output } => {
-            commands::unpack::unpack_archive(input, output)?;
+        Commands::Unpack {
+            input,
+            file: Some(file),
+            output,
+            encoding,
+            io_buffer_size,
+            overwrite,
+            key,
+            sanitize_names,
+            ..
+        } => {
+            commands::unpack::unpack_file(
+                input,
+                file,
+                output,
+                &commands::unpack::UnpackOptions {
+                    name_encoding: *encoding,
+                    io_buffer_size: *io_buffer_size,
+                    overwrite: *overwrite,
+                    key: *key,
+                    sanitize_names: *sanitize_names,
+                    ..Default::default()
+                },
+            )?;
         }
-        Commands::Pack { input, output } => {
+        Commands::Unpack {
+            input,
+            file: None,
+            output,
+            include,
+            strip_components,
+            flatten,
+            encoding,
+            io_buffer_size,
+            overwrite,
+            jobs,
+            key,
+            sanitize_names,
+            memory_budget,
+            resume,
+            #[cfg(feature = "tar")]
+            tar,
+        } => {
+            let filter = commands::unpack::include_filter(include)?;
+            let options = commands::unpack::UnpackOptions {
+                filter: filter.as_ref().map(|f| f as commands::unpack::UnpackFilter),
+                name_encoding: *encoding,
+                io_buffer_size: *io_buffer_size,
+                strip_components: *strip_components,
+                flatten: *flatten,
+                overwrite: *overwrite,
+                max_threads: *jobs,
+                key: *key,
+                sanitize_names: *sanitize_names,
+                memory_budget: *memory_budget,
+                resume: *resume,
+                ..Default::default()
+            };
+            let on_progress = commands::progress_bar("Unpack complete", cli.quiet);
+            #[cfg(feature = "tar")]
+            if let Some(tar) = tar {
+                commands::unpack::unpack_to_tar(input, tar, &options, &on_progress)?;
+                return Ok(());
+            }
+            commands::unpack::unpack_archive(input, output, &options, &on_progress)?;
+        }
+        Commands::Pack {
+            input,
+            output,
+            max_volume_size,
+            dedup,
+            io_buffer_size,
+            dry_run,
+            jobs,
+            key,
+            header_checksum,
+            permissions,
+            allow_duplicates,
+            name,
+            base_dir,
+        } => {
             info!("Packing from config {} to output dir {}", input, output);
-            commands::pack::pack_archive(input, output)?;
+            commands::pack::pack_archive(
+                input,
+                output,
+                &commands::pack::PackOptions {
+                    max_volume_size: *max_volume_size,
+                    dedup: *dedup,
+                    io_buffer_size: *io_buffer_size,
+                    dry_run: *dry_run,
+                    max_threads: *jobs,
+                    key: *key,
+                    header_checksum: *header_checksum,
+                    permissions: *permissions,
+                    allow_duplicates: *allow_duplicates,
+                    archive_name: name.clone(),
+                    base_dir: base_dir.clone(),
+                    ..Default::default()
+                },
+                &commands::progress_bar("Pack complete", cli.quiet),
+            )?;
+        }
+        Commands::PackDir {
+            input,
+            output,
+            name,
+            compression,
+            max_volume_size,
+            dedup,
+            dry_run,
+            jobs,
+        } => {
+            let name = match name {
+                Some(name) => name.clone(),
+                None => {
+                    let dir = std::path::Path::new(input).canonicalize()?;
+                    let stem = dir
+                        .file_name()
+                        .map_or("archive".into(), |n| n.to_string_lossy());
+                    format!("{}.dz", stem)
+                }
+            };
+            info!("Packing directory {} to {}/{}", input, output, name);
+            commands::pack::pack_directory(
+                input,
+                output,
+                &name,
+                *compression,
+                &commands::pack::PackOptions {
+                    max_volume_size: *max_volume_size,
+                    dedup: *dedup,
+                    dry_run: *dry_run,
+                    max_threads: *jobs,
+                    ..Default::default()
+                },
+                &commands::progress_bar("Pack complete", cli.quiet),
+            )?;
+        }
+        Commands::Append {
+            archive,
+            files,
+            base_dir,
+            compression,
+        } => {
+            commands::append::append_to_archive(archive, files, base_dir, *compression)?;
+        }
+        Commands::Combine { input, output } => {
+            commands::combine::combine_archive(input, output)?;
+        }
+        Commands::DumpChunk {
+            input,
+            chunk_id,
+            output,
+        } => {
+            commands::dump::dump_chunk(input, *chunk_id, output)?;
+        }
+        Commands::ExportChunks { input, output, raw } => {
+            commands::export::export_chunks(input, output, *raw)?;
+        }
+        Commands::Transcode {
+            input,
+            output,
+            compression,
+            include,
+        } => {
+            commands::transcode::transcode_archive(input, output, *compression, include)?;
+        }
+        Commands::List {
+            input,
+            format,
+            encoding,
+            volumes: true,
+        } => {
+            commands::list::list_volumes(input, *encoding, *format)?;
+        }
+        Commands::List {
+            input,
+            format,
+            encoding,
+            volumes: false,
+        } => {
+            commands::list::list_archive(input, *encoding, *format)?;
+        }
+        Commands::Verify {
+            input,
+            encoding,
+            header: true,
+            ..
+        } => {
+            if !commands::verify::verify_header(input, *encoding)? {
+                return Err(std::io::Error::other("Header checksum mismatch").into());
+            }
+        }
+        Commands::Verify {
+            input,
+            encoding,
+            fast: true,
+            header: false,
+        } => {
+            let problems = commands::verify::verify_structure(input, *encoding)?;
+            if problems > 0 {
+                return Err(std::io::Error::other(format!(
+                    "Structural check found {} problem(s)",
+                    problems
+                ))
+                .into());
+            }
         }
-        Commands::Verify { input } => {
-            commands::verify::verify_archive(input)?;
+        Commands::Verify {
+            input,
+            encoding,
+            fast: false,
+            header: false,
+        } => {
+            let failed = commands::verify::verify_archive(input, *encoding)?;
+            if failed > 0 {
+                return Err(std::io::Error::other(format!(
+                    "Verification failed for {} file(s)",
+                    failed
+                ))
+                .into());
+            }
         }
     }
 
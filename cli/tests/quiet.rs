@@ -0,0 +1,41 @@
+use std::path::Path;
+use std::process::Command;
+
+fn dzip_cli(args: &[&Path]) -> std::process::Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_dzip-cli"))
+        .args(args)
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    output
+}
+
+#[test]
+fn test_quiet_unpack_writes_nothing_to_stderr() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("a.txt"), b"alpha").unwrap();
+    let config = dir.path().join("pack.toml");
+    std::fs::write(
+        &config,
+        "archives = [\"test.dz\"]\nbase_dir = \".\"\n\
+         [[files]]\npath = \"a.txt\"\narchive_file_index = 0\ncompression = \"Zlib\"\n",
+    )
+    .unwrap();
+    let packed = dir.path().join("packed");
+    dzip_cli(&[Path::new("pack"), &config, Path::new("--output"), &packed]);
+
+    let archive = packed.join("test.dz");
+    let out = dir.path().join("out");
+    let unpack = |extra: &[&Path]| {
+        let mut args = vec![Path::new("unpack"), &archive, Path::new("--output"), &out];
+        args.extend(extra);
+        dzip_cli(&args)
+    };
+    let loud = unpack(&[]);
+    assert!(!loud.stderr.is_empty());
+
+    let quiet = unpack(&[Path::new("--quiet")]);
+    assert_eq!(String::from_utf8_lossy(&quiet.stderr), "");
+    assert_eq!(std::fs::read(out.join("a.txt")).unwrap(), b"alpha");
+}
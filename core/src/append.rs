@@ -0,0 +1,215 @@
+//! Adding files to an existing archive without repacking it.
+
+use crate::archive::{ArchiveMetadata, normalize_logical_path};
+use crate::encoding::NameEncoding;
+use crate::error::{DzipError, Result};
+use crate::format::{CHUNK_ZERO, Chunk, VERSION_WIDE, needs_wide_format};
+use crate::reader::DzipReader;
+use crate::writer::{
+    CompressionMethod, DzipWriter, checksum, chunk_length, chunk_offset, compress_data,
+};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// A file to add to an archive.
+#[derive(Debug, Clone)]
+pub struct NewFile {
+    /// Logical path inside the archive. Either separator is accepted.
+    pub path: String,
+    pub data: Vec<u8>,
+    pub compression: CompressionMethod,
+}
+
+/// Appends `new_files` to the archive whose main file is `archive_path`.
+///
+/// New chunks always go to the main file (Volume 0). Since the header grows with the new
+/// entries, the main file is rewritten: a fresh file holding the new header, the existing
+/// Volume 0 chunk data and then the new chunks is written next to it and renamed over the
+/// original once complete. Offsets of existing Volume 0 chunks are shifted by the header
+/// growth; auxiliary volumes are left untouched. The trailer is carried over and its
+/// sections extended if the archive has one; new files get no modification time. A version
+/// 0 archive that outgrows its 16-bit counts is upgraded to version 1.
+///
+/// Encrypted archives are rejected. Names are read and written as UTF-8. Fails with
+/// [`DzipError::DuplicateFile`] if a path is already present, leaving the archive unchanged.
+pub fn append_files(archive_path: &Path, new_files: &[NewFile]) -> Result<()> {
+    let mut reader = DzipReader::new(File::open(archive_path)?);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+    drop(reader);
+    // The keystream of a chunk depends on its offset, which moves when the header grows
+    if metadata.is_encrypted() {
+        return Err(DzipError::Unsupported(
+            "Appending to encrypted archives is not supported".to_string(),
+        ));
+    }
+
+    // Existing Volume 0 data is copied as a single block, from its first chunk on. Chunks
+    // storing nothing may have any offset, often 0, so they do not count.
+    let data_start = metadata
+        .chunks
+        .iter()
+        .filter(|c| c.file == 0 && c.flags & CHUNK_ZERO == 0 && c.compressed_length > 0)
+        .map(|c| c.offset as u64)
+        .min()
+        .unwrap_or(metadata.main_data_end)
+        .min(metadata.main_data_end);
+    let data_len = metadata.main_data_end - data_start;
+
+    let old_num_chunks = metadata.chunks.len();
+    let mut bodies = Vec::with_capacity(new_files.len());
+    for file in new_files {
+        if metadata.find_file(&file.path).is_some() {
+            return Err(DzipError::DuplicateFile(file.path.clone()));
+        }
+        let path = normalize_logical_path(&file.path);
+        let (dir, name) = match path.rsplit_once('\\') {
+            Some((dir, name)) => (Some(dir), name),
+            None => (None, path.as_str()),
+        };
+        if name.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid file path: {}", file.path),
+            )
+            .into());
+        }
+        let dir_id = match dir {
+            None => 0,
+            Some(dir) => directory_id(&mut metadata, dir),
+        };
+
+        let (flags, data) = compress_data(&file.data, file.compression)?;
+        let chunk_id = metadata.chunks.len() as u32;
+        metadata.user_files.push(name.to_string());
+        metadata.file_map.push((dir_id, vec![chunk_id]));
+        metadata.chunks.push(Chunk {
+            offset: 0, // Assigned below, once the header size is known
+            compressed_length: chunk_length(data.len())?,
+            decompressed_length: chunk_length(file.data.len())?,
+            flags,
+            file: 0,
+        });
+        if let Some(crcs) = metadata
+            .trailer
+            .as_mut()
+            .and_then(|t| t.chunk_crcs.as_mut())
+        {
+            crcs.push(checksum(&file.data));
+        }
+        if let Some(mtimes) = metadata
+            .trailer
+            .as_mut()
+            .and_then(|t| t.file_mtimes.as_mut())
+        {
+            mtimes.push(0);
+        }
+        if let Some(modes) = metadata
+            .trailer
+            .as_mut()
+            .and_then(|t| t.file_modes.as_mut())
+        {
+            modes.push(0);
+        }
+        bodies.push(data);
+    }
+    metadata.settings.num_user_files = metadata.user_files.len() as u32;
+    metadata.settings.num_directories = (metadata.directories.len() + 1) as u32;
+    metadata.chunk_settings.num_chunks = metadata.chunks.len() as u32;
+    if needs_wide_format(
+        metadata.user_files.len(),
+        metadata.directories.len() + 1,
+        metadata.chunks.len(),
+    ) {
+        metadata.settings.version = VERSION_WIDE;
+    }
+
+    let header_len = metadata.header_len()?;
+    for chunk in metadata.chunks[..old_num_chunks]
+        .iter_mut()
+        .filter(|c| c.file == 0)
+    {
+        let offset = (chunk.offset as u64).saturating_sub(data_start);
+        chunk.offset = chunk_offset(offset + header_len, 0)?;
+    }
+    let mut offset = header_len + data_len;
+    for (chunk, body) in metadata.chunks[old_num_chunks..].iter_mut().zip(&bodies) {
+        chunk.offset = chunk_offset(offset, 0)?;
+        offset += body.len() as u64;
+    }
+    update_header_crc(&mut metadata)?;
+
+    let mut tmp_name = archive_path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = archive_path.with_file_name(tmp_name);
+    let result = write_appended(
+        archive_path,
+        &tmp_path,
+        &metadata,
+        data_start,
+        data_len,
+        &bodies,
+    );
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+        return result;
+    }
+    std::fs::rename(&tmp_path, archive_path)?;
+    Ok(())
+}
+
+fn write_appended(
+    archive_path: &Path,
+    tmp_path: &Path,
+    metadata: &ArchiveMetadata,
+    data_start: u64,
+    data_len: u64,
+    bodies: &[Vec<u8>],
+) -> Result<()> {
+    let mut writer = DzipWriter::new(BufWriter::new(File::create(tmp_path)?));
+    metadata.write_header(&mut writer)?;
+    let mut out = writer.into_inner();
+
+    let mut original = File::open(archive_path)?;
+    original.seek(SeekFrom::Start(data_start))?;
+    std::io::copy(&mut original.take(data_len), &mut out)?;
+    for body in bodies {
+        out.write_all(body)?;
+    }
+
+    let mut writer = DzipWriter::new(out);
+    if let Some(trailer) = &metadata.trailer {
+        writer.write_trailer(trailer)?;
+    }
+    writer.into_inner().flush()?;
+    Ok(())
+}
+
+/// Recomputes the header checksum in the trailer, if the archive has one, for the header
+/// as [`ArchiveMetadata::write_header`] writes it with UTF-8 names.
+pub(crate) fn update_header_crc(metadata: &mut ArchiveMetadata) -> Result<()> {
+    if metadata
+        .trailer
+        .as_ref()
+        .is_some_and(|t| t.header_crc.is_some())
+    {
+        let crc = checksum(&metadata.encode_header(NameEncoding::default())?);
+        if let Some(trailer) = &mut metadata.trailer {
+            trailer.header_crc = Some(crc);
+        }
+    }
+    Ok(())
+}
+
+/// ID of the directory `dir`, adding it to the directory list if needed.
+fn directory_id(metadata: &mut ArchiveMetadata, dir: &str) -> u32 {
+    let existing = metadata
+        .directories
+        .iter()
+        .position(|d| normalize_logical_path(d).trim_end_matches('\\') == dir);
+    let index = existing.unwrap_or_else(|| {
+        metadata.directories.push(dir.to_string());
+        metadata.directories.len() - 1
+    });
+    index as u32 + 1
+}
@@ -0,0 +1,781 @@
+use crate::crypto::{ChunkCipher, EncryptionKey};
+use crate::encoding::NameEncoding;
+use crate::error::{DzipError, Result};
+use crate::format::*;
+use crate::reader::{DzipReader, VolumeSource};
+use crate::writer::{DzipWriter, checksum};
+use std::collections::HashMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// All header sections of an archive, read in one go.
+///
+/// This is the read-only view of an archive: loading it only parses the header and the
+/// trailer, so tools can inspect files, chunk offsets and flags without decompressing
+/// anything.
+#[derive(Debug, Clone)]
+pub struct ArchiveMetadata {
+    /// Format version and the file and directory counts
+    pub settings: ArchiveSettings,
+    /// File names of the user files (without directory)
+    pub user_files: Vec<String>,
+    /// Directory paths, excluding the implicit root directory (ID 0)
+    pub directories: Vec<String>,
+    /// Order of the file and directory names in the string table
+    pub string_layout: StringLayout,
+    /// Directory ID and list of chunk IDs for each user file
+    pub file_map: Vec<(u32, Vec<u32>)>,
+    /// Chunk and volume counts
+    pub chunk_settings: ChunkSettings,
+    /// Chunk table, indexed by chunk ID
+    pub chunks: Vec<Chunk>,
+    /// File names of the auxiliary volumes (Volume 1, Volume 2, ...)
+    pub volume_files: Vec<String>,
+    /// Only present if the archive contains DZ chunks
+    pub range_settings: Option<RangeSettings>,
+    /// dzip-rs extensions (checksums, mtimes), if the archive has them
+    pub trailer: Option<ArchiveTrailer>,
+    /// Offset in the main file where chunk data ends (start of the trailer, or EOF)
+    pub main_data_end: u64,
+}
+
+/// Loads the metadata of an archive whose main file is held in memory.
+///
+/// Meant as the entry point for fuzzing the header parser: malformed input of any kind
+/// gives an error, never a panic.
+pub fn try_load_metadata(bytes: &[u8]) -> Result<ArchiveMetadata> {
+    ArchiveMetadata::load(&mut DzipReader::new(std::io::Cursor::new(bytes)))
+}
+
+impl ArchiveMetadata {
+    /// Reads the complete header and the optional trailer from the main file.
+    ///
+    /// Fails with [`DzipError::ChunkNotFound`] if the file map references a chunk the
+    /// chunk table does not define, and with [`DzipError::ChunkVolumeOutOfRange`] if a
+    /// chunk is stored in a volume beyond the volume count.
+    ///
+    /// Archives with DZ chunks but without range settings get the default ones, with a
+    /// warning (see [`RangeSettings`]).
+    pub fn load<R: Read + Seek>(reader: &mut DzipReader<R>) -> Result<Self> {
+        let mut metadata = Self::load_head(reader)?;
+        let chunks = section(
+            "chunk table",
+            reader.read_chunks(metadata.chunk_settings.num_chunks as usize),
+        )?;
+        metadata.check_file_map(chunks.len())?;
+        for (chunk_id, chunk) in chunks.iter().enumerate() {
+            metadata.check_chunk_volume(chunk_id as u32, chunk)?;
+        }
+        metadata.chunks = chunks;
+        metadata.volume_files = section(
+            "volume list",
+            reader.read_file_list(metadata.num_volume_files()),
+        )?;
+
+        if metadata.chunks.iter().any(|c| (c.flags & CHUNK_DZ) != 0) {
+            metadata.range_settings = Some(read_range_settings(reader, &metadata.chunks)?);
+        }
+        metadata.load_trailer(reader)?;
+        Ok(metadata)
+    }
+
+    /// Like [`Self::load`], but skips over the chunk table, whose entries are read one at a
+    /// time by [`LazyArchiveMetadata::chunk`].
+    ///
+    /// Suits archives with a huge chunk table of which only a few files are needed. The
+    /// range settings of DZ chunks are not read, as finding out whether there are any takes
+    /// the whole table.
+    pub fn load_lazy<R: Read + Seek>(reader: &mut DzipReader<R>) -> Result<LazyArchiveMetadata> {
+        let mut metadata = Self::load_head(reader)?;
+        metadata.check_file_map(metadata.chunk_settings.num_chunks as usize)?;
+        let chunk_table_start = reader.position()?;
+        reader.seek_to(chunk_table_start + metadata.chunk_settings.num_chunks as u64 * 16)?;
+        metadata.volume_files = section(
+            "volume list",
+            reader.read_file_list(metadata.num_volume_files()),
+        )?;
+        metadata.load_trailer(reader)?;
+        Ok(LazyArchiveMetadata {
+            metadata,
+            chunk_table_start,
+        })
+    }
+
+    /// Reads the sections before the chunk table, leaving the later ones empty.
+    fn load_head<R: Read + Seek>(reader: &mut DzipReader<R>) -> Result<Self> {
+        let settings = section("archive settings", reader.read_archive_settings())?;
+
+        // Note: The first directory is root and has no string entry.
+        let strings_count = (settings.num_user_files as usize + settings.num_directories as usize)
+            .saturating_sub(1);
+        // Each string takes at least its terminator, each file map entry its directory ID
+        // and terminator.
+        let id_len = reader.format_version().id_len();
+        check_fits(
+            reader,
+            strings_count + settings.num_user_files as usize * 2 * id_len,
+        )?;
+        let (user_files, directories) = section(
+            "string table",
+            reader.read_names(
+                settings.num_user_files as usize,
+                settings.num_directories as usize,
+            ),
+        )?;
+
+        let file_map = section(
+            "file map",
+            reader.read_file_chunk_map(settings.num_user_files as usize),
+        )?;
+        let chunk_settings = section("chunk settings", reader.read_chunk_settings())?;
+        check_fits(
+            reader,
+            chunk_settings.num_chunks as usize * 16
+                + chunk_settings.num_archive_files.saturating_sub(1) as usize,
+        )?;
+
+        Ok(Self {
+            settings,
+            user_files,
+            directories,
+            string_layout: reader.string_layout(),
+            file_map,
+            chunk_settings,
+            chunks: Vec::new(),
+            volume_files: Vec::new(),
+            range_settings: None,
+            trailer: None,
+            main_data_end: 0,
+        })
+    }
+
+    /// Fails with [`DzipError::ChunkNotFound`] if the file map references a chunk ID of
+    /// `num_chunks` or more.
+    fn check_file_map(&self, num_chunks: usize) -> Result<()> {
+        match self
+            .file_map
+            .iter()
+            .flat_map(|(_, chunk_ids)| chunk_ids)
+            .find(|&&id| id as usize >= num_chunks)
+        {
+            Some(&chunk_id) => Err(DzipError::ChunkNotFound(chunk_id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails with [`DzipError::ChunkVolumeOutOfRange`] if `chunk` is stored in a volume the
+    /// chunk settings do not count. The main file always counts.
+    fn check_chunk_volume(&self, chunk_id: u32, chunk: &Chunk) -> Result<()> {
+        let num_volumes = self.chunk_settings.num_archive_files.max(1);
+        if chunk.file >= num_volumes {
+            return Err(DzipError::ChunkVolumeOutOfRange {
+                chunk: chunk_id,
+                volume: chunk.file,
+                num_volumes,
+            });
+        }
+        Ok(())
+    }
+
+    fn num_volume_files(&self) -> usize {
+        self.chunk_settings.num_archive_files.saturating_sub(1) as usize
+    }
+
+    fn load_trailer<R: Read + Seek>(&mut self, reader: &mut DzipReader<R>) -> Result<()> {
+        (self.trailer, self.main_data_end) = match reader.read_trailer()? {
+            Some((trailer, start)) => (Some(trailer), start),
+            None => (None, reader.stream_len()?),
+        };
+        Ok(())
+    }
+
+    /// Writes all header sections in file order.
+    ///
+    /// The counts in `settings` and `chunk_settings` are written as stored, so they must
+    /// match the lists. The trailer is not part of the header and is not written.
+    pub fn write_header<W: Write + Seek>(&self, writer: &mut DzipWriter<W>) -> Result<()> {
+        writer.write_archive_settings(&self.settings)?;
+        writer.write_names(&self.user_files, &self.directories, self.string_layout)?;
+        writer.write_file_chunk_map(&self.file_map)?;
+        writer.write_chunk_settings(&self.chunk_settings)?;
+        writer.write_chunks(&self.chunks)?;
+        writer.write_strings(&self.volume_files)?;
+        if let Some(range_settings) = &self.range_settings {
+            writer.write_global_settings(range_settings)?;
+        }
+        Ok(())
+    }
+
+    /// The header as written by [`Self::write_header`], with names in `encoding`.
+    pub fn encode_header(&self, encoding: NameEncoding) -> Result<Vec<u8>> {
+        let mut writer = DzipWriter::new(std::io::Cursor::new(Vec::new()));
+        writer.set_name_encoding(encoding);
+        self.write_header(&mut writer)?;
+        Ok(writer.into_inner().into_inner())
+    }
+
+    /// Size in bytes of the header as written by [`Self::write_header`].
+    ///
+    /// Chunk offsets and lengths are fixed-width fields, so the size can be measured before
+    /// the chunks are placed and stays the same once their offsets are set.
+    pub fn header_len(&self) -> Result<u64> {
+        Ok(self.encode_header(NameEncoding::default())?.len() as u64)
+    }
+
+    /// Recomputes the CRC32 of the header in the main file and returns it together with the
+    /// one stored in the trailer, as `(stored, actual)`. `None` if the archive stores no
+    /// header checksum.
+    ///
+    /// Only the header is read. `reader` must decode names the way it did when loading.
+    pub fn check_header_crc<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+    ) -> Result<Option<(u32, u32)>> {
+        let Some(stored) = self.trailer.as_ref().and_then(|t| t.header_crc) else {
+            return Ok(None);
+        };
+        let len = self.encode_header(reader.name_encoding())?.len();
+        let header = reader.read_bytes_at(0, len)?;
+        Ok(Some((stored, checksum(&header))))
+    }
+
+    /// Clamps chunk sizes to the actual volume boundaries (see [`crate::reader::correct_chunk_sizes`]).
+    ///
+    /// Volumes that cannot be opened are left uncorrected. Fails with
+    /// [`DzipError::ChunkOutOfBounds`] if a chunk starts past the end of its volume.
+    pub fn correct_chunk_sizes(&mut self, volume_source: &mut dyn VolumeSource) -> Result<()> {
+        let file_sizes = self.volume_sizes(volume_source);
+        crate::reader::correct_chunk_sizes(&mut self.chunks, &file_sizes)
+    }
+
+    /// Like [`Self::correct_chunk_sizes`], but only opens the volumes holding chunks of the
+    /// user files in `file_indices`. Chunks in other volumes keep the sizes the header
+    /// declares, so only the chunks of these files should be read afterwards.
+    pub fn correct_file_chunk_sizes(
+        &mut self,
+        file_indices: &[usize],
+        volume_source: &mut dyn VolumeSource,
+    ) -> Result<()> {
+        let mut volume_ids = Vec::new();
+        for &i in file_indices {
+            volume_ids.extend(self.file_chunks(i)?.iter().map(|c| c.file));
+        }
+        volume_ids.sort_unstable();
+        volume_ids.dedup();
+        let file_sizes = self.sizes_of_volumes(volume_ids, volume_source);
+        crate::reader::correct_chunk_sizes(&mut self.chunks, &file_sizes)
+    }
+
+    /// Whether the chunks of the archive are encrypted (see [`crate::crypto`]).
+    pub fn is_encrypted(&self) -> bool {
+        self.trailer
+            .as_ref()
+            .is_some_and(|t| t.encryption.is_some())
+    }
+
+    /// The cipher to pass to [`DzipReader::set_cipher`] to read an encrypted archive, or
+    /// `None` if the archive is not encrypted. Fails with [`DzipError::Encryption`] if `key`
+    /// is not the one the archive was written with.
+    pub fn cipher(&self, key: &EncryptionKey) -> Result<Option<ChunkCipher>> {
+        self.trailer
+            .as_ref()
+            .and_then(|t| t.encryption.as_ref())
+            .map(|params| ChunkCipher::open(key, params))
+            .transpose()
+    }
+
+    /// Size of the chunk data of each volume that can be opened, by volume ID. For the
+    /// main file (ID 0) this ends at [`Self::main_data_end`].
+    pub fn volume_sizes(&self, volume_source: &mut dyn VolumeSource) -> HashMap<u16, u64> {
+        self.sizes_of_volumes(1..=self.volume_files.len() as u16, volume_source)
+    }
+
+    /// [`Self::volume_sizes`] of the main file and the volumes `ids` only.
+    fn sizes_of_volumes(
+        &self,
+        ids: impl IntoIterator<Item = u16>,
+        volume_source: &mut dyn VolumeSource,
+    ) -> HashMap<u16, u64> {
+        let mut file_sizes = HashMap::new();
+        file_sizes.insert(0u16, self.main_data_end);
+        for id in ids.into_iter().filter(|&id| id != 0) {
+            let len = volume_source
+                .open_volume(id)
+                .and_then(|volume| Ok(volume.seek(SeekFrom::End(0))?));
+            match len {
+                Ok(len) => {
+                    file_sizes.insert(id, len);
+                }
+                Err(e) => log::debug!("Cannot determine size of volume {}: {}", id, e),
+            }
+        }
+        file_sizes
+    }
+
+    /// Directory path of a directory ID, or `None` for the root directory.
+    pub fn directory(&self, dir_id: u32) -> Option<&str> {
+        if dir_id == 0 {
+            return None;
+        }
+        self.directories
+            .get(dir_id as usize - 1)
+            .map(String::as_str)
+    }
+
+    /// Directory ID and chunk IDs of a user file. Fails with
+    /// [`DzipError::FileIndexOutOfRange`] if the archive has no file `file_index`.
+    pub fn file_entry(&self, file_index: usize) -> Result<&(u32, Vec<u32>)> {
+        self.file_map
+            .get(file_index)
+            .ok_or(DzipError::FileIndexOutOfRange {
+                index: file_index,
+                num_files: self.file_map.len(),
+            })
+    }
+
+    /// Logical path of a user file inside the archive, using `\` as separator.
+    pub fn file_path(&self, file_index: usize) -> Result<String> {
+        let (dir_id, _) = self.file_entry(file_index)?;
+        let name = self
+            .user_files
+            .get(file_index)
+            .ok_or(DzipError::FileIndexOutOfRange {
+                index: file_index,
+                num_files: self.user_files.len(),
+            })?;
+        let mut path = String::new();
+        if let Some(dir) = self.directory(*dir_id) {
+            path.push_str(dir);
+            if !path.ends_with('/') && !path.ends_with('\\') {
+                path.push('\\');
+            }
+        }
+        path.push_str(name);
+        Ok(path)
+    }
+
+    /// Logical path of a user file inside the archive, using `/` as separator.
+    pub fn resolve_path(&self, file_index: usize) -> Result<String> {
+        Ok(self.file_path(file_index)?.replace('\\', "/"))
+    }
+
+    /// Comment stored in the archive trailer, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.trailer.as_ref()?.comment.as_deref()
+    }
+
+    /// Modification time of a user file in seconds since the Unix epoch, if the archive
+    /// records it.
+    pub fn file_mtime(&self, file_index: usize) -> Option<u64> {
+        self.trailer
+            .as_ref()?
+            .file_mtimes
+            .as_ref()?
+            .get(file_index)
+            .copied()
+            .filter(|&mtime| mtime != 0)
+    }
+
+    /// Unix permission bits of a user file (e.g. `0o755`), if the archive records them.
+    pub fn file_mode(&self, file_index: usize) -> Option<u32> {
+        self.trailer
+            .as_ref()?
+            .file_modes
+            .as_ref()?
+            .get(file_index)
+            .copied()
+            .filter(|&mode| mode != 0)
+    }
+
+    /// Finds a user file by its logical path. Either separator is accepted.
+    pub fn find_file(&self, logical_path: &str) -> Option<usize> {
+        let wanted = normalize_logical_path(logical_path);
+        (0..self.file_map.len()).find(|&i| {
+            self.file_path(i)
+                .is_ok_and(|path| normalize_logical_path(&path) == wanted)
+        })
+    }
+
+    /// Like [`Self::find_file`], but fails if no file or more than one file has the path.
+    pub fn find_unique_file(&self, logical_path: &str) -> Result<usize> {
+        let wanted = normalize_logical_path(logical_path);
+        let mut matches = (0..self.file_map.len()).filter(|&i| {
+            self.file_path(i)
+                .is_ok_and(|path| normalize_logical_path(&path) == wanted)
+        });
+        let index = matches
+            .next()
+            .ok_or_else(|| DzipError::FileNotFound(logical_path.to_string()))?;
+        if matches.next().is_some() {
+            return Err(DzipError::AmbiguousPath(logical_path.to_string()));
+        }
+        Ok(index)
+    }
+
+    /// The chunks of a user file, in file order.
+    pub fn file_chunks(&self, file_index: usize) -> Result<Vec<Chunk>> {
+        self.file_entry(file_index)?
+            .1
+            .iter()
+            .map(|&chunk_id| {
+                self.chunks
+                    .get(chunk_id as usize)
+                    .copied()
+                    .ok_or(DzipError::ChunkNotFound(chunk_id))
+            })
+            .collect()
+    }
+
+    /// Decompresses a single user file, concatenating its chunks in file order.
+    ///
+    /// Files with many chunks are decompressed in parallel
+    /// (see [`DzipReader::for_each_chunk_data`]).
+    pub fn read_file<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+        volume_source: &mut dyn VolumeSource,
+        file_index: usize,
+    ) -> Result<Vec<u8>> {
+        let chunks = self.file_chunks(file_index)?;
+        let ids = &self.file_entry(file_index)?.1;
+        let mut data = Vec::new();
+        reader.for_each_chunk_data(ids, &chunks, volume_source, |_, chunk| {
+            data.extend(chunk?);
+            Ok(())
+        })?;
+        warn_on_media_data(&self.resolve_path(file_index)?, &chunks, &data);
+        Ok(data)
+    }
+
+    /// Decompresses the chunks of a user file one at a time, in file order, as the iterator
+    /// is advanced.
+    ///
+    /// Unlike [`Self::read_file`], only one chunk is held in memory at a time, which suits
+    /// on-demand access such as a virtual filesystem. Volumes stay open in `volume_source`
+    /// between chunks.
+    pub fn chunk_stream<'a, R: Read + Seek>(
+        &self,
+        reader: &'a mut DzipReader<R>,
+        volume_source: &'a mut dyn VolumeSource,
+        file_index: usize,
+    ) -> Result<impl Iterator<Item = Result<Vec<u8>>> + 'a> {
+        let chunks = self.file_chunks(file_index)?;
+        let ids = self.file_entry(file_index)?.1.clone();
+        Ok(ids
+            .into_iter()
+            .zip(chunks)
+            .map(move |(id, chunk)| reader.read_chunk_data_with_volumes(id, &chunk, volume_source)))
+    }
+
+    /// Opens a user file for reading and seeking like a regular file.
+    ///
+    /// Chunks are decompressed when the read position first enters them, and the last one
+    /// is kept, so sequential reads decompress every chunk once. The chunk sizes are taken
+    /// from the chunk table, so placeholder lengths should be corrected first (see
+    /// [`Self::correct_chunk_sizes`]).
+    pub fn open_file<'a, R: Read + Seek>(
+        &self,
+        reader: &'a mut DzipReader<R>,
+        volume_source: &'a mut dyn VolumeSource,
+        file_index: usize,
+    ) -> Result<ArchiveFile<'a, R>> {
+        let chunks = self.file_chunks(file_index)?;
+        let ids = self.file_entry(file_index)?.1.clone();
+        let mut starts = Vec::with_capacity(chunks.len() + 1);
+        let mut len = 0u64;
+        for chunk in &chunks {
+            starts.push(len);
+            len += chunk.decompressed_length as u64;
+        }
+        starts.push(len);
+        Ok(ArchiveFile {
+            reader,
+            volume_source,
+            ids,
+            chunks,
+            starts,
+            position: 0,
+            cached: None,
+        })
+    }
+
+    /// Decompresses a single user file given its logical path, without touching the filesystem.
+    pub fn extract_one<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+        volume_source: &mut dyn VolumeSource,
+        logical_path: &str,
+    ) -> Result<Vec<u8>> {
+        let index = self.find_unique_file(logical_path)?;
+        self.read_file(reader, volume_source, index)
+    }
+
+    /// Decompresses every user file into memory, returning them in file index order with
+    /// their logical paths (using `/` as separator).
+    ///
+    /// The chunks of all files go through a single [`DzipReader::for_each_chunk_data`] pass,
+    /// so batches of chunks are decompressed in parallel whatever the file sizes.
+    pub fn extract_all_to_memory<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+        volume_source: &mut dyn VolumeSource,
+    ) -> Result<Vec<(String, Vec<u8>)>> {
+        let mut ids = Vec::new();
+        let mut chunks = Vec::new();
+        // Index of the file each chunk in `chunks` belongs to
+        let mut owners = Vec::new();
+        let mut files = Vec::with_capacity(self.file_map.len());
+        for index in 0..self.file_map.len() {
+            let file_chunks = self.file_chunks(index)?;
+            owners.extend(std::iter::repeat_n(index, file_chunks.len()));
+            ids.extend_from_slice(&self.file_map[index].1);
+            chunks.extend(file_chunks);
+            files.push((self.resolve_path(index)?, Vec::new()));
+        }
+        reader.for_each_chunk_data(&ids, &chunks, volume_source, |i, data| {
+            let buffer = &mut files[owners[i]].1;
+            // Most files are a single chunk, whose buffer is taken as is
+            if buffer.is_empty() {
+                *buffer = data?;
+            } else {
+                buffer.extend(data?);
+            }
+            Ok(())
+        })?;
+        Ok(files)
+    }
+
+    /// Decompresses a single user file given its logical path into `writer`, in chunk order.
+    ///
+    /// The output is only ever appended to, so `writer` may be a pipe or stdout.
+    /// Returns the number of bytes written.
+    pub fn extract_file_to<R: Read + Seek, W: Write>(
+        &self,
+        reader: &mut DzipReader<R>,
+        volume_source: &mut dyn VolumeSource,
+        logical_path: &str,
+        writer: &mut W,
+    ) -> Result<u64> {
+        let index = self.find_unique_file(logical_path)?;
+        let ids = &self.file_entry(index)?.1;
+        let mut written = 0;
+        reader.for_each_chunk_data(ids, &self.file_chunks(index)?, volume_source, |_, data| {
+            let data = data?;
+            writer.write_all(&data)?;
+            written += data.len() as u64;
+            Ok(())
+        })?;
+        Ok(written)
+    }
+}
+
+/// The metadata of an archive whose chunk table is read on demand, see
+/// [`ArchiveMetadata::load_lazy`].
+#[derive(Debug, Clone)]
+pub struct LazyArchiveMetadata {
+    /// Every section but the chunk table: `chunks` is empty and `range_settings` unset.
+    /// Names, the file map and the trailer can be used as usual.
+    pub metadata: ArchiveMetadata,
+    /// Offset of the chunk table in the main file
+    chunk_table_start: u64,
+}
+
+impl LazyArchiveMetadata {
+    /// Reads the entry of one chunk from the chunk table.
+    ///
+    /// Lengths are as listed; placeholder lengths are not corrected (see
+    /// [`ArchiveMetadata::correct_chunk_sizes`]), as that needs the whole table.
+    pub fn chunk<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+        chunk_id: u32,
+    ) -> Result<Chunk> {
+        if chunk_id >= self.metadata.chunk_settings.num_chunks {
+            return Err(DzipError::ChunkNotFound(chunk_id));
+        }
+        reader.seek_to(self.chunk_table_start + chunk_id as u64 * 16)?;
+        let chunk = section("chunk table", reader.read_chunks(1))?.remove(0);
+        self.metadata.check_chunk_volume(chunk_id, &chunk)?;
+        Ok(chunk)
+    }
+
+    /// The chunks of a user file, in file order. See [`ArchiveMetadata::file_chunks`].
+    pub fn file_chunks<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+        file_index: usize,
+    ) -> Result<Vec<Chunk>> {
+        self.metadata
+            .file_entry(file_index)?
+            .1
+            .iter()
+            .map(|&chunk_id| self.chunk(reader, chunk_id))
+            .collect()
+    }
+
+    /// Decompresses a single user file. See [`ArchiveMetadata::read_file`].
+    pub fn read_file<R: Read + Seek>(
+        &self,
+        reader: &mut DzipReader<R>,
+        volume_source: &mut dyn VolumeSource,
+        file_index: usize,
+    ) -> Result<Vec<u8>> {
+        let chunks = self.file_chunks(reader, file_index)?;
+        let ids = &self.metadata.file_entry(file_index)?.1;
+        let mut data = Vec::new();
+        reader.for_each_chunk_data(ids, &chunks, volume_source, |_, chunk| {
+            data.extend(chunk?);
+            Ok(())
+        })?;
+        warn_on_media_data(&self.metadata.resolve_path(file_index)?, &chunks, &data);
+        Ok(data)
+    }
+}
+
+/// A user file opened with [`ArchiveMetadata::open_file`], implementing [`Read`] and
+/// [`Seek`] over its decompressed data.
+pub struct ArchiveFile<'a, R: Read + Seek> {
+    reader: &'a mut DzipReader<R>,
+    volume_source: &'a mut dyn VolumeSource,
+    /// Chunk IDs of the file, for log messages
+    ids: Vec<u32>,
+    chunks: Vec<Chunk>,
+    /// Offset of each chunk in the file, followed by the file length
+    starts: Vec<u64>,
+    position: u64,
+    /// Index and data of the chunk decompressed last
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+impl<R: Read + Seek> ArchiveFile<'_, R> {
+    /// Length of the decompressed file in bytes.
+    pub fn len(&self) -> u64 {
+        self.starts[self.chunks.len()]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The decompressed data of chunk `index`, from the cache if possible.
+    fn chunk_data(&mut self, index: usize) -> Result<&[u8]> {
+        if self
+            .cached
+            .as_ref()
+            .is_none_or(|(cached, _)| *cached != index)
+        {
+            let data = self.reader.read_chunk_data_with_volumes(
+                self.ids[index],
+                &self.chunks[index],
+                self.volume_source,
+            )?;
+            self.cached = Some((index, data));
+        }
+        Ok(self.cached.as_ref().map_or(&[], |(_, data)| data))
+    }
+}
+
+impl<R: Read + Seek> Read for ArchiveFile<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.position >= self.len() {
+            return Ok(0);
+        }
+        // The last chunk starting at or before the position; empty chunks are skipped
+        let index = self.starts[..self.chunks.len()].partition_point(|&s| s <= self.position) - 1;
+        let offset = (self.position - self.starts[index]) as usize;
+        let data = self.chunk_data(index).map_err(std::io::Error::other)?;
+        let Some(available) = data.get(offset..).filter(|a| !a.is_empty()) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "chunk is shorter than listed in the chunk table",
+            ));
+        };
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for ArchiveFile<'_, R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Fails with [`DzipError::InvalidHeader`] if the rest of the file is shorter than
+/// `min_len`, so that corrupt counts are caught before anything is allocated for them.
+fn check_fits<R: Read + Seek>(reader: &mut DzipReader<R>, min_len: usize) -> Result<()> {
+    let remaining = reader.remaining_len()?;
+    if min_len as u64 > remaining {
+        log::debug!(
+            "Header declares at least {} more bytes, but only {} remain",
+            min_len,
+            remaining
+        );
+        return Err(DzipError::InvalidHeader);
+    }
+    Ok(())
+}
+
+/// Logs a warning if the data of a file stored as MP3 or JPEG does not look like one (see
+/// [`crate::reader::check_media_data`]).
+fn warn_on_media_data(path: &str, chunks: &[Chunk], data: &[u8]) {
+    let Some(first) = chunks.first() else {
+        return;
+    };
+    if let Some(problem) = crate::reader::check_media_data(first.flags, data, true) {
+        log::warn!("{}: {}", path, problem);
+    }
+}
+
+/// Reads the range settings after the volume list, or returns the default ones if the
+/// archive omits them: the file ends there, or chunk data starts within the block.
+fn read_range_settings<R: Read + Seek>(
+    reader: &mut DzipReader<R>,
+    chunks: &[Chunk],
+) -> Result<RangeSettings> {
+    let start = reader.position()?;
+    let overlapped = chunks.iter().any(|c| {
+        c.file == 0
+            && c.flags & CHUNK_ZERO == 0
+            && c.compressed_length > 0
+            && (start..start + RANGE_SETTINGS_LEN).contains(&(c.offset as u64))
+    });
+    if !overlapped {
+        match reader.read_global_settings() {
+            Err(DzipError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {}
+            result => return result,
+        }
+    }
+    log::warn!(
+        "Archive has DZ chunks but no range settings at offset {}, using the defaults",
+        start
+    );
+    reader.seek_to(start)?;
+    Ok(RangeSettings::default())
+}
+
+/// Reports a header section that ends early as [`DzipError::TruncatedSection`].
+fn section<T>(name: &'static str, result: Result<T>) -> Result<T> {
+    result.map_err(|e| match e {
+        DzipError::Io(io) if io.kind() == std::io::ErrorKind::UnexpectedEof => {
+            DzipError::TruncatedSection(name)
+        }
+        e => e,
+    })
+}
+
+pub(crate) fn normalize_logical_path(path: &str) -> String {
+    path.replace('/', "\\").trim_start_matches('\\').to_string()
+}
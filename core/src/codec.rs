@@ -0,0 +1,233 @@
+//! Pluggable chunk codecs.
+//!
+//! A [`CodecRegistry`] maps chunk flags to [`Codec`]s. Readers and [`crate::PackBuilder`]
+//! consult it for every chunk, so archives using a custom method (e.g. a game-specific
+//! variant) can be read and written by registering a codec for its flag.
+
+use crate::error::{DzipError, Result};
+use crate::format::*;
+use crate::reader::decode_chunk;
+use crate::writer::{CompressOptions, CompressionMethod, compress_stream};
+use std::io::{Read, Write};
+use std::sync::Arc;
+
+/// Compression method of a chunk, identified by a chunk flag.
+pub trait Codec: Send + Sync {
+    /// The chunk flag marking data of this codec, e.g. [`CHUNK_ZLIB`].
+    fn flag(&self) -> u16;
+
+    /// Compresses all of `input` into `output`.
+    fn compress(&self, input: &mut dyn Read, output: &mut dyn Write) -> Result<()>;
+
+    /// Decompresses the stored data of a chunk into `output`. `decompressed_length` is the
+    /// size recorded in the chunk table.
+    fn decompress(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        decompressed_length: u32,
+    ) -> Result<()>;
+
+    /// Like [`Self::compress`], with codec settings, and returns the flags of the chunk.
+    /// Only the built-in codecs use `options`.
+    fn compress_with(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        _options: &CompressOptions,
+    ) -> Result<u16> {
+        self.compress(input, output)?;
+        Ok(self.flag())
+    }
+
+    /// Decompresses a whole chunk. The built-in codecs override this to handle the quirks
+    /// of existing archives (see [`decode_chunk`]).
+    fn decompress_chunk(&self, buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(chunk.decompressed_length as usize);
+        self.decompress(
+            &mut buffer.as_slice(),
+            &mut output,
+            chunk.decompressed_length,
+        )?;
+        Ok(output)
+    }
+}
+
+/// One of the compression methods dzip-rs implements itself.
+struct BuiltinCodec(CompressionMethod);
+
+impl Codec for BuiltinCodec {
+    fn flag(&self) -> u16 {
+        self.0.flag()
+    }
+
+    fn compress(&self, input: &mut dyn Read, output: &mut dyn Write) -> Result<()> {
+        self.compress_with(input, output, &CompressOptions::default())?;
+        Ok(())
+    }
+
+    fn decompress(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        decompressed_length: u32,
+    ) -> Result<()> {
+        let mut buffer = Vec::new();
+        input.read_to_end(&mut buffer)?;
+        let chunk = Chunk {
+            offset: 0,
+            compressed_length: buffer.len() as u32,
+            decompressed_length,
+            flags: self.flag(),
+            file: 0,
+        };
+        output.write_all(&decode_chunk(buffer, &chunk)?)?;
+        Ok(())
+    }
+
+    fn compress_with(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        options: &CompressOptions,
+    ) -> Result<u16> {
+        Ok(compress_stream(input, output, self.0, options)?.flags)
+    }
+
+    fn decompress_chunk(&self, buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+        decode_chunk(buffer, chunk)
+    }
+}
+
+/// Default of [`CodecRegistry::set_max_chunk_size`]: 1 GiB.
+pub const DEFAULT_MAX_CHUNK_SIZE: u64 = 1 << 30;
+
+/// The codecs available for reading and writing chunks.
+#[derive(Clone)]
+pub struct CodecRegistry {
+    codecs: Vec<Arc<dyn Codec>>,
+    max_chunk_size: u64,
+}
+
+impl Default for CodecRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CodecRegistry {
+    /// A registry with the built-in codecs: Zlib, Bzip, LZMA, Copy, Zero, MP3, JPEG,
+    /// RandomAccess and, with the `zstd` feature, Zstd.
+    pub fn new() -> Self {
+        let mut registry = Self {
+            codecs: Vec::new(),
+            max_chunk_size: DEFAULT_MAX_CHUNK_SIZE,
+        };
+        for method in [
+            CompressionMethod::Zlib,
+            CompressionMethod::Bzip,
+            CompressionMethod::Lzma,
+            CompressionMethod::Copy,
+            CompressionMethod::Zero,
+            CompressionMethod::Mp3,
+            CompressionMethod::Jpeg,
+            CompressionMethod::RandomAccess,
+            #[cfg(feature = "zstd")]
+            CompressionMethod::Zstd,
+        ] {
+            registry.register(BuiltinCodec(method));
+        }
+        registry
+    }
+
+    /// Adds a codec. It takes precedence over codecs registered before it, including the
+    /// built-in ones.
+    pub fn register(&mut self, codec: impl Codec + 'static) {
+        self.codecs.push(Arc::new(codec));
+    }
+
+    /// Rejects chunks listing more than `bytes` of decompressed data before decompressing
+    /// them, so that archives from untrusted sources cannot make a reader allocate more.
+    /// Defaults to [`DEFAULT_MAX_CHUNK_SIZE`].
+    pub fn set_max_chunk_size(&mut self, bytes: u64) {
+        self.max_chunk_size = bytes;
+    }
+
+    pub fn max_chunk_size(&self) -> u64 {
+        self.max_chunk_size
+    }
+
+    /// The codec for a chunk with the given flags, if any of them is registered.
+    pub fn get(&self, flags: u16) -> Option<&dyn Codec> {
+        self.codecs
+            .iter()
+            .rev()
+            .find(|codec| flags & codec.flag() != 0)
+            .map(|codec| codec.as_ref())
+    }
+
+    /// Decompresses the stored data of a chunk with the codec for its flags.
+    ///
+    /// Chunks that no codec claims are passed to [`decode_chunk`], which reports
+    /// unsupported methods. So do chunks claimed by a built-in codec that carry flag bits
+    /// no codec is registered for.
+    ///
+    /// Chunks listing more than [`Self::max_chunk_size`] bytes fail with
+    /// [`DzipError::Decompression`], and so do chunks decompressing to more than they list.
+    /// The built-in codecs stop as soon as they overrun, so a small chunk cannot expand
+    /// without bound.
+    ///
+    /// Every chunk is logged at trace level, by its ID `id`, with the flag of the codec that
+    /// handled it and its lengths. A chunk decompressing to fewer bytes than the chunk table
+    /// lists is logged as a warning, as that usually means the archive is corrupt.
+    pub fn decompress_chunk(&self, id: u32, buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+        // Encrypted chunks are decrypted when read, codecs only see the compressed data
+        let chunk = &Chunk {
+            flags: chunk.flags & !CHUNK_ENCRYPTED,
+            ..*chunk
+        };
+        if chunk.decompressed_length as u64 > self.max_chunk_size {
+            return Err(DzipError::Decompression(format!(
+                "Chunk at volume {} offset {} lists {} bytes, more than the limit of {}",
+                chunk.file, chunk.offset, chunk.decompressed_length, self.max_chunk_size
+            )));
+        }
+        let stored = buffer.len();
+        let codec = self.get(chunk.flags);
+        let data = match codec {
+            Some(codec) => codec.decompress_chunk(buffer, chunk)?,
+            None => decode_chunk(buffer, chunk)?,
+        };
+        log::trace!(
+            "Chunk {} at volume {} offset {}: flags {:#x}, codec {:#x}, {} bytes stored, {} declared, {} produced",
+            id,
+            chunk.file,
+            chunk.offset,
+            chunk.flags,
+            codec.map_or(0, |codec| codec.flag()),
+            stored,
+            chunk.decompressed_length,
+            data.len()
+        );
+        if data.len() > chunk.decompressed_length as usize {
+            return Err(DzipError::Decompression(format!(
+                "Chunk at volume {} offset {} decompressed to {} bytes, more than the {} listed",
+                chunk.file,
+                chunk.offset,
+                data.len(),
+                chunk.decompressed_length
+            )));
+        }
+        if data.len() < chunk.decompressed_length as usize {
+            log::warn!(
+                "Chunk {} at volume {} offset {} decompressed to {} bytes, but the chunk table lists {}",
+                id,
+                chunk.file,
+                chunk.offset,
+                data.len(),
+                chunk.decompressed_length
+            );
+        }
+        Ok(data)
+    }
+}
@@ -0,0 +1,95 @@
+//! Merging the volumes of a split archive into a single file, and rewriting archives in
+//! the newest format version.
+
+use crate::archive::ArchiveMetadata;
+use crate::error::{DzipError, Result};
+use crate::format::FormatVersion;
+use crate::reader::{DzipReader, VolumeSource};
+use crate::rewrite::{copy_stored_chunk, write_then_rename};
+use crate::volume::FileSystemVolumeManager;
+use crate::writer::DzipWriter;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Writes the archive whose main file is `archive_path` as a single main file at
+/// `output_path`, the inverse of [`crate::PackBuilder::max_volume_size`].
+///
+/// Chunks are copied as stored, in chunk ID order, without recompressing them. Every
+/// chunk then lives in Volume 0 and the volume list is empty; the trailer is carried over.
+/// Volumes missing under their listed name are looked for under their numbered name (see
+/// [`FileSystemVolumeManager::set_numbered_fallback`]). The output is written next to its
+/// final path and renamed once complete, so `output_path` may be `archive_path`.
+///
+/// Encrypted archives are rejected. Names are read and written as UTF-8. Returns the
+/// metadata of the combined archive.
+pub fn combine_volumes(archive_path: &Path, output_path: &Path) -> Result<ArchiveMetadata> {
+    rewrite(archive_path, output_path, None)
+}
+
+/// Rewrites the archive whose main file is `archive_path` in [`FormatVersion::LATEST`] at
+/// `output_path`, e.g. to lift the limits of [`FormatVersion::Legacy`] before appending
+/// files to it.
+///
+/// The archive is written like by [`combine_volumes`], as a single main file, with its
+/// header in the new version. Archives already in the newest version are copied as they
+/// are. Encrypted archives are rejected. Returns the metadata of the upgraded archive.
+pub fn upgrade(archive_path: &Path, output_path: &Path) -> Result<ArchiveMetadata> {
+    rewrite(archive_path, output_path, Some(FormatVersion::LATEST))
+}
+
+/// [`combine_volumes`], writing the header in `version` if set.
+fn rewrite(
+    archive_path: &Path,
+    output_path: &Path,
+    version: Option<FormatVersion>,
+) -> Result<ArchiveMetadata> {
+    let mut reader = DzipReader::new(File::open(archive_path)?);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+    drop(reader);
+    // The keystream of a chunk depends on its volume and offset
+    if metadata.is_encrypted() {
+        return Err(DzipError::Unsupported(
+            "Combining encrypted archives is not supported".to_string(),
+        ));
+    }
+
+    let mut volumes = FileSystemVolumeManager::new(
+        archive_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    if let Some(name) = archive_path.file_name() {
+        volumes.set_numbered_fallback(&name.to_string_lossy());
+    }
+    metadata.correct_chunk_sizes(&mut volumes)?;
+
+    write_then_rename(output_path, |tmp_path| {
+        write_combined(archive_path, tmp_path, &mut metadata, version, &mut volumes)
+    })?;
+    if let Some(version) = version {
+        metadata.settings.version = version.into();
+    }
+    Ok(metadata)
+}
+
+fn write_combined(
+    archive_path: &Path,
+    tmp_path: &Path,
+    metadata: &mut ArchiveMetadata,
+    version: Option<FormatVersion>,
+    volumes: &mut dyn VolumeSource,
+) -> Result<()> {
+    let mut writer = DzipWriter::new(BufWriter::new(File::create(tmp_path)?));
+    if let Some(version) = version {
+        writer.set_format_version(version);
+    }
+    let mut main = BufReader::new(File::open(archive_path)?);
+    writer.write_archive_with(metadata, &mut |_, chunk, out| {
+        copy_stored_chunk(chunk, &mut main, volumes, out)
+    })?;
+    writer.into_inner().flush()?;
+    Ok(())
+}
@@ -0,0 +1,187 @@
+//! Optional encryption of chunk data (`encryption` feature).
+//!
+//! The stored bytes of each chunk are encrypted with AES-256 in CTR mode after compression.
+//! Encrypted chunks carry [`CHUNK_ENCRYPTED`] in addition to their method flag, and the
+//! trailer holds the [`EncryptionParams`] of the archive.
+//!
+//! Every archive gets a random salt, from which a per-archive key is derived, so the same
+//! key can be reused across archives. The counter block of a chunk is its volume and offset,
+//! which keeps the keystreams of chunks apart and only needs the chunk table entry to read
+//! a chunk.
+//!
+//! Keys are 32 raw bytes. dzip-rs does not derive keys from passphrases; use a proper key
+//! derivation function such as Argon2 for that.
+
+use crate::error::{DzipError, Result};
+use crate::format::{CHUNK_ENCRYPTED, Chunk, EncryptionParams};
+
+/// AES-256 key of an encrypted archive.
+pub type EncryptionKey = [u8; 32];
+
+/// Encrypts and decrypts the chunks of one archive.
+///
+/// Created with [`crate::ArchiveMetadata::cipher`] for reading and by
+/// [`crate::PackBuilder::encryption_key`] for writing.
+#[derive(Clone)]
+pub struct ChunkCipher {
+    /// Key derived from the given key and the salt of the archive
+    archive_key: EncryptionKey,
+    params: EncryptionParams,
+}
+
+impl std::fmt::Debug for ChunkCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChunkCipher")
+            .field("params", &self.params)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChunkCipher {
+    /// A cipher for a new archive, with a random salt.
+    pub(crate) fn generate(key: &EncryptionKey) -> Result<Self> {
+        #[cfg(feature = "encryption")]
+        {
+            let mut salt = [0u8; 16];
+            getrandom::fill(&mut salt).map_err(|e| DzipError::Encryption(e.to_string()))?;
+            let archive_key = derive_archive_key(key, &salt);
+            let params = EncryptionParams {
+                salt,
+                key_check: key_check(&archive_key),
+            };
+            Ok(Self {
+                archive_key,
+                params,
+            })
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            let _ = key;
+            Err(feature_required())
+        }
+    }
+
+    /// The cipher of an existing archive. Fails if `key` is not the one it was written with.
+    pub(crate) fn open(key: &EncryptionKey, params: &EncryptionParams) -> Result<Self> {
+        #[cfg(feature = "encryption")]
+        {
+            let archive_key = derive_archive_key(key, &params.salt);
+            if key_check(&archive_key) != params.key_check {
+                return Err(DzipError::Encryption("Wrong encryption key".to_string()));
+            }
+            Ok(Self {
+                archive_key,
+                params: *params,
+            })
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            let _ = (key, params);
+            Err(feature_required())
+        }
+    }
+
+    /// Salt and key check to store in the trailer.
+    pub(crate) fn params(&self) -> EncryptionParams {
+        self.params
+    }
+
+    /// Encrypts or decrypts `data`, which starts `position` bytes into the stored data of
+    /// `chunk`.
+    pub(crate) fn apply(&self, chunk: &Chunk, position: u64, data: &mut [u8]) {
+        #[cfg(feature = "encryption")]
+        {
+            use ctr::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+            let mut iv = [0u8; 16];
+            iv[..2].copy_from_slice(&chunk.file.to_be_bytes());
+            iv[2..6].copy_from_slice(&chunk.offset.to_be_bytes());
+            let mut cipher = ctr::Ctr32BE::<aes::Aes256>::new(&self.archive_key.into(), &iv.into());
+            cipher.seek(position);
+            cipher.apply_keystream(data);
+        }
+        #[cfg(not(feature = "encryption"))]
+        {
+            // Ciphers cannot be created without the feature
+            let _ = (chunk, position, data, self.archive_key);
+            unreachable!("ChunkCipher without the encryption feature");
+        }
+    }
+}
+
+/// Decrypts the stored bytes of `chunk` in place if it is encrypted. `data` starts
+/// `position` bytes into the chunk.
+pub(crate) fn decrypt_chunk(
+    cipher: Option<&ChunkCipher>,
+    chunk: &Chunk,
+    position: u64,
+    data: &mut [u8],
+) -> Result<()> {
+    if chunk.flags & CHUNK_ENCRYPTED == 0 {
+        return Ok(());
+    }
+    if cfg!(not(feature = "encryption")) {
+        return Err(feature_required());
+    }
+    let cipher = cipher.ok_or_else(|| {
+        DzipError::Encryption("Chunk is encrypted, but no key was given".to_string())
+    })?;
+    cipher.apply(chunk, position, data);
+    Ok(())
+}
+
+/// Parses a key given as 64 hexadecimal digits.
+pub fn parse_key(hex: &str) -> Result<EncryptionKey> {
+    let invalid = || DzipError::Config("Encryption key must be 64 hexadecimal digits".to_string());
+    let hex = hex.trim();
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let mut key = [0u8; 32];
+    for (byte, digits) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).map_err(|_| invalid())?;
+        *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+    }
+    Ok(key)
+}
+
+fn feature_required() -> DzipError {
+    DzipError::Unsupported("Encrypted archives require the `encryption` feature".to_string())
+}
+
+/// Encrypts the salt, tweaked to make two blocks, with the archive key.
+#[cfg(feature = "encryption")]
+fn derive_archive_key(key: &EncryptionKey, salt: &[u8; 16]) -> EncryptionKey {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    let aes = aes::Aes256::new(key.into());
+    let mut archive_key = [0u8; 32];
+    for (i, half) in archive_key.chunks_mut(16).enumerate() {
+        let mut block = *salt;
+        block[15] ^= i as u8 + 1;
+        let mut block = block.into();
+        aes.encrypt_block(&mut block);
+        half.copy_from_slice(&block);
+    }
+    archive_key
+}
+
+/// Encryption of an all-zero block with the archive key.
+#[cfg(feature = "encryption")]
+fn key_check(archive_key: &EncryptionKey) -> [u8; 16] {
+    use aes::cipher::{BlockEncrypt, KeyInit};
+    let mut block = [0u8; 16].into();
+    aes::Aes256::new(archive_key.into()).encrypt_block(&mut block);
+    block.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key() {
+        let key = parse_key(&"0f".repeat(32)).unwrap();
+        assert_eq!(key, [0x0f; 32]);
+        assert!(parse_key("0f").is_err());
+        assert!(parse_key(&"zz".repeat(32)).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+//! A serializable snapshot of the structure of an archive.
+
+use crate::archive::ArchiveMetadata;
+use crate::error::Result;
+use crate::reader::DzipReader;
+use crate::volume::FileSystemVolumeManager;
+use crate::writer::CompressionMethod;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+
+/// Everything the header and trailer of an archive say about it, in a form meant for
+/// serializing, e.g. to diff archives across game patches.
+///
+/// Unlike a pack config, which describes how to build an archive, this is a read-only
+/// record of one: IDs, offsets and flags are kept as stored. Fields are only ever added,
+/// so snapshots of different versions stay comparable.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArchiveDescriptor {
+    /// Format version of the header
+    pub version: u8,
+    /// File names of the auxiliary volumes (Volume 1, Volume 2, ...)
+    pub volumes: Vec<String>,
+    /// Directory paths, excluding the implicit root directory (ID 0)
+    pub directories: Vec<String>,
+    pub files: Vec<FileDescriptor>,
+    /// Chunk table, indexed by chunk ID
+    pub chunks: Vec<ChunkDescriptor>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub comment: Option<String>,
+    /// Offset in the main file where chunk data ends (start of the trailer, or EOF)
+    pub main_data_end: u64,
+}
+
+/// A user file of an [`ArchiveDescriptor`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDescriptor {
+    /// Logical path with `/` separators
+    pub path: String,
+    /// ID of the directory holding the file (0 = root)
+    pub directory: u32,
+    /// IDs of the chunks holding the file's data, in order
+    pub chunks: Vec<u32>,
+    /// Decompressed size in bytes
+    pub size: u64,
+    /// Modification time in seconds since the Unix epoch, if the archive records it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mtime: Option<u64>,
+    /// Unix permission bits, if the archive records them
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub mode: Option<u32>,
+}
+
+/// A chunk table entry of an [`ArchiveDescriptor`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkDescriptor {
+    /// Volume holding the chunk (0 = main file)
+    pub volume: u16,
+    pub offset: u32,
+    pub compressed_length: u32,
+    pub decompressed_length: u32,
+    pub flags: u16,
+    /// Method named by the flags, if dzip-rs knows it
+    pub method: Option<CompressionMethod>,
+    /// CRC32 of the decompressed data, if the archive records it
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub crc: Option<u32>,
+}
+
+impl ArchiveDescriptor {
+    /// Describes the archive `metadata` was loaded from. Chunk lengths are taken as they
+    /// are in `metadata`, so correct them first to describe what readers decode.
+    pub fn new(metadata: &ArchiveMetadata) -> Result<Self> {
+        let crcs = metadata
+            .trailer
+            .as_ref()
+            .and_then(|t| t.chunk_crcs.as_ref());
+        let files = metadata
+            .file_map
+            .iter()
+            .enumerate()
+            .map(|(i, (directory, chunk_ids))| {
+                Ok(FileDescriptor {
+                    path: metadata.resolve_path(i)?,
+                    directory: *directory,
+                    chunks: chunk_ids.clone(),
+                    size: chunk_ids
+                        .iter()
+                        .filter_map(|&id| metadata.chunks.get(id as usize))
+                        .map(|c| c.decompressed_length as u64)
+                        .sum(),
+                    mtime: metadata.file_mtime(i),
+                    mode: metadata.file_mode(i),
+                })
+            })
+            .collect::<Result<_>>()?;
+        let chunks = metadata
+            .chunks
+            .iter()
+            .enumerate()
+            .map(|(id, chunk)| ChunkDescriptor {
+                volume: chunk.file,
+                offset: chunk.offset,
+                compressed_length: chunk.compressed_length,
+                decompressed_length: chunk.decompressed_length,
+                flags: chunk.flags,
+                method: CompressionMethod::from_flags(chunk.flags),
+                crc: crcs.and_then(|crcs| crcs.get(id).copied()),
+            })
+            .collect();
+        Ok(Self {
+            version: metadata.settings.version,
+            volumes: metadata.volume_files.clone(),
+            directories: metadata.directories.clone(),
+            files,
+            chunks,
+            comment: metadata.comment().map(str::to_string),
+            main_data_end: metadata.main_data_end,
+        })
+    }
+}
+
+/// Describes the archive whose main file is `archive_path`, with chunk lengths corrected
+/// against its volumes (see [`ArchiveMetadata::correct_chunk_sizes`]). Volumes are found
+/// like in [`crate::combine_volumes`]; missing ones leave their chunks as listed.
+///
+/// Names are read as UTF-8. No chunk data is read.
+pub fn describe(archive_path: &Path) -> Result<ArchiveDescriptor> {
+    let mut reader = DzipReader::new(File::open(archive_path)?);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+    let mut volumes = FileSystemVolumeManager::new(
+        archive_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    if let Some(name) = archive_path.file_name() {
+        volumes.set_numbered_fallback(&name.to_string_lossy());
+    }
+    metadata.correct_chunk_sizes(&mut volumes)?;
+    ArchiveDescriptor::new(&metadata)
+}
@@ -0,0 +1,147 @@
+use crate::error::{DzipError, Result};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Character encoding of the file and directory names stored in an archive.
+///
+/// Archives from Japanese or Chinese games often store names in a legacy code page
+/// rather than UTF-8. Some store them as UTF-16LE, terminated by two zero bytes instead of
+/// one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NameEncoding {
+    #[default]
+    Utf8,
+    ShiftJis,
+    Gbk,
+    Windows1252,
+    Utf16Le,
+}
+
+impl NameEncoding {
+    fn code_page(self) -> Option<&'static encoding_rs::Encoding> {
+        match self {
+            NameEncoding::Utf8 | NameEncoding::Utf16Le => None,
+            NameEncoding::ShiftJis => Some(encoding_rs::SHIFT_JIS),
+            NameEncoding::Gbk => Some(encoding_rs::GBK),
+            NameEncoding::Windows1252 => Some(encoding_rs::WINDOWS_1252),
+        }
+    }
+
+    /// The bytes ending each name: a zero code unit.
+    pub fn terminator(self) -> &'static [u8] {
+        match self {
+            NameEncoding::Utf16Le => &[0, 0],
+            _ => &[0],
+        }
+    }
+
+    /// Decodes a name read from the archive (without its terminator).
+    pub fn decode(self, bytes: Vec<u8>) -> Result<String> {
+        if self == NameEncoding::Utf16Le {
+            let invalid = || DzipError::Encoding(format!("Invalid UTF-16LE name: {:02x?}", bytes));
+            if !bytes.len().is_multiple_of(2) {
+                return Err(invalid());
+            }
+            let units = bytes
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+            return char::decode_utf16(units)
+                .collect::<std::result::Result<String, _>>()
+                .map_err(|_| invalid());
+        }
+        match self.code_page() {
+            None => Ok(String::from_utf8(bytes)?),
+            Some(code_page) => code_page
+                .decode_without_bom_handling_and_without_replacement(&bytes)
+                .map(|s| s.into_owned())
+                .ok_or_else(|| {
+                    DzipError::Encoding(format!(
+                        "Invalid {} name: {:02x?}",
+                        code_page.name(),
+                        bytes
+                    ))
+                }),
+        }
+    }
+
+    /// Encodes a name for writing to the archive (without its terminator).
+    pub fn encode(self, name: &str) -> Result<Vec<u8>> {
+        if self == NameEncoding::Utf16Le {
+            return Ok(name.encode_utf16().flat_map(u16::to_le_bytes).collect());
+        }
+        match self.code_page() {
+            None => Ok(name.as_bytes().to_vec()),
+            Some(code_page) => {
+                let (bytes, _, had_errors) = code_page.encode(name);
+                if had_errors {
+                    return Err(DzipError::Encoding(format!(
+                        "'{}' cannot be represented in {}",
+                        name,
+                        code_page.name()
+                    )));
+                }
+                Ok(bytes.into_owned())
+            }
+        }
+    }
+}
+
+impl FromStr for NameEncoding {
+    type Err = DzipError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_lowercase().replace(['-', '_'], "").as_str() {
+            "utf8" => Ok(NameEncoding::Utf8),
+            "shiftjis" | "sjis" | "cp932" => Ok(NameEncoding::ShiftJis),
+            "gbk" | "cp936" => Ok(NameEncoding::Gbk),
+            "windows1252" | "cp1252" => Ok(NameEncoding::Windows1252),
+            "utf16le" | "utf16" => Ok(NameEncoding::Utf16Le),
+            _ => Err(DzipError::Encoding(format!("Unknown name encoding: {}", s))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shift_jis_roundtrip() {
+        let name = "画像\\テクスチャ.bmp";
+        let bytes = NameEncoding::ShiftJis.encode(name).unwrap();
+        assert_ne!(bytes, name.as_bytes());
+        assert_eq!(NameEncoding::ShiftJis.decode(bytes.clone()).unwrap(), name);
+        // Shift-JIS bytes are not valid UTF-8
+        assert!(NameEncoding::Utf8.decode(bytes).is_err());
+    }
+
+    #[test]
+    fn test_utf16le_roundtrip() {
+        let name = "Données\\画像.bmp";
+        let bytes = NameEncoding::Utf16Le.encode(name).unwrap();
+        assert_eq!(&bytes[..4], b"D\0o\0");
+        assert_eq!(bytes.len(), name.encode_utf16().count() * 2);
+        assert_eq!(NameEncoding::Utf16Le.decode(bytes).unwrap(), name);
+        // Odd lengths and unpaired surrogates
+        assert!(NameEncoding::Utf16Le.decode(vec![b'a']).is_err());
+        assert!(NameEncoding::Utf16Le.decode(vec![0x00, 0xd8]).is_err());
+    }
+
+    #[test]
+    fn test_unrepresentable_name() {
+        assert!(NameEncoding::Windows1252.encode("テスト").is_err());
+    }
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!(
+            "Shift_JIS".parse::<NameEncoding>().unwrap(),
+            NameEncoding::ShiftJis
+        );
+        assert_eq!(
+            "cp1252".parse::<NameEncoding>().unwrap(),
+            NameEncoding::Windows1252
+        );
+        assert!("ebcdic".parse::<NameEncoding>().is_err());
+    }
+}
@@ -9,20 +9,95 @@ pub enum DzipError {
     #[error("Invalid DTRZ header")]
     InvalidHeader,
 
+    #[error("Not a dzip archive: magic {0:#010x}, expected 0x5a525444")]
+    InvalidMagic(u32),
+
+    #[error("Archive truncated in {0}")]
+    TruncatedSection(&'static str),
+
+    #[error("Invalid archive trailer")]
+    InvalidTrailer,
+
     #[error("Unsupported version: {0}")]
     UnsupportedVersion(u8),
 
     #[error("UTF-8 error: {0}")]
     Utf8(#[from] std::string::FromUtf8Error),
 
+    #[error("Encoding error: {0}")]
+    Encoding(String),
+
+    #[error("Decompression failed: {0}")]
+    Decompression(String),
+
+    #[error("Invalid configuration: {0}")]
+    Config(String),
+
     #[error("Unsupported compression method: flags={0:#x}")]
     UnsupportedCompression(u16),
 
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    #[error("Unsafe path rejected: {0}")]
+    Security(String),
+
+    #[error("File not found in archive: {0}")]
+    FileNotFound(String),
+
+    #[error("Path matches more than one file in archive: {0}")]
+    AmbiguousPath(String),
+
+    #[error("File already exists in archive: {0}")]
+    DuplicateFile(String),
+
+    #[error("File {index} not found, the archive has {num_files} file(s)")]
+    FileIndexOutOfRange { index: usize, num_files: usize },
+
+    #[error("Chunk {0} not found in chunk table")]
+    ChunkNotFound(u32),
+
+    #[error(
+        "Chunk {chunk} starts at offset {offset}, past the end of volume {volume} ({volume_len} bytes)"
+    )]
+    ChunkOutOfBounds {
+        chunk: u32,
+        volume: u16,
+        offset: u32,
+        volume_len: u64,
+    },
+
+    #[error(
+        "Chunk {chunk} is stored in volume {volume}, but the archive only has {num_volumes} volume(s)"
+    )]
+    ChunkVolumeOutOfRange {
+        chunk: u32,
+        volume: u16,
+        num_volumes: u16,
+    },
+
+    #[error("Chunk {chunk} overlaps chunk {next} in volume {volume}")]
+    ChunkOverlap { chunk: u32, next: u32, volume: u16 },
+
+    #[error(
+        "Volume {0} would exceed 4 GiB, which chunk offsets cannot address; split the archive into smaller volumes"
+    )]
+    VolumeTooLarge(u16),
+
+    #[error("Chunk of {0} bytes exceeds the 4 GiB limit")]
+    ChunkTooLarge(u64),
+
     #[error("Volume {0} not found in file list")]
     VolumeNotFound(u16),
 
     #[error("Failed to open volume {0}: {1}")]
     VolumeOpenError(u16, String),
+
+    #[error("Encryption error: {0}")]
+    Encryption(String),
+
+    #[error("Operation cancelled")]
+    Cancelled,
 }
 
 pub type Result<T> = std::result::Result<T, DzipError>;
@@ -13,26 +13,212 @@
 //! - Various global decoder settings...
 //!
 //! - File data
+//!
+//! Version 1 (dzip-rs) lifts the 65535 limit on files, directories and chunks:
+//! - ArchiveSettings is followed by NumUserFiles and NumDirectories as u32 (the u16
+//!   fields before the version hold the counts saturated to 65535)
+//! - Directory and chunk IDs in the User-File list are u32, terminated by 0xFFFFFFFF
+//! - ChunkSettings.NumChunks is a u32
+//!
+//! dzip-rs may additionally append an optional trailer to the main file, after all chunk
+//! data (see [`ArchiveTrailer`]). Readers that only follow chunk offsets never see it.
+//!
+//! The version only changes with the header layout, and readers pick the layout of each
+//! section from it (see [`FormatVersion`]). Optional data such as checksums and
+//! modification times goes into trailer sections instead, which readers skip if they do
+//! not know them, so archives using them stay readable as version 0. Older archives are
+//! rewritten to the newest version with [`crate::upgrade`].
+
+use crate::error::DzipError;
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ArchiveSettings {
     /// Identification 'DTRZ'
     pub header: u32,
     /// Number of original user-files stored in this archive
-    pub num_user_files: u16,
+    pub num_user_files: u32,
     /// Number of stored directories.
     /// Note: The first directory is always the root directory.
-    pub num_directories: u16,
+    pub num_directories: u32,
     /// Version ID of this settings structure
     pub version: u8,
 }
 
+/// 'DTRZ' in little endian, the first four bytes of every main file
+pub const ARCHIVE_MAGIC: u32 = 0x5A525444;
+
+/// Byte order of the numbers in the header.
+///
+/// Archives are little-endian, but some console builds of the format store the header
+/// big-endian. The dzip-rs trailer is always little-endian.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
+}
+
+impl Endian {
+    fn swapped(self) -> Self {
+        match self {
+            Endian::Little => Endian::Big,
+            Endian::Big => Endian::Little,
+        }
+    }
+
+    /// The byte order in which the magic number reads as `magic`, judging from reading it
+    /// in `self` order.
+    pub(crate) fn detect(self, magic: u32) -> Option<Self> {
+        if magic == ARCHIVE_MAGIC {
+            Some(self)
+        } else if magic.swap_bytes() == ARCHIVE_MAGIC {
+            Some(self.swapped())
+        } else {
+            None
+        }
+    }
+}
+
+/// Order of the file and directory names in the string table.
+///
+/// Archives list the file names first, but some variants list the directories first.
+/// Nothing in the header tells them apart, so readers detect the layout with
+/// [`Self::detect`] and writers keep it in [`crate::ArchiveMetadata::string_layout`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StringLayout {
+    #[default]
+    FilesFirst,
+    DirectoriesFirst,
+}
+
+impl StringLayout {
+    /// Splits a string table holding `num_user_files` file names into the file names and
+    /// the directory names.
+    pub fn split(
+        self,
+        mut strings: Vec<String>,
+        num_user_files: usize,
+    ) -> (Vec<String>, Vec<String>) {
+        let num_user_files = num_user_files.min(strings.len());
+        match self {
+            StringLayout::FilesFirst => {
+                let directories = strings.split_off(num_user_files);
+                (strings, directories)
+            }
+            StringLayout::DirectoriesFirst => {
+                let files = strings.split_off(strings.len() - num_user_files);
+                (files, strings)
+            }
+        }
+    }
+
+    /// The layout of a string table holding `num_user_files` file names.
+    ///
+    /// File names cannot hold path separators, so a table is only taken as
+    /// [`Self::DirectoriesFirst`] if reading it files first would give a file name with a
+    /// separator and reading it directories first would not. Otherwise it is
+    /// [`Self::FilesFirst`], even if the directories come first but are all top-level.
+    pub fn detect(strings: &[String], num_user_files: usize) -> Self {
+        let num_user_files = num_user_files.min(strings.len());
+        let has_separator = |names: &[String]| names.iter().any(|name| name.contains(['\\', '/']));
+        if has_separator(&strings[..num_user_files])
+            && !has_separator(&strings[strings.len() - num_user_files..])
+        {
+            StringLayout::DirectoriesFirst
+        } else {
+            StringLayout::FilesFirst
+        }
+    }
+}
+
+/// Original format with 16-bit counts and IDs
+pub const VERSION_LEGACY: u8 = 0;
+/// dzip-rs format with 32-bit counts and IDs
+pub const VERSION_WIDE: u8 = 1;
+
+/// The header layouts dzip-rs reads and writes, identified by the version byte of
+/// [`ArchiveSettings`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FormatVersion {
+    /// [`VERSION_LEGACY`], readable by the original tools
+    Legacy,
+    /// [`VERSION_WIDE`]
+    Wide,
+}
+
+impl FormatVersion {
+    /// The newest version dzip-rs knows.
+    pub const LATEST: Self = Self::Wide;
+
+    /// Whether counts and IDs are stored as u32.
+    pub fn is_wide(self) -> bool {
+        self == Self::Wide
+    }
+
+    /// Size in bytes of a directory or chunk ID, and of the counts stored like them.
+    pub fn id_len(self) -> usize {
+        match self {
+            Self::Legacy => 2,
+            Self::Wide => 4,
+        }
+    }
+
+    /// The ID ending the chunk list of a file in the file map.
+    pub fn id_terminator(self) -> u32 {
+        match self {
+            Self::Legacy => 0xFFFF,
+            Self::Wide => 0xFFFF_FFFF,
+        }
+    }
+}
+
+impl TryFrom<u8> for FormatVersion {
+    type Error = DzipError;
+
+    fn try_from(version: u8) -> Result<Self, DzipError> {
+        match version {
+            VERSION_LEGACY => Ok(Self::Legacy),
+            VERSION_WIDE => Ok(Self::Wide),
+            _ => Err(DzipError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+impl From<FormatVersion> for u8 {
+    fn from(version: FormatVersion) -> u8 {
+        match version {
+            FormatVersion::Legacy => VERSION_LEGACY,
+            FormatVersion::Wide => VERSION_WIDE,
+        }
+    }
+}
+
+impl ArchiveSettings {
+    /// Whether counts and IDs are stored as u32.
+    pub fn is_wide(&self) -> bool {
+        self.version == VERSION_WIDE
+    }
+
+    /// The header layout of the archive. Fails for versions dzip-rs does not know.
+    pub fn format_version(&self) -> Result<FormatVersion, DzipError> {
+        FormatVersion::try_from(self.version)
+    }
+}
+
+/// Whether an archive of this size can only be stored in the [`VERSION_WIDE`] format.
+///
+/// Chunk ID 0xFFFF is the file map terminator in version 0, so at most 0xFFFF chunks fit.
+pub fn needs_wide_format(num_user_files: usize, num_directories: usize, num_chunks: usize) -> bool {
+    num_user_files > u16::MAX as usize || num_directories > u16::MAX as usize || num_chunks > 0xFFFF
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct ChunkSettings {
     /// Number of files used to store this archive
     pub num_archive_files: u16,
     /// Number of chunks they're divided up into
-    pub num_chunks: u16,
+    pub num_chunks: u32,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -61,8 +247,29 @@ pub const CHUNK_ZERO: u16 = 0x80; // Set to indicate a zerod-out chunk
 pub const CHUNK_COPYCOMP: u16 = 0x100; // Set to indicate a copy-coded (ie no compression) chunk
 pub const CHUNK_LZMA: u16 = 0x200; // Set to indicate a lzma encoded chunk
 pub const CHUNK_RANDOMACCESS: u16 = 0x400; // Set to indicate whole chunk should be buffered for random access
+pub const CHUNK_ZSTD: u16 = 0x800; // dzip-rs extension: set to indicate a zstd chunk
+pub const CHUNK_ENCRYPTED: u16 = 0x4000; // dzip-rs extension: set to indicate encrypted stored data
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// All chunk flags dzip-rs knows. Chunks with other bits set use a method it cannot read,
+/// unless a codec registered for them claims the chunk.
+pub const CHUNK_KNOWN_FLAGS: u16 = CHUNK_COMBUF
+    | CHUNK_DZ
+    | CHUNK_ZLIB
+    | CHUNK_BZIP
+    | CHUNK_MP3
+    | CHUNK_JPEG
+    | CHUNK_ZERO
+    | CHUNK_COPYCOMP
+    | CHUNK_LZMA
+    | CHUNK_RANDOMACCESS
+    | CHUNK_ZSTD
+    | CHUNK_ENCRYPTED;
+
+/// Settings of the range decoder of DZ chunks, stored after the volume list.
+///
+/// The default, all zeros, is what [`crate::PackBuilder`] writes, and what archives that
+/// omit the block are read with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct RangeSettings {
     /// log2(LZ-77 window size)
     pub win_size: u8,
@@ -85,3 +292,59 @@ pub struct RangeSettings {
     /// minimum match length for external references
     pub big_min_match: u8,
 }
+
+/// Size in bytes of the [`RangeSettings`] block
+pub const RANGE_SETTINGS_LEN: u64 = 10;
+
+/// Identification 'DZTR' of the optional dzip-rs trailer
+pub const TRAILER_MAGIC: u32 = 0x52545A44;
+/// Trailer section holding one CRC32 per chunk
+pub const TRAILER_TAG_CHUNK_CRC32: u32 = 1;
+/// Trailer section holding one modification time (u64) per user file
+pub const TRAILER_TAG_FILE_MTIME: u32 = 2;
+/// Trailer section holding a free-form UTF-8 comment
+pub const TRAILER_TAG_COMMENT: u32 = 3;
+/// Trailer section holding the [`EncryptionParams`] of an encrypted archive
+pub const TRAILER_TAG_ENCRYPTION: u32 = 4;
+/// Trailer section holding the CRC32 of the header (u32)
+pub const TRAILER_TAG_HEADER_CRC32: u32 = 5;
+/// Trailer section holding one Unix permission mode (u32) per user file
+pub const TRAILER_TAG_FILE_MODE: u32 = 6;
+/// Permission bits stored and restored by dzip-rs. The setuid, setgid and sticky bits are
+/// dropped, so that archives from untrusted sources cannot create setuid executables.
+pub const FILE_MODE_BITS: u32 = 0o777;
+
+/// Optional extension data stored at the end of the main archive file.
+///
+/// Layout:
+/// - Sections (tag: u32, length: u32, payload)
+/// - Total length of the sections: u32
+/// - Identification 'DZTR'
+///
+/// Unknown sections are skipped, so new ones can be added without breaking older readers.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveTrailer {
+    /// CRC32 of each chunk's decompressed data, indexed by chunk ID
+    pub chunk_crcs: Option<Vec<u32>>,
+    /// Modification time of each user file in seconds since the Unix epoch, indexed by
+    /// file. 0 if unknown.
+    pub file_mtimes: Option<Vec<u64>>,
+    /// Free-form comment about the archive
+    pub comment: Option<String>,
+    /// Present if chunks are encrypted (see [`crate::crypto`])
+    pub encryption: Option<EncryptionParams>,
+    /// CRC32 of the header, everything in the main file before the chunk data
+    pub header_crc: Option<u32>,
+    /// Unix permission bits of each user file (e.g. `0o755`), indexed by file. 0 if
+    /// unknown.
+    pub file_modes: Option<Vec<u32>>,
+}
+
+/// Salt and key check of an encrypted archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptionParams {
+    /// Random value the archive key is derived from
+    pub salt: [u8; 16],
+    /// Encryption of a zero block with the archive key, to detect wrong keys
+    pub key_check: [u8; 16],
+}
@@ -1,13 +1,40 @@
+pub mod append;
+pub mod archive;
+pub mod codec;
+pub mod combine;
+pub mod crypto;
+pub mod describe;
+pub mod encoding;
 pub mod error;
 pub mod format;
+pub mod pack;
 pub mod path;
+pub mod progress;
 pub mod reader;
+mod rewrite;
+pub mod transcode;
 pub mod volume;
 pub mod writer;
 
+pub use append::{NewFile, append_files};
+pub use archive::{ArchiveFile, ArchiveMetadata, LazyArchiveMetadata, try_load_metadata};
+pub use codec::{Codec, CodecRegistry};
+pub use combine::{combine_volumes, upgrade};
+pub use crypto::{ChunkCipher, EncryptionKey};
+pub use describe::{ArchiveDescriptor, ChunkDescriptor, FileDescriptor, describe};
+pub use encoding::NameEncoding;
 pub use error::{DzipError, Result};
-pub use format::{ArchiveSettings, Chunk, ChunkSettings, RangeSettings};
-pub use writer::{CompressionMethod, compress_data};
+pub use format::{
+    ArchiveSettings, ArchiveTrailer, Chunk, ChunkSettings, Endian, FormatVersion, RangeSettings,
+    StringLayout,
+};
+pub use pack::{PackBuilder, PackEstimate, PackReport};
+pub use progress::ProgressEvent;
+pub use transcode::transcode;
+pub use writer::{
+    CompressOptions, CompressedStream, CompressionMethod, LzmaVariant, checksum, compress_data,
+    compress_data_with, compress_stream,
+};
 
 // #[cfg(test)]
 // mod tests;
@@ -0,0 +1,1327 @@
+//! Building new archives from a list of files.
+
+use crate::archive::ArchiveMetadata;
+use crate::codec::CodecRegistry;
+use crate::crypto::{ChunkCipher, EncryptionKey};
+use crate::encoding::NameEncoding;
+use crate::error::{DzipError, Result};
+use crate::format::*;
+use crate::progress::ProgressEvent;
+use crate::writer::{
+    CompressOptions, CompressionMethod, DzipWriter, HashingReader, LzmaVariant, WriteSeek,
+    checksum, chunk_length, chunk_offset, compress_stream,
+};
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Name of the main file if none is set with [`PackBuilder::archive_names`].
+pub const DEFAULT_ARCHIVE_NAME: &str = "archive.dz";
+
+/// Destination of the volumes written by [`PackBuilder`].
+pub trait PackSink {
+    /// Creates the volume with the given index (0 is the main file) on first use and
+    /// returns it on later calls. `name` is the volume's name in the archive's file list.
+    fn open_volume(&mut self, id: u16, name: &str) -> Result<&mut dyn WriteSeek>;
+}
+
+/// Where the input files of a pack come from.
+///
+/// Paths are the relative paths listed in the pack config.
+pub trait PackSource: Sync {
+    fn exists(&self, path: &Path) -> bool;
+
+    fn open_file(&self, path: &Path) -> Result<Box<dyn Read + Send + '_>>;
+
+    /// Modification time in seconds since the Unix epoch, if known.
+    fn modified(&self, _path: &Path) -> Option<u64> {
+        None
+    }
+
+    /// Unix permission bits of a file (e.g. `0o755`), if known.
+    fn mode(&self, _path: &Path) -> Option<u32> {
+        None
+    }
+
+    /// Size of a file in bytes. Reads the whole file unless overridden.
+    fn file_len(&self, path: &Path) -> Result<u64> {
+        Ok(std::io::copy(
+            &mut self.open_file(path)?,
+            &mut std::io::sink(),
+        )?)
+    }
+}
+
+/// A pack source reading files below a base directory.
+pub struct FileSystemPackSource {
+    base_dir: PathBuf,
+}
+
+impl FileSystemPackSource {
+    pub fn new(base_dir: PathBuf) -> Self {
+        Self { base_dir }
+    }
+}
+
+impl PackSource for FileSystemPackSource {
+    fn exists(&self, path: &Path) -> bool {
+        self.base_dir.join(path).is_file()
+    }
+
+    fn open_file(&self, path: &Path) -> Result<Box<dyn Read + Send + '_>> {
+        let full_path = self.base_dir.join(path);
+        debug!("Reading {}", full_path.display());
+        let file = std::fs::File::open(&full_path).map_err(|e| {
+            DzipError::Io(std::io::Error::new(
+                e.kind(),
+                format!("Failed to read {}: {}", full_path.display(), e),
+            ))
+        })?;
+        Ok(Box::new(file))
+    }
+
+    fn modified(&self, path: &Path) -> Option<u64> {
+        std::fs::metadata(self.base_dir.join(path))
+            .and_then(|m| m.modified())
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+    }
+
+    #[cfg(unix)]
+    fn mode(&self, path: &Path) -> Option<u32> {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(self.base_dir.join(path))
+            .ok()?
+            .permissions()
+            .mode();
+        Some(mode & FILE_MODE_BITS)
+    }
+
+    fn file_len(&self, path: &Path) -> Result<u64> {
+        Ok(std::fs::metadata(self.base_dir.join(path))?.len())
+    }
+}
+
+/// A pack source serving files from memory, e.g. generated assets or a virtual filesystem.
+#[derive(Default)]
+pub struct MemoryPackSource {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryPackSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert(&mut self, path: impl Into<PathBuf>, data: Vec<u8>) {
+        self.files.insert(path.into(), data);
+    }
+
+    fn get(&self, path: &Path) -> std::io::Result<&Vec<u8>> {
+        self.files.get(path).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Source file not found: {}", path.display()),
+            )
+        })
+    }
+}
+
+impl PackSource for MemoryPackSource {
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(path)
+    }
+
+    fn open_file(&self, path: &Path) -> Result<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(self.get(path)?.as_slice()))
+    }
+
+    fn file_len(&self, path: &Path) -> Result<u64> {
+        Ok(self.get(path)?.len() as u64)
+    }
+}
+
+/// A file queued for packing.
+struct PackEntry<'a> {
+    path: String,
+    input: PackInput<'a>,
+    compression: CompressionMethod,
+    options: CompressOptions,
+    /// Flag of a registered codec overriding `compression`
+    codec: Option<u16>,
+    /// Set on the chunks in addition to the flags of the method
+    extra_flags: u16,
+    volume: u16,
+    mtime: Option<u64>,
+    mode: Option<u32>,
+    /// Listed without any chunk instead of an empty Zero chunk
+    no_chunks: bool,
+}
+
+/// Where the data of a queued file comes from.
+enum PackInput<'a> {
+    Data(Vec<u8>),
+    /// Opened and streamed through the compressor when the archive is built
+    Source(&'a dyn PackSource, PathBuf),
+}
+
+/// A file's data after the compression phase, before it is assigned to a volume.
+struct CompressedFile {
+    volume: u16,
+    data: Vec<u8>,
+    original_len: u32,
+    flags: u16,
+    /// CRC32 of the uncompressed data
+    crc: u32,
+    no_chunks: bool,
+}
+
+/// Builds an archive from files held in memory or read from a [`PackSource`].
+///
+/// Files become one chunk each, in the order they are added (see [`Self::no_chunks`] for
+/// the exception): chunk IDs follow file order, and each volume holds its chunks in ID
+/// order. Files are compressed in parallel, but the order does not depend on which
+/// finishes first. The compressed chunks are kept in memory until the volumes are written.
+/// Directories are assigned IDs in the byte order of their paths, so packing the same files
+/// in the same order always gives the same archive. The main file gets a trailer with chunk
+/// CRCs and, if set, file modification times.
+///
+/// ```no_run
+/// use dzip_core::CompressionMethod;
+/// use dzip_core::pack::PackBuilder;
+/// use dzip_core::volume::MemoryPackSink;
+///
+/// let mut sink = MemoryPackSink::new();
+/// let metadata = PackBuilder::new(&mut sink)
+///     .add_file("textures/a.png", vec![0; 16], CompressionMethod::Copy)
+///     .add_file("readme.txt", b"hello".to_vec(), CompressionMethod::Zlib)
+///     .volume_limit(64 * 1024 * 1024)
+///     .build()?;
+/// # Ok::<(), dzip_core::DzipError>(())
+/// ```
+pub struct PackBuilder<'a> {
+    sink: &'a mut dyn PackSink,
+    archives: Vec<String>,
+    entries: Vec<PackEntry<'a>>,
+    volume_limit: Option<u64>,
+    dedup: bool,
+    name_encoding: NameEncoding,
+    string_layout: StringLayout,
+    version: Option<FormatVersion>,
+    comment: Option<String>,
+    encryption_key: Option<EncryptionKey>,
+    header_checksum: bool,
+    allow_duplicates: bool,
+    codecs: Arc<CodecRegistry>,
+    on_progress: &'a (dyn Fn(ProgressEvent) + Sync),
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a> PackBuilder<'a> {
+    pub fn new(sink: &'a mut dyn PackSink) -> Self {
+        Self {
+            sink,
+            archives: vec![DEFAULT_ARCHIVE_NAME.to_string()],
+            entries: Vec::new(),
+            volume_limit: None,
+            dedup: false,
+            name_encoding: NameEncoding::default(),
+            string_layout: StringLayout::default(),
+            version: None,
+            comment: None,
+            encryption_key: None,
+            header_checksum: false,
+            allow_duplicates: false,
+            codecs: Arc::new(CodecRegistry::new()),
+            on_progress: &|_| {},
+            cancel: None,
+        }
+    }
+
+    /// Names of the volumes, starting with the main file.
+    ///
+    /// Without a volume limit these are exactly the volumes written. With a limit, further
+    /// volumes are named `name.d01`, `name.d02`, ... after the main file.
+    pub fn archive_names(mut self, names: Vec<String>) -> Self {
+        self.archives = names;
+        self
+    }
+
+    /// Adds a file to the main file (Volume 0). Either separator is accepted in `logical_path`.
+    pub fn add_file(
+        self,
+        logical_path: &str,
+        data: Vec<u8>,
+        compression: CompressionMethod,
+    ) -> Self {
+        self.add_file_to_volume(logical_path, data, compression, 0)
+    }
+
+    /// Adds a file to the given volume. The volume is ignored if a volume limit is set.
+    pub fn add_file_to_volume(
+        self,
+        logical_path: &str,
+        data: Vec<u8>,
+        compression: CompressionMethod,
+        volume: u16,
+    ) -> Self {
+        self.push_entry(logical_path, PackInput::Data(data), compression, volume)
+    }
+
+    /// Adds the file `source_path` of `source` to the main file. The file is only opened
+    /// when the archive is built and is compressed as it is read, so its uncompressed data
+    /// is never held in memory.
+    pub fn add_source_file(
+        self,
+        logical_path: &str,
+        source: &'a dyn PackSource,
+        source_path: &Path,
+        compression: CompressionMethod,
+    ) -> Self {
+        self.add_source_file_to_volume(logical_path, source, source_path, compression, 0)
+    }
+
+    /// Like [`Self::add_source_file`], for the given volume.
+    pub fn add_source_file_to_volume(
+        self,
+        logical_path: &str,
+        source: &'a dyn PackSource,
+        source_path: &Path,
+        compression: CompressionMethod,
+        volume: u16,
+    ) -> Self {
+        let input = PackInput::Source(source, source_path.to_path_buf());
+        self.push_entry(logical_path, input, compression, volume)
+    }
+
+    fn push_entry(
+        mut self,
+        logical_path: &str,
+        input: PackInput<'a>,
+        compression: CompressionMethod,
+        volume: u16,
+    ) -> Self {
+        self.entries.push(PackEntry {
+            path: logical_path.to_string(),
+            input,
+            compression,
+            options: CompressOptions::default(),
+            codec: None,
+            extra_flags: 0,
+            volume,
+            mtime: None,
+            mode: None,
+            no_chunks: false,
+        });
+        self
+    }
+
+    /// Sets the modification time of the file added last, in seconds since the Unix epoch.
+    ///
+    /// Modification times are stored in the trailer if any file has one.
+    pub fn mtime(mut self, secs: u64) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.mtime = Some(secs);
+        }
+        self
+    }
+
+    /// Sets the Unix permission bits of the file added last, e.g. `0o755`. Bits outside
+    /// [`FILE_MODE_BITS`] are dropped.
+    ///
+    /// Permissions are stored in the trailer if any file has them. Readers that do not
+    /// know the section ignore it.
+    pub fn mode(mut self, mode: u32) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.mode = Some(mode & FILE_MODE_BITS);
+        }
+        self
+    }
+
+    /// Lists the file added last with no chunks at all, as some archives do for empty
+    /// files. By default, empty files get one empty Zero chunk. Building fails if the file
+    /// is not empty.
+    pub fn no_chunks(mut self) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.no_chunks = true;
+        }
+        self
+    }
+
+    /// Sets the LZMA framing of the file added last, if it is compressed with LZMA.
+    pub fn lzma_variant(mut self, variant: LzmaVariant) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.options.lzma_variant = variant;
+        }
+        self
+    }
+
+    /// Sets the Bzip2 level (1 to 9) of the file added last, if it is compressed with Bzip.
+    pub fn bzip_level(mut self, level: u32) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.options.bzip_level = Some(level);
+        }
+        self
+    }
+
+    /// Compresses the file added last with the codec registered for `flag` (see
+    /// [`Self::codecs`]) instead of its compression method.
+    pub fn codec(mut self, flag: u16) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.codec = Some(flag);
+        }
+        self
+    }
+
+    /// Sets `flags` on the chunks of the file added last, in addition to the flags of its
+    /// compression method, e.g. [`CHUNK_RANDOMACCESS`] on Zlib chunks as some archives do.
+    /// The data is not changed. Building fails if `flags` holds bits dzip-rs does not know
+    /// or [`CHUNK_ENCRYPTED`]. Empty files still get a plain Zero chunk.
+    pub fn extra_flags(mut self, flags: u16) -> Self {
+        if let Some(entry) = self.entries.last_mut() {
+            entry.extra_flags = flags;
+        }
+        self
+    }
+
+    /// Sets the codecs used to compress files (the built-in ones by default).
+    pub fn codecs(mut self, codecs: Arc<CodecRegistry>) -> Self {
+        self.codecs = codecs;
+        self
+    }
+
+    /// Splits the output into volumes of at most `max_volume_size` bytes.
+    pub fn volume_limit(mut self, max_volume_size: u64) -> Self {
+        self.volume_limit = Some(max_volume_size);
+        self
+    }
+
+    /// Stores byte-identical files only once.
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Sets the encoding of the names stored in the archive (UTF-8 by default).
+    pub fn name_encoding(mut self, encoding: NameEncoding) -> Self {
+        self.name_encoding = encoding;
+        self
+    }
+
+    /// Sets the order of the names in the string table (files first by default).
+    pub fn string_layout(mut self, layout: StringLayout) -> Self {
+        self.string_layout = layout;
+        self
+    }
+
+    /// Forces a format version instead of picking the smallest one that fits.
+    pub fn version(mut self, version: FormatVersion) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    /// Stores a free-form comment in the archive trailer.
+    pub fn comment(mut self, comment: impl Into<String>) -> Self {
+        self.comment = Some(comment.into());
+        self
+    }
+
+    /// Encrypts the stored data of all chunks with `key` (see [`crate::crypto`]). Needs the
+    /// `encryption` feature.
+    pub fn encryption_key(mut self, key: EncryptionKey) -> Self {
+        self.encryption_key = Some(key);
+        self
+    }
+
+    /// Stores a CRC32 of the header in the trailer, so that edits to the file, directory
+    /// and chunk tables can be detected without reading any chunk data (see
+    /// [`ArchiveMetadata::check_header_crc`]).
+    pub fn header_checksum(mut self, header_checksum: bool) -> Self {
+        self.header_checksum = header_checksum;
+        self
+    }
+
+    /// Allows several files with the same path. By default [`Self::build`] fails with
+    /// [`DzipError::Config`] before compressing anything, as extracting such an archive
+    /// keeps only one of them.
+    pub fn allow_duplicates(mut self, allow_duplicates: bool) -> Self {
+        self.allow_duplicates = allow_duplicates;
+        self
+    }
+
+    /// Is told the number of files to pack, then receives one `Inc(1)` per file written to
+    /// the sink and `Finish` once the archive is written.
+    pub fn on_progress(mut self, on_progress: &'a (dyn Fn(ProgressEvent) + Sync)) -> Self {
+        self.on_progress = on_progress;
+        self
+    }
+
+    /// Stops packing with [`DzipError::Cancelled`] once `cancel` is set, e.g. from another
+    /// thread. The flag is checked before each file is compressed and before the archive is
+    /// written, so nothing is written after a cancellation during compression.
+    pub fn cancel_flag(mut self, cancel: &'a AtomicBool) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Compresses all files and writes the archive to the sink.
+    ///
+    /// Returns the metadata of the written archive.
+    pub fn build(self) -> Result<ArchiveMetadata> {
+        let Self {
+            sink,
+            archives: configured_archives,
+            entries,
+            volume_limit,
+            dedup,
+            name_encoding,
+            string_layout,
+            version,
+            comment,
+            encryption_key,
+            header_checksum,
+            allow_duplicates,
+            codecs,
+            on_progress,
+            cancel,
+        } = self;
+        let check_cancel = || match cancel {
+            Some(cancel) if cancel.load(Ordering::Relaxed) => Err(DzipError::Cancelled),
+            _ => Ok(()),
+        };
+
+        if configured_archives.is_empty() {
+            return Err(DzipError::Config("No archives specified".to_string()));
+        }
+        if volume_limit == Some(0) {
+            return Err(DzipError::Config(
+                "max_volume_size must be greater than zero".to_string(),
+            ));
+        }
+        if !allow_duplicates {
+            let duplicates = duplicate_paths(entries.iter().map(|entry| entry.path.as_str()));
+            if !duplicates.is_empty() {
+                let list: Vec<String> = duplicates
+                    .iter()
+                    .map(|(path, count)| format!("{} ({} files)", path, count))
+                    .collect();
+                return Err(DzipError::Config(format!(
+                    "Duplicate paths: {}",
+                    list.join(", ")
+                )));
+            }
+        }
+        let cipher = encryption_key
+            .map(|key| ChunkCipher::generate(&key))
+            .transpose()?;
+
+        // --- Prepare Metadata ---
+        let NameTables {
+            user_files,
+            directories,
+            file_dir_ids,
+        } = name_tables(&entries)?;
+        let file_mtimes = file_mtimes(&entries);
+        let file_modes = file_modes(&entries);
+
+        // --- Compress in Parallel ---
+        info!("Compressing chunks in parallel...");
+        on_progress(ProgressEvent::Start(entries.len() as u64));
+        let processed_files: Vec<CompressedFile> = entries
+            .into_par_iter()
+            .map(|entry| {
+                check_cancel()?;
+                if entry.extra_flags & (CHUNK_ENCRYPTED | !CHUNK_KNOWN_FLAGS) != 0 {
+                    return Err(DzipError::Config(format!(
+                        "{}: cannot set chunk flags {:#x}",
+                        entry.path, entry.extra_flags
+                    )));
+                }
+                debug!("Compressing {}", entry.path);
+                let mut input = HashingReader::new(match &entry.input {
+                    PackInput::Data(data) => Box::new(data.as_slice()),
+                    PackInput::Source(source, path) => source.open_file(path)?,
+                });
+                let mut data = Vec::new();
+                let mut flags =
+                    compress_entry(&entry, &codecs, &mut input, &mut data)? | entry.extra_flags;
+                let original_len = input.len();
+                // An empty stream of e.g. Zlib still has a header; empty files are stored as
+                // zero chunks whatever their method
+                if original_len == 0 {
+                    data.clear();
+                    flags = CHUNK_ZERO;
+                } else if entry.no_chunks {
+                    return Err(DzipError::Config(format!(
+                        "{} is {} bytes long, but only empty files can have no chunks",
+                        entry.path, original_len
+                    )));
+                }
+                Ok(CompressedFile {
+                    volume: entry.volume,
+                    data,
+                    original_len: u32::try_from(original_len)
+                        .map_err(|_| DzipError::ChunkTooLarge(original_len))?,
+                    flags,
+                    crc: input.crc(),
+                    no_chunks: entry.no_chunks,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        check_cancel()?;
+        info!("Compression complete");
+
+        // --- Deduplicate ---
+        let duplicate_of = if dedup {
+            find_duplicates(&processed_files)
+        } else {
+            vec![None; processed_files.len()]
+        };
+        let num_duplicates = duplicate_of.iter().filter(|d| d.is_some()).count();
+        if num_duplicates > 0 {
+            info!(
+                "Deduplicated {} of {} files",
+                num_duplicates,
+                processed_files.len()
+            );
+        }
+        let num_chunks = processed_files
+            .iter()
+            .zip(&duplicate_of)
+            .filter(|(file, dup)| !file.no_chunks && dup.is_none())
+            .count();
+
+        // --- Pick Format Version ---
+        let version = pick_version(version, &user_files, &directories, num_chunks)?;
+        // --- Build Metadata ---
+        // Everything but the location of the chunks is known now. The header and trailer
+        // are encoded up front to learn their size; the header does not change size once
+        // the chunk offsets are filled in.
+        let mut chunks = Vec::with_capacity(num_chunks);
+        let mut chunk_crcs = Vec::with_capacity(num_chunks);
+        let mut file_map = Vec::with_capacity(processed_files.len());
+        let mut file_chunk_ids = Vec::with_capacity(processed_files.len());
+        for (i, file) in processed_files.iter().enumerate() {
+            let chunk_id = match duplicate_of[i] {
+                _ if file.no_chunks => None,
+                Some(original) => file_chunk_ids[original],
+                None => {
+                    // Zero chunks store nothing to encrypt
+                    let encrypted = cipher.is_some() && !file.data.is_empty();
+                    chunks.push(Chunk {
+                        offset: 0,
+                        compressed_length: chunk_length(file.data.len())?,
+                        decompressed_length: file.original_len,
+                        flags: if encrypted {
+                            file.flags | CHUNK_ENCRYPTED
+                        } else {
+                            file.flags
+                        },
+                        file: 0,
+                    });
+                    chunk_crcs.push(file.crc);
+                    Some(chunks.len() as u32 - 1)
+                }
+            };
+            file_chunk_ids.push(chunk_id);
+            file_map.push((file_dir_ids[i], chunk_id.into_iter().collect()));
+        }
+
+        let mut metadata = new_metadata(
+            version,
+            user_files,
+            directories,
+            file_map,
+            chunks,
+            ArchiveTrailer {
+                chunk_crcs: Some(chunk_crcs),
+                file_mtimes,
+                comment,
+                encryption: cipher.as_ref().map(ChunkCipher::params),
+                // Known once the header is final, but already counted in the trailer size
+                header_crc: header_checksum.then_some(0),
+                file_modes,
+            },
+        );
+        metadata.string_layout = string_layout;
+
+        // --- Assign Volumes ---
+        // Without a size limit every chunk goes to the volume it was added to.
+        // With a limit, chunks are laid out sequentially and roll over to a new split volume
+        // whenever the current one would exceed the limit.
+        let (archives, volume_ids) = match volume_limit {
+            Some(limit) => {
+                // Duplicates take up no space of their own.
+                let sizes: Vec<u64> = processed_files
+                    .iter()
+                    .zip(&duplicate_of)
+                    .map(|(file, dup)| match dup {
+                        Some(_) => 0,
+                        None => file.data.len() as u64,
+                    })
+                    .collect();
+                assign_split_volumes(
+                    &mut metadata,
+                    &configured_archives,
+                    &sizes,
+                    limit,
+                    name_encoding,
+                )?
+            }
+            None => {
+                let volume_ids: Vec<u16> = processed_files.iter().map(|file| file.volume).collect();
+                (configured_archives, volume_ids)
+            }
+        };
+        set_volumes(&mut metadata, &archives);
+
+        // --- Open Volumes ---
+        for (i, name) in archives.iter().enumerate() {
+            info!("Opening volume {}: {}", i, name);
+            sink.open_volume(i as u16, name)?;
+        }
+
+        // Seek Volume 0 past the header, which is written last.
+        let header_size = metadata.encode_header(name_encoding)?.len() as u64;
+        sink.open_volume(0, &archives[0])?
+            .seek(SeekFrom::Start(header_size))?;
+
+        // --- Write Chunks ---
+        info!("Writing compressed chunks to volumes...");
+        for (i, (file, volume)) in processed_files.into_iter().zip(volume_ids).enumerate() {
+            // Duplicates and files without chunks have nothing to write
+            let Some(chunk_id) = file_chunk_ids[i].filter(|_| duplicate_of[i].is_none()) else {
+                on_progress(ProgressEvent::Inc(1));
+                continue;
+            };
+            let name = archives
+                .get(volume as usize)
+                .ok_or(DzipError::VolumeNotFound(volume))?;
+            let writer = sink.open_volume(volume, name)?;
+            let chunk = &mut metadata.chunks[chunk_id as usize];
+            chunk.offset = chunk_offset(writer.stream_position()?, volume)?;
+            chunk.file = volume;
+            let mut data = file.data;
+            if let Some(cipher) = cipher
+                .as_ref()
+                .filter(|_| chunk.flags & CHUNK_ENCRYPTED != 0)
+            {
+                cipher.apply(chunk, 0, &mut data);
+            }
+            writer.write_all(&data)?;
+            on_progress(ProgressEvent::Inc(1));
+        }
+
+        let header = metadata.encode_header(name_encoding)?;
+        debug_assert_eq!(header.len() as u64, header_size);
+        if let Some(crc) = metadata
+            .trailer
+            .as_mut()
+            .and_then(|t| t.header_crc.as_mut())
+        {
+            *crc = checksum(&header);
+        }
+
+        // --- Write Trailer ---
+        // Volume 0 is still positioned right after its chunk data.
+        let main = sink.open_volume(0, &archives[0])?;
+        let main_data_end = main.stream_position()?;
+        main.write_all(&encode_trailer(&metadata)?)?;
+
+        // --- Write Header ---
+        info!("Writing header to Volume 0...");
+        main.seek(SeekFrom::Start(0))?;
+        main.write_all(&header)?;
+
+        for (i, name) in archives.iter().enumerate() {
+            sink.open_volume(i as u16, name)?.flush()?;
+        }
+
+        on_progress(ProgressEvent::Finish);
+        info!("Pack complete.");
+        Ok(ArchiveMetadata {
+            main_data_end,
+            ..metadata
+        })
+    }
+
+    /// Estimates the size of the archive [`Self::build`] would write, without writing
+    /// anything or compressing whole files.
+    ///
+    /// Files stored as they are (Copy, Zero, MP3, JPEG, RandomAccess) are sized exactly.
+    /// Files of other methods are sized by compressing their first [`ESTIMATE_SAMPLE_LEN`]
+    /// bytes, which is exact for smaller files. Larger ones are expected to compress like
+    /// their sample; they may take anything from nothing to their size plus the worst-case
+    /// expansion of the built-in codecs. With dedup, files of the same size may be
+    /// duplicates, which only the lower bound assumes.
+    pub fn estimate(self) -> Result<PackEstimate> {
+        if self.archives.is_empty() {
+            return Err(DzipError::Config("No archives specified".to_string()));
+        }
+        if self.volume_limit == Some(0) {
+            return Err(DzipError::Config(
+                "max_volume_size must be greater than zero".to_string(),
+            ));
+        }
+        let files = self
+            .entries
+            .par_iter()
+            .map(|entry| estimate_entry(entry, &self.codecs))
+            .collect::<Result<Vec<_>>>()?;
+
+        // For the lower bound, each file of the same size as an earlier one is a duplicate
+        // of the smallest of them.
+        let mut min_sizes: Vec<Option<u64>> = files.iter().map(|f| Some(f.min)).collect();
+        if self.dedup {
+            let mut first_of_len: HashMap<u64, usize> = HashMap::new();
+            for (i, file) in files.iter().enumerate() {
+                if let Some(&first) = first_of_len.get(&file.raw) {
+                    min_sizes[first] = min_sizes[first].min(min_sizes[i]);
+                    min_sizes[i] = None;
+                } else {
+                    first_of_len.insert(file.raw, i);
+                }
+            }
+        }
+        let all = |size: fn(&FileEstimate) -> u64| -> Vec<Option<u64>> {
+            files.iter().map(|f| Some(size(f))).collect()
+        };
+        Ok(PackEstimate {
+            raw: files.iter().map(|f| f.raw).sum(),
+            min: self.estimate_archive(&min_sizes)?,
+            expected: self.estimate_archive(&all(|f| f.expected))?,
+            max: self.estimate_archive(&all(|f| f.max))?,
+        })
+    }
+
+    /// Size of the archive if each file takes the given number of bytes, or `None` for
+    /// duplicates.
+    fn estimate_archive(&self, sizes: &[Option<u64>]) -> Result<u64> {
+        let NameTables {
+            user_files,
+            directories,
+            file_dir_ids,
+        } = name_tables(&self.entries)?;
+        let mut chunks = Vec::new();
+        let mut chunk_sizes = Vec::new();
+        let mut file_map = Vec::with_capacity(sizes.len());
+        for ((entry, size), dir_id) in self.entries.iter().zip(sizes).zip(file_dir_ids) {
+            // Duplicates reference some other chunk; which one does not change the size
+            let chunk_id = match size {
+                Some(size) => {
+                    chunks.push(Chunk {
+                        offset: 0,
+                        compressed_length: 0,
+                        decompressed_length: 0,
+                        flags: entry.codec.unwrap_or(entry.compression.flag()) | entry.extra_flags,
+                        file: 0,
+                    });
+                    chunk_sizes.push(*size);
+                    chunks.len() as u32 - 1
+                }
+                None => 0,
+            };
+            file_map.push((dir_id, vec![chunk_id]));
+        }
+        let version = pick_version(self.version, &user_files, &directories, chunks.len())?;
+        let num_chunks = chunks.len();
+        let mut metadata = new_metadata(
+            version,
+            user_files,
+            directories,
+            file_map,
+            chunks,
+            ArchiveTrailer {
+                chunk_crcs: Some(vec![0; num_chunks]),
+                file_mtimes: file_mtimes(&self.entries),
+                comment: self.comment.clone(),
+                encryption: self.encryption_key.map(|_| EncryptionParams {
+                    salt: [0; 16],
+                    key_check: [0; 16],
+                }),
+                header_crc: self.header_checksum.then_some(0),
+                file_modes: file_modes(&self.entries),
+            },
+        );
+        match self.volume_limit {
+            Some(limit) => {
+                assign_split_volumes(
+                    &mut metadata,
+                    &self.archives,
+                    &chunk_sizes,
+                    limit,
+                    self.name_encoding,
+                )?;
+            }
+            None => set_volumes(&mut metadata, &self.archives),
+        }
+        Ok(metadata.encode_header(self.name_encoding)?.len() as u64
+            + encode_trailer(&metadata)?.len() as u64
+            + chunk_sizes.iter().sum::<u64>())
+    }
+}
+
+/// Number of bytes of a file [`PackBuilder::estimate`] compresses to estimate its size.
+pub const ESTIMATE_SAMPLE_LEN: u64 = 64 * 1024;
+
+/// Estimated size of an archive, from [`PackBuilder::estimate`]. Sizes are totals over all
+/// volumes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PackEstimate {
+    /// Total size of the input files
+    pub raw: u64,
+    /// The archive is at least this large
+    pub min: u64,
+    /// Expected size, if every file compresses like its sample
+    pub expected: u64,
+    /// The archive is at most this large
+    pub max: u64,
+}
+
+/// Size summary of a packed archive, per compression method.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PackReport {
+    /// Total decompressed size of all chunks
+    pub total_raw: u64,
+    /// Total size of all chunks as stored in the volumes
+    pub total_compressed: u64,
+    /// Decompressed and stored size of the chunks of each method
+    pub per_method: HashMap<CompressionMethod, (u64, u64)>,
+    /// Number of chunks
+    pub chunk_count: usize,
+}
+
+impl PackReport {
+    /// Sums up the chunk table of an archive. Chunks shared by several files
+    /// (see [`PackBuilder::dedup`]) are counted once.
+    pub fn new(chunks: &[Chunk]) -> Self {
+        let mut report = Self {
+            chunk_count: chunks.len(),
+            ..Self::default()
+        };
+        for chunk in chunks {
+            let raw = chunk.decompressed_length as u64;
+            let compressed = chunk.compressed_length as u64;
+            report.total_raw += raw;
+            report.total_compressed += compressed;
+            let sizes = report
+                .per_method
+                // Chunks of custom codecs are counted as Dz
+                .entry(CompressionMethod::from_flags(chunk.flags).unwrap_or(CompressionMethod::Dz))
+                .or_default();
+            sizes.0 += raw;
+            sizes.1 += compressed;
+        }
+        report
+    }
+
+    /// Stored size relative to the decompressed size, or `None` if there is no data.
+    pub fn ratio(&self) -> Option<f64> {
+        (self.total_raw > 0).then(|| self.total_compressed as f64 / self.total_raw as f64)
+    }
+}
+
+/// Compresses `input` as the data of `entry` into `output` and returns the chunk flags.
+fn compress_entry(
+    entry: &PackEntry,
+    codecs: &CodecRegistry,
+    input: &mut dyn Read,
+    output: &mut Vec<u8>,
+) -> Result<u16> {
+    let flag = entry.codec.unwrap_or(entry.compression.flag());
+    match codecs.get(flag) {
+        Some(codec) => codec.compress_with(input, output, &entry.options),
+        None if entry.codec.is_some() => Err(DzipError::UnsupportedCompression(flag)),
+        // Methods without a codec are stored as-is
+        None => Ok(compress_stream(input, output, entry.compression, &entry.options)?.flags),
+    }
+}
+
+/// Estimated stored size of a file, see [`PackBuilder::estimate`].
+struct FileEstimate {
+    raw: u64,
+    min: u64,
+    expected: u64,
+    max: u64,
+}
+
+fn estimate_entry(entry: &PackEntry, codecs: &CodecRegistry) -> Result<FileEstimate> {
+    let raw = match &entry.input {
+        PackInput::Data(data) => data.len() as u64,
+        PackInput::Source(source, path) => source.file_len(path)?,
+    };
+    let exact = |len| FileEstimate {
+        raw,
+        min: len,
+        expected: len,
+        max: len,
+    };
+    if raw == 0 {
+        // Stored as a zero chunk
+        return Ok(exact(0));
+    }
+    if entry.codec.is_none() {
+        match entry.compression {
+            CompressionMethod::Zero => return Ok(exact(0)),
+            CompressionMethod::Copy
+            | CompressionMethod::Mp3
+            | CompressionMethod::Jpeg
+            | CompressionMethod::RandomAccess => return Ok(exact(raw)),
+            _ => {}
+        }
+    }
+
+    let input: Box<dyn Read + Send + '_> = match &entry.input {
+        PackInput::Data(data) => Box::new(data.as_slice()),
+        PackInput::Source(source, path) => source.open_file(path)?,
+    };
+    let mut sample = Vec::new();
+    compress_entry(
+        entry,
+        codecs,
+        &mut input.take(ESTIMATE_SAMPLE_LEN),
+        &mut sample,
+    )?;
+    let sample_len = sample.len() as u64;
+    if raw <= ESTIMATE_SAMPLE_LEN {
+        return Ok(exact(sample_len));
+    }
+    // Well above the worst case of the built-in codecs on incompressible data
+    let max = raw + raw / 16 + 4096;
+    let expected = (sample_len as u128 * raw as u128 / ESTIMATE_SAMPLE_LEN as u128) as u64;
+    Ok(FileEstimate {
+        raw,
+        min: 0,
+        expected: expected.min(max),
+        max,
+    })
+}
+
+/// The string table of the files and the directory of each, see [`name_tables`].
+struct NameTables {
+    /// File names, without their directory
+    user_files: Vec<String>,
+    /// Directory paths; the root directory (ID 0) is implicit and has no string
+    directories: Vec<String>,
+    /// Directory ID of each file
+    file_dir_ids: Vec<u32>,
+}
+
+/// The string table of the files, [file names..., directory paths...], and the directory
+/// ID of each file.
+fn name_tables(entries: &[PackEntry]) -> Result<NameTables> {
+    let split_paths = entries
+        .iter()
+        .map(|entry| split_logical_path(&entry.path))
+        .collect::<Result<Vec<_>>>()?;
+    // Directory IDs follow the byte order of the directory paths, not the order of the
+    // files, so the directory table only depends on which directories are used.
+    let directories: Vec<String> = split_paths
+        .iter()
+        .filter_map(|(dir, _)| dir.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let dir_map: HashMap<&str, u32> = directories
+        .iter()
+        .enumerate()
+        .map(|(i, dir)| (dir.as_str(), i as u32 + 1)) // path -> dir_id (1-based)
+        .collect();
+    let mut user_files = Vec::with_capacity(entries.len());
+    let mut file_dir_ids = Vec::with_capacity(entries.len());
+    for (dir, name) in split_paths {
+        file_dir_ids.push(dir.map_or(0, |dir| dir_map[dir.as_str()]));
+        user_files.push(name);
+    }
+    Ok(NameTables {
+        user_files,
+        directories,
+        file_dir_ids,
+    })
+}
+
+/// The modification times for the trailer, if any file has one.
+fn file_mtimes(entries: &[PackEntry]) -> Option<Vec<u64>> {
+    entries.iter().any(|entry| entry.mtime.is_some()).then(|| {
+        entries
+            .iter()
+            .map(|entry| entry.mtime.unwrap_or(0))
+            .collect()
+    })
+}
+
+/// The permission bits for the trailer, if any file has them.
+fn file_modes(entries: &[PackEntry]) -> Option<Vec<u32>> {
+    entries.iter().any(|entry| entry.mode.is_some()).then(|| {
+        entries
+            .iter()
+            .map(|entry| entry.mode.unwrap_or(0))
+            .collect()
+    })
+}
+
+/// The format version to write: `version` if set and able to hold the archive, otherwise
+/// the legacy format unless the counts need the wide one.
+fn pick_version(
+    version: Option<FormatVersion>,
+    user_files: &[String],
+    directories: &[String],
+    num_chunks: usize,
+) -> Result<FormatVersion> {
+    let num_user_files = user_files.len();
+    let num_directories = directories.len() + 1;
+    let needs_wide = needs_wide_format(num_user_files, num_directories, num_chunks);
+    match version {
+        None if needs_wide => Ok(FormatVersion::Wide),
+        None => Ok(FormatVersion::Legacy),
+        Some(FormatVersion::Legacy) if needs_wide => Err(DzipError::Config(format!(
+            "{} files, {} directories and {} chunks exceed format version {}, use version {}",
+            num_user_files, num_directories, num_chunks, VERSION_LEGACY, VERSION_WIDE
+        ))),
+        Some(v) => Ok(v),
+    }
+}
+
+/// The metadata of a new archive in a single volume, with the chunks not yet placed.
+fn new_metadata(
+    version: FormatVersion,
+    user_files: Vec<String>,
+    directories: Vec<String>,
+    file_map: Vec<(u32, Vec<u32>)>,
+    chunks: Vec<Chunk>,
+    trailer: ArchiveTrailer,
+) -> ArchiveMetadata {
+    let has_dz = chunks.iter().any(|c| (c.flags & CHUNK_DZ) != 0);
+    ArchiveMetadata {
+        settings: ArchiveSettings {
+            header: ARCHIVE_MAGIC,
+            num_user_files: user_files.len() as u32,
+            num_directories: (directories.len() + 1) as u32,
+            version: version.into(),
+        },
+        user_files,
+        directories,
+        string_layout: StringLayout::default(),
+        file_map,
+        chunk_settings: ChunkSettings {
+            num_archive_files: 1,
+            num_chunks: chunks.len() as u32,
+        },
+        chunks,
+        volume_files: Vec::new(),
+        range_settings: has_dz.then(RangeSettings::default),
+        trailer: Some(trailer),
+        main_data_end: 0, // Known once the chunks are written
+    }
+}
+
+fn set_volumes(metadata: &mut ArchiveMetadata, archives: &[String]) {
+    metadata.chunk_settings.num_archive_files = archives.len() as u16;
+    metadata.volume_files = archives[1..].to_vec();
+}
+
+/// Lays out chunks of `sizes` bytes in order under a volume size limit, and returns the
+/// volume names and the volume of each chunk. The volume list of `metadata` is set
+/// accordingly.
+fn assign_split_volumes(
+    metadata: &mut ArchiveMetadata,
+    configured_archives: &[String],
+    sizes: &[u64],
+    limit: u64,
+    name_encoding: NameEncoding,
+) -> Result<(Vec<String>, Vec<u16>)> {
+    // The trailer also ends up in Volume 0.
+    let trailer_size = encode_trailer(metadata)?.len() as u64;
+    // The header lives in Volume 0 and grows with the split file list, so repeat until the
+    // number of volumes no longer changes. The count only ever grows, so this converges.
+    let mut archives = vec![configured_archives[0].clone()];
+    loop {
+        set_volumes(metadata, &archives);
+        let header_size = metadata.encode_header(name_encoding)?.len() as u64;
+        let volume_ids = split_into_volumes(sizes, header_size + trailer_size, limit);
+        let needed = volume_ids.last().map_or(1, |&v| v + 1);
+        if needed > u16::MAX as usize {
+            return Err(DzipError::Config(
+                "Too many volumes, increase max_volume_size".to_string(),
+            ));
+        }
+        if needed == archives.len() {
+            return Ok((archives, volume_ids.into_iter().map(|v| v as u16).collect()));
+        }
+        archives = (0..needed)
+            .map(|i| split_volume_name(configured_archives, i))
+            .collect();
+    }
+}
+
+/// Splits a logical path into its directory (archive format, `None` for the root) and
+/// file name.
+fn split_logical_path(path: &str) -> Result<(Option<String>, String)> {
+    let mut parts: Vec<&str> = path
+        .split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+    let name = parts.pop().ok_or_else(|| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            format!("Invalid file path: {}", path),
+        )
+    })?;
+    let dir = (!parts.is_empty()).then(|| parts.join("\\"));
+    Ok((dir, name.to_string()))
+}
+
+/// The logical paths that name the same file more than once, with `/` as separator, and
+/// how many times each occurs, in order of first occurrence.
+///
+/// Paths are compared as stored: separators and `.` components do not matter, case does.
+/// Invalid paths are skipped.
+pub fn duplicate_paths<'p>(paths: impl IntoIterator<Item = &'p str>) -> Vec<(String, usize)> {
+    let mut counts: Vec<((Option<String>, String), usize)> = Vec::new();
+    let mut index: HashMap<(Option<String>, String), usize> = HashMap::new();
+    for path in paths {
+        let Ok(key) = split_logical_path(path) else {
+            continue;
+        };
+        match index.get(&key) {
+            Some(&i) => counts[i].1 += 1,
+            None => {
+                index.insert(key.clone(), counts.len());
+                counts.push((key, 1));
+            }
+        }
+    }
+    counts
+        .into_iter()
+        .filter(|&(_, count)| count > 1)
+        .map(|((dir, name), count)| match dir {
+            Some(dir) => (format!("{}/{}", dir.replace('\\', "/"), name), count),
+            None => (name, count),
+        })
+        .collect()
+}
+
+/// For each file, the index of an earlier file whose chunk is byte-identical, if any.
+///
+/// Identical inputs compressed with the same method produce identical chunks, so later
+/// copies can reference the chunk of the first one instead of storing it again.
+/// Candidates are found by CRC, size and flags, then confirmed by comparing the data.
+fn find_duplicates(files: &[CompressedFile]) -> Vec<Option<usize>> {
+    let mut seen: HashMap<(u32, u32, u16), Vec<usize>> = HashMap::new();
+    files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+            if file.no_chunks {
+                return None;
+            }
+            let candidates = seen
+                .entry((file.crc, file.original_len, file.flags))
+                .or_default();
+            let original = candidates
+                .iter()
+                .copied()
+                .find(|&j| files[j].data == file.data);
+            if original.is_none() {
+                candidates.push(i);
+            }
+            original
+        })
+        .collect()
+}
+
+/// The dzip-rs trailer of `metadata`, or nothing if it has none.
+fn encode_trailer(metadata: &ArchiveMetadata) -> Result<Vec<u8>> {
+    let mut writer = DzipWriter::new(std::io::Cursor::new(Vec::new()));
+    if let Some(trailer) = &metadata.trailer {
+        writer.write_trailer(trailer)?;
+    }
+    Ok(writer.into_inner().into_inner())
+}
+
+/// Assigns chunks to volumes in order, starting a new volume whenever adding the next
+/// chunk would push the current one over `max_volume_size`.
+///
+/// Volume 0 starts out holding the header, so it may end up holding nothing else.
+/// A chunk larger than the limit is still placed on its own in a fresh volume,
+/// since chunks cannot be split across volumes.
+fn split_into_volumes(chunk_sizes: &[u64], header_size: u64, max_volume_size: u64) -> Vec<usize> {
+    let mut volume_ids = Vec::with_capacity(chunk_sizes.len());
+    let mut current_volume = 0;
+    let mut current_size = header_size;
+
+    for &size in chunk_sizes {
+        if current_size > 0 && current_size + size > max_volume_size {
+            current_volume += 1;
+            current_size = 0;
+        }
+        if size > max_volume_size {
+            warn!(
+                "Chunk of {} bytes exceeds the volume size limit of {} bytes",
+                size, max_volume_size
+            );
+        }
+        current_size += size;
+        volume_ids.push(current_volume);
+    }
+    volume_ids
+}
+
+/// Name of the split volume at `index`.
+///
+/// Names listed in the config are used as-is; further volumes follow the classic
+/// `name.d01`, `name.d02`, ... pattern derived from the main archive name.
+pub(crate) fn split_volume_name(configured: &[String], index: usize) -> String {
+    if let Some(name) = configured.get(index) {
+        return name.clone();
+    }
+    let stem = std::path::Path::new(&configured[0])
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy();
+    format!("{}.d{:02}", stem, index)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_into_volumes() {
+        // Header of 10 bytes, limit of 100 bytes
+        let ids = split_into_volumes(&[40, 40, 40, 150, 10], 10, 100);
+        assert_eq!(ids, vec![0, 0, 1, 2, 3]);
+
+        // Header leaves no room for the first chunk in Volume 0
+        let ids = split_into_volumes(&[50, 50], 80, 100);
+        assert_eq!(ids, vec![1, 1]);
+    }
+
+    #[test]
+    fn test_split_volume_name() {
+        let configured = vec!["data.dz".to_string()];
+        assert_eq!(split_volume_name(&configured, 0), "data.dz");
+        assert_eq!(split_volume_name(&configured, 1), "data.d01");
+        assert_eq!(split_volume_name(&configured, 12), "data.d12");
+    }
+
+    #[test]
+    fn test_split_logical_path() {
+        assert_eq!(
+            split_logical_path("a/b\\c.bin").unwrap(),
+            (Some("a\\b".to_string()), "c.bin".to_string())
+        );
+        assert_eq!(
+            split_logical_path("./c.bin").unwrap(),
+            (None, "c.bin".to_string())
+        );
+        assert!(split_logical_path("a/").is_ok());
+        assert!(split_logical_path("/").is_err());
+    }
+}
@@ -1,8 +1,23 @@
 use crate::{DzipError, Result};
+use std::borrow::Cow;
 use std::path::{Component, Path, PathBuf};
 
+/// Characters Windows does not allow in file names, besides control characters
+const WINDOWS_INVALID_CHARS: &[char] = &['<', '>', ':', '"', '|', '?', '*'];
+
+/// Device names Windows reserves, with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest path Windows accepts without the `\\?\` prefix
+#[cfg(windows)]
+const WINDOWS_MAX_PATH: usize = 260;
+
 /// Sanitize a path to ensure it is safe for extraction.
-/// prevent Zip Slip attacks by disallowing absolute paths and `..` components.
+/// prevent Zip Slip attacks by disallowing drive prefixes and `..` components.
+/// A leading root is stripped to make the path relative.
 pub fn sanitize_path(path: &Path) -> Result<PathBuf> {
     let mut clean_path = PathBuf::new();
     for component in path.components() {
@@ -12,13 +27,16 @@ pub fn sanitize_path(path: &Path) -> Result<PathBuf> {
                 // Skip root directory component to make path relative
             }
             Component::Prefix(_) => {
-                // Skip prefix (e.g., C:)
+                return Err(DzipError::Security(format!(
+                    "'{}' contains a drive prefix",
+                    path.display()
+                )));
             }
             Component::CurDir => {}
             Component::ParentDir => {
-                return Err(DzipError::Io(std::io::Error::new(
-                    std::io::ErrorKind::InvalidInput,
-                    "Invalid path component '..' (Zip Slip prevention)",
+                return Err(DzipError::Security(format!(
+                    "'{}' contains '..' (Zip Slip prevention)",
+                    path.display()
                 )));
             }
         }
@@ -56,6 +74,75 @@ pub fn from_archive_format(path_str: &str) -> Result<PathBuf> {
     sanitize_path(path)
 }
 
+/// Rewrites each component of a logical path (either separator) into a name Windows can
+/// create: `<>:"|?*` and control characters become `_`, trailing dots and spaces are
+/// replaced by `_`, and reserved device names like `CON` or `nul.txt` get a leading `_`.
+///
+/// Returns the path unchanged if nothing needed rewriting.
+pub fn windows_safe_path(path: &str) -> Cow<'_, str> {
+    let is_invalid = |c: char| c.is_control() || WINDOWS_INVALID_CHARS.contains(&c);
+    let needs_rewrite = |name: &str| {
+        name.contains(is_invalid)
+            || (name.ends_with(['.', ' ']) && name != "." && name != "..")
+            || is_reserved_name(name)
+    };
+    if !path.split(['/', '\\']).any(needs_rewrite) {
+        return Cow::Borrowed(path);
+    }
+
+    let components: Vec<String> = path
+        .split(['/', '\\'])
+        .map(|name| {
+            if !needs_rewrite(name) {
+                return name.to_string();
+            }
+            let mut safe: String = name
+                .chars()
+                .map(|c| if is_invalid(c) { '_' } else { c })
+                .collect();
+            let trimmed = safe.trim_end_matches(['.', ' ']).len();
+            if trimmed < safe.len() {
+                safe.truncate(trimmed);
+                safe.push('_');
+            }
+            if is_reserved_name(&safe) {
+                safe.insert(0, '_');
+            }
+            safe
+        })
+        .collect();
+    Cow::Owned(components.join("/"))
+}
+
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Turns a path longer than Windows' `MAX_PATH` into an absolute extended-length path
+/// (`\\?\C:\...` or `\\?\UNC\server\share\...`), which the Windows file APIs accept up to
+/// about 32,767 characters. Other paths, and all paths on other platforms, are returned
+/// unchanged.
+pub fn extended_length_path(path: &Path) -> Result<PathBuf> {
+    #[cfg(windows)]
+    {
+        let os_str = path.as_os_str();
+        if os_str.len() < WINDOWS_MAX_PATH || os_str.to_string_lossy().starts_with(r"\\?\") {
+            return Ok(path.to_path_buf());
+        }
+        let absolute = std::path::absolute(path)?;
+        let absolute = absolute.to_string_lossy();
+        Ok(PathBuf::from(match absolute.strip_prefix(r"\\") {
+            Some(unc) => format!(r"\\?\UNC\{}", unc),
+            None => format!(r"\\?\{}", absolute),
+        }))
+    }
+    #[cfg(not(windows))]
+    Ok(path.to_path_buf())
+}
+
 /// Resolve a relative path from a string that might contain mixed separators (Internet/Windows style).
 /// This splits the path by both `/` and `\` and reconstructs it using the system's native separator.
 /// It also performs sanitization (Zip Slip prevention).
@@ -70,9 +157,9 @@ pub fn resolve_relative_path(path_str: &str) -> Result<PathBuf> {
             continue;
         }
         if part == ".." {
-            return Err(DzipError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid path component '..' (Zip Slip prevention)",
+            return Err(DzipError::Security(format!(
+                "'{}' contains '..' (Zip Slip prevention)",
+                path_str
             )));
         }
 
@@ -82,9 +169,9 @@ pub fn resolve_relative_path(path_str: &str) -> Result<PathBuf> {
         if part.contains(':') {
             // For safety, just treat as invalid or skip?
             // Let's treat as invalid for now to be safe.
-            return Err(DzipError::Io(std::io::Error::new(
-                std::io::ErrorKind::InvalidInput,
-                "Invalid path component containing ':'",
+            return Err(DzipError::Security(format!(
+                "'{}' contains a drive prefix or ':'",
+                path_str
             )));
         }
 
@@ -140,9 +227,31 @@ mod tests {
         assert_eq!(resolved, expected);
     }
 
+    #[test]
+    fn test_windows_safe_path() {
+        assert!(matches!(
+            windows_safe_path("folder\\file.txt"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(windows_safe_path("what?\\a:b*.txt"), "what_/a_b_.txt");
+        assert_eq!(windows_safe_path("dir/trailing. "), "dir/trailing_");
+        assert_eq!(windows_safe_path("aux/Nul.txt"), "_aux/_Nul.txt");
+        assert_eq!(windows_safe_path("tab\there"), "tab_here");
+        // `..` is left for resolve_relative_path to reject
+        assert_eq!(windows_safe_path("../x?"), "../x_");
+    }
+
     #[test]
     fn test_resolve_relative_path_zip_slip() {
         let p = "folder\\../file.txt";
-        assert!(resolve_relative_path(p).is_err());
+        assert!(matches!(
+            resolve_relative_path(p),
+            Err(DzipError::Security(_))
+        ));
+        let p = "C:\\Windows\\file.txt";
+        assert!(matches!(
+            resolve_relative_path(p),
+            Err(DzipError::Security(_))
+        ));
     }
 }
@@ -0,0 +1,18 @@
+/// Progress notifications emitted by long-running operations.
+///
+/// `Start` is always sent first and `Finish` last; the `Inc` events in between may come
+/// from several worker threads, but always add up to the total announced by `Start`.
+/// Callbacks therefore have to be `Sync`, and the events of different files arrive in no
+/// particular order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// The operation started and will report this many steps
+    Start(u64),
+    /// This many more steps completed
+    Inc(u64),
+    /// The file with this logical path completed. Only informational: it is followed by
+    /// the `Inc` event counting the file.
+    FileDone(String),
+    /// All steps completed
+    Finish,
+}
@@ -1,33 +1,187 @@
+use crate::codec::CodecRegistry;
+use crate::crypto::{ChunkCipher, decrypt_chunk};
+use crate::encoding::NameEncoding;
 use crate::error::{DzipError, Result};
 use crate::format::*;
-use byteorder::{LittleEndian, ReadBytesExt};
-use std::io::{BufRead, BufReader, Read, Seek};
+use crate::writer::LzmaVariant;
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
+use rayon::prelude::*;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// Files with more chunks than this are decompressed in parallel by
+/// [`DzipReader::for_each_chunk_data`].
+pub const PARALLEL_CHUNK_THRESHOLD: usize = 8;
+
+/// Capacity of the buffers around archive files unless configured otherwise.
+pub const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// `size`, or [`DEFAULT_BUFFER_SIZE`] with a warning if it is zero.
+pub(crate) fn buffer_size(size: usize) -> usize {
+    if size == 0 {
+        log::warn!(
+            "I/O buffer size must be greater than zero, using {} bytes",
+            DEFAULT_BUFFER_SIZE
+        );
+        return DEFAULT_BUFFER_SIZE;
+    }
+    size
+}
 
 pub struct DzipReader<R: Read + Seek> {
     reader: BufReader<R>,
+    name_encoding: NameEncoding,
+    endian: Endian,
+    /// Set by `read_archive_settings`, picks the width of IDs and counts
+    version: FormatVersion,
+    codecs: Arc<CodecRegistry>,
+    cipher: Option<ChunkCipher>,
+    string_layout: StringLayout,
+    /// Cleared by `set_string_layout`
+    detect_string_layout: bool,
 }
 
 impl<R: Read + Seek> DzipReader<R> {
     pub fn new(reader: R) -> Self {
+        Self::with_buffer_size(reader, DEFAULT_BUFFER_SIZE)
+    }
+
+    /// Creates a reader whose read buffer holds `size` bytes. Zero falls back to
+    /// [`DEFAULT_BUFFER_SIZE`].
+    pub fn with_buffer_size(reader: R, size: usize) -> Self {
         Self {
-            reader: BufReader::new(reader),
+            reader: BufReader::with_capacity(buffer_size(size), reader),
+            name_encoding: NameEncoding::default(),
+            endian: Endian::default(),
+            version: FormatVersion::Legacy,
+            codecs: Arc::new(CodecRegistry::new()),
+            cipher: None,
+            string_layout: StringLayout::default(),
+            detect_string_layout: true,
+        }
+    }
+
+    /// Sets the codecs used to decompress chunks (the built-in ones by default).
+    pub fn set_codecs(&mut self, codecs: Arc<CodecRegistry>) {
+        self.codecs = codecs;
+    }
+
+    /// Sets the cipher used to decrypt encrypted chunks, see [`crate::ArchiveMetadata::cipher`].
+    /// Reading an encrypted chunk without one fails.
+    pub fn set_cipher(&mut self, cipher: Option<ChunkCipher>) {
+        self.cipher = cipher;
+    }
+
+    /// Sets the encoding used to decode file, directory and volume names (UTF-8 by default).
+    pub fn set_name_encoding(&mut self, encoding: NameEncoding) {
+        self.name_encoding = encoding;
+    }
+
+    /// Sets the byte order of the header (little-endian by default).
+    /// [`Self::read_archive_settings`] switches to the other order if the magic number
+    /// reads byte-swapped.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+    }
+
+    /// Byte order of the header, as detected by [`Self::read_archive_settings`].
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    /// Format version of the header, as read by [`Self::read_archive_settings`].
+    pub fn format_version(&self) -> FormatVersion {
+        self.version
+    }
+
+    /// Reads the string table in `layout` instead of detecting its layout (see
+    /// [`StringLayout::detect`]).
+    pub fn set_string_layout(&mut self, layout: StringLayout) {
+        self.string_layout = layout;
+        self.detect_string_layout = false;
+    }
+
+    /// Layout of the string table, as detected by [`Self::read_names`].
+    pub fn string_layout(&self) -> StringLayout {
+        self.string_layout
+    }
+
+    /// Reads the string table of the header and splits it into the file names and the
+    /// directory names, detecting its layout unless one was set.
+    pub fn read_names(
+        &mut self,
+        num_user_files: usize,
+        num_directories: usize,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        // The root directory has no string entry
+        let strings = self.read_strings((num_user_files + num_directories).saturating_sub(1))?;
+        if self.detect_string_layout {
+            self.string_layout = StringLayout::detect(&strings, num_user_files);
+            if self.string_layout != StringLayout::default() {
+                log::debug!("String table is {:?}", self.string_layout);
+            }
+        }
+        Ok(self.string_layout.split(strings, num_user_files))
+    }
+
+    fn read_u16_field(&mut self) -> std::io::Result<u16> {
+        match self.endian {
+            Endian::Little => self.reader.read_u16::<LittleEndian>(),
+            Endian::Big => self.reader.read_u16::<BigEndian>(),
+        }
+    }
+
+    fn read_u32_field(&mut self) -> std::io::Result<u32> {
+        match self.endian {
+            Endian::Little => self.reader.read_u32::<LittleEndian>(),
+            Endian::Big => self.reader.read_u32::<BigEndian>(),
         }
     }
 
+    pub(crate) fn name_encoding(&self) -> NameEncoding {
+        self.name_encoding
+    }
+
+    /// Reads `len` bytes of the main file from `offset` on, as stored.
+    pub(crate) fn read_bytes_at(&mut self, offset: u64, len: usize) -> Result<Vec<u8>> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let mut buffer = vec![0u8; len];
+        self.reader.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
     pub fn read_archive_settings(&mut self) -> Result<ArchiveSettings> {
         log::debug!(
             "Reading archive settings at offset {}",
             self.reader.stream_position().unwrap_or(0)
         );
-        let header = self.reader.read_u32::<LittleEndian>()?;
-        if header != 0x5A525444 {
-            // 'DTRZ' in little endian (ZRTD)
-            return Err(DzipError::InvalidHeader);
+        let header = self.read_u32_field()?;
+        match self.endian.detect(header) {
+            Some(endian) => {
+                if endian != self.endian {
+                    log::debug!("Header is {:?}-endian", endian);
+                }
+                self.endian = endian;
+            }
+            None => return Err(DzipError::InvalidMagic(header)),
         }
+        let header = ARCHIVE_MAGIC;
 
-        let num_user_files = self.reader.read_u16::<LittleEndian>()?;
-        let num_directories = self.reader.read_u16::<LittleEndian>()?;
+        let mut num_user_files = self.read_u16_field()? as u32;
+        let mut num_directories = self.read_u16_field()? as u32;
         let version = self.reader.read_u8()?;
+        self.version = FormatVersion::try_from(version)?;
+        match self.version {
+            FormatVersion::Legacy => {}
+            FormatVersion::Wide => {
+                num_user_files = self.read_u32_field()?;
+                num_directories = self.read_u32_field()?;
+            }
+        }
+        // The root directory is always counted
+        if num_directories == 0 {
+            return Err(DzipError::InvalidHeader);
+        }
 
         Ok(ArchiveSettings {
             header,
@@ -54,24 +208,36 @@ impl<R: Read + Seek> DzipReader<R> {
 
     fn read_null_terminated_string(&mut self) -> Result<String> {
         let mut bytes = Vec::new();
-        let _ = self.reader.read_until(0, &mut bytes)?;
-        if bytes.last() == Some(&0) {
-            bytes.pop();
+        if self.name_encoding == NameEncoding::Utf16Le {
+            // Read whole code units, the terminator is a zero unit
+            loop {
+                let mut unit = [0u8; 2];
+                self.reader.read_exact(&mut unit)?;
+                if unit == [0, 0] {
+                    break;
+                }
+                bytes.extend_from_slice(&unit);
+            }
+        } else {
+            let _ = self.reader.read_until(0, &mut bytes)?;
+            if bytes.pop() != Some(0) {
+                return Err(DzipError::Io(std::io::ErrorKind::UnexpectedEof.into()));
+            }
         }
-        Ok(String::from_utf8(bytes)?)
+        self.name_encoding.decode(bytes)
     }
 
     /// Reads the User-File to Chunk-And-Directory list.
     /// Returns a vector of tuples: (Directory ID, List of Chunk IDs).
-    pub fn read_file_chunk_map(&mut self, num_files: usize) -> Result<Vec<(u16, Vec<u16>)>> {
+    pub fn read_file_chunk_map(&mut self, num_files: usize) -> Result<Vec<(u32, Vec<u32>)>> {
         log::debug!("Reading file chunk map for {} files", num_files);
         let mut map = Vec::with_capacity(num_files);
         for _ in 0..num_files {
-            let dir_id = self.reader.read_u16::<LittleEndian>()?;
+            let dir_id = self.read_id()?;
             let mut chunks = Vec::new();
             loop {
-                let chunk_id = self.reader.read_u16::<LittleEndian>()?;
-                if chunk_id == 0xFFFF {
+                let chunk_id = self.read_id()?;
+                if chunk_id == self.id_terminator() {
                     break;
                 }
                 chunks.push(chunk_id);
@@ -81,9 +247,20 @@ impl<R: Read + Seek> DzipReader<R> {
         Ok(map)
     }
 
+    fn read_id(&mut self) -> Result<u32> {
+        Ok(match self.version {
+            FormatVersion::Legacy => self.read_u16_field()? as u32,
+            FormatVersion::Wide => self.read_u32_field()?,
+        })
+    }
+
+    fn id_terminator(&self) -> u32 {
+        self.version.id_terminator()
+    }
+
     pub fn read_chunk_settings(&mut self) -> Result<ChunkSettings> {
-        let num_archive_files = self.reader.read_u16::<LittleEndian>()?;
-        let num_chunks = self.reader.read_u16::<LittleEndian>()?;
+        let num_archive_files = self.read_u16_field()?;
+        let num_chunks = self.read_id()?;
         Ok(ChunkSettings {
             num_archive_files,
             num_chunks,
@@ -98,11 +275,11 @@ impl<R: Read + Seek> DzipReader<R> {
         );
         let mut chunks = Vec::with_capacity(count);
         for _ in 0..count {
-            let offset = self.reader.read_u32::<LittleEndian>()?;
-            let compressed_length = self.reader.read_u32::<LittleEndian>()?;
-            let decompressed_length = self.reader.read_u32::<LittleEndian>()?;
-            let flags = self.reader.read_u16::<LittleEndian>()?;
-            let file = self.reader.read_u16::<LittleEndian>()?;
+            let offset = self.read_u32_field()?;
+            let compressed_length = self.read_u32_field()?;
+            let decompressed_length = self.read_u32_field()?;
+            let flags = self.read_u16_field()?;
+            let file = self.read_u16_field()?;
             chunks.push(Chunk {
                 offset,
                 compressed_length,
@@ -148,176 +325,846 @@ impl<R: Read + Seek> DzipReader<R> {
         Ok(files)
     }
 
+    /// Reads the optional dzip-rs trailer from the end of the file.
+    ///
+    /// Returns the trailer and the offset at which it starts (i.e. where chunk data ends),
+    /// or `None` if the file has no trailer. Chunk data may end like a trailer by chance, so
+    /// besides its last 8 bytes, the sections of a trailer must exactly fill its length.
+    pub fn read_trailer(&mut self) -> Result<Option<(ArchiveTrailer, u64)>> {
+        let file_len = self.reader.seek(SeekFrom::End(0))?;
+        if file_len < 8 {
+            return Ok(None);
+        }
+        self.reader.seek(SeekFrom::Start(file_len - 8))?;
+        let sections_len = self.reader.read_u32::<LittleEndian>()? as u64;
+        let magic = self.reader.read_u32::<LittleEndian>()?;
+        if magic != TRAILER_MAGIC || sections_len > file_len - 8 {
+            return Ok(None);
+        }
+
+        let start = file_len - 8 - sections_len;
+        if !self.sections_fit(start, sections_len)? {
+            log::debug!("Ignoring trailer magic without a valid section table");
+            return Ok(None);
+        }
+        log::debug!("Reading trailer at offset {}", start);
+        self.reader.seek(SeekFrom::Start(start))?;
+
+        let mut trailer = ArchiveTrailer::default();
+        let mut remaining = sections_len;
+        while remaining >= 8 {
+            let tag = self.reader.read_u32::<LittleEndian>()?;
+            let len = self.reader.read_u32::<LittleEndian>()? as u64;
+            remaining -= 8;
+            if len > remaining {
+                return Err(DzipError::InvalidTrailer);
+            }
+            match tag {
+                TRAILER_TAG_CHUNK_CRC32 => {
+                    let mut crcs = Vec::with_capacity((len / 4) as usize);
+                    for _ in 0..len / 4 {
+                        crcs.push(self.reader.read_u32::<LittleEndian>()?);
+                    }
+                    self.reader.seek_relative((len % 4) as i64)?;
+                    trailer.chunk_crcs = Some(crcs);
+                }
+                TRAILER_TAG_FILE_MTIME => {
+                    let mut mtimes = Vec::with_capacity((len / 8) as usize);
+                    for _ in 0..len / 8 {
+                        mtimes.push(self.reader.read_u64::<LittleEndian>()?);
+                    }
+                    self.reader.seek_relative((len % 8) as i64)?;
+                    trailer.file_mtimes = Some(mtimes);
+                }
+                TRAILER_TAG_COMMENT => {
+                    let mut comment = vec![0u8; len as usize];
+                    self.reader.read_exact(&mut comment)?;
+                    trailer.comment = Some(String::from_utf8_lossy(&comment).into_owned());
+                }
+                TRAILER_TAG_ENCRYPTION if len == 32 => {
+                    let mut params = EncryptionParams {
+                        salt: [0; 16],
+                        key_check: [0; 16],
+                    };
+                    self.reader.read_exact(&mut params.salt)?;
+                    self.reader.read_exact(&mut params.key_check)?;
+                    trailer.encryption = Some(params);
+                }
+                TRAILER_TAG_ENCRYPTION => return Err(DzipError::InvalidTrailer),
+                TRAILER_TAG_HEADER_CRC32 if len == 4 => {
+                    trailer.header_crc = Some(self.reader.read_u32::<LittleEndian>()?);
+                }
+                TRAILER_TAG_HEADER_CRC32 => return Err(DzipError::InvalidTrailer),
+                TRAILER_TAG_FILE_MODE => {
+                    let mut modes = Vec::with_capacity((len / 4) as usize);
+                    for _ in 0..len / 4 {
+                        modes.push(self.reader.read_u32::<LittleEndian>()?);
+                    }
+                    self.reader.seek_relative((len % 4) as i64)?;
+                    trailer.file_modes = Some(modes);
+                }
+                _ => {
+                    log::debug!("Skipping unknown trailer section {:#x}", tag);
+                    self.reader.seek_relative(len as i64)?;
+                }
+            }
+            remaining -= len;
+        }
+
+        Ok(Some((trailer, start)))
+    }
+
+    /// Whether the trailer sections starting at `start` exactly fill `sections_len` bytes,
+    /// reading only their headers.
+    fn sections_fit(&mut self, start: u64, sections_len: u64) -> Result<bool> {
+        let mut offset = start;
+        let end = start + sections_len;
+        while end - offset >= 8 {
+            self.reader.seek(SeekFrom::Start(offset + 4))?;
+            let len = self.reader.read_u32::<LittleEndian>()? as u64;
+            if len > end - offset - 8 {
+                return Ok(false);
+            }
+            offset += 8 + len;
+        }
+        Ok(offset == end)
+    }
+
+    /// Length of the underlying file.
+    pub fn stream_len(&mut self) -> std::io::Result<u64> {
+        self.reader.seek(SeekFrom::End(0))
+    }
+
+    /// Number of bytes between the current position and the end of the underlying file.
+    pub fn remaining_len(&mut self) -> std::io::Result<u64> {
+        let position = self.reader.stream_position()?;
+        let len = self.reader.seek(SeekFrom::End(0))?;
+        self.reader.seek(SeekFrom::Start(position))?;
+        Ok(len.saturating_sub(position))
+    }
+
     pub fn position(&mut self) -> std::io::Result<u64> {
         self.reader.stream_position()
     }
 
-    pub fn read_chunk_data(&mut self, chunk: &Chunk) -> Result<Vec<u8>> {
-        Self::decompress_chunk_data(&mut self.reader, chunk)
+    /// Moves to `offset` in the main file.
+    pub(crate) fn seek_to(&mut self, offset: u64) -> std::io::Result<()> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+
+    /// Reads and decompresses a single chunk of the main file.
+    ///
+    /// `chunk` is usually taken from [`crate::ArchiveMetadata::chunks`] after
+    /// [`crate::ArchiveMetadata::correct_chunk_sizes`], since some archives list placeholder
+    /// lengths, and `id` is its index there, which log messages refer to it by. Chunks stored
+    /// in split volumes fail with [`DzipError::VolumeNotFound`]; use
+    /// [`Self::read_chunk_data_with_volumes`] for those.
+    pub fn read_chunk_data(&mut self, id: u32, chunk: &Chunk) -> Result<Vec<u8>> {
+        if chunk.file != 0 {
+            return Err(DzipError::VolumeNotFound(chunk.file));
+        }
+        Self::decompress_chunk_data(
+            &mut self.reader,
+            &self.codecs,
+            self.cipher.as_ref(),
+            id,
+            chunk,
+        )
     }
 
+    /// Reads and decompresses a single chunk, opening split volumes through
+    /// `volume_source`.
+    ///
+    /// To read a whole file, see [`crate::ArchiveMetadata::read_file`], which concatenates
+    /// its chunks.
     pub fn read_chunk_data_with_volumes(
         &mut self,
+        id: u32,
         chunk: &Chunk,
         volume_source: &mut dyn VolumeSource,
     ) -> Result<Vec<u8>> {
         if chunk.file == 0 {
-            Self::decompress_chunk_data(&mut self.reader, chunk)
+            Self::decompress_chunk_data(
+                &mut self.reader,
+                &self.codecs,
+                self.cipher.as_ref(),
+                id,
+                chunk,
+            )
         } else {
             let reader = volume_source.open_volume(chunk.file)?;
-            Self::decompress_chunk_data(reader, chunk)
+            Self::decompress_chunk_data(reader, &self.codecs, self.cipher.as_ref(), id, chunk)
         }
     }
 
-    fn decompress_chunk_data(reader: &mut dyn ReadSeek, chunk: &Chunk) -> Result<Vec<u8>> {
-        log::trace!(
-            "Decompressing Chunk: offset={}, comp={}, decomp={}, flags={:x}",
-            chunk.offset,
-            chunk.compressed_length,
+    /// Reads the bytes a chunk stores, without decrypting or decompressing them, opening
+    /// split volumes through `volume_source`. ZERO chunks store nothing.
+    ///
+    /// Useful to inspect chunks of methods dzip-rs cannot decode.
+    pub fn read_stored_chunk_with_volumes(
+        &mut self,
+        chunk: &Chunk,
+        volume_source: &mut dyn VolumeSource,
+    ) -> Result<Vec<u8>> {
+        if chunk.file == 0 {
+            Self::read_stored_chunk(&mut self.reader, chunk)
+        } else {
+            Self::read_stored_chunk(volume_source.open_volume(chunk.file)?, chunk)
+        }
+    }
+
+    /// Framing of an LZMA chunk, or `None` for chunks of other methods.
+    ///
+    /// Only the start of the chunk is read.
+    pub fn lzma_variant_with_volumes(
+        &mut self,
+        chunk: &Chunk,
+        volume_source: &mut dyn VolumeSource,
+    ) -> Result<Option<LzmaVariant>> {
+        if (chunk.flags & CHUNK_LZMA) == 0 {
+            return Ok(None);
+        }
+        let reader: &mut dyn ReadSeek = match chunk.file {
+            0 => &mut self.reader,
+            id => volume_source.open_volume(id)?,
+        };
+        reader.seek(SeekFrom::Start(chunk.offset as u64))?;
+        let mut prefix = Vec::with_capacity(13);
+        reader
+            .take(chunk.compressed_length.min(13) as u64)
+            .read_to_end(&mut prefix)?;
+        decrypt_chunk(self.cipher.as_ref(), chunk, 0, &mut prefix)?;
+        Ok(Some(LzmaVariant::detect(
+            &prefix,
             chunk.decompressed_length,
-            chunk.flags
-        );
-        // Handle Zero chunk (optimization for empty/zeroed regions)
+        )))
+    }
+
+    /// Reads `len` bytes starting at `start` within the decompressed data of a chunk
+    /// in the main file.
+    ///
+    /// See [`Self::read_range_with_volumes`].
+    pub fn read_range(&mut self, id: u32, chunk: &Chunk, start: u64, len: u64) -> Result<Vec<u8>> {
+        Self::read_chunk_range(
+            &mut self.reader,
+            &self.codecs,
+            self.cipher.as_ref(),
+            id,
+            chunk,
+            start,
+            len,
+        )
+    }
+
+    /// Reads `len` bytes starting at `start` within the decompressed data of a chunk.
+    ///
+    /// Chunks stored without compression, which includes `CHUNK_RANDOMACCESS` chunks
+    /// without another method flag, are read directly at the requested position.
+    /// `CHUNK_RANDOMACCESS` is only a hint that a chunk should be buffered whole
+    /// (see DZSettings.h) and carries no sub-block index, so compressed chunks are
+    /// decompressed in full and sliced.
+    pub fn read_range_with_volumes(
+        &mut self,
+        id: u32,
+        chunk: &Chunk,
+        start: u64,
+        len: u64,
+        volume_source: &mut dyn VolumeSource,
+    ) -> Result<Vec<u8>> {
+        if chunk.file == 0 {
+            Self::read_chunk_range(
+                &mut self.reader,
+                &self.codecs,
+                self.cipher.as_ref(),
+                id,
+                chunk,
+                start,
+                len,
+            )
+        } else {
+            let reader = volume_source.open_volume(chunk.file)?;
+            Self::read_chunk_range(
+                reader,
+                &self.codecs,
+                self.cipher.as_ref(),
+                id,
+                chunk,
+                start,
+                len,
+            )
+        }
+    }
+
+    fn read_chunk_range(
+        reader: &mut dyn ReadSeek,
+        codecs: &CodecRegistry,
+        cipher: Option<&ChunkCipher>,
+        id: u32,
+        chunk: &Chunk,
+        start: u64,
+        len: u64,
+    ) -> Result<Vec<u8>> {
+        let end = start
+            .checked_add(len)
+            .filter(|&end| end <= chunk.decompressed_length as u64)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "Range {}+{} exceeds chunk of {} bytes",
+                        start, len, chunk.decompressed_length
+                    ),
+                )
+            })?;
+
+        if is_stored_raw(chunk) && chunk.compressed_length as u64 >= end {
+            reader.seek(SeekFrom::Start(chunk.offset as u64 + start))?;
+            let mut buffer = vec![0u8; len as usize];
+            reader.read_exact(&mut buffer)?;
+            decrypt_chunk(cipher, chunk, start, &mut buffer)?;
+            return Ok(buffer);
+        }
+
+        let data = Self::decompress_chunk_data(reader, codecs, cipher, id, chunk)?;
+        data.get(start as usize..end as usize)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Chunk decompressed to fewer bytes than expected",
+                )
+                .into()
+            })
+    }
+
+    /// Decompresses the chunks of one file in order, passing each result to `f` along with
+    /// the chunk's index in `chunks`. `ids` holds the ID of each chunk, for log messages.
+    ///
+    /// Up to [`PARALLEL_CHUNK_THRESHOLD`] chunks are handled one by one. Beyond that, the
+    /// compressed data is read sequentially in batches and each batch is decompressed on the
+    /// rayon pool, so a single large file uses all cores. `f` is still called in chunk order,
+    /// with at most one batch of decompressed data held in memory.
+    ///
+    /// Read and decompression errors are passed to `f`; errors returned by `f` abort.
+    pub fn for_each_chunk_data(
+        &mut self,
+        ids: &[u32],
+        chunks: &[Chunk],
+        volume_source: &mut dyn VolumeSource,
+        mut f: impl FnMut(usize, Result<Vec<u8>>) -> Result<()>,
+    ) -> Result<()> {
+        if chunks.len() <= PARALLEL_CHUNK_THRESHOLD {
+            for (i, (&id, chunk)) in ids.iter().zip(chunks).enumerate() {
+                f(
+                    i,
+                    self.read_chunk_data_with_volumes(id, chunk, volume_source),
+                )?;
+            }
+            return Ok(());
+        }
+
+        let batch_len = rayon::current_num_threads() * 2;
+        for (batch_index, (batch_ids, batch)) in ids
+            .chunks(batch_len)
+            .zip(chunks.chunks(batch_len))
+            .enumerate()
+        {
+            let raw: Vec<Result<Vec<u8>>> = batch
+                .iter()
+                .map(|chunk| match chunk.file {
+                    0 => Self::read_raw_chunk(&mut self.reader, self.cipher.as_ref(), chunk),
+                    id => Self::read_raw_chunk(
+                        volume_source.open_volume(id)?,
+                        self.cipher.as_ref(),
+                        chunk,
+                    ),
+                })
+                .collect();
+            let decoded: Vec<Result<Vec<u8>>> = raw
+                .into_par_iter()
+                .zip(batch_ids.par_iter().zip(batch))
+                .map(|(raw, (&id, chunk))| self.codecs.decompress_chunk(id, raw?, chunk))
+                .collect();
+            for (i, data) in decoded.into_iter().enumerate() {
+                f(batch_index * batch_len + i, data)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn decompress_chunk_data(
+        reader: &mut dyn ReadSeek,
+        codecs: &CodecRegistry,
+        cipher: Option<&ChunkCipher>,
+        id: u32,
+        chunk: &Chunk,
+    ) -> Result<Vec<u8>> {
+        let buffer = Self::read_raw_chunk(reader, cipher, chunk)?;
+        codecs.decompress_chunk(id, buffer, chunk)
+    }
+
+    /// Reads and decrypts the stored bytes of a chunk. ZERO chunks store nothing.
+    fn read_raw_chunk(
+        reader: &mut dyn ReadSeek,
+        cipher: Option<&ChunkCipher>,
+        chunk: &Chunk,
+    ) -> Result<Vec<u8>> {
+        if (chunk.flags & CHUNK_ZERO) != 0 {
+            return Ok(Vec::new());
+        }
+        let mut buffer = Self::read_stored_chunk(reader, chunk)?;
+        decrypt_chunk(cipher, chunk, 0, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Reads the stored bytes of a chunk as they are. ZERO chunks store nothing.
+    fn read_stored_chunk(reader: &mut dyn ReadSeek, chunk: &Chunk) -> Result<Vec<u8>> {
         // Must be handled before seeking, as offset might be invalid/virtual for zero chunks.
         if (chunk.flags & CHUNK_ZERO) != 0 {
-            return Ok(vec![0u8; chunk.decompressed_length as usize]);
+            return Ok(Vec::new());
         }
 
         reader.seek(std::io::SeekFrom::Start(chunk.offset as u64))?;
-
-        // Read compressed data
         let mut buffer = vec![0u8; chunk.compressed_length as usize];
         reader.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+}
 
-        // If explicitly flagged as copy encoded, or no compression flags set?
-        // Actually, let's just check flags.
-        // User confirmed: CHUNK_MP3 and CHUNK_JPEG are equivalent to CHUNK_COPYCOMP
-        if (chunk.flags & (CHUNK_COPYCOMP | CHUNK_MP3 | CHUNK_JPEG)) != 0 {
-            return Ok(buffer);
+/// Decompresses the stored bytes of a chunk, as read from its volume.
+pub fn decode_chunk(buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+    log::trace!(
+        "Decompressing Chunk: offset={}, comp={}, decomp={}, flags={:x}",
+        chunk.offset,
+        chunk.compressed_length,
+        chunk.decompressed_length,
+        chunk.flags
+    );
+    // An unknown bit may change how the data is stored, so decoding by the known bits
+    // alone could return garbage.
+    if chunk.flags & !CHUNK_KNOWN_FLAGS != 0 {
+        return Err(DzipError::UnsupportedCompression(chunk.flags));
+    }
+
+    // Handle Zero chunk (optimization for empty/zeroed regions)
+    if (chunk.flags & CHUNK_ZERO) != 0 {
+        return Ok(vec![0u8; chunk.decompressed_length as usize]);
+    }
+
+    if (chunk.flags & CHUNK_COPYCOMP) != 0 {
+        return copied_data(buffer, chunk);
+    }
+
+    if (chunk.flags & CHUNK_MP3) != 0 {
+        return decode_mp3(buffer, chunk);
+    }
+
+    if (chunk.flags & CHUNK_JPEG) != 0 {
+        return decode_jpeg(buffer, chunk);
+    }
+
+    // Handle RandomAccess chunks (usually stored uncompressed if no other compression flag is set)
+    if (chunk.flags & CHUNK_RANDOMACCESS) != 0 {
+        // Check if any actual compression flag is ALSO set.
+        // If LZMA/ZLIB/BZIP/DZ are NOT set, then it's just raw data with a type hint.
+        if (chunk.flags & (CHUNK_LZMA | CHUNK_ZLIB | CHUNK_BZIP | CHUNK_DZ | CHUNK_ZSTD)) == 0 {
+            return copied_data(buffer, chunk);
+        }
+    }
+
+    if (chunk.flags & CHUNK_ZLIB) != 0 {
+        return decode_zlib(buffer, chunk);
+    }
+
+    if (chunk.flags & CHUNK_BZIP) != 0 {
+        // Heuristic for "Equal Lengths" Quirk:
+        // Bzip2 header must start with "BZh".
+        if !buffer.starts_with(b"BZh") && holds_raw_data(chunk) {
+            return Ok(raw_data(buffer, chunk));
         }
 
-        // Handle RandomAccess chunks (usually stored uncompressed if no other compression flag is set)
-        if (chunk.flags & CHUNK_RANDOMACCESS) != 0 {
-            // Check if any actual compression flag is ALSO set.
-            // If LZMA/ZLIB/BZIP/DZ are NOT set, then it's just raw data with a type hint.
-            if (chunk.flags & (CHUNK_LZMA | CHUNK_ZLIB | CHUNK_BZIP | CHUNK_DZ)) == 0 {
+        let mut decoder = bzip2::read::BzDecoder::new(&buffer[..]).take(output_limit(chunk));
+        let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
+        match decoder.read_to_end(&mut decompressed) {
+            Ok(_) => return check_overrun(decompressed, chunk),
+            Err(_) if chunk.compressed_length == chunk.decompressed_length => {
                 return Ok(buffer);
             }
+            Err(e) => return Err(DzipError::Io(e)),
         }
+    }
 
-        if (chunk.flags & CHUNK_ZLIB) != 0 {
-            // Heuristic for "Equal Lengths" Quirk:
-            if chunk.compressed_length == chunk.decompressed_length {
-                // Typical Zlib header starts with 0x78 (Deflate, 32k win).
-                // If it doesn't look like Zlib, assume raw.
-                if buffer.is_empty() || (buffer[0] & 0x0F) != 0x08 {
-                    // Low nibble 8 = Deflate.
-                    // 0x78 is extremely common (CINFO=7 => 32k window).
-                    // If not deflate, likely raw.
-                    return Ok(buffer);
-                }
-            }
+    if (chunk.flags & CHUNK_LZMA) != 0 {
+        let variant = LzmaVariant::detect(&buffer, chunk.decompressed_length);
+        // Heuristic for "Equal Lengths" Quirk ambiguity:
+        if variant != LzmaVariant::Xz
+            && holds_raw_data(chunk)
+            && (buffer.is_empty() || buffer[0] != 0x5d)
+        {
+            // Does not start with typical LZMA property byte. Likely Raw.
+            return Ok(raw_data(buffer, chunk));
+        }
 
-            // Check for GZIP header (0x1f 0x8b)
-            if buffer.len() >= 2 && buffer[0] == 0x1f && buffer[1] == 0x8b {
-                let mut decoder = flate2::read::GzDecoder::new(&buffer[..]);
-                let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
-                match std::io::Read::read_to_end(&mut decoder, &mut decompressed) {
-                    Ok(_) => return Ok(decompressed),
-                    Err(e) => {
-                        // If we extracted the full expected length, ignore the error (likely missing footer)
-                        if decompressed.len() == chunk.decompressed_length as usize {
-                            return Ok(decompressed);
-                        }
-                        return Err(DzipError::Io(e));
-                    }
+        let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
+        let mut output = BoundedOutput {
+            data: &mut decompressed,
+            limit: output_limit(chunk) as usize,
+        };
+        let mut reader = std::io::Cursor::new(&buffer[..]);
+        let result = match variant {
+            LzmaVariant::Alone => lzma_rs::lzma_decompress(&mut reader, &mut output),
+            LzmaVariant::Raw => lzma_rs::lzma_decompress_with_options(
+                &mut reader,
+                &mut output,
+                &lzma_rs::decompress::Options {
+                    unpacked_size: lzma_rs::decompress::UnpackedSize::UseProvided(Some(
+                        chunk.decompressed_length as u64,
+                    )),
+                    ..Default::default()
+                },
+            ),
+            LzmaVariant::Xz => lzma_rs::xz_decompress(&mut reader, &mut output),
+        };
+        let decompressed = check_overrun(decompressed, chunk)?;
+        match result {
+            Ok(_) => return Ok(decompressed),
+            Err(e) => {
+                let threshold = (chunk.compressed_length as f32 * 0.8) as usize;
+                if !decompressed.is_empty() && decompressed.len() > threshold {
+                    log::warn!(
+                        "LZMA decompression finished with error '{}' but produced {} bytes (> 80%). Returning partial data.",
+                        e,
+                        decompressed.len()
+                    );
+                    return Ok(decompressed);
                 }
-            }
-
-            let mut decoder = flate2::read::ZlibDecoder::new(&buffer[..]);
-            let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
-            match std::io::Read::read_to_end(&mut decoder, &mut decompressed) {
-                Ok(_) => return Ok(decompressed),
-                Err(_) if chunk.compressed_length == chunk.decompressed_length => {
+                if chunk.compressed_length == chunk.decompressed_length {
+                    log::debug!(
+                        "LZMA failed with error '{}' but lengths match (fallback to raw).",
+                        e
+                    );
                     return Ok(buffer);
                 }
-                Err(e) => return Err(DzipError::Io(e)),
+                return Err(DzipError::Decompression(format!("LZMA: {}", e)));
             }
         }
+    }
 
-        if (chunk.flags & CHUNK_BZIP) != 0 {
-            // Heuristic for "Equal Lengths" Quirk:
-            if chunk.compressed_length == chunk.decompressed_length {
-                // Bzip2 header must start with "BZh".
-                if buffer.len() < 3 || &buffer[0..3] != b"BZh" {
-                    return Ok(buffer);
-                }
-            }
+    #[cfg(feature = "zstd")]
+    if (chunk.flags & CHUNK_ZSTD) != 0 {
+        let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
+        zstd::stream::read::Decoder::new(&buffer[..])?
+            .take(output_limit(chunk))
+            .read_to_end(&mut decompressed)?;
+        return check_overrun(decompressed, chunk);
+    }
 
-            let mut decoder = bzip2::read::BzDecoder::new(&buffer[..]);
-            let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
-            match std::io::Read::read_to_end(&mut decoder, &mut decompressed) {
-                Ok(_) => return Ok(decompressed),
-                Err(_) if chunk.compressed_length == chunk.decompressed_length => {
-                    return Ok(buffer);
-                }
-                Err(e) => return Err(DzipError::Io(e)),
-            }
+    // TODO: Implement other decompression methods (e.g. CHUNK_DZ)
+    Err(DzipError::UnsupportedCompression(chunk.flags))
+}
+
+/// Whether a compressed chunk may hold its data stored raw, which its header marks by
+/// listing equal lengths. Whether it really is raw depends on the data not starting like
+/// the codec's stream.
+fn holds_raw_data(chunk: &Chunk) -> bool {
+    chunk.compressed_length == chunk.decompressed_length
+}
+
+/// The data of a chunk stored raw. Anything after the original size, e.g. padding or a
+/// signature after the last chunk of a volume, is not part of it.
+fn raw_data(mut buffer: Vec<u8>, chunk: &Chunk) -> Vec<u8> {
+    buffer.truncate(chunk.decompressed_length as usize);
+    buffer
+}
+
+/// The data of a chunk stored as it is (Copy, or RandomAccess without a method), which
+/// must hold at least its original size. Fewer bytes usually mean a truncated volume, and
+/// fail rather than give a short file.
+fn copied_data(buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+    if buffer.len() < chunk.decompressed_length as usize {
+        return Err(DzipError::Decompression(format!(
+            "Stored chunk at volume {} offset {} holds {} bytes, {} expected; the volume may be truncated",
+            chunk.file,
+            chunk.offset,
+            buffer.len(),
+            chunk.decompressed_length
+        )));
+    }
+    Ok(raw_data(buffer, chunk))
+}
+
+/// Decompresses a ZLIB chunk, whose framing is sniffed from its first bytes: archives
+/// hold zlib streams, but older dzip-rs versions wrote gzip and some tools raw deflate.
+///
+/// - `1f 8b`: gzip
+/// - A valid zlib header (deflate method, check bits): zlib
+/// - Anything else, or a zlib header that fails to decode: raw deflate
+///
+/// Chunks that may hold raw data (see [`holds_raw_data`]) and do not inflate to their
+/// listed size are returned as stored.
+fn decode_zlib(buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+    let expected = chunk.decompressed_length as usize;
+    let inflate = |decoder: &mut dyn Read| -> std::io::Result<Vec<u8>> {
+        let mut decompressed = Vec::with_capacity(expected);
+        decoder
+            .take(output_limit(chunk))
+            .read_to_end(&mut decompressed)?;
+        Ok(decompressed)
+    };
+
+    if buffer.starts_with(&[0x1f, 0x8b]) {
+        let mut decoder = flate2::read::GzDecoder::new(&buffer[..]).take(output_limit(chunk));
+        let mut decompressed = Vec::with_capacity(expected);
+        return match decoder.read_to_end(&mut decompressed) {
+            Ok(_) => check_overrun(decompressed, chunk),
+            // The full length came out, the footer is likely missing
+            Err(_) if decompressed.len() == expected => Ok(decompressed),
+            Err(e) => Err(DzipError::Io(e)),
+        };
+    }
+
+    let zlib_error = if has_zlib_header(&buffer) {
+        match inflate(&mut flate2::read::ZlibDecoder::new(&buffer[..])) {
+            Ok(decompressed) => return check_overrun(decompressed, chunk),
+            Err(e) => Some(e),
         }
+    } else {
+        None
+    };
+    match inflate(&mut flate2::read::DeflateDecoder::new(&buffer[..])) {
+        Ok(decompressed) if decompressed.len() == expected => Ok(decompressed),
+        // Heuristic for the "Equal Lengths" quirk
+        _ if holds_raw_data(chunk) => Ok(raw_data(buffer, chunk)),
+        Ok(decompressed) => check_overrun(decompressed, chunk),
+        Err(e) => Err(DzipError::Io(zlib_error.unwrap_or(e))),
+    }
+}
 
-        if (chunk.flags & CHUNK_LZMA) != 0 {
-            // Heuristic for "Equal Lengths" Quirk ambiguity:
-            if chunk.compressed_length == chunk.decompressed_length
-                && (buffer.is_empty() || buffer[0] != 0x5d)
-            {
-                // Does not start with typical LZMA property byte. Likely Raw.
-                return Ok(buffer);
-            }
+/// How many bytes to let a decoder produce for `chunk`: one more than its listed size, so
+/// that overruns are detected (see [`check_overrun`]) without letting a small chunk expand
+/// without bound.
+fn output_limit(chunk: &Chunk) -> u64 {
+    chunk.decompressed_length as u64 + 1
+}
 
-            let mut decompressed = Vec::with_capacity(chunk.decompressed_length as usize);
-            let mut reader = std::io::Cursor::new(&buffer[..]);
-            // lzma-rs usually handles LZMA headers automatically.
-            match lzma_rs::lzma_decompress(&mut reader, &mut decompressed) {
-                Ok(_) => return Ok(decompressed),
-                Err(e) => {
-                    let threshold = (chunk.compressed_length as f32 * 0.8) as usize;
-                    if !decompressed.is_empty() && decompressed.len() > threshold {
-                        eprintln!(
-                            "WARN: LZMA decompression finished with error '{}' but produced {} bytes (> 80%). Returning partial data.",
-                            e,
-                            decompressed.len()
-                        );
-                        return Ok(decompressed);
-                    }
-                    if chunk.compressed_length == chunk.decompressed_length {
-                        eprintln!(
-                            "debug: LZMA failed with error '{}' but lengths match (fallback to raw).",
-                            e
-                        );
-                        return Ok(buffer);
-                    }
-                    return Err(DzipError::Io(std::io::Error::other(e)));
-                }
+/// Fails if a decoder produced more than the listed size of `chunk`.
+fn check_overrun(decompressed: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+    if decompressed.len() > chunk.decompressed_length as usize {
+        return Err(DzipError::Decompression(format!(
+            "chunk expands to more than the {} bytes listed for it",
+            chunk.decompressed_length
+        )));
+    }
+    Ok(decompressed)
+}
+
+/// Collects decoder output up to `limit` bytes, failing writes beyond it. The part of a
+/// write that still fits is kept, so overruns show in the length of `data`.
+struct BoundedOutput<'a> {
+    data: &'a mut Vec<u8>,
+    limit: usize,
+}
+
+impl std::io::Write for BoundedOutput<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let room = self.limit - self.data.len();
+        if room == 0 && !buf.is_empty() {
+            return Err(std::io::Error::other("decoder output limit reached"));
+        }
+        let n = buf.len().min(room);
+        self.data.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Whether `buffer` starts with a zlib header: deflate with a window of at most 32 KiB,
+/// and check bits making the first two bytes a multiple of 31.
+fn has_zlib_header(buffer: &[u8]) -> bool {
+    match buffer {
+        [cmf, flg, ..] => {
+            cmf & 0x0F == 8 && cmf >> 4 <= 7 && u16::from_be_bytes([*cmf, *flg]) % 31 == 0
+        }
+        _ => false,
+    }
+}
+
+/// Whether a chunk stores its data uncompressed. A JPEG chunk stored smaller than its image
+/// had its tables stripped, so it is not raw; see [`decode_jpeg`].
+fn is_stored_raw(chunk: &Chunk) -> bool {
+    let flags = chunk.flags;
+    (flags & CHUNK_COPYCOMP) != 0
+        || ((flags & CHUNK_JPEG) != 0 && chunk.compressed_length >= chunk.decompressed_length)
+        || ((flags & CHUNK_RANDOMACCESS) != 0
+            && (flags
+                & (CHUNK_LZMA
+                    | CHUNK_ZLIB
+                    | CHUNK_BZIP
+                    | CHUNK_DZ
+                    | CHUNK_ZERO
+                    | CHUNK_MP3
+                    | CHUNK_ZSTD))
+                == 0)
+}
+
+/// Decodes an MP3 chunk stored as a plain frame stream.
+///
+/// Chunks stored smaller than their original size carry a transformed frame stream,
+/// which is not supported; they are rejected rather than extracted as raw bytes. The
+/// contents are not checked, as a chunk may hold any part of a file (see
+/// [`check_media_data`]).
+fn decode_mp3(mut buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+    if chunk.compressed_length < chunk.decompressed_length {
+        return Err(DzipError::Unsupported(format!(
+            "transformed MP3 chunk ({} bytes stored for {} bytes of audio)",
+            chunk.compressed_length, chunk.decompressed_length
+        )));
+    }
+    buffer.truncate(chunk.decompressed_length as usize);
+    Ok(buffer)
+}
+
+/// Whether `data` starts like an `.mp3` file: an ID3v2 tag or an MPEG audio frame header.
+pub(crate) fn is_mp3_stream(data: &[u8]) -> bool {
+    data.starts_with(b"ID3") || (data.len() >= 2 && data[0] == 0xFF && (data[1] & 0xE0) == 0xE0)
+}
+
+/// Decodes a JPEG chunk stored as a `.jpg` file, or a part of one.
+///
+/// Chunks stored smaller than their original size carry the entropy-coded image without
+/// its tables, which is not supported; they are rejected rather than extracted as an
+/// unreadable image. The contents are checked per file by [`check_media_data`].
+fn decode_jpeg(mut buffer: Vec<u8>, chunk: &Chunk) -> Result<Vec<u8>> {
+    if chunk.compressed_length < chunk.decompressed_length {
+        return Err(DzipError::Unsupported(format!(
+            "transformed JPEG chunk ({} bytes stored for {} bytes of image): reattaching the \
+             stripped quantization (DQT) and Huffman (DHT) tables is not implemented",
+            chunk.compressed_length, chunk.decompressed_length
+        )));
+    }
+    buffer.truncate(chunk.decompressed_length as usize);
+    Ok(buffer)
+}
+
+/// Checks the data of a file whose first chunk has `flags` against its MP3 or JPEG flag,
+/// and describes what is wrong with it, if anything. Data of other methods always passes.
+///
+/// `data` is the whole file if `complete`, or only its start, e.g. its first chunk; only
+/// the start of the file is then checked. Readers only warn about problems, as the bytes
+/// are extracted as stored either way.
+pub fn check_media_data(flags: u16, data: &[u8], complete: bool) -> Option<String> {
+    if data.is_empty() {
+        return None;
+    }
+    if flags & CHUNK_MP3 != 0 {
+        return (!is_mp3_stream(data))
+            .then(|| "MP3 data does not start with an ID3 tag or MPEG frame sync".to_string());
+    }
+    if flags & CHUNK_JPEG == 0 {
+        return None;
+    }
+    if !is_jpeg_stream(data) {
+        return Some("JPEG data does not start with a start of image marker (FFD8)".to_string());
+    }
+    if !complete {
+        return None;
+    }
+    let mut missing = missing_jpeg_segments(data);
+    if !data.ends_with(&[0xFF, 0xD9]) {
+        missing.push("end of image marker (FFD9)");
+    }
+    (!missing.is_empty()).then(|| format!("JPEG data lacks {}", missing.join(", ")))
+}
+
+/// Whether `data` starts like a `.jpg` file: a start of image marker.
+pub(crate) fn is_jpeg_stream(data: &[u8]) -> bool {
+    data.starts_with(&[0xFF, 0xD8])
+}
+
+/// The header segments a baseline decoder needs that are missing from a JPEG file starting
+/// with a start of image marker. Only the header up to the start of scan is examined.
+fn missing_jpeg_segments(data: &[u8]) -> Vec<&'static str> {
+    let (mut dqt, mut dht, mut sof, mut sos) = (false, false, false, false);
+    let mut pos = 2;
+    while pos + 4 <= data.len() && data[pos] == 0xFF {
+        let marker = data[pos + 1];
+        match marker {
+            // Fill byte before a marker
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // Markers without a payload
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            0xDA => {
+                sos = true;
+                break;
             }
+            0xDB => dqt = true,
+            0xC4 => dht = true,
+            0xC0..=0xCF if marker != 0xC8 && marker != 0xCC => sof = true,
+            _ => {}
         }
+        pos += 2 + u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+    }
 
-        // TODO: Implement other decompression methods (e.g. CHUNK_DZ)
-        Err(DzipError::UnsupportedCompression(chunk.flags))
+    let mut missing = Vec::new();
+    if !dqt {
+        missing.push("quantization tables (DQT)");
+    }
+    if !dht {
+        missing.push("Huffman tables (DHT)");
+    }
+    if !sof {
+        missing.push("frame header (SOF)");
+    }
+    if !sos {
+        missing.push("scan header (SOS)");
     }
+    missing
 }
 
+/// A seekable byte stream, such as an open volume.
 pub trait ReadSeek: Read + Seek {}
 impl<T: Read + Seek> ReadSeek for T {}
 
+/// Gives access to the split volumes of an archive.
+///
+/// The main file (volume 0) is read through the [`DzipReader`]; a source only serves the
+/// volumes of [`crate::ArchiveMetadata::volume_files`]. See
+/// [`crate::volume::FileSystemVolumeManager`] and [`crate::volume::MemoryVolumeManager`].
 pub trait VolumeSource {
     /// Open the volume with the given index (1-based, corresponding to the file list)
     fn open_volume(&mut self, id: u16) -> Result<&mut dyn ReadSeek>;
 }
 
+/// Whether the compressed length of a chunk is only a placeholder: compressed chunks
+/// whose header lists the same compressed and decompressed length. [`correct_chunk_sizes`]
+/// replaces it by the space up to the next chunk.
+pub fn has_placeholder_length(chunk: &Chunk) -> bool {
+    let is_compressed = (chunk.flags & (CHUNK_LZMA | CHUNK_ZLIB | CHUNK_BZIP | CHUNK_DZ)) != 0;
+    is_compressed && chunk.compressed_length == chunk.decompressed_length
+}
+
 /// Corrects chunk sizes based on actual file boundaries.
 ///
 /// Some archives (like testnew.dz) have incorrect compressed_length headers (e.g., listing uncompressed size).
 /// This function clamps compressed lengths to the available space between chunks or EOF.
+/// The space after the last chunk of a file may include trailing padding or a signature,
+/// so a placeholder length there is only extended to EOF if another placeholder of the
+/// archive proved not to be the real size; otherwise it is kept if it fits.
+/// Chunks starting past the end of their file cannot be read at all and are reported as
+/// [`DzipError::ChunkOutOfBounds`]. A real (not placeholder) length running into the next
+/// chunk means a corrupt header and is reported as [`DzipError::ChunkOverlap`]; only the
+/// last chunk of a file is clamped to its end. Files missing from `file_sizes` are left
+/// uncorrected.
 ///
 /// # Arguments
 /// * `chunks` - The list of chunks to correct.
@@ -325,23 +1172,55 @@ pub trait VolumeSource {
 pub fn correct_chunk_sizes(
     chunks: &mut [crate::format::Chunk],
     file_sizes: &std::collections::HashMap<u16, u64>,
-) {
+) -> Result<()> {
     use crate::format::*;
     let mut chunks_by_file: std::collections::HashMap<u16, Vec<usize>> =
         std::collections::HashMap::new();
+    // Zero chunks store no data and their offset may be virtual, so they neither need
+    // correcting nor bound the chunk before them.
     for (i, chunk) in chunks.iter().enumerate() {
-        chunks_by_file.entry(chunk.file).or_default().push(i);
+        if (chunk.flags & CHUNK_ZERO) == 0 {
+            chunks_by_file.entry(chunk.file).or_default().push(i);
+        }
     }
 
+    let mut files = Vec::new();
     for (file_id, mut indices) in chunks_by_file {
-        indices.sort_by_key(|&i| chunks[i].offset);
+        // Empty chunks sharing an offset with the next one come first
+        indices.sort_by_key(|&i| (chunks[i].offset, chunks[i].compressed_length));
+        if let Some(&file_size) = file_sizes.get(&file_id) {
+            files.push((file_id, file_size, indices));
+        }
+    }
 
-        let file_size = *file_sizes.get(&file_id).unwrap_or(&0);
+    // Whether the writer of the archive used placeholders: some placeholder length differs
+    // from the space its chunk takes up to the next one, or exceeds the end of the file
+    let uses_placeholders = files.iter().any(|(_, file_size, indices)| {
+        indices.iter().enumerate().any(|(i, &idx)| {
+            let chunk = &chunks[idx];
+            let listed = chunk.compressed_length as u64;
+            has_placeholder_length(chunk)
+                && match indices.get(i + 1) {
+                    Some(&next) => chunks[next].offset as u64 != chunk.offset as u64 + listed,
+                    None => chunk.offset as u64 + listed > *file_size,
+                }
+        })
+    });
 
+    for (file_id, file_size, indices) in files {
         for i in 0..indices.len() {
             let idx = indices[i];
             let chunk_offset = chunks[idx].offset as u64;
 
+            if chunk_offset > file_size {
+                return Err(DzipError::ChunkOutOfBounds {
+                    chunk: idx as u32,
+                    volume: file_id,
+                    offset: chunks[idx].offset,
+                    volume_len: file_size,
+                });
+            }
+
             // Determine the limit (end of region)
             let limit = if i + 1 < indices.len() {
                 chunks[indices[i + 1]].offset as u64
@@ -354,13 +1233,13 @@ pub fn correct_chunk_sizes(
             // If header claims more than available, clamp it.
             // BMS Logic: If SIZE == ZSIZE (equal lengths) for compressed chunks, it means
             // the size is unknown/placeholder, so we SHOULD use the available size (next offset - current).
-            let is_compressed =
-                (chunks[idx].flags & (CHUNK_LZMA | CHUNK_ZLIB | CHUNK_BZIP | CHUNK_DZ)) != 0;
-            let equal_sizes = chunks[idx].compressed_length == chunks[idx].decompressed_length;
-
-            if is_compressed && equal_sizes {
-                // Always update to available size (whether larger or smaller)
-                if chunks[idx].compressed_length != available as u32 {
+            if has_placeholder_length(&chunks[idx]) {
+                // Update to the available size, whether larger or smaller, unless only the
+                // end of the file follows and the listed length may well be the real one
+                let is_last = i + 1 == indices.len();
+                let fits = chunks[idx].compressed_length as u64 <= available;
+                let trusted = is_last && fits && !uses_placeholders;
+                if chunks[idx].compressed_length != available as u32 && !trusted {
                     log::debug!(
                         "Correcting Equal-Size Chunk {} from {} to {} (File {}, Offset {})",
                         idx,
@@ -372,6 +1251,13 @@ pub fn correct_chunk_sizes(
                     chunks[idx].compressed_length = available as u32;
                 }
             } else if (chunks[idx].compressed_length as u64) > available {
+                if let Some(&next) = indices.get(i + 1) {
+                    return Err(DzipError::ChunkOverlap {
+                        chunk: idx as u32,
+                        next: next as u32,
+                        volume: file_id,
+                    });
+                }
                 log::debug!(
                     "Correcting Chunk {} size from {} to {} (File {}, Offset {})",
                     idx,
@@ -384,4 +1270,5 @@ pub fn correct_chunk_sizes(
             }
         }
     }
+    Ok(())
 }
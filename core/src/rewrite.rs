@@ -0,0 +1,50 @@
+//! Helpers shared by the functions that write an existing archive anew, such as
+//! [`crate::combine_volumes`] and [`crate::transcode`].
+
+use crate::error::Result;
+use crate::format::{CHUNK_ZERO, Chunk};
+use crate::reader::{ReadSeek, VolumeSource};
+use std::io::{Read, SeekFrom, Write};
+use std::path::Path;
+
+/// Calls `write` with a temporary path next to `output_path`, and renames the file written
+/// there to `output_path` once `write` succeeds, so that `output_path` may be the archive
+/// being read. The temporary file is removed if `write` fails.
+pub(crate) fn write_then_rename(
+    output_path: &Path,
+    write: impl FnOnce(&Path) -> Result<()>,
+) -> Result<()> {
+    let mut tmp_name = output_path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = output_path.with_file_name(tmp_name);
+    if let Err(e) = write(&tmp_path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    std::fs::rename(&tmp_path, output_path)?;
+    Ok(())
+}
+
+/// Copies a chunk as stored from `main` (Volume 0) or its volume in `volumes` to `out`,
+/// and returns the number of bytes copied. ZERO chunks store nothing.
+pub(crate) fn copy_stored_chunk(
+    chunk: &Chunk,
+    main: &mut dyn ReadSeek,
+    volumes: &mut dyn VolumeSource,
+    out: &mut dyn Write,
+) -> Result<u64> {
+    if chunk.flags & CHUNK_ZERO != 0 || chunk.compressed_length == 0 {
+        return Ok(0);
+    }
+    let len = chunk.compressed_length as u64;
+    let input: &mut dyn ReadSeek = match chunk.file {
+        0 => main,
+        volume => volumes.open_volume(volume)?,
+    };
+    input.seek(SeekFrom::Start(chunk.offset as u64))?;
+    let copied = std::io::copy(&mut input.take(len), out)?;
+    if copied != len {
+        return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(len)
+}
@@ -0,0 +1,99 @@
+//! Recompressing the chunks of an existing archive with another method.
+
+use crate::archive::ArchiveMetadata;
+use crate::error::{DzipError, Result};
+use crate::reader::{DzipReader, VolumeSource};
+use crate::rewrite::{copy_stored_chunk, write_then_rename};
+use crate::volume::FileSystemVolumeManager;
+use crate::writer::{CompressionMethod, DzipWriter, chunk_length, compress_data};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// Writes the archive whose main file is `archive_path` to `output_path`, with the chunks
+/// of every file whose logical path (with `/` separators) `filter` accepts recompressed
+/// with `method`.
+///
+/// Chunks are decompressed and compressed again one at a time; the chunks of other files are
+/// copied as stored. A chunk shared by several files is recompressed if any of them is
+/// accepted. The file map, names and directories are kept, and so is the trailer, as the
+/// decompressed data does not change. Like [`crate::combine_volumes`], the output is a
+/// single main file holding every chunk, written next to its final path and renamed once
+/// complete, so `output_path` may be `archive_path`.
+///
+/// Encrypted archives are rejected. Names are read and written as UTF-8. Returns the
+/// metadata of the new archive.
+pub fn transcode(
+    archive_path: &Path,
+    output_path: &Path,
+    method: CompressionMethod,
+    filter: &dyn Fn(&str) -> bool,
+) -> Result<ArchiveMetadata> {
+    let mut reader = DzipReader::new(File::open(archive_path)?);
+    let mut metadata = ArchiveMetadata::load(&mut reader)?;
+    drop(reader);
+    if metadata.is_encrypted() {
+        return Err(DzipError::Unsupported(
+            "Transcoding encrypted archives is not supported".to_string(),
+        ));
+    }
+
+    let mut volumes = FileSystemVolumeManager::new(
+        archive_path
+            .parent()
+            .unwrap_or(Path::new("."))
+            .to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    if let Some(name) = archive_path.file_name() {
+        volumes.set_numbered_fallback(&name.to_string_lossy());
+    }
+    metadata.correct_chunk_sizes(&mut volumes)?;
+
+    let mut selected = HashSet::new();
+    for (i, (_, chunk_ids)) in metadata.file_map.iter().enumerate() {
+        if filter(&metadata.resolve_path(i)?) {
+            selected.extend(chunk_ids.iter().copied());
+        }
+    }
+
+    write_then_rename(output_path, |tmp_path| {
+        write_transcoded(
+            archive_path,
+            tmp_path,
+            &mut metadata,
+            &selected,
+            method,
+            &mut volumes,
+        )
+    })?;
+    Ok(metadata)
+}
+
+fn write_transcoded(
+    archive_path: &Path,
+    tmp_path: &Path,
+    metadata: &mut ArchiveMetadata,
+    selected: &HashSet<u32>,
+    method: CompressionMethod,
+    volumes: &mut dyn VolumeSource,
+) -> Result<()> {
+    let mut writer = DzipWriter::new(BufWriter::new(File::create(tmp_path)?));
+    let mut reader = DzipReader::new(File::open(archive_path)?);
+    let mut main = BufReader::new(File::open(archive_path)?);
+    writer.write_archive_with(metadata, &mut |id, chunk, out| {
+        if selected.contains(&id) {
+            let data = reader.read_chunk_data_with_volumes(id, chunk, volumes)?;
+            let (flags, body) = compress_data(&data, method)?;
+            out.write_all(&body)?;
+            chunk.flags = flags;
+            chunk.compressed_length = chunk_length(body.len())?;
+            chunk.decompressed_length = chunk_length(data.len())?;
+            return Ok(body.len() as u64);
+        }
+        copy_stored_chunk(chunk, &mut main, volumes, out)
+    })?;
+    writer.into_inner().flush()?;
+    Ok(())
+}
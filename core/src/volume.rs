@@ -1,15 +1,20 @@
 use crate::error::{DzipError, Result};
-use crate::reader::{ReadSeek, VolumeSource};
+use crate::pack::PackSink;
+use crate::reader::{DEFAULT_BUFFER_SIZE, ReadSeek, VolumeSource, buffer_size};
+use crate::writer::WriteSeek;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::fs::File;
+use std::io::{BufReader, BufWriter, Cursor};
 use std::path::PathBuf;
 
 /// A volume manager that reads volumes from the filesystem using a base directory and a file list.
 pub struct FileSystemVolumeManager {
     base_dir: PathBuf,
     file_list: Vec<String>,
-    open_files: HashMap<u16, File>,
+    open_files: HashMap<u16, BufReader<File>>,
+    buffer_size: usize,
+    numbered_fallback: Option<String>,
 }
 
 impl FileSystemVolumeManager {
@@ -23,8 +28,54 @@ impl FileSystemVolumeManager {
             base_dir,
             file_list,
             open_files: HashMap::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            numbered_fallback: None,
         }
     }
+
+    /// Looks for volumes that are missing from the file list or from the disk under the
+    /// numbered names [`crate::PackBuilder`] gives them, `name.d01`, `name.d02`, ..., where
+    /// `name` is `main_file_name` without its extension.
+    pub fn set_numbered_fallback(&mut self, main_file_name: &str) {
+        self.numbered_fallback = Some(main_file_name.to_string());
+    }
+
+    fn open_file(&self, id: u16) -> Result<File> {
+        let listed = self.file_list.get((id - 1) as usize);
+        let error = match listed {
+            Some(file_name) => {
+                let path = self.base_dir.join(file_name);
+                log::debug!("Opening volume {}: {}", id, path.display());
+                match File::open(&path) {
+                    Ok(file) => return Ok(file),
+                    Err(e) => DzipError::VolumeOpenError(id, e.to_string()),
+                }
+            }
+            None => DzipError::VolumeNotFound(id),
+        };
+
+        let Some(main_file_name) = &self.numbered_fallback else {
+            return Err(error);
+        };
+        let numbered =
+            crate::pack::split_volume_name(std::slice::from_ref(main_file_name), id as usize);
+        let Ok(file) = File::open(self.base_dir.join(&numbered)) else {
+            return Err(error);
+        };
+        log::warn!(
+            "Volume {} ({}) not found, using {} instead",
+            id,
+            listed.map_or("not in the volume list", String::as_str),
+            numbered
+        );
+        Ok(file)
+    }
+
+    /// Sets the read buffer size for volumes opened from now on. Zero falls back to
+    /// [`DEFAULT_BUFFER_SIZE`].
+    pub fn set_buffer_size(&mut self, size: usize) {
+        self.buffer_size = buffer_size(size);
+    }
 }
 
 impl VolumeSource for FileSystemVolumeManager {
@@ -35,25 +86,250 @@ impl VolumeSource for FileSystemVolumeManager {
         // In dzip-rs, VolumeSource is used for "Auxiliary" volumes.
         // The DzipReader usually manages the main reader.
         if id == 0 {
-            return Err(DzipError::Io(std::io::Error::other(
-                "Volume ID 0 is reserved for main file",
-            )));
+            // Volume 0 is the main file, which is read through the DzipReader
+            return Err(DzipError::VolumeNotFound(0));
         }
 
-        let list_index = (id - 1) as usize;
-        if list_index >= self.file_list.len() {
-            return Err(DzipError::VolumeNotFound(id));
+        if !self.open_files.contains_key(&id) {
+            let file = self.open_file(id)?;
+            self.open_files
+                .insert(id, BufReader::with_capacity(self.buffer_size, file));
         }
+        Ok(self.open_files.get_mut(&id).unwrap())
+    }
+}
 
+/// A volume manager that serves volumes from memory, keyed by their name in the file list.
+///
+/// Useful for tests and for archives that never touch the filesystem, e.g. fetched over the
+/// network. The main file is read by wrapping its buffer in a [`Cursor`] for the `DzipReader`.
+pub struct MemoryVolumeManager {
+    file_list: Vec<String>,
+    volumes: HashMap<String, Cursor<Vec<u8>>>,
+}
+
+impl MemoryVolumeManager {
+    /// Creates a new MemoryVolumeManager.
+    ///
+    /// # Arguments
+    /// * `file_list` - List of filenames for auxiliary volumes (Volume 1, Volume 2, ...).
+    /// * `volumes` - Contents of the auxiliary volumes, keyed by filename.
+    pub fn new(file_list: Vec<String>, volumes: HashMap<String, Vec<u8>>) -> Self {
+        Self {
+            file_list,
+            volumes: volumes
+                .into_iter()
+                .map(|(name, data)| (name, Cursor::new(data)))
+                .collect(),
+        }
+    }
+}
+
+impl VolumeSource for MemoryVolumeManager {
+    fn open_volume(&mut self, id: u16) -> Result<&mut dyn ReadSeek> {
+        if id == 0 {
+            // Volume 0 is the main file, which is read through the DzipReader
+            return Err(DzipError::VolumeNotFound(0));
+        }
+
+        let file_name = self
+            .file_list
+            .get((id - 1) as usize)
+            .ok_or(DzipError::VolumeNotFound(id))?;
+        match self.volumes.get_mut(file_name) {
+            Some(volume) => Ok(volume),
+            None => Err(DzipError::VolumeOpenError(
+                id,
+                format!("{} not provided", file_name),
+            )),
+        }
+    }
+}
+
+/// A pack sink that creates volumes as files in a directory.
+pub struct FileSystemPackSink {
+    base_dir: PathBuf,
+    open_files: HashMap<u16, BufWriter<File>>,
+    buffer_size: usize,
+}
+
+impl FileSystemPackSink {
+    /// Creates a new FileSystemPackSink writing into `base_dir`, which must exist.
+    pub fn new(base_dir: PathBuf) -> Self {
+        Self {
+            base_dir,
+            open_files: HashMap::new(),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+        }
+    }
+
+    /// Sets the write buffer size for volumes created from now on. Zero falls back to
+    /// [`DEFAULT_BUFFER_SIZE`].
+    pub fn set_buffer_size(&mut self, size: usize) {
+        self.buffer_size = buffer_size(size);
+    }
+}
+
+impl PackSink for FileSystemPackSink {
+    fn open_volume(&mut self, id: u16, name: &str) -> Result<&mut dyn WriteSeek> {
         match self.open_files.entry(id) {
             Entry::Occupied(e) => Ok(e.into_mut()),
             Entry::Vacant(e) => {
-                let file_name = &self.file_list[list_index];
-                let path = self.base_dir.join(file_name);
-                log::debug!("Opening volume {}: {}", id, path.display());
-                let file =
-                    File::open(&path).map_err(|e| DzipError::VolumeOpenError(id, e.to_string()))?;
-                Ok(e.insert(file))
+                let path = self.base_dir.join(name);
+                log::debug!("Creating volume {}: {}", id, path.display());
+                let file = File::create(&path)?;
+                Ok(e.insert(BufWriter::with_capacity(self.buffer_size, file)))
+            }
+        }
+    }
+}
+
+/// A pack sink that keeps the written volumes in memory.
+///
+/// The result plugs into [`MemoryVolumeManager`] for reading the archive back.
+#[derive(Default)]
+pub struct MemoryPackSink {
+    volumes: HashMap<u16, (String, Cursor<Vec<u8>>)>,
+}
+
+impl MemoryPackSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Contents of the written volumes, keyed by filename.
+    pub fn into_volumes(self) -> HashMap<String, Vec<u8>> {
+        self.volumes
+            .into_values()
+            .map(|(name, data)| (name, data.into_inner()))
+            .collect()
+    }
+
+    /// The main file and a [`MemoryVolumeManager`] serving the split volumes, to read the
+    /// archive back without looking up volume names. `None` if nothing was written.
+    pub fn into_archive(mut self) -> Option<(Vec<u8>, MemoryVolumeManager)> {
+        let (_, main) = self.volumes.remove(&0)?;
+        let last = self.volumes.keys().copied().max().unwrap_or(0);
+        let file_list = (1..=last)
+            .map(|id| {
+                self.volumes
+                    .get(&id)
+                    .map_or("", |(name, _)| name)
+                    .to_string()
+            })
+            .collect();
+        let volumes = MemoryVolumeManager::new(file_list, self.into_volumes());
+        Some((main.into_inner(), volumes))
+    }
+}
+
+impl PackSink for MemoryPackSink {
+    fn open_volume(&mut self, id: u16, name: &str) -> Result<&mut dyn WriteSeek> {
+        let (_, volume) = self
+            .volumes
+            .entry(id)
+            .or_insert_with(|| (name.to_string(), Cursor::new(Vec::new())));
+        Ok(volume)
+    }
+}
+
+/// A memory-mapped volume that can be shared between threads.
+#[cfg(feature = "mmap")]
+#[derive(Clone)]
+pub struct SharedMmap(std::sync::Arc<memmap2::Mmap>);
+
+#[cfg(feature = "mmap")]
+impl AsRef<[u8]> for SharedMmap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// A volume manager that memory-maps the main file and all auxiliary volumes up front.
+///
+/// Reads become plain memory copies instead of buffered seeks and `read` syscalls, which
+/// pays off for multi-gigabyte archives. Cloning is cheap and shares the mappings, so each
+/// rayon worker can take its own clone.
+///
+/// The files must not be modified while they are mapped.
+#[cfg(feature = "mmap")]
+#[derive(Clone)]
+pub struct MmapVolumeManager {
+    main: SharedMmap,
+    volumes: Vec<Option<SharedMmap>>,
+    cursors: HashMap<u16, Cursor<SharedMmap>>,
+}
+
+#[cfg(feature = "mmap")]
+impl MmapVolumeManager {
+    /// Maps the main file and every auxiliary volume found next to it.
+    ///
+    /// Volumes that cannot be opened are reported when a chunk in them is read.
+    ///
+    /// # Arguments
+    /// * `main_path` - Path of the main archive file.
+    /// * `file_list` - List of filenames for auxiliary volumes (Volume 1, Volume 2, ...).
+    pub fn new(main_path: &std::path::Path, file_list: &[String]) -> Result<Self> {
+        let base_dir = main_path.parent().unwrap_or(std::path::Path::new("."));
+        let main = Self::map(main_path)?;
+        let volumes = file_list
+            .iter()
+            .map(|name| {
+                let path = base_dir.join(name);
+                Self::map(&path)
+                    .inspect_err(|e| log::debug!("Cannot map volume {}: {}", path.display(), e))
+                    .ok()
+            })
+            .collect();
+        Ok(Self {
+            main,
+            volumes,
+            cursors: HashMap::new(),
+        })
+    }
+
+    fn map(path: &std::path::Path) -> Result<SharedMmap> {
+        let file = File::open(path)?;
+        // SAFETY: The mapping is read-only; the caller must not modify the files while mapped.
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Ok(SharedMmap(std::sync::Arc::new(mmap)))
+    }
+
+    /// A reader over the main file, for use with `DzipReader`.
+    pub fn main_reader(&self) -> Cursor<SharedMmap> {
+        Cursor::new(self.main.clone())
+    }
+
+    /// Mapped length of a volume (0 for the main file), if it is present.
+    pub fn volume_len(&self, id: u16) -> Option<u64> {
+        if id == 0 {
+            return Some(self.main.as_ref().len() as u64);
+        }
+        self.volumes
+            .get((id - 1) as usize)?
+            .as_ref()
+            .map(|m| m.as_ref().len() as u64)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl VolumeSource for MmapVolumeManager {
+    fn open_volume(&mut self, id: u16) -> Result<&mut dyn ReadSeek> {
+        if id == 0 {
+            // Volume 0 is the main file, which is read through the DzipReader
+            return Err(DzipError::VolumeNotFound(0));
+        }
+
+        match self.cursors.entry(id) {
+            Entry::Occupied(e) => Ok(e.into_mut()),
+            Entry::Vacant(e) => {
+                let mmap = self
+                    .volumes
+                    .get((id - 1) as usize)
+                    .ok_or(DzipError::VolumeNotFound(id))?
+                    .clone()
+                    .ok_or_else(|| DzipError::VolumeOpenError(id, "not mapped".to_string()))?;
+                Ok(e.insert(Cursor::new(mmap)))
             }
         }
     }
@@ -1,68 +1,172 @@
 use crate::DzipError;
+use crate::archive::ArchiveMetadata;
+use crate::encoding::NameEncoding;
 use crate::error::Result;
 use crate::format::*;
-use byteorder::{LittleEndian, WriteBytesExt};
+use byteorder::{BigEndian, LittleEndian, WriteBytesExt};
 use log::warn;
 use serde::{Deserialize, Serialize};
-use std::io::{Seek, Write};
+use std::io::{self, Read, Seek, Write};
 use std::str::FromStr;
 
 pub struct DzipWriter<W: Write + Seek> {
     writer: W,
+    name_encoding: NameEncoding,
+    endian: Endian,
+    /// Written instead of the version of the settings, if set
+    forced_version: Option<FormatVersion>,
+    /// Set by `write_archive_settings`, picks the width of IDs and counts
+    version: FormatVersion,
 }
 
 impl<W: Write + Seek> DzipWriter<W> {
     pub fn new(writer: W) -> Self {
-        Self { writer }
+        Self {
+            writer,
+            name_encoding: NameEncoding::default(),
+            endian: Endian::default(),
+            forced_version: None,
+            version: FormatVersion::Legacy,
+        }
+    }
+
+    /// Sets the encoding used to encode file, directory and volume names (UTF-8 by default).
+    pub fn set_name_encoding(&mut self, encoding: NameEncoding) {
+        self.name_encoding = encoding;
+    }
+
+    /// Sets the byte order of the header (little-endian by default). The trailer is always
+    /// written little-endian.
+    pub fn set_endian(&mut self, endian: Endian) {
+        self.endian = endian;
+    }
+
+    /// Writes the header in `version` instead of the version of the archive settings, e.g.
+    /// to upgrade an archive (see [`crate::upgrade`]).
+    pub fn set_format_version(&mut self, version: FormatVersion) {
+        self.forced_version = Some(version);
+    }
+
+    fn write_u16_field(&mut self, value: u16) -> io::Result<()> {
+        match self.endian {
+            Endian::Little => self.writer.write_u16::<LittleEndian>(value),
+            Endian::Big => self.writer.write_u16::<BigEndian>(value),
+        }
+    }
+
+    fn write_u32_field(&mut self, value: u32) -> io::Result<()> {
+        match self.endian {
+            Endian::Little => self.writer.write_u32::<LittleEndian>(value),
+            Endian::Big => self.writer.write_u32::<BigEndian>(value),
+        }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
     }
 
     pub fn write_archive_settings(&mut self, settings: &ArchiveSettings) -> Result<()> {
         log::debug!("Writing archive settings: {:?}", settings);
-        self.writer.write_u32::<LittleEndian>(settings.header)?; // Should be 0x5A525444
-        self.writer
-            .write_u16::<LittleEndian>(settings.num_user_files)?;
-        self.writer
-            .write_u16::<LittleEndian>(settings.num_directories)?;
-        self.writer.write_u8(settings.version)?;
+        self.version = match self.forced_version {
+            Some(version) => version,
+            None => settings.format_version()?,
+        };
+        if !self.version.is_wide()
+            && (settings.num_user_files > u16::MAX as u32
+                || settings.num_directories > u16::MAX as u32)
+        {
+            return Err(DzipError::Config(format!(
+                "{} files and {} directories exceed the version {} format, use version {}",
+                settings.num_user_files, settings.num_directories, VERSION_LEGACY, VERSION_WIDE
+            )));
+        }
+        self.write_u32_field(settings.header)?; // Should be ARCHIVE_MAGIC
+        self.write_u16_field(settings.num_user_files.min(u16::MAX as u32) as u16)?;
+        self.write_u16_field(settings.num_directories.min(u16::MAX as u32) as u16)?;
+        self.writer.write_u8(self.version.into())?;
+        match self.version {
+            FormatVersion::Legacy => {}
+            FormatVersion::Wide => {
+                self.write_u32_field(settings.num_user_files)?;
+                self.write_u32_field(settings.num_directories)?;
+            }
+        }
         Ok(())
     }
 
     pub fn write_strings(&mut self, strings: &[String]) -> Result<()> {
         for s in strings {
-            self.writer.write_all(s.as_bytes())?;
-            self.writer.write_u8(0)?; // null terminator
+            self.writer.write_all(&self.name_encoding.encode(s)?)?;
+            self.writer.write_all(self.name_encoding.terminator())?;
         }
         Ok(())
     }
 
-    pub fn write_file_chunk_map(&mut self, map: &[(u16, Vec<u16>)]) -> Result<()> {
+    /// Writes the string table of the header with the names in the order of `layout`.
+    pub fn write_names(
+        &mut self,
+        user_files: &[String],
+        directories: &[String],
+        layout: StringLayout,
+    ) -> Result<()> {
+        match layout {
+            StringLayout::FilesFirst => {
+                self.write_strings(user_files)?;
+                self.write_strings(directories)
+            }
+            StringLayout::DirectoriesFirst => {
+                self.write_strings(directories)?;
+                self.write_strings(user_files)
+            }
+        }
+    }
+
+    pub fn write_file_chunk_map(&mut self, map: &[(u32, Vec<u32>)]) -> Result<()> {
         for (dir_id, chunks) in map {
-            self.writer.write_u16::<LittleEndian>(*dir_id)?;
+            self.write_id(*dir_id)?;
             for &chunk_id in chunks {
-                self.writer.write_u16::<LittleEndian>(chunk_id)?;
+                self.write_id(chunk_id)?;
+            }
+            self.write_id(self.id_terminator())?; // Terminator
+        }
+        Ok(())
+    }
+
+    /// Writes a directory or chunk ID, or a count of them, in the width of the format.
+    fn write_id(&mut self, id: u32) -> Result<()> {
+        match self.version {
+            FormatVersion::Legacy => {
+                let id = u16::try_from(id).map_err(|_| {
+                    DzipError::Config(format!(
+                        "ID {} exceeds the version {} format, use version {}",
+                        id, VERSION_LEGACY, VERSION_WIDE
+                    ))
+                })?;
+                self.write_u16_field(id)?;
             }
-            self.writer.write_u16::<LittleEndian>(0xFFFF)?; // Terminator
+            FormatVersion::Wide => self.write_u32_field(id)?,
         }
         Ok(())
     }
 
+    fn id_terminator(&self) -> u32 {
+        self.version.id_terminator()
+    }
+
     pub fn write_chunk_settings(&mut self, settings: &ChunkSettings) -> Result<()> {
-        self.writer
-            .write_u16::<LittleEndian>(settings.num_archive_files)?;
-        self.writer.write_u16::<LittleEndian>(settings.num_chunks)?;
+        self.write_u16_field(settings.num_archive_files)?;
+        self.write_id(settings.num_chunks)?;
         Ok(())
     }
 
     pub fn write_chunks(&mut self, chunks: &[Chunk]) -> Result<()> {
         log::debug!("Writing {} chunks", chunks.len());
         for chunk in chunks {
-            self.writer.write_u32::<LittleEndian>(chunk.offset)?;
-            self.writer
-                .write_u32::<LittleEndian>(chunk.compressed_length)?;
-            self.writer
-                .write_u32::<LittleEndian>(chunk.decompressed_length)?;
-            self.writer.write_u16::<LittleEndian>(chunk.flags)?;
-            self.writer.write_u16::<LittleEndian>(chunk.file)?;
+            self.write_u32_field(chunk.offset)?;
+            self.write_u32_field(chunk.compressed_length)?;
+            self.write_u32_field(chunk.decompressed_length)?;
+            self.write_u16_field(chunk.flags)?;
+            self.write_u16_field(chunk.file)?;
         }
         Ok(())
     }
@@ -80,9 +184,162 @@ impl<W: Write + Seek> DzipWriter<W> {
         self.writer.write_u8(settings.big_min_match)?;
         Ok(())
     }
+
+    /// Writes the optional dzip-rs trailer at the current position,
+    /// which must be the end of the main file.
+    pub fn write_trailer(&mut self, trailer: &ArchiveTrailer) -> Result<()> {
+        let mut sections_len = 0u32;
+        if let Some(crcs) = &trailer.chunk_crcs {
+            let len = (crcs.len() * 4) as u32;
+            self.writer
+                .write_u32::<LittleEndian>(TRAILER_TAG_CHUNK_CRC32)?;
+            self.writer.write_u32::<LittleEndian>(len)?;
+            for &crc in crcs {
+                self.writer.write_u32::<LittleEndian>(crc)?;
+            }
+            sections_len += 8 + len;
+        }
+        if let Some(mtimes) = &trailer.file_mtimes {
+            let len = (mtimes.len() * 8) as u32;
+            self.writer
+                .write_u32::<LittleEndian>(TRAILER_TAG_FILE_MTIME)?;
+            self.writer.write_u32::<LittleEndian>(len)?;
+            for &mtime in mtimes {
+                self.writer.write_u64::<LittleEndian>(mtime)?;
+            }
+            sections_len += 8 + len;
+        }
+        if let Some(comment) = &trailer.comment {
+            let len = comment.len() as u32;
+            self.writer.write_u32::<LittleEndian>(TRAILER_TAG_COMMENT)?;
+            self.writer.write_u32::<LittleEndian>(len)?;
+            self.writer.write_all(comment.as_bytes())?;
+            sections_len += 8 + len;
+        }
+        if let Some(params) = &trailer.encryption {
+            self.writer
+                .write_u32::<LittleEndian>(TRAILER_TAG_ENCRYPTION)?;
+            self.writer.write_u32::<LittleEndian>(32)?;
+            self.writer.write_all(&params.salt)?;
+            self.writer.write_all(&params.key_check)?;
+            sections_len += 8 + 32;
+        }
+        if let Some(crc) = trailer.header_crc {
+            self.writer
+                .write_u32::<LittleEndian>(TRAILER_TAG_HEADER_CRC32)?;
+            self.writer.write_u32::<LittleEndian>(4)?;
+            self.writer.write_u32::<LittleEndian>(crc)?;
+            sections_len += 8 + 4;
+        }
+        if let Some(modes) = &trailer.file_modes {
+            let len = (modes.len() * 4) as u32;
+            self.writer
+                .write_u32::<LittleEndian>(TRAILER_TAG_FILE_MODE)?;
+            self.writer.write_u32::<LittleEndian>(len)?;
+            for &mode in modes {
+                self.writer.write_u32::<LittleEndian>(mode)?;
+            }
+            sections_len += 8 + len;
+        }
+        self.writer.write_u32::<LittleEndian>(sections_len)?;
+        self.writer.write_u32::<LittleEndian>(TRAILER_MAGIC)?;
+        Ok(())
+    }
+
+    /// Writes a complete archive held in a single main file: the header of `metadata`, the
+    /// stored bytes of every chunk and the trailer, if any. The writer must be at the start
+    /// of the file.
+    ///
+    /// `bodies` holds the stored bytes of each chunk by chunk ID, empty for ZERO chunks.
+    /// The chunk offsets, volumes and compressed lengths, the volume list and
+    /// `main_data_end` of `metadata` are set to match what is written, and so is the header
+    /// checksum if the trailer has one. Encrypted archives are rejected, as moving their
+    /// chunks would change the keystream.
+    pub fn write_archive(
+        &mut self,
+        metadata: &mut ArchiveMetadata,
+        bodies: &[Vec<u8>],
+    ) -> Result<()> {
+        if bodies.len() != metadata.chunks.len() {
+            return Err(DzipError::Config(format!(
+                "{} chunk bodies given for {} chunks",
+                bodies.len(),
+                metadata.chunks.len()
+            )));
+        }
+        self.write_archive_with(metadata, &mut |id, _, out| {
+            let body = &bodies[id as usize];
+            out.write_all(body)?;
+            Ok(body.len() as u64)
+        })
+    }
+
+    /// Like [`Self::write_archive`], but the stored bytes of each chunk are written by
+    /// `write_chunk`, in chunk ID order, so that they need not all be held in memory.
+    ///
+    /// `write_chunk` is given the chunk ID and the chunk as listed in `metadata`, and
+    /// returns the number of bytes it wrote. It may change the flags and decompressed length of the chunk, e.g.
+    /// when recompressing it; ZERO chunks keep their compressed length.
+    pub fn write_archive_with(
+        &mut self,
+        metadata: &mut ArchiveMetadata,
+        write_chunk: &mut dyn FnMut(u32, &mut Chunk, &mut dyn Write) -> Result<u64>,
+    ) -> Result<()> {
+        if metadata.is_encrypted() {
+            return Err(DzipError::Unsupported(
+                "Rewriting encrypted archives is not supported".to_string(),
+            ));
+        }
+
+        metadata.chunk_settings.num_archive_files = 1;
+        metadata.volume_files.clear();
+        // The chunk data goes after room for the header, which is written once the offsets
+        // are known (see `ArchiveMetadata::header_len`).
+        let header_len = self.encode_header(metadata)?.len() as u64;
+        self.writer.seek(io::SeekFrom::Start(header_len))?;
+        let mut offset = header_len;
+        for (id, chunk) in metadata.chunks.iter_mut().enumerate() {
+            let len = write_chunk(id as u32, chunk, &mut self.writer)?;
+            chunk.offset = chunk_offset(offset, 0)?;
+            if chunk.flags & CHUNK_ZERO == 0 {
+                chunk.compressed_length = chunk_length(len as usize)?;
+            }
+            chunk.file = 0;
+            offset += len;
+        }
+        metadata.main_data_end = offset;
+
+        let header = self.encode_header(metadata)?;
+        if let Some(crc) = metadata
+            .trailer
+            .as_mut()
+            .and_then(|t| t.header_crc.as_mut())
+        {
+            *crc = checksum(&header);
+        }
+        if let Some(trailer) = &metadata.trailer {
+            self.write_trailer(trailer)?;
+        }
+        self.writer.seek(io::SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        Ok(())
+    }
+
+    /// The header of `metadata` with the name encoding and byte order of this writer.
+    fn encode_header(&self, metadata: &ArchiveMetadata) -> Result<Vec<u8>> {
+        let mut writer = DzipWriter::new(io::Cursor::new(Vec::new()));
+        writer.set_name_encoding(self.name_encoding);
+        writer.set_endian(self.endian);
+        writer.forced_version = self.forced_version;
+        metadata.write_header(&mut writer)?;
+        Ok(writer.into_inner().into_inner())
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub trait WriteSeek: Write + Seek {}
+impl<T: Write + Seek> WriteSeek for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum CompressionMethod {
     Dz,
     Bzip,
@@ -94,6 +351,76 @@ pub enum CompressionMethod {
     Lzma,
     Combuf,
     RandomAccess,
+    /// Zstandard, a dzip-rs extension that other tools cannot read. Compressing and
+    /// decompressing it requires the `zstd` feature.
+    Zstd,
+}
+
+impl CompressionMethod {
+    /// Method a chunk was stored with, judging by its flags, or `None` if it has no method
+    /// flag (e.g. a chunk of a custom codec). The inverse of [`Self::flag`].
+    pub fn from_flags(flags: u16) -> Option<Self> {
+        let method = if (flags & CHUNK_ZLIB) != 0 {
+            CompressionMethod::Zlib
+        } else if (flags & CHUNK_BZIP) != 0 {
+            CompressionMethod::Bzip
+        } else if (flags & CHUNK_COPYCOMP) != 0 {
+            CompressionMethod::Copy
+        } else if (flags & CHUNK_ZERO) != 0 {
+            CompressionMethod::Zero
+        } else if (flags & CHUNK_MP3) != 0 {
+            CompressionMethod::Mp3
+        } else if (flags & CHUNK_JPEG) != 0 {
+            CompressionMethod::Jpeg
+        } else if (flags & CHUNK_LZMA) != 0 {
+            CompressionMethod::Lzma
+        } else if (flags & CHUNK_DZ) != 0 {
+            CompressionMethod::Dz
+        } else if (flags & CHUNK_COMBUF) != 0 {
+            CompressionMethod::Combuf
+        } else if (flags & CHUNK_RANDOMACCESS) != 0 {
+            CompressionMethod::RandomAccess
+        } else if (flags & CHUNK_ZSTD) != 0 {
+            CompressionMethod::Zstd
+        } else {
+            return None;
+        };
+        Some(method)
+    }
+
+    /// The chunk flag marking data of this method.
+    pub fn flag(self) -> u16 {
+        match self {
+            CompressionMethod::Dz => CHUNK_DZ,
+            CompressionMethod::Bzip => CHUNK_BZIP,
+            CompressionMethod::Zlib => CHUNK_ZLIB,
+            CompressionMethod::Copy => CHUNK_COPYCOMP,
+            CompressionMethod::Zero => CHUNK_ZERO,
+            CompressionMethod::Mp3 => CHUNK_MP3,
+            CompressionMethod::Jpeg => CHUNK_JPEG,
+            CompressionMethod::Lzma => CHUNK_LZMA,
+            CompressionMethod::Combuf => CHUNK_COMBUF,
+            CompressionMethod::RandomAccess => CHUNK_RANDOMACCESS,
+            CompressionMethod::Zstd => CHUNK_ZSTD,
+        }
+    }
+
+    /// Name of the method as written in pack configs, e.g. `"Lzma"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            CompressionMethod::Dz => "Dz",
+            CompressionMethod::Bzip => "Bzip",
+            CompressionMethod::Zlib => "Zlib",
+            CompressionMethod::Copy => "Copy",
+            CompressionMethod::Zero => "Zero",
+            CompressionMethod::Mp3 => "Mp3",
+            CompressionMethod::Jpeg => "Jpeg",
+            CompressionMethod::Lzma => "Lzma",
+            CompressionMethod::Combuf => "Combuf",
+            CompressionMethod::RandomAccess => "RandomAccess",
+            CompressionMethod::Zstd => "Zstd",
+        }
+    }
 }
 
 impl FromStr for CompressionMethod {
@@ -111,43 +438,243 @@ impl FromStr for CompressionMethod {
             "lzma" => Ok(CompressionMethod::Lzma),
             "combuf" => Ok(CompressionMethod::Combuf),
             "randomaccess" => Ok(CompressionMethod::RandomAccess),
-            _ => Err(DzipError::Io(std::io::Error::other(format!(
+            "zstd" => Ok(CompressionMethod::Zstd),
+            _ => Err(DzipError::Config(format!(
                 "Unknown compression method: {}",
                 s
-            )))),
+            ))),
         }
     }
 }
 
+/// Framing of the data in `CHUNK_LZMA` chunks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum LzmaVariant {
+    /// Legacy `.lzma` format: properties, dictionary size and a 64-bit unpacked size
+    /// (unknown, so the stream ends with an end marker)
+    #[default]
+    Alone,
+    /// Properties and dictionary size only; the unpacked size is the chunk's decompressed
+    /// length and there is no end marker
+    Raw,
+    /// `.xz` container
+    Xz,
+}
+
+impl LzmaVariant {
+    /// Identifies the framing of a `CHUNK_LZMA` chunk's stored bytes.
+    ///
+    /// `.xz` streams start with their magic. In the other two, the range coder output
+    /// starts right after the 5-byte header; the `.lzma` format has an unpacked size there
+    /// instead, which is either unknown (all ones) or the chunk's decompressed length.
+    pub fn detect(data: &[u8], decompressed_length: u32) -> Self {
+        const XZ_MAGIC: &[u8] = &[0xFD, b'7', b'z', b'X', b'Z', 0x00];
+        if data.starts_with(XZ_MAGIC) {
+            return LzmaVariant::Xz;
+        }
+        match data.get(5..13) {
+            Some(size)
+                if size == [0xFF; 8] || size == (decompressed_length as u64).to_le_bytes() =>
+            {
+                LzmaVariant::Alone
+            }
+            _ => LzmaVariant::Raw,
+        }
+    }
+}
+
+/// Codec settings beyond the compression method.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressOptions {
+    pub lzma_variant: LzmaVariant,
+    /// Bzip2 compression level, 1 (fastest) to 9 (best); 6 if unset
+    pub bzip_level: Option<u32>,
+}
+
+/// Converts a position in `volume` to a chunk offset, which must fit in 32 bits.
+pub fn chunk_offset(position: u64, volume: u16) -> Result<u32> {
+    u32::try_from(position).map_err(|_| DzipError::VolumeTooLarge(volume))
+}
+
+/// Converts the size of a chunk's data to its 32-bit header field.
+pub fn chunk_length(len: usize) -> Result<u32> {
+    u32::try_from(len).map_err(|_| DzipError::ChunkTooLarge(len as u64))
+}
+
+/// CRC32 of a chunk's decompressed data, as stored in the trailer.
+pub fn checksum(data: &[u8]) -> u32 {
+    crc32fast::hash(data)
+}
+
 pub fn compress_data(data: &[u8], method: CompressionMethod) -> Result<(u16, Vec<u8>)> {
-    match method {
-        CompressionMethod::Copy => Ok((CHUNK_COPYCOMP, data.to_vec())),
-        CompressionMethod::Zero => Ok((CHUNK_ZERO, Vec::new())), // Zero chunk has 0 compressed size
+    compress_data_with(data, method, &CompressOptions::default())
+}
+
+/// Like [`compress_data`], with codec settings.
+pub fn compress_data_with(
+    data: &[u8],
+    method: CompressionMethod,
+    options: &CompressOptions,
+) -> Result<(u16, Vec<u8>)> {
+    let mut output = Vec::new();
+    let stream = compress_stream(data, &mut output, method, options)?;
+    Ok((stream.flags, output))
+}
+
+/// What [`compress_stream`] learned about its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressedStream {
+    /// Chunk flags of the compressed data
+    pub flags: u16,
+    /// Number of bytes read from the input
+    pub original_len: u64,
+    /// CRC32 of the input, see [`checksum`]
+    pub crc: u32,
+}
+
+/// Compresses everything `reader` yields into `writer`, holding only small buffers in
+/// memory. The output is the same as that of [`compress_data_with`].
+pub fn compress_stream<R: Read, W: Write>(
+    reader: R,
+    mut writer: W,
+    method: CompressionMethod,
+    options: &CompressOptions,
+) -> Result<CompressedStream> {
+    let mut input = HashingReader::new(reader);
+    let flags = match method {
+        CompressionMethod::Copy => {
+            io::copy(&mut input, &mut writer)?;
+            CHUNK_COPYCOMP
+        }
+        CompressionMethod::Zero => {
+            // Zero chunk has 0 compressed size
+            io::copy(&mut input, &mut io::sink())?;
+            CHUNK_ZERO
+        }
+        // Stored uncompressed, so ranges can be read without decoding the whole chunk
+        CompressionMethod::RandomAccess => {
+            io::copy(&mut input, &mut writer)?;
+            CHUNK_RANDOMACCESS
+        }
+        // MP3 frames are stored as-is; the reader only accepts this passthrough form
+        CompressionMethod::Mp3 => {
+            let mut head = Vec::with_capacity(4);
+            input.by_ref().take(4).read_to_end(&mut head)?;
+            if !head.is_empty() && !crate::reader::is_mp3_stream(&head) {
+                warn!("Data stored as MP3 does not look like an MP3 stream");
+            }
+            writer.write_all(&head)?;
+            io::copy(&mut input, &mut writer)?;
+            CHUNK_MP3
+        }
+        // JPEG files are stored whole, tables included; the reader only accepts this form
+        CompressionMethod::Jpeg => {
+            let mut head = Vec::with_capacity(2);
+            input.by_ref().take(2).read_to_end(&mut head)?;
+            if !head.is_empty() && !crate::reader::is_jpeg_stream(&head) {
+                warn!("Data stored as JPEG does not look like a JPEG file");
+            }
+            writer.write_all(&head)?;
+            io::copy(&mut input, &mut writer)?;
+            CHUNK_JPEG
+        }
         CompressionMethod::Zlib => {
             use flate2::Compression;
             use flate2::write::GzEncoder;
-            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(data).map_err(DzipError::Io)?;
-            Ok((CHUNK_ZLIB, encoder.finish().map_err(DzipError::Io)?))
+            let mut encoder = GzEncoder::new(&mut writer, Compression::default());
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            CHUNK_ZLIB
         }
         CompressionMethod::Bzip => {
             use bzip2::Compression;
             use bzip2::write::BzEncoder;
-            let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
-            encoder.write_all(data).map_err(DzipError::Io)?;
-            Ok((CHUNK_BZIP, encoder.finish().map_err(DzipError::Io)?))
+            let level = match options.bzip_level {
+                Some(level) => Compression::try_new(level).ok_or_else(|| {
+                    DzipError::Config(format!("Bzip level must be 1 to 9, got {}", level))
+                })?,
+                None => Compression::default(),
+            };
+            let mut encoder = BzEncoder::new(&mut writer, level);
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            CHUNK_BZIP
         }
         CompressionMethod::Lzma => {
             // lzma-rs
-            let mut output = Vec::new();
-            lzma_rs::lzma_compress(&mut std::io::Cursor::new(data), &mut output)
-                .map_err(|e| DzipError::Io(std::io::Error::other(e)))?;
-            Ok((CHUNK_LZMA, output))
+            let mut input = io::BufReader::new(&mut input);
+            match options.lzma_variant {
+                LzmaVariant::Alone => lzma_rs::lzma_compress(&mut input, &mut writer),
+                LzmaVariant::Raw => lzma_rs::lzma_compress_with_options(
+                    &mut input,
+                    &mut writer,
+                    &lzma_rs::compress::Options {
+                        unpacked_size: lzma_rs::compress::UnpackedSize::SkipWritingToHeader,
+                    },
+                ),
+                LzmaVariant::Xz => lzma_rs::xz_compress(&mut input, &mut writer),
+            }?;
+            CHUNK_LZMA
+        }
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd => {
+            let mut encoder = zstd::Encoder::new(&mut writer, 0)?;
+            io::copy(&mut input, &mut encoder)?;
+            encoder.finish()?;
+            CHUNK_ZSTD
+        }
+        #[cfg(not(feature = "zstd"))]
+        CompressionMethod::Zstd => {
+            return Err(DzipError::Unsupported(
+                "Zstd compression requires the `zstd` feature".to_string(),
+            ));
         }
         // Fallback to Copy for unsupported types
         _ => {
             warn!("Unsupported compression {:?}, using Copy", method);
-            Ok((CHUNK_COPYCOMP, data.to_vec()))
+            io::copy(&mut input, &mut writer)?;
+            CHUNK_COPYCOMP
+        }
+    };
+    Ok(CompressedStream {
+        flags,
+        original_len: input.len(),
+        crc: input.crc(),
+    })
+}
+
+/// Counts and checksums the bytes passing through it.
+pub(crate) struct HashingReader<R> {
+    inner: R,
+    len: u64,
+    hasher: crc32fast::Hasher,
+}
+
+impl<R> HashingReader<R> {
+    pub(crate) fn new(inner: R) -> Self {
+        Self {
+            inner,
+            len: 0,
+            hasher: crc32fast::Hasher::new(),
         }
     }
+
+    /// Number of bytes read so far.
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// CRC32 of the bytes read so far, see [`checksum`].
+    pub(crate) fn crc(&self) -> u32 {
+        self.hasher.clone().finalize()
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
 }
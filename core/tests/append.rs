@@ -0,0 +1,169 @@
+mod common;
+
+use common::{fixture_or_skip, read_all};
+use dzip_core::reader::DzipReader;
+use dzip_core::volume::MemoryPackSink;
+use dzip_core::{
+    ArchiveMetadata, CompressionMethod, DzipError, NewFile, PackBuilder, StringLayout, append_files,
+};
+use std::fs::File;
+
+#[test]
+fn test_append_to_split_archive() {
+    let Some(source) = fixture_or_skip("ExampleSplitArchive") else {
+        return;
+    };
+    let dir = tempfile::tempdir().unwrap();
+    for entry in std::fs::read_dir(&source).unwrap() {
+        let entry = entry.unwrap();
+        std::fs::copy(entry.path(), dir.path().join(entry.file_name())).unwrap();
+    }
+    let archive = dir.path().join("testnew.dz");
+    let original = read_all(&archive);
+
+    let new_files = [
+        NewFile {
+            path: "TXT/Appended.txt".to_string(),
+            data: b"appended text".repeat(50),
+            compression: CompressionMethod::Zlib,
+        },
+        NewFile {
+            path: "extra/data.bin".to_string(),
+            data: vec![3u8; 4000],
+            compression: CompressionMethod::Copy,
+        },
+    ];
+    append_files(&archive, &new_files).unwrap();
+
+    let appended = read_all(&archive);
+    assert_eq!(appended.len(), original.len() + 2);
+    assert_eq!(&appended[..original.len()], &original[..]);
+    assert_eq!(appended[original.len()].0, "TXT\\Appended.txt");
+    assert_eq!(appended[original.len()].1, new_files[0].data);
+    assert_eq!(appended[original.len() + 1].0, "extra\\data.bin");
+    assert_eq!(appended[original.len() + 1].1, new_files[1].data);
+
+    // Existing paths are rejected and the archive is left as it was
+    let before = std::fs::read(&archive).unwrap();
+    assert!(matches!(
+        append_files(&archive, &new_files[..1]),
+        Err(DzipError::DuplicateFile(_))
+    ));
+    assert_eq!(std::fs::read(&archive).unwrap(), before);
+}
+
+#[test]
+fn test_append_updates_header_checksum() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .header_checksum(true)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Zlib)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.dz");
+    std::fs::write(&archive, sink.into_volumes().remove("archive.dz").unwrap()).unwrap();
+
+    append_files(
+        &archive,
+        &[NewFile {
+            path: "dir/b.txt".to_string(),
+            data: b"bravo".to_vec(),
+            compression: CompressionMethod::Copy,
+        }],
+    )
+    .unwrap();
+
+    let mut reader = DzipReader::new(File::open(&archive).unwrap());
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.user_files.len(), 2);
+    let (stored, actual) = metadata.check_header_crc(&mut reader).unwrap().unwrap();
+    assert_eq!(stored, actual);
+}
+
+#[test]
+fn test_append_keeps_string_layout() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .string_layout(StringLayout::DirectoriesFirst)
+        .add_file("tex/ui/a.png", b"png".to_vec(), CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.dz");
+    std::fs::write(&archive, sink.into_volumes().remove("archive.dz").unwrap()).unwrap();
+
+    append_files(
+        &archive,
+        &[NewFile {
+            path: "readme.txt".to_string(),
+            data: b"hello".to_vec(),
+            compression: CompressionMethod::Copy,
+        }],
+    )
+    .unwrap();
+
+    let mut reader = DzipReader::new(File::open(&archive).unwrap());
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.string_layout, StringLayout::DirectoriesFirst);
+    assert_eq!(
+        read_all(&archive),
+        [
+            ("tex\\ui\\a.png".to_string(), b"png".to_vec()),
+            ("readme.txt".to_string(), b"hello".to_vec()),
+        ]
+    );
+}
+
+#[test]
+fn test_append_to_archive_with_empty_file() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("empty.txt", Vec::new(), CompressionMethod::Copy)
+        .add_file("zero.bin", vec![0u8; 64], CompressionMethod::Zero)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Zlib)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("archive.dz");
+    let mut main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    // Chunks storing nothing may have any offset; some writers leave it at 0
+    let mut metadata =
+        ArchiveMetadata::load(&mut DzipReader::new(std::io::Cursor::new(&main))).unwrap();
+    for chunk in &mut metadata.chunks[..2] {
+        chunk.offset = 0;
+    }
+    let mut cursor = std::io::Cursor::new(&mut main);
+    metadata
+        .write_header(&mut dzip_core::writer::DzipWriter::new(&mut cursor))
+        .unwrap();
+    std::fs::write(&archive, &main).unwrap();
+    let original = read_all(&archive);
+
+    append_files(
+        &archive,
+        &[NewFile {
+            path: "b.txt".to_string(),
+            data: b"bravo".to_vec(),
+            compression: CompressionMethod::Copy,
+        }],
+    )
+    .unwrap();
+
+    let appended = read_all(&archive);
+    assert_eq!(&appended[..3], &original[..]);
+    assert_eq!(appended[3], ("b.txt".to_string(), b"bravo".to_vec()));
+    // Only the stored data is copied, not the header before it
+    let mut reader = DzipReader::new(File::open(&archive).unwrap());
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let stored: u64 = metadata
+        .chunks
+        .iter()
+        .map(|c| c.compressed_length as u64)
+        .sum();
+    assert_eq!(
+        metadata.main_data_end,
+        metadata.header_len().unwrap() + stored
+    );
+}
@@ -0,0 +1,1163 @@
+mod common;
+
+use common::{fixture_or_skip, test_data};
+use dzip_core::format::{
+    ARCHIVE_MAGIC, CHUNK_COPYCOMP, CHUNK_DZ, CHUNK_ZLIB, RangeSettings, StringLayout,
+};
+use dzip_core::reader::{DzipReader, PARALLEL_CHUNK_THRESHOLD};
+use dzip_core::volume::{FileSystemVolumeManager, MemoryPackSink, MemoryVolumeManager};
+use dzip_core::writer::DzipWriter;
+use dzip_core::{
+    ArchiveMetadata, ArchiveSettings, Chunk, ChunkSettings, CompressionMethod, DzipError,
+    PackBuilder, compress_data, try_load_metadata,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Cursor;
+
+#[test]
+fn test_extract_one() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+
+    for name in ["BMP/Image8.bmp", "TXT/Text1.txt", "TXT/Text3.txt"] {
+        let expected = std::fs::read(test_data(&format!("DerbhExampleFiles/{}", name))).unwrap();
+        let data = metadata
+            .extract_one(&mut reader, &mut volumes, name)
+            .unwrap();
+        assert_eq!(data, expected, "{}", name);
+    }
+
+    // Backslash separators work as well
+    assert!(
+        metadata
+            .extract_one(&mut reader, &mut volumes, "TXT\\Text2.txt")
+            .is_ok()
+    );
+
+    assert!(matches!(
+        metadata.extract_one(&mut reader, &mut volumes, "TXT/missing.txt"),
+        Err(DzipError::FileNotFound(_))
+    ));
+}
+
+#[test]
+fn test_chunk_stream() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+
+    let mut multi_chunk_files = 0;
+    for i in 0..metadata.file_map.len() {
+        let expected = metadata.read_file(&mut reader, &mut volumes, i).unwrap();
+        let chunks: Vec<Vec<u8>> = metadata
+            .chunk_stream(&mut reader, &mut volumes, i)
+            .unwrap()
+            .collect::<dzip_core::Result<_>>()
+            .unwrap();
+        assert_eq!(chunks.len(), metadata.file_map[i].1.len());
+        assert_eq!(
+            chunks.concat(),
+            expected,
+            "{}",
+            metadata.file_path(i).unwrap()
+        );
+        if chunks.len() > 1 {
+            multi_chunk_files += 1;
+        }
+    }
+    assert!(multi_chunk_files > 0);
+}
+
+#[test]
+fn test_open_file_seek() {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+
+    let index = (0..metadata.file_map.len())
+        .find(|&i| metadata.file_map[i].1.len() > 1)
+        .unwrap();
+    let expected = metadata
+        .read_file(&mut reader, &mut volumes, index)
+        .unwrap();
+    let first_chunk = metadata.file_chunks(index).unwrap()[0].decompressed_length as usize;
+    let mut file = metadata
+        .open_file(&mut reader, &mut volumes, index)
+        .unwrap();
+    assert_eq!(file.len(), expected.len() as u64);
+
+    // A slice in the middle, spanning a chunk boundary
+    let start = first_chunk - 100;
+    let mut slice = vec![0u8; 300];
+    file.seek(SeekFrom::Start(start as u64)).unwrap();
+    file.read_exact(&mut slice).unwrap();
+    assert_eq!(slice, expected[start..start + 300]);
+
+    // Backwards, relative to the current position and to the end
+    file.seek(SeekFrom::Current(-250)).unwrap();
+    file.read_exact(&mut slice[..50]).unwrap();
+    assert_eq!(slice[..50], expected[start + 50..start + 100]);
+    file.seek(SeekFrom::End(-10)).unwrap();
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail).unwrap();
+    assert_eq!(tail, expected[expected.len() - 10..]);
+    assert!(
+        file.seek(SeekFrom::Current(-(expected.len() as i64) - 1))
+            .is_err()
+    );
+
+    file.rewind().unwrap();
+    let mut all = Vec::new();
+    file.read_to_end(&mut all).unwrap();
+    assert_eq!(all, expected);
+}
+
+#[test]
+fn test_metadata_inspection() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+
+    let files: Vec<(String, usize)> = (0..metadata.file_map.len())
+        .map(|i| {
+            (
+                metadata.resolve_path(i).unwrap(),
+                metadata.file_map[i].1.len(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        files,
+        [
+            ("Image16b.bmp", 1),
+            ("BMP/Image16.bmp", 2),
+            ("BMP/Image4.bmp", 1),
+            ("BMP/Image8.bmp", 1),
+            ("TXT/Text1.txt", 1),
+            ("TXT/Text2.txt", 1),
+            ("TXT/Text3.txt", 1),
+        ]
+        .map(|(p, n)| (p.to_string(), n))
+    );
+    assert_eq!(metadata.directories.len(), 2);
+    assert_eq!(metadata.chunks.len(), 8);
+    assert_eq!(metadata.volume_files.len(), 3);
+}
+
+#[test]
+fn test_file_index_out_of_range() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), HashMap::new());
+
+    let out_of_range = |e| {
+        matches!(
+            e,
+            DzipError::FileIndexOutOfRange {
+                index: 1,
+                num_files: 1
+            }
+        )
+    };
+    assert!(out_of_range(metadata.file_path(1).unwrap_err()));
+    assert!(out_of_range(metadata.resolve_path(1).unwrap_err()));
+    assert!(out_of_range(metadata.file_chunks(1).unwrap_err()));
+    assert!(out_of_range(
+        metadata
+            .read_file(&mut reader, &mut volumes, 1)
+            .unwrap_err()
+    ));
+    assert_eq!(
+        metadata.read_file(&mut reader, &mut volumes, 0).unwrap(),
+        b"alpha"
+    );
+}
+
+#[test]
+fn test_extract_file_to() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+
+    // A Vec is a plain `Write` with no `Seek`, like a pipe
+    let mut out = Vec::new();
+    let written = metadata
+        .extract_file_to(&mut reader, &mut volumes, "BMP/Image8.bmp", &mut out)
+        .unwrap();
+    let expected = std::fs::read(test_data("DerbhExampleFiles/BMP/Image8.bmp")).unwrap();
+    assert_eq!(out, expected);
+    assert_eq!(written, expected.len() as u64);
+
+    assert!(matches!(
+        metadata.extract_file_to(&mut reader, &mut volumes, "missing.bmp", &mut out),
+        Err(DzipError::FileNotFound(_))
+    ));
+
+    // The same path listed twice cannot be resolved
+    metadata.user_files.push(metadata.user_files[0].clone());
+    metadata.file_map.push(metadata.file_map[0].clone());
+    let duplicated = metadata.file_path(0).unwrap();
+    assert!(matches!(
+        metadata.extract_file_to(&mut reader, &mut volumes, &duplicated, &mut out),
+        Err(DzipError::AmbiguousPath(_))
+    ));
+}
+
+#[test]
+fn test_truncated_header() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let data = std::fs::read(&path).unwrap();
+    let header_len = {
+        let mut reader = DzipReader::new(Cursor::new(&data));
+        ArchiveMetadata::load(&mut reader)
+            .unwrap()
+            .header_len()
+            .unwrap() as usize
+    };
+    for (cut, expected) in [(5, "archive settings"), (header_len - 20, "volume list")] {
+        let mut reader = DzipReader::new(Cursor::new(&data[..cut]));
+        match ArchiveMetadata::load(&mut reader) {
+            Err(DzipError::TruncatedSection(section)) => assert_eq!(section, expected),
+            other => panic!("Cut at {}: unexpected {:?}", cut, other.map(|_| ())),
+        }
+    }
+
+    // Cut before the string table: the declared counts cannot fit
+    let mut reader = DzipReader::new(Cursor::new(&data[..12]));
+    assert!(matches!(
+        ArchiveMetadata::load(&mut reader),
+        Err(DzipError::InvalidHeader)
+    ));
+}
+
+#[test]
+fn test_malformed_headers_do_not_panic() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a/b.txt", b"bravo".to_vec(), CompressionMethod::Zlib)
+        .add_file("c/d.bin", vec![1u8; 300], CompressionMethod::Copy)
+        .comment("comment")
+        .volume_limit(200)
+        .build()
+        .unwrap();
+    let packed = sink.into_volumes().remove("archive.dz").unwrap();
+    let mut samples = vec![packed];
+    if let Ok(data) = std::fs::read(test_data("ExampleSplitArchive/testnew.dz")) {
+        samples.push(data);
+    }
+
+    // Any outcome but a panic is fine
+    for data in &samples {
+        let header_len = try_load_metadata(data).unwrap().header_len().unwrap() as usize;
+        for cut in 0..data.len() {
+            let _ = try_load_metadata(&data[..cut]);
+        }
+        for pos in 0..header_len {
+            for byte in [0x00, 0x01, 0x7F, 0xFF] {
+                let mut corrupt = data.clone();
+                corrupt[pos] = byte;
+                let _ = try_load_metadata(&corrupt);
+            }
+        }
+        // Corrupt trailer sections
+        for pos in data.len().saturating_sub(64)..data.len() {
+            let mut corrupt = data.clone();
+            corrupt[pos] ^= 0xFF;
+            let _ = try_load_metadata(&corrupt);
+        }
+    }
+}
+
+#[test]
+fn test_invalid_magic_and_version() {
+    let settings = |header: u32, version: u8| {
+        let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .write_archive_settings(&ArchiveSettings {
+                header,
+                num_user_files: 0,
+                num_directories: 1,
+                version,
+            })
+            .unwrap();
+        writer.into_inner().into_inner()
+    };
+
+    let mut reader = DzipReader::new(Cursor::new(settings(0x04034B50, 0)));
+    assert!(matches!(
+        ArchiveMetadata::load(&mut reader),
+        Err(DzipError::InvalidMagic(0x04034B50))
+    ));
+
+    // The version byte follows the magic and the two counts
+    let mut unknown = settings(ARCHIVE_MAGIC, 0);
+    unknown[8] = 7;
+    let mut reader = DzipReader::new(Cursor::new(unknown));
+    assert!(matches!(
+        ArchiveMetadata::load(&mut reader),
+        Err(DzipError::UnsupportedVersion(7))
+    ));
+
+    // Nor are unknown versions written
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    assert!(matches!(
+        writer.write_archive_settings(&ArchiveSettings {
+            header: ARCHIVE_MAGIC,
+            num_user_files: 0,
+            num_directories: 1,
+            version: 7,
+        }),
+        Err(DzipError::UnsupportedVersion(7))
+    ));
+}
+
+#[test]
+fn test_format_version() {
+    use dzip_core::FormatVersion;
+
+    let path = test_data("ExampleSingleArchive/test1.dz");
+    if path.exists() {
+        let mut reader = DzipReader::new(File::open(&path).unwrap());
+        let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+        assert_eq!(
+            metadata.settings.format_version().unwrap(),
+            FormatVersion::Legacy
+        );
+    }
+
+    let mut sink = MemoryPackSink::new();
+    let metadata = PackBuilder::new(&mut sink)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Zlib)
+        .version(FormatVersion::LATEST)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let loaded = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(loaded.settings, metadata.settings);
+    assert_eq!(
+        loaded.settings.format_version().unwrap(),
+        FormatVersion::Wide
+    );
+    assert!(loaded.settings.is_wide());
+
+    assert_eq!(FormatVersion::try_from(0).unwrap(), FormatVersion::Legacy);
+    assert_eq!(u8::from(FormatVersion::Wide), 1);
+    assert!(matches!(
+        FormatVersion::try_from(2),
+        Err(DzipError::UnsupportedVersion(2))
+    ));
+}
+
+#[test]
+fn test_zero_directories_rejected() {
+    // Not even the root directory: the string count would underflow
+    for version in [0, 1] {
+        let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .write_archive_settings(&ArchiveSettings {
+                header: ARCHIVE_MAGIC,
+                num_user_files: 0,
+                num_directories: 0,
+                version,
+            })
+            .unwrap();
+        writer
+            .write_chunk_settings(&ChunkSettings {
+                num_archive_files: 1,
+                num_chunks: 0,
+            })
+            .unwrap();
+        let data = writer.into_inner().into_inner();
+        assert!(matches!(
+            try_load_metadata(&data),
+            Err(DzipError::InvalidHeader)
+        ));
+    }
+}
+
+#[test]
+fn test_absurd_counts_rejected() {
+    use dzip_core::format::{ArchiveSettings, ChunkSettings};
+    use dzip_core::writer::DzipWriter;
+
+    let build = |num_user_files: u32, num_chunks: u32| {
+        let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .write_archive_settings(&ArchiveSettings {
+                header: 0x5A525444,
+                num_user_files,
+                num_directories: 1,
+                version: 0,
+            })
+            .unwrap();
+        if num_user_files == 0 {
+            writer
+                .write_chunk_settings(&ChunkSettings {
+                    num_archive_files: 1,
+                    num_chunks,
+                })
+                .unwrap();
+        }
+        writer.into_inner().into_inner()
+    };
+
+    for data in [build(0xFFFF, 0), build(0, 0xFFFF)] {
+        let mut reader = DzipReader::new(Cursor::new(data));
+        assert!(matches!(
+            ArchiveMetadata::load(&mut reader),
+            Err(DzipError::InvalidHeader)
+        ));
+    }
+}
+
+#[test]
+fn test_chunk_offset_past_end() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    // Previously clamped to an empty chunk, which extracted as a truncated file
+    let index = metadata.chunks.iter().position(|c| c.file == 1).unwrap();
+    metadata.chunks[index].offset = 1_000_000;
+    match metadata.correct_chunk_sizes(&mut volumes) {
+        Err(DzipError::ChunkOutOfBounds {
+            chunk,
+            volume,
+            offset,
+            ..
+        }) => {
+            assert_eq!(chunk as usize, index);
+            assert_eq!(volume, 1);
+            assert_eq!(offset, 1_000_000);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_load_lazy_matches_eager() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 300], CompressionMethod::Zlib)
+        .add_file("dir/b.bin", vec![2u8; 300], CompressionMethod::Copy)
+        .add_file("c.txt", b"charlie".to_vec(), CompressionMethod::Lzma)
+        .comment("lazy".to_string())
+        .volume_limit(400)
+        .build()
+        .unwrap();
+    let (main, mut volumes) = sink.into_archive().unwrap();
+    let eager = ArchiveMetadata::load(&mut DzipReader::new(Cursor::new(&main))).unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let lazy = ArchiveMetadata::load_lazy(&mut reader).unwrap();
+
+    assert!(lazy.metadata.chunks.is_empty());
+    assert_eq!(lazy.metadata.file_map, eager.file_map);
+    assert_eq!(lazy.metadata.volume_files, eager.volume_files);
+    assert_eq!(lazy.metadata.main_data_end, eager.main_data_end);
+    assert_eq!(lazy.metadata.comment(), Some("lazy"));
+    // In reverse, so that every read seeks
+    for id in (0..eager.chunks.len()).rev() {
+        assert_eq!(
+            lazy.chunk(&mut reader, id as u32).unwrap(),
+            eager.chunks[id]
+        );
+    }
+    assert!(matches!(
+        lazy.chunk(&mut reader, eager.chunks.len() as u32),
+        Err(DzipError::ChunkNotFound(_))
+    ));
+
+    let index = lazy.metadata.find_unique_file("dir/b.bin").unwrap();
+    assert_eq!(
+        lazy.read_file(&mut reader, &mut volumes, index).unwrap(),
+        vec![2u8; 300]
+    );
+    assert!(matches!(
+        lazy.file_chunks(&mut reader, 3),
+        Err(DzipError::FileIndexOutOfRange {
+            index: 3,
+            num_files: 3
+        })
+    ));
+    assert!(matches!(
+        lazy.read_file(&mut reader, &mut volumes, 3),
+        Err(DzipError::FileIndexOutOfRange { .. })
+    ));
+}
+
+#[test]
+fn test_overlapping_chunks() {
+    let chunk = |offset, compressed_length| Chunk {
+        offset,
+        compressed_length,
+        decompressed_length: compressed_length,
+        flags: CHUNK_COPYCOMP,
+        file: 0,
+    };
+    let sizes = HashMap::from([(0u16, 30u64)]);
+
+    // Listed out of offset order: the 10-byte chunk at 0 runs into the one at 5
+    let mut chunks = [chunk(5, 10), chunk(0, 10), chunk(20, 10)];
+    match dzip_core::reader::correct_chunk_sizes(&mut chunks, &sizes) {
+        Err(DzipError::ChunkOverlap {
+            chunk,
+            next,
+            volume,
+        }) => {
+            assert_eq!((chunk, next, volume), (1, 0, 0));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // Adjacent chunks and an empty one sharing an offset are fine
+    let mut chunks = [chunk(10, 10), chunk(0, 10), chunk(10, 0), chunk(20, 10)];
+    dzip_core::reader::correct_chunk_sizes(&mut chunks, &sizes).unwrap();
+}
+
+#[test]
+fn test_file_map_references_missing_chunk() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 100], CompressionMethod::Copy)
+        .add_file("b.bin", vec![2u8; 100], CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let mut main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    // Rewrite the header in place with a file map pointing past the chunk table
+    let mut metadata = ArchiveMetadata::load(&mut DzipReader::new(Cursor::new(&main))).unwrap();
+    metadata.file_map[1].1 = vec![5];
+    let mut cursor = Cursor::new(&mut main);
+    metadata
+        .write_header(&mut DzipWriter::new(&mut cursor))
+        .unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    assert!(matches!(
+        ArchiveMetadata::load(&mut reader),
+        Err(DzipError::ChunkNotFound(5))
+    ));
+}
+
+#[test]
+fn test_describe_fixture() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+    let descriptor = dzip_core::describe(&path).unwrap();
+
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+    assert_eq!(descriptor.version, metadata.settings.version);
+    assert_eq!(descriptor.volumes, metadata.volume_files);
+    assert!(!descriptor.volumes.is_empty());
+    assert_eq!(descriptor.files.len(), metadata.file_map.len());
+    assert_eq!(descriptor.chunks.len(), metadata.chunks.len());
+    for (described, chunk) in descriptor.chunks.iter().zip(&metadata.chunks) {
+        assert_eq!(described.volume, chunk.file);
+        assert_eq!(described.offset, chunk.offset);
+        assert_eq!(described.compressed_length, chunk.compressed_length);
+        assert_eq!(described.flags, chunk.flags);
+    }
+
+    let text = test_data("DerbhExampleFiles/TXT/Text1.txt");
+    let file = descriptor
+        .files
+        .iter()
+        .find(|f| f.path == "TXT/Text1.txt")
+        .unwrap();
+    assert_eq!(file.size, std::fs::metadata(text).unwrap().len());
+    assert_eq!(
+        descriptor.directories[file.directory as usize - 1].replace('\\', "/"),
+        "TXT"
+    );
+
+    let json = serde_json::to_value(&descriptor).unwrap();
+    assert_eq!(json["version"], descriptor.version);
+    assert_eq!(json["chunks"][0]["offset"], descriptor.chunks[0].offset);
+    assert!(json["files"][0]["path"].is_string());
+    let parsed: dzip_core::ArchiveDescriptor = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed, descriptor);
+}
+
+#[test]
+fn test_chunk_in_missing_volume() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 100], CompressionMethod::Copy)
+        .add_file("b.bin", vec![2u8; 100], CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let mut main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    // Rewrite the header in place with a chunk in volume 1 of an archive without volumes
+    let mut metadata = ArchiveMetadata::load(&mut DzipReader::new(Cursor::new(&main))).unwrap();
+    assert_eq!(metadata.chunk_settings.num_archive_files, 1);
+    metadata.chunks[1].file = 1;
+    let mut cursor = Cursor::new(&mut main);
+    metadata
+        .write_header(&mut DzipWriter::new(&mut cursor))
+        .unwrap();
+
+    let error = ArchiveMetadata::load(&mut DzipReader::new(Cursor::new(&main))).unwrap_err();
+    assert!(matches!(
+        error,
+        DzipError::ChunkVolumeOutOfRange {
+            chunk: 1,
+            volume: 1,
+            num_volumes: 1
+        }
+    ));
+    assert_eq!(
+        error.to_string(),
+        "Chunk 1 is stored in volume 1, but the archive only has 1 volume(s)"
+    );
+
+    let mut reader = DzipReader::new(Cursor::new(&main));
+    let lazy = ArchiveMetadata::load_lazy(&mut reader).unwrap();
+    lazy.chunk(&mut reader, 0).unwrap();
+    assert!(matches!(
+        lazy.chunk(&mut reader, 1),
+        Err(DzipError::ChunkVolumeOutOfRange { .. })
+    ));
+}
+
+#[test]
+fn test_correct_file_chunk_sizes_opens_only_their_volumes() {
+    /// Records the volumes opened through it.
+    struct Recording<'a>(MemoryVolumeManager, &'a mut Vec<u16>);
+    impl dzip_core::reader::VolumeSource for Recording<'_> {
+        fn open_volume(
+            &mut self,
+            id: u16,
+        ) -> dzip_core::Result<&mut dyn dzip_core::reader::ReadSeek> {
+            self.1.push(id);
+            self.0.open_volume(id)
+        }
+    }
+
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .archive_names(vec![
+            "archive.dz".to_string(),
+            "a.d01".to_string(),
+            "a.d02".to_string(),
+        ])
+        .add_file_to_volume("a.txt", b"alpha".to_vec(), CompressionMethod::Zlib, 1)
+        .add_file_to_volume("b.txt", b"bravo".to_vec(), CompressionMethod::Zlib, 2)
+        .build()
+        .unwrap();
+    let mut volumes = sink.into_volumes();
+    let main = volumes.remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+
+    // The second volume is missing, but only the first one is needed for a.txt
+    volumes.remove("a.d02");
+    let mut opened = Vec::new();
+    let mut source = Recording(
+        MemoryVolumeManager::new(metadata.volume_files.clone(), volumes),
+        &mut opened,
+    );
+    metadata
+        .correct_file_chunk_sizes(&[0], &mut source)
+        .unwrap();
+    let a = metadata.read_file(&mut reader, &mut source, 0).unwrap();
+    assert_eq!(a, b"alpha");
+    assert_eq!(opened, [1, 1]);
+}
+
+/// Header of an archive with one file of one chunk at `offset`, without range settings.
+fn single_chunk_header(offset: u32, compressed_length: u32, flags: u16) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut writer = DzipWriter::new(Cursor::new(&mut buffer));
+    writer
+        .write_archive_settings(&ArchiveSettings {
+            header: ARCHIVE_MAGIC,
+            num_user_files: 1,
+            num_directories: 1,
+            version: 0,
+        })
+        .unwrap();
+    writer.write_strings(&["a.bin".to_string()]).unwrap();
+    writer.write_file_chunk_map(&[(0, vec![0])]).unwrap();
+    writer
+        .write_chunk_settings(&ChunkSettings {
+            num_archive_files: 1,
+            num_chunks: 1,
+        })
+        .unwrap();
+    writer
+        .write_chunks(&[Chunk {
+            offset,
+            compressed_length,
+            decompressed_length: 5,
+            flags,
+            file: 0,
+        }])
+        .unwrap();
+    buffer
+}
+
+#[test]
+fn test_missing_range_settings() {
+    let header_len = single_chunk_header(0, 0, 0).len() as u32;
+
+    // DZ chunk data right after the volume list
+    let mut archive = single_chunk_header(header_len, 12, CHUNK_DZ);
+    archive.extend_from_slice(&[0xAB; 12]);
+    let metadata = try_load_metadata(&archive).unwrap();
+    assert_eq!(metadata.range_settings, Some(RangeSettings::default()));
+    assert_eq!(metadata.main_data_end, archive.len() as u64);
+
+    // The header ends the file
+    let archive = single_chunk_header(header_len, 0, CHUNK_DZ);
+    let metadata = try_load_metadata(&archive).unwrap();
+    assert_eq!(metadata.range_settings, Some(RangeSettings::default()));
+
+    // Range settings that are there are read
+    let mut archive = single_chunk_header(header_len + 10, 12, CHUNK_DZ);
+    archive.extend(1..=10);
+    archive.extend_from_slice(&[0xAB; 12]);
+    let metadata = try_load_metadata(&archive).unwrap();
+    let settings = metadata.range_settings.unwrap();
+    assert_eq!((settings.win_size, settings.big_min_match), (1, 10));
+
+    // Archives without DZ chunks ignore bytes after the volume list
+    let mut archive = single_chunk_header(header_len + 10, 5, CHUNK_COPYCOMP);
+    archive.extend_from_slice(&[0xFF; 10]);
+    archive.extend_from_slice(b"hello");
+    let mut reader = DzipReader::new(Cursor::new(archive));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.range_settings, None);
+    assert_eq!(
+        reader.read_chunk_data(0, &metadata.chunks[0]).unwrap(),
+        b"hello"
+    );
+}
+
+#[test]
+fn test_copy_chunk_in_truncated_volume() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 300], CompressionMethod::Copy)
+        .add_file("b.bin", vec![2u8; 300], CompressionMethod::Copy)
+        .volume_limit(400)
+        .build()
+        .unwrap();
+    let mut volumes = sink.into_volumes();
+    let main = volumes.remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let last = metadata.chunks[1];
+    assert_ne!(last.file, 0);
+    // Cut the volume holding the last chunk 100 bytes short
+    let name = &metadata.volume_files[last.file as usize - 1];
+    volumes
+        .get_mut(name)
+        .unwrap()
+        .truncate(last.offset as usize + 200);
+
+    let mut volume_manager = MemoryVolumeManager::new(metadata.volume_files.clone(), volumes);
+    metadata.correct_chunk_sizes(&mut volume_manager).unwrap();
+    assert_eq!(metadata.chunks[1].compressed_length, 200);
+    assert_eq!(
+        metadata
+            .read_file(&mut reader, &mut volume_manager, 0)
+            .unwrap(),
+        vec![1u8; 300]
+    );
+    match metadata.read_file(&mut reader, &mut volume_manager, 1) {
+        Err(DzipError::Decompression(message)) => assert!(message.contains("truncated")),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_zero_chunk_with_virtual_offset() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 100], CompressionMethod::Copy)
+        .add_file("zero.bin", vec![0u8; 5000], CompressionMethod::Zero)
+        .add_file("c.bin", vec![3u8; 100], CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    // Point the zero chunk into the middle of the first chunk's data
+    metadata.chunks[1].offset = metadata.chunks[0].offset + 10;
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), HashMap::new());
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+    assert_eq!(metadata.chunks[0].compressed_length, 100);
+    assert_eq!(metadata.chunks[1].compressed_length, 0);
+
+    let a = metadata
+        .extract_one(&mut reader, &mut volumes, "a.bin")
+        .unwrap();
+    assert_eq!(a, vec![1u8; 100]);
+    let zero = metadata
+        .extract_one(&mut reader, &mut volumes, "zero.bin")
+        .unwrap();
+    assert_eq!(zero, vec![0u8; 5000]);
+}
+
+#[test]
+fn test_extract_from_memory() {
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let main = std::fs::read(&path).unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+
+    let volumes: HashMap<String, Vec<u8>> = metadata
+        .volume_files
+        .iter()
+        .map(|name| {
+            let data = std::fs::read(path.parent().unwrap().join(name)).unwrap();
+            (name.clone(), data)
+        })
+        .collect();
+    let mut volumes = MemoryVolumeManager::new(metadata.volume_files.clone(), volumes);
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+
+    // Compare against extraction from the filesystem
+    let mut fs_reader = DzipReader::new(File::open(&path).unwrap());
+    let mut fs_volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    for i in 0..metadata.file_map.len() {
+        let data = metadata.read_file(&mut reader, &mut volumes, i).unwrap();
+        let expected = metadata
+            .read_file(&mut fs_reader, &mut fs_volumes, i)
+            .unwrap();
+        assert_eq!(data, expected, "{}", metadata.file_path(i).unwrap());
+    }
+
+    // Missing volumes are reported, not silently skipped
+    let mut empty = MemoryVolumeManager::new(metadata.volume_files.clone(), HashMap::new());
+    let (id, split_chunk) = (0..)
+        .zip(&metadata.chunks)
+        .find(|(_, c)| c.file != 0)
+        .unwrap();
+    assert!(matches!(
+        reader.read_chunk_data_with_volumes(id, split_chunk, &mut empty),
+        Err(DzipError::VolumeOpenError(..))
+    ));
+}
+
+#[test]
+fn test_many_chunk_file() {
+    let methods = [
+        CompressionMethod::Zlib,
+        CompressionMethod::Copy,
+        CompressionMethod::Bzip,
+        CompressionMethod::Zero,
+    ];
+    let num_chunks = 4 * PARALLEL_CHUNK_THRESHOLD + 3;
+    let pieces: Vec<Vec<u8>> = (0..num_chunks)
+        .map(|i| match methods[i % methods.len()] {
+            CompressionMethod::Zero => vec![0u8; 100 + i],
+            _ => (0..1000 + i).map(|b| (b * i) as u8).collect(),
+        })
+        .collect();
+    let compressed: Vec<(u16, Vec<u8>)> = pieces
+        .iter()
+        .enumerate()
+        .map(|(i, piece)| compress_data(piece, methods[i % methods.len()]).unwrap())
+        .collect();
+
+    // Every other chunk goes to Volume 1
+    let mut metadata = ArchiveMetadata {
+        settings: ArchiveSettings {
+            header: 0x5A525444,
+            num_user_files: 1,
+            num_directories: 1,
+            version: 0,
+        },
+        user_files: vec!["big.bin".to_string()],
+        directories: Vec::new(),
+        string_layout: StringLayout::FilesFirst,
+        file_map: vec![(0, (0..num_chunks as u32).collect())],
+        chunk_settings: ChunkSettings {
+            num_archive_files: 2,
+            num_chunks: num_chunks as u32,
+        },
+        chunks: Vec::new(),
+        volume_files: vec!["big.d01".to_string()],
+        range_settings: None,
+        trailer: None,
+        main_data_end: 0,
+    };
+    metadata.chunks = vec![
+        Chunk {
+            offset: 0,
+            compressed_length: 0,
+            decompressed_length: 0,
+            flags: 0,
+            file: 0,
+        };
+        num_chunks
+    ];
+    let mut volumes = [Vec::new(), Vec::new()];
+    volumes[0].resize(metadata.header_len().unwrap() as usize, 0);
+    for (i, ((flags, data), piece)) in compressed.iter().zip(&pieces).enumerate() {
+        let volume = &mut volumes[i % 2];
+        metadata.chunks[i] = Chunk {
+            offset: volume.len() as u32,
+            compressed_length: data.len() as u32,
+            decompressed_length: piece.len() as u32,
+            flags: *flags,
+            file: (i % 2) as u16,
+        };
+        volume.extend_from_slice(data);
+    }
+    let [mut main, split] = volumes;
+    let mut writer = DzipWriter::new(Cursor::new(&mut main));
+    metadata.write_header(&mut writer).unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volume_manager = MemoryVolumeManager::new(
+        metadata.volume_files.clone(),
+        HashMap::from([("big.d01".to_string(), split)]),
+    );
+    let data = metadata
+        .extract_one(&mut reader, &mut volume_manager, "big.bin")
+        .unwrap();
+    assert_eq!(data, pieces.concat());
+
+    let mut order = Vec::new();
+    let ids: Vec<u32> = (0..metadata.chunks.len() as u32).collect();
+    reader
+        .for_each_chunk_data(&ids, &metadata.chunks, &mut volume_manager, |i, data| {
+            assert_eq!(data.unwrap(), pieces[i]);
+            order.push(i);
+            Ok(())
+        })
+        .unwrap();
+    assert_eq!(order, (0..num_chunks).collect::<Vec<_>>());
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_extract_from_mmap() {
+    use dzip_core::volume::MmapVolumeManager;
+
+    let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+
+    let mut fs_reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut fs_reader).unwrap();
+    let mut fs_volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut fs_volumes).unwrap();
+
+    let manager = MmapVolumeManager::new(&path, &metadata.volume_files).unwrap();
+    assert_eq!(
+        manager.volume_len(0),
+        Some(std::fs::metadata(&path).unwrap().len())
+    );
+
+    // Each thread works on its own clone of the shared mappings
+    std::thread::scope(|scope| {
+        for i in 0..metadata.file_map.len() {
+            let mut volumes = manager.clone();
+            let metadata = &metadata;
+            scope.spawn(move || {
+                let mut reader = DzipReader::new(volumes.main_reader());
+                metadata.read_file(&mut reader, &mut volumes, i).unwrap()
+            });
+        }
+    });
+
+    for i in 0..metadata.file_map.len() {
+        let mut volumes = manager.clone();
+        let mut reader = DzipReader::new(volumes.main_reader());
+        let data = metadata.read_file(&mut reader, &mut volumes, i).unwrap();
+        let expected = metadata
+            .read_file(&mut fs_reader, &mut fs_volumes, i)
+            .unwrap();
+        assert_eq!(data, expected, "{}", metadata.file_path(i).unwrap());
+    }
+}
+
+#[test]
+fn test_last_chunk_with_trailing_padding() {
+    let mut sink = MemoryPackSink::new();
+    let built = PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 100], CompressionMethod::Copy)
+        .add_file("b.bin", vec![0x11u8; 100], CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    // Drop the trailer and pad the data region instead, like an appended signature
+    let mut main = sink.into_volumes().remove("archive.dz").unwrap();
+    main.truncate(built.main_data_end as usize);
+    main.extend_from_slice(&[0xEE; 64]);
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert!(metadata.trailer.is_none());
+    // A Zlib chunk with equal lengths holding raw data: its length is a placeholder
+    metadata.chunks[1].flags = CHUNK_ZLIB;
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), HashMap::new());
+    let mut padded = metadata.clone();
+    padded.correct_chunk_sizes(&mut volumes).unwrap();
+    // No other placeholder shows the archive uses them, so the listed length is kept
+    assert_eq!(padded.chunks[1].compressed_length, 100);
+    let b = padded
+        .extract_one(&mut reader, &mut volumes, "b.bin")
+        .unwrap();
+    assert_eq!(b, vec![0x11u8; 100]);
+
+    // A chunk that does not fit its listed length does, and the last one then extends
+    // to EOF; with more bytes than its original size, it is not taken as raw data
+    metadata.chunks[0].flags = CHUNK_ZLIB;
+    metadata.chunks[0].decompressed_length = 200;
+    metadata.chunks[0].compressed_length = 200;
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+    assert_eq!(metadata.chunks[1].compressed_length, 164);
+    assert!(
+        metadata
+            .extract_one(&mut reader, &mut volumes, "b.bin")
+            .is_err()
+    );
+}
+
+#[test]
+fn test_volume_found_by_numbered_name() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .archive_names(vec!["game.dz".to_string(), "part1.dz".to_string()])
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Copy)
+        .add_file_to_volume("b.txt", b"bravo".to_vec(), CompressionMethod::Zlib, 1)
+        .build()
+        .unwrap();
+    let mut volumes = sink.into_volumes();
+    let main = volumes.remove("game.dz").unwrap();
+    // The split was renamed after the numbered pattern
+    std::fs::write(
+        dir.path().join("game.d01"),
+        volumes.remove("part1.dz").unwrap(),
+    )
+    .unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.volume_files, ["part1.dz"]);
+
+    let mut volumes =
+        FileSystemVolumeManager::new(dir.path().to_path_buf(), metadata.volume_files.clone());
+    assert!(matches!(
+        metadata.extract_one(&mut reader, &mut volumes, "b.txt"),
+        Err(DzipError::VolumeOpenError(1, _))
+    ));
+
+    volumes.set_numbered_fallback("game.dz");
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+    let b = metadata
+        .extract_one(&mut reader, &mut volumes, "b.txt")
+        .unwrap();
+    assert_eq!(b, b"bravo");
+}
+
+#[test]
+fn test_read_single_chunk() {
+    let path = test_data("ExampleSplitArchive/testnew.dz");
+    let mut reader = DzipReader::new(File::open(&path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+
+    let index = metadata.find_unique_file("BMP/Image16.bmp").unwrap();
+    let chunks = metadata.file_chunks(index).unwrap();
+    assert_eq!(chunks.len(), 2);
+    let full = metadata
+        .read_file(&mut reader, &mut volumes, index)
+        .unwrap();
+
+    let id = metadata.file_map[index].1[0];
+    let first = reader
+        .read_chunk_data_with_volumes(id, &chunks[0], &mut volumes)
+        .unwrap();
+    assert_eq!(first.len(), chunks[0].decompressed_length as usize);
+    assert_eq!(first, full[..first.len()]);
+
+    // The chunk lives in a split volume, which the main file reader cannot open
+    assert_ne!(chunks[0].file, 0);
+    assert!(matches!(
+        reader.read_chunk_data(id, &chunks[0]),
+        Err(DzipError::VolumeNotFound(id)) if id == chunks[0].file
+    ));
+}
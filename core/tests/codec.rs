@@ -0,0 +1,768 @@
+use dzip_core::format::*;
+use dzip_core::reader::{DzipReader, check_media_data};
+use dzip_core::volume::{MemoryPackSink, MemoryVolumeManager};
+use dzip_core::writer::DzipWriter;
+use dzip_core::{
+    ArchiveMetadata, Codec, CodecRegistry, CompressOptions, CompressionMethod, DzipError,
+    LzmaVariant, PackBuilder, checksum, compress_data, compress_data_with, compress_stream,
+};
+use std::io::{Cursor, Read, Write};
+use std::sync::Arc;
+
+/// Stores `data` as a single chunk at offset 0 and reads it back.
+fn read_back(stored: &[u8], flags: u16, decompressed_length: u32) -> dzip_core::Result<Vec<u8>> {
+    let chunk = Chunk {
+        offset: 0,
+        compressed_length: stored.len() as u32,
+        decompressed_length,
+        flags,
+        file: 0,
+    };
+    DzipReader::new(Cursor::new(stored)).read_chunk_data(0, &chunk)
+}
+
+/// A minimal MPEG-1 Layer III stream: one 128 kbit/s, 44.1 kHz frame.
+fn mp3_frame() -> Vec<u8> {
+    let mut frame = vec![0xFF, 0xFB, 0x90, 0x64];
+    frame.resize(417, 0);
+    frame
+}
+
+#[test]
+fn test_mp3_passthrough() {
+    let mp3 = mp3_frame();
+    let (flags, stored) = compress_data(&mp3, CompressionMethod::Mp3).unwrap();
+    assert_eq!(flags, CHUNK_MP3);
+
+    let data = read_back(&stored, flags, mp3.len() as u32).unwrap();
+    assert_eq!(data, mp3);
+    // MPEG frame sync: 11 set bits
+    assert_eq!(data[0], 0xFF);
+    assert_eq!(data[1] & 0xE0, 0xE0);
+}
+
+#[test]
+fn test_mp3_transformed_is_unsupported() {
+    let mp3 = mp3_frame();
+    // Stored smaller than the original: a transformed frame stream
+    assert!(matches!(
+        read_back(&mp3[..200], CHUNK_MP3, mp3.len() as u32),
+        Err(DzipError::Unsupported(_))
+    ));
+    // Stored at full size but not an MP3 stream: passed through, flagged per file
+    assert_eq!(read_back(&[0x12; 64], CHUNK_MP3, 64).unwrap(), [0x12; 64]);
+    assert!(check_media_data(CHUNK_MP3, &[0x12; 64], true).is_some());
+    assert_eq!(check_media_data(CHUNK_MP3, &mp3, true), None);
+}
+
+/// A minimal baseline JPEG: a 1x1 grayscale image with its tables.
+fn jpeg_image() -> Vec<u8> {
+    let mut jpeg = vec![0xFF, 0xD8];
+    // Quantization table 0
+    jpeg.extend([0xFF, 0xDB, 0x00, 0x43, 0x00]);
+    jpeg.extend([1u8; 64]);
+    // Baseline frame: 8 bit, 1x1, one component using table 0
+    jpeg.extend([
+        0xFF, 0xC0, 0x00, 0x0B, 0x08, 0x00, 0x01, 0x00, 0x01, 0x01, 0x01, 0x11, 0x00,
+    ]);
+    // DC Huffman table 0 with a single one-bit code for category 0
+    jpeg.extend([0xFF, 0xC4, 0x00, 0x14, 0x00, 0x01]);
+    jpeg.extend([0u8; 15]);
+    jpeg.push(0x00);
+    // AC Huffman table 0 with a single one-bit code for end of block
+    jpeg.extend([0xFF, 0xC4, 0x00, 0x14, 0x10, 0x01]);
+    jpeg.extend([0u8; 15]);
+    jpeg.push(0x00);
+    // Scan of the single component, then the entropy-coded block
+    jpeg.extend([0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00]);
+    jpeg.extend([0x3F, 0xFF, 0xD9]);
+    jpeg
+}
+
+#[test]
+fn test_jpeg_passthrough() {
+    let jpeg = jpeg_image();
+    let (flags, stored) = compress_data(&jpeg, CompressionMethod::Jpeg).unwrap();
+    assert_eq!(flags, CHUNK_JPEG);
+
+    let data = read_back(&stored, flags, jpeg.len() as u32).unwrap();
+    assert_eq!(data, jpeg);
+    assert!(data.starts_with(&[0xFF, 0xD8]));
+    assert!(data.ends_with(&[0xFF, 0xD9]));
+}
+
+#[test]
+fn test_jpeg_without_tables_is_unsupported() {
+    let jpeg = jpeg_image();
+    // Stored smaller than the original: the tables were stripped
+    let result = read_back(&jpeg[100..], CHUNK_JPEG, jpeg.len() as u32);
+    assert!(matches!(result, Err(DzipError::Unsupported(ref m)) if m.contains("DHT")));
+
+    // A range of such a chunk is refused too, not read straight from the stripped data
+    let stored = &jpeg[100..];
+    let chunk = Chunk {
+        offset: 0,
+        compressed_length: stored.len() as u32,
+        decompressed_length: jpeg.len() as u32,
+        flags: CHUNK_JPEG,
+        file: 0,
+    };
+    let result = DzipReader::new(Cursor::new(stored)).read_range(0, &chunk, 0, 16);
+    assert!(matches!(result, Err(DzipError::Unsupported(_))));
+
+    // Stored at full size, but the Huffman tables are missing: passed through, flagged
+    // per file
+    let mut stripped = jpeg[..2 + 69 + 13].to_vec();
+    stripped.extend_from_slice(&jpeg[2 + 69 + 13 + 44..]);
+    let result = read_back(&stripped, CHUNK_JPEG, stripped.len() as u32);
+    assert_eq!(result.unwrap(), stripped);
+    assert_eq!(
+        check_media_data(CHUNK_JPEG, &stripped, true).unwrap(),
+        "JPEG data lacks Huffman tables (DHT)"
+    );
+    assert_eq!(check_media_data(CHUNK_JPEG, &jpeg, true), None);
+
+    // Not a JPEG file at all
+    assert_eq!(read_back(&[0x12; 64], CHUNK_JPEG, 64).unwrap(), [0x12; 64]);
+    assert!(check_media_data(CHUNK_JPEG, &[0x12; 64], true).is_some());
+}
+
+/// An archive holding `data` as one file split over two chunks with `flags`.
+fn two_chunk_archive(data: &[u8], flags: u16) -> Vec<u8> {
+    let mut metadata = ArchiveMetadata {
+        settings: ArchiveSettings {
+            header: ARCHIVE_MAGIC,
+            num_user_files: 1,
+            num_directories: 1,
+            version: 0,
+        },
+        user_files: vec!["media".to_string()],
+        directories: Vec::new(),
+        string_layout: StringLayout::FilesFirst,
+        file_map: vec![(0, vec![0, 1])],
+        chunk_settings: ChunkSettings {
+            num_archive_files: 1,
+            num_chunks: 2,
+        },
+        chunks: Vec::new(),
+        volume_files: Vec::new(),
+        range_settings: None,
+        trailer: None,
+        main_data_end: 0,
+    };
+    let (first, second) = data.split_at(data.len() / 2);
+    let mut offset = 0;
+    for part in [first, second] {
+        metadata.chunks.push(Chunk {
+            offset,
+            compressed_length: part.len() as u32,
+            decompressed_length: part.len() as u32,
+            flags,
+            file: 0,
+        });
+        offset += part.len() as u32;
+    }
+    let header_len = metadata.header_len().unwrap() as u32;
+    for chunk in &mut metadata.chunks {
+        chunk.offset += header_len;
+    }
+    let mut archive = Vec::new();
+    metadata
+        .write_header(&mut DzipWriter::new(Cursor::new(&mut archive)))
+        .unwrap();
+    archive.extend_from_slice(data);
+    archive
+}
+
+#[test]
+fn test_media_split_over_chunks() {
+    for (data, flags) in [(jpeg_image(), CHUNK_JPEG), (mp3_frame(), CHUNK_MP3)] {
+        let archive = two_chunk_archive(&data, flags);
+        let mut reader = DzipReader::new(Cursor::new(archive));
+        let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+        let mut volumes = MemoryVolumeManager::new(Vec::new(), Default::default());
+
+        // Neither half is a complete file on its own
+        let (first, second) = data.split_at(data.len() / 2);
+        assert_eq!(
+            reader.read_chunk_data(0, &metadata.chunks[0]).unwrap(),
+            first
+        );
+        assert_eq!(
+            reader.read_chunk_data(1, &metadata.chunks[1]).unwrap(),
+            second
+        );
+        assert_eq!(
+            metadata.read_file(&mut reader, &mut volumes, 0).unwrap(),
+            data
+        );
+        assert_eq!(check_media_data(flags, &data, true), None);
+        assert_eq!(check_media_data(flags, first, false), None);
+    }
+}
+
+#[test]
+fn test_random_access_range() {
+    let data: Vec<u8> = (0..5000u32).map(|i| (i * 7 % 251) as u8).collect();
+
+    // Uncompressed random-access chunk, read in place
+    let (flags, stored) = compress_data(&data, CompressionMethod::RandomAccess).unwrap();
+    assert_eq!(flags, CHUNK_RANDOMACCESS);
+    let chunk = Chunk {
+        offset: 0,
+        compressed_length: stored.len() as u32,
+        decompressed_length: data.len() as u32,
+        flags,
+        file: 0,
+    };
+    let mut reader = DzipReader::new(Cursor::new(&stored));
+    assert_eq!(reader.read_chunk_data(0, &chunk).unwrap(), data);
+    assert_eq!(
+        reader.read_range(0, &chunk, 1234, 1000).unwrap(),
+        &data[1234..2234]
+    );
+
+    // Compressed random-access chunk, decompressed in full and sliced
+    let (zlib_flags, stored) = compress_data(&data, CompressionMethod::Zlib).unwrap();
+    let chunk = Chunk {
+        compressed_length: stored.len() as u32,
+        flags: zlib_flags | CHUNK_RANDOMACCESS,
+        ..chunk
+    };
+    let mut reader = DzipReader::new(Cursor::new(&stored));
+    let full = reader.read_chunk_data(0, &chunk).unwrap();
+    assert_eq!(full, data);
+    assert_eq!(
+        reader.read_range(0, &chunk, 2500, 17).unwrap(),
+        &full[2500..2517]
+    );
+
+    assert!(reader.read_range(0, &chunk, 4990, 11).is_err());
+}
+
+#[test]
+fn test_zlib_framings() {
+    use flate2::Compression;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+
+    let data = b"sniffed from the first bytes ".repeat(200);
+    let len = data.len() as u32;
+    let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+    gzip.write_all(&data).unwrap();
+    let gzip = gzip.finish().unwrap();
+    let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+    zlib.write_all(&data).unwrap();
+    let zlib = zlib.finish().unwrap();
+    let mut deflate = DeflateEncoder::new(Vec::new(), Compression::default());
+    deflate.write_all(&data).unwrap();
+    let deflate = deflate.finish().unwrap();
+
+    assert_eq!(&gzip[..2], [0x1f, 0x8b]);
+    assert_eq!(zlib[0], 0x78);
+    for (name, stored) in [("gzip", &gzip), ("zlib", &zlib), ("deflate", &deflate)] {
+        assert_eq!(
+            read_back(stored, CHUNK_ZLIB, len).unwrap(),
+            data,
+            "{}",
+            name
+        );
+    }
+
+    // Incompressible data in stored deflate blocks is longer than the original, but is
+    // still inflated rather than taken as raw data
+    let noise: Vec<u8> = (0..5000u32)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+        .collect();
+    let mut stored_blocks = DeflateEncoder::new(Vec::new(), Compression::none());
+    stored_blocks.write_all(&noise).unwrap();
+    let stored_blocks = stored_blocks.finish().unwrap();
+    assert!(stored_blocks.len() > noise.len());
+    assert_eq!(
+        read_back(&stored_blocks, CHUNK_ZLIB, noise.len() as u32).unwrap(),
+        noise
+    );
+
+    // Neither framing and not raw data either
+    assert!(read_back(&[0xFF; 64], CHUNK_ZLIB, 1000).is_err());
+}
+
+#[test]
+fn test_decompression_limits() {
+    // Chunks expanding to far more than they list are stopped, whatever the method
+    let bomb = vec![0u8; 4 << 20];
+    let methods = [
+        CompressionMethod::Zlib,
+        CompressionMethod::Bzip,
+        CompressionMethod::Lzma,
+        #[cfg(feature = "zstd")]
+        CompressionMethod::Zstd,
+    ];
+    for method in methods {
+        let (flags, stored) = compress_data(&bomb, method).unwrap();
+        assert!(
+            matches!(
+                read_back(&stored, flags, 1000),
+                Err(DzipError::Decompression(_))
+            ),
+            "{:?}",
+            method
+        );
+        assert_eq!(read_back(&stored, flags, bomb.len() as u32).unwrap(), bomb);
+    }
+
+    // Chunks listing more than the limit are rejected before they are decompressed
+    let chunk = Chunk {
+        offset: 0,
+        compressed_length: 0,
+        decompressed_length: u32::MAX,
+        flags: CHUNK_ZERO,
+        file: 0,
+    };
+    let mut registry = CodecRegistry::new();
+    assert!(matches!(
+        registry.decompress_chunk(0, Vec::new(), &chunk),
+        Err(DzipError::Decompression(_))
+    ));
+    registry.set_max_chunk_size(100);
+    let chunk = Chunk {
+        decompressed_length: 101,
+        ..chunk
+    };
+    assert!(matches!(
+        registry.decompress_chunk(0, Vec::new(), &chunk),
+        Err(DzipError::Decompression(_))
+    ));
+    let chunk = Chunk {
+        decompressed_length: 100,
+        ..chunk
+    };
+    assert_eq!(
+        registry.decompress_chunk(0, Vec::new(), &chunk).unwrap(),
+        [0; 100]
+    );
+}
+
+#[test]
+fn test_lzma_variants() {
+    let data: Vec<u8> = (0..3000u32).map(|i| (i % 13) as u8).collect();
+    for variant in [LzmaVariant::Alone, LzmaVariant::Raw, LzmaVariant::Xz] {
+        for input in [&data[..], &[]] {
+            let (flags, stored) = compress_data_with(
+                input,
+                CompressionMethod::Lzma,
+                &CompressOptions {
+                    lzma_variant: variant,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            assert_eq!(flags, CHUNK_LZMA);
+            assert_eq!(LzmaVariant::detect(&stored, input.len() as u32), variant);
+
+            let read = read_back(&stored, flags, input.len() as u32).unwrap();
+            assert_eq!(read, input, "{:?}", variant);
+        }
+    }
+}
+
+#[test]
+fn test_compress_stream_from_reader() {
+    let data = b"streamed through the compressor ".repeat(2000);
+    for method in [
+        CompressionMethod::Copy,
+        CompressionMethod::Zero,
+        CompressionMethod::Zlib,
+        CompressionMethod::Bzip,
+        CompressionMethod::Lzma,
+    ] {
+        let mut stored = Vec::new();
+        let stream = compress_stream(
+            Cursor::new(&data),
+            &mut stored,
+            method,
+            &CompressOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(stream.original_len, data.len() as u64, "{:?}", method);
+        assert_eq!(stream.crc, checksum(&data), "{:?}", method);
+
+        let (flags, expected) = compress_data(&data, method).unwrap();
+        assert_eq!(stream.flags, flags, "{:?}", method);
+        assert_eq!(stored, expected, "{:?}", method);
+        if method != CompressionMethod::Zero {
+            let decoded = read_back(&stored, flags, data.len() as u32).unwrap();
+            assert_eq!(decoded, data, "{:?}", method);
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn test_zstd_roundtrip() {
+    let data = b"zstd compresses repetitive asset data well. ".repeat(100);
+    let (flags, stored) = compress_data(&data, CompressionMethod::Zstd).unwrap();
+    assert_eq!(flags, CHUNK_ZSTD);
+    assert!(stored.len() < data.len());
+    assert_eq!(read_back(&stored, flags, data.len() as u32).unwrap(), data);
+
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", data.clone(), CompressionMethod::Zstd)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(
+        CompressionMethod::from_flags(metadata.chunks[0].flags),
+        Some(CompressionMethod::Zstd)
+    );
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), Default::default());
+    let a = metadata
+        .extract_one(&mut reader, &mut volumes, "a.bin")
+        .unwrap();
+    assert_eq!(a, data);
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn test_zstd_requires_feature() {
+    assert!(matches!(
+        compress_data(b"data", CompressionMethod::Zstd),
+        Err(DzipError::Unsupported(_))
+    ));
+    assert!(matches!(
+        read_back(b"data", CHUNK_ZSTD, 4),
+        Err(DzipError::UnsupportedCompression(CHUNK_ZSTD))
+    ));
+}
+
+#[test]
+fn test_method_flag_roundtrip() {
+    for method in [
+        CompressionMethod::Dz,
+        CompressionMethod::Bzip,
+        CompressionMethod::Zlib,
+        CompressionMethod::Copy,
+        CompressionMethod::Zero,
+        CompressionMethod::Mp3,
+        CompressionMethod::Jpeg,
+        CompressionMethod::Lzma,
+        CompressionMethod::Combuf,
+        CompressionMethod::RandomAccess,
+        CompressionMethod::Zstd,
+    ] {
+        assert_eq!(CompressionMethod::from_flags(method.flag()), Some(method));
+        assert_eq!(method.name().parse::<CompressionMethod>().unwrap(), method);
+    }
+    // The method flag wins over modifiers
+    assert_eq!(
+        CompressionMethod::from_flags(CHUNK_ZLIB | CHUNK_RANDOMACCESS | CHUNK_ENCRYPTED),
+        Some(CompressionMethod::Zlib)
+    );
+    assert_eq!(CompressionMethod::from_flags(0), None);
+    assert_eq!(CompressionMethod::from_flags(CHUNK_XOR), None);
+}
+
+#[test]
+fn test_zstd_is_explicit_only() {
+    assert_eq!(
+        "zstd".parse::<CompressionMethod>().unwrap(),
+        CompressionMethod::Zstd
+    );
+    // No legacy flag maps to Zstd
+    for flag in [
+        CHUNK_COMBUF,
+        CHUNK_DZ,
+        CHUNK_ZLIB,
+        CHUNK_BZIP,
+        CHUNK_MP3,
+        CHUNK_JPEG,
+        CHUNK_ZERO,
+        CHUNK_COPYCOMP,
+        CHUNK_LZMA,
+        CHUNK_RANDOMACCESS,
+    ] {
+        assert_ne!(
+            CompressionMethod::from_flags(flag),
+            Some(CompressionMethod::Zstd)
+        );
+        assert_eq!(flag & CHUNK_ZSTD, 0);
+    }
+}
+
+/// A stand-in for a proprietary method: every byte XORed with a key.
+struct XorCodec;
+
+const CHUNK_XOR: u16 = 0x1000;
+
+impl Codec for XorCodec {
+    fn flag(&self) -> u16 {
+        CHUNK_XOR
+    }
+
+    fn compress(&self, input: &mut dyn Read, output: &mut dyn Write) -> dzip_core::Result<()> {
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        output.write_all(&data.iter().map(|b| b ^ 0x5A).collect::<Vec<_>>())?;
+        Ok(())
+    }
+
+    fn decompress(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        _decompressed_length: u32,
+    ) -> dzip_core::Result<()> {
+        self.compress(input, output)
+    }
+}
+
+#[test]
+fn test_custom_codec_roundtrip() {
+    let mut registry = CodecRegistry::new();
+    registry.register(XorCodec);
+    let registry = Arc::new(registry);
+
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .codecs(registry.clone())
+        .add_file(
+            "secret.bin",
+            b"custom codec".to_vec(),
+            CompressionMethod::Copy,
+        )
+        .codec(CHUNK_XOR)
+        .add_file("plain.txt", b"built-in".to_vec(), CompressionMethod::Zlib)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let secret = metadata.chunks[metadata.file_map[0].1[0] as usize];
+    assert_eq!(secret.flags, CHUNK_XOR);
+    // Without the codec the chunk cannot be decoded
+    assert!(reader.read_chunk_data(0, &secret).is_err());
+
+    reader.set_codecs(registry);
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), Default::default());
+    for (path, expected) in [
+        ("secret.bin", &b"custom codec"[..]),
+        ("plain.txt", b"built-in"),
+    ] {
+        let data = metadata
+            .extract_one(&mut reader, &mut volumes, path)
+            .unwrap();
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+fn test_unknown_flag_bits_are_reported() {
+    for flags in [
+        CHUNK_XOR,
+        CHUNK_XOR | CHUNK_COPYCOMP,
+        CHUNK_XOR | CHUNK_ZERO,
+    ] {
+        assert!(matches!(
+            read_back(b"data", flags, 4),
+            Err(DzipError::UnsupportedCompression(f)) if f == flags
+        ));
+    }
+    assert_eq!(read_back(b"data", CHUNK_COPYCOMP, 4).unwrap(), b"data");
+    assert_eq!(CHUNK_KNOWN_FLAGS & CHUNK_XOR, 0);
+
+    // A registered codec claims the bit
+    let mut registry = CodecRegistry::new();
+    registry.register(XorCodec);
+    let chunk = Chunk {
+        offset: 0,
+        compressed_length: 4,
+        decompressed_length: 4,
+        flags: CHUNK_XOR,
+        file: 0,
+    };
+    assert_eq!(
+        registry.decompress_chunk(0, vec![0x5A; 4], &chunk).unwrap(),
+        [0; 4]
+    );
+}
+
+/// Stores data as-is under the DZ flag, whose chunks make the header carry global
+/// settings.
+struct StoredDzCodec;
+
+impl Codec for StoredDzCodec {
+    fn flag(&self) -> u16 {
+        CHUNK_DZ
+    }
+
+    fn compress(&self, input: &mut dyn Read, output: &mut dyn Write) -> dzip_core::Result<()> {
+        std::io::copy(input, output)?;
+        Ok(())
+    }
+
+    fn decompress(
+        &self,
+        input: &mut dyn Read,
+        output: &mut dyn Write,
+        _decompressed_length: u32,
+    ) -> dzip_core::Result<()> {
+        self.compress(input, output)
+    }
+}
+
+#[test]
+fn test_dz_chunks_leave_room_for_global_settings() {
+    let mut registry = CodecRegistry::new();
+    registry.register(StoredDzCodec);
+    let registry = Arc::new(registry);
+
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .codecs(registry.clone())
+        .add_file("a.bin", b"range coded".to_vec(), CompressionMethod::Copy)
+        .codec(CHUNK_DZ)
+        .add_file("b.txt", b"plain".to_vec(), CompressionMethod::Zlib)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    reader.set_codecs(registry);
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert!(metadata.range_settings.is_some());
+    // The data region starts right after the header, global settings included
+    assert_eq!(
+        metadata.chunks[0].offset as u64,
+        metadata.header_len().unwrap()
+    );
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), Default::default());
+    for (path, expected) in [("a.bin", &b"range coded"[..]), ("b.txt", b"plain")] {
+        let data = metadata
+            .extract_one(&mut reader, &mut volumes, path)
+            .unwrap();
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+fn test_bzip_levels() {
+    let data = b"bzip2 level test data ".repeat(500);
+    for level in [1, 9] {
+        let options = CompressOptions {
+            bzip_level: Some(level),
+            ..Default::default()
+        };
+        let (flags, stored) = compress_data_with(&data, CompressionMethod::Bzip, &options).unwrap();
+        assert_eq!(flags, CHUNK_BZIP);
+        // The block size digit of the stream header is the level
+        assert_eq!(&stored[..4], format!("BZh{}", level).as_bytes());
+        assert_eq!(read_back(&stored, flags, data.len() as u32).unwrap(), data);
+    }
+
+    for level in [0, 10] {
+        let options = CompressOptions {
+            bzip_level: Some(level),
+            ..Default::default()
+        };
+        assert!(matches!(
+            compress_data_with(&data, CompressionMethod::Bzip, &options),
+            Err(DzipError::Config(_))
+        ));
+    }
+
+    let (flags, stored) = compress_data(&[], CompressionMethod::Bzip).unwrap();
+    assert_eq!(read_back(&stored, flags, 0).unwrap(), b"");
+}
+
+#[test]
+fn test_bzip_pack_roundtrip() {
+    let data = b"packed with bzip2 ".repeat(1000);
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.txt", data.clone(), CompressionMethod::Bzip)
+        .bzip_level(1)
+        .add_file("empty.txt", Vec::new(), CompressionMethod::Bzip)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = MemoryVolumeManager::new(Vec::new(), Default::default());
+    let a = metadata
+        .extract_one(&mut reader, &mut volumes, "a.txt")
+        .unwrap();
+    assert_eq!(a, data);
+    let empty = metadata
+        .extract_one(&mut reader, &mut volumes, "empty.txt")
+        .unwrap();
+    assert!(empty.is_empty());
+}
+
+/// Records log messages at warn level and above, from whichever test logs them.
+struct CapturingLogger(std::sync::Mutex<Vec<String>>);
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger(std::sync::Mutex::new(Vec::new()));
+
+#[test]
+fn test_length_mismatch_warning() {
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+    // A distinct offset tells the records of this test apart from those of other tests
+    let chunk = |data: &[u8]| Chunk {
+        offset: 0x4242,
+        compressed_length: data.len() as u32,
+        decompressed_length: 100,
+        flags: CHUNK_ZLIB,
+        file: 3,
+    };
+    let warnings = || -> Vec<String> {
+        LOGGER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| message.contains("offset 16962"))
+            .cloned()
+            .collect()
+    };
+
+    let registry = CodecRegistry::new();
+    let (_, data) = compress_data(&[7; 100], CompressionMethod::Zlib).unwrap();
+    let full = registry
+        .decompress_chunk(5, data.clone(), &chunk(&data))
+        .unwrap();
+    assert_eq!(full.len(), 100);
+    assert!(warnings().is_empty());
+
+    // A stream holding less than the chunk table lists
+    let (_, data) = compress_data(&[7; 60], CompressionMethod::Zlib).unwrap();
+    let truncated = registry
+        .decompress_chunk(5, data.clone(), &chunk(&data))
+        .unwrap();
+    assert_eq!(truncated.len(), 60);
+    assert_eq!(
+        warnings(),
+        [
+            "Chunk 5 at volume 3 offset 16962 decompressed to 60 bytes, but the chunk table lists 100"
+        ]
+    );
+}
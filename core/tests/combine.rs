@@ -0,0 +1,81 @@
+mod common;
+
+use common::{fixture_or_skip, read_all};
+use dzip_core::reader::DzipReader;
+use dzip_core::{
+    ArchiveMetadata, CompressionMethod, FormatVersion, PackBuilder, combine_volumes, upgrade,
+};
+use std::fs::File;
+
+#[test]
+fn test_combine_split_archive() {
+    let Some(source) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+        return;
+    };
+    let original = read_all(&source);
+
+    // The output lives in a directory without the volumes, so it must stand on its own
+    let dir = tempfile::tempdir().unwrap();
+    let combined = dir.path().join("combined.dz");
+    let metadata = combine_volumes(&source, &combined).unwrap();
+    assert!(metadata.volume_files.is_empty());
+    assert_eq!(metadata.chunk_settings.num_archive_files, 1);
+    assert!(metadata.chunks.iter().all(|c| c.file == 0));
+    assert_eq!(
+        std::fs::metadata(&combined).unwrap().len(),
+        metadata.main_data_end
+    );
+
+    let mut reader = DzipReader::new(File::open(&combined).unwrap());
+    let loaded = ArchiveMetadata::load(&mut reader).unwrap();
+    assert!(loaded.volume_files.is_empty());
+    assert_eq!(loaded.chunks, metadata.chunks);
+    assert_eq!(read_all(&combined), original);
+    assert!(!dir.path().join("combined.dz.tmp").exists());
+}
+
+#[test]
+fn test_upgrade_legacy_archive() {
+    let mut sink = dzip_core::volume::MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .header_checksum(true)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Zlib)
+        .add_file("dir/b.txt", b"bravo".to_vec(), CompressionMethod::Copy)
+        .add_file("empty", Vec::new(), CompressionMethod::Zero)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let legacy = dir.path().join("legacy.dz");
+    std::fs::write(&legacy, sink.into_volumes().remove("archive.dz").unwrap()).unwrap();
+    let original = read_all(&legacy);
+    let mut reader = DzipReader::new(File::open(&legacy).unwrap());
+    let loaded = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(
+        loaded.settings.format_version().unwrap(),
+        FormatVersion::Legacy
+    );
+
+    let upgraded = dir.path().join("upgraded.dz");
+    let metadata = upgrade(&legacy, &upgraded).unwrap();
+    assert_eq!(
+        metadata.settings.format_version().unwrap(),
+        FormatVersion::LATEST
+    );
+
+    let mut reader = DzipReader::new(File::open(&upgraded).unwrap());
+    let loaded = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(reader.format_version(), FormatVersion::Wide);
+    assert_eq!(loaded.settings, metadata.settings);
+    assert_eq!(loaded.chunks, metadata.chunks);
+    let (stored, actual) = loaded.check_header_crc(&mut reader).unwrap().unwrap();
+    assert_eq!(stored, actual);
+    assert_eq!(read_all(&upgraded), original);
+
+    // Upgrading again keeps the version
+    let again = dir.path().join("again.dz");
+    upgrade(&upgraded, &again).unwrap();
+    assert_eq!(
+        std::fs::read(&again).unwrap(),
+        std::fs::read(&upgraded).unwrap()
+    );
+}
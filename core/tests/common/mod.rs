@@ -0,0 +1,53 @@
+//! Helpers shared by the integration tests.
+
+// Each test crate compiles this module and uses only some of the helpers
+#![allow(dead_code)]
+
+use dzip_core::ArchiveMetadata;
+use dzip_core::reader::DzipReader;
+use dzip_core::volume::FileSystemVolumeManager;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Path of a fixture below the `test_data` directory of the repository.
+pub fn test_data(path: &str) -> PathBuf {
+    let mut p = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("../test_data");
+    p.push(path);
+    p
+}
+
+/// Path of a fixture below `test_data`, or `None` if it is missing, in which case the
+/// calling test should return early:
+///
+/// ```ignore
+/// let Some(path) = fixture_or_skip("ExampleSplitArchive/testnew.dz") else {
+///     return;
+/// };
+/// ```
+pub fn fixture_or_skip(path: &str) -> Option<PathBuf> {
+    let path = test_data(path);
+    if path.exists() {
+        Some(path)
+    } else {
+        eprintln!("Test file not found at {:?}, skipping.", path);
+        None
+    }
+}
+
+/// Reads every file of the archive as (logical path, contents).
+pub fn read_all(path: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut reader = DzipReader::new(File::open(path).unwrap());
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volumes = FileSystemVolumeManager::new(
+        path.parent().unwrap().to_path_buf(),
+        metadata.volume_files.clone(),
+    );
+    metadata.correct_chunk_sizes(&mut volumes).unwrap();
+    (0..metadata.file_map.len())
+        .map(|i| {
+            let data = metadata.read_file(&mut reader, &mut volumes, i).unwrap();
+            (metadata.file_path(i).unwrap(), data)
+        })
+        .collect()
+}
@@ -0,0 +1,375 @@
+use dzip_core::format::CHUNK_ZERO;
+use dzip_core::pack::PackSource;
+use dzip_core::reader::DzipReader;
+use dzip_core::volume::{MemoryPackSink, MemoryVolumeManager};
+use dzip_core::{
+    ArchiveMetadata, CompressionMethod, DzipError, PackBuilder, PackReport, ProgressEvent,
+};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[test]
+fn test_pack_builder_in_memory() {
+    let mut sink = MemoryPackSink::new();
+    let built = PackBuilder::new(&mut sink)
+        .archive_names(vec!["test.dz".to_string()])
+        .add_file("data/a.bin", vec![7u8; 300], CompressionMethod::Zlib)
+        .add_file("b.txt", b"bravo".to_vec(), CompressionMethod::Copy)
+        .volume_limit(200)
+        .build()
+        .unwrap();
+    assert_eq!(built.user_files, ["a.bin", "b.txt"]);
+    assert_eq!(built.directories, ["data"]);
+    assert_eq!(built.file_map, [(1, vec![0]), (0, vec![1])]);
+
+    let mut volumes = sink.into_volumes();
+    let main = volumes.remove("test.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.chunks, built.chunks);
+    assert_eq!(metadata.volume_files, built.volume_files);
+    assert_eq!(metadata.main_data_end, built.main_data_end);
+
+    let mut volume_manager = MemoryVolumeManager::new(metadata.volume_files.clone(), volumes);
+    let a = metadata
+        .extract_one(&mut reader, &mut volume_manager, "data/a.bin")
+        .unwrap();
+    assert_eq!(a, vec![7u8; 300]);
+    let b = metadata
+        .extract_one(&mut reader, &mut volume_manager, "b.txt")
+        .unwrap();
+    assert_eq!(b, b"bravo");
+}
+
+#[test]
+fn test_memory_sink_into_archive() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 300], CompressionMethod::Copy)
+        .add_file("b.bin", vec![2u8; 300], CompressionMethod::Copy)
+        .add_file("c.bin", vec![3u8; 300], CompressionMethod::Copy)
+        .volume_limit(400)
+        .build()
+        .unwrap();
+
+    let (main, mut volumes) = sink.into_archive().unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert!(!metadata.volume_files.is_empty());
+    let files = metadata
+        .extract_all_to_memory(&mut reader, &mut volumes)
+        .unwrap();
+    assert_eq!(
+        files,
+        [
+            ("a.bin".to_string(), vec![1u8; 300]),
+            ("b.bin".to_string(), vec![2u8; 300]),
+            ("c.bin".to_string(), vec![3u8; 300]),
+        ]
+    );
+
+    assert!(MemoryPackSink::new().into_archive().is_none());
+}
+
+#[test]
+fn test_duplicate_paths() {
+    let mut sink = MemoryPackSink::new();
+    let result = PackBuilder::new(&mut sink)
+        .add_file("dir/a.bin", vec![1], CompressionMethod::Copy)
+        .add_file("b.bin", vec![2], CompressionMethod::Copy)
+        .add_file("dir\\./a.bin", vec![3], CompressionMethod::Copy)
+        .add_file("Dir/a.bin", vec![4], CompressionMethod::Copy)
+        .build();
+    assert!(matches!(result, Err(DzipError::Config(_))));
+    assert!(sink.into_volumes().is_empty());
+
+    let mut sink = MemoryPackSink::new();
+    let built = PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1], CompressionMethod::Copy)
+        .add_file("a.bin", vec![2], CompressionMethod::Copy)
+        .allow_duplicates(true)
+        .build()
+        .unwrap();
+    assert_eq!(built.user_files, ["a.bin", "a.bin"]);
+
+    assert_eq!(
+        dzip_core::pack::duplicate_paths(["x/y", "z", "x\\y", "x/y", "/z"]),
+        [("x/y".to_string(), 3), ("z".to_string(), 2)]
+    );
+}
+
+#[test]
+fn test_pack_report() {
+    let text = b"hello hello hello hello hello hello".repeat(20);
+    let mut sink = MemoryPackSink::new();
+    let built = PackBuilder::new(&mut sink)
+        .add_file("a.txt", text.clone(), CompressionMethod::Zlib)
+        .add_file("b.txt", text.clone(), CompressionMethod::Bzip)
+        .add_file("c.txt", text.clone(), CompressionMethod::Zlib)
+        .add_file("d.bin", b"raw".to_vec(), CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let report = PackReport::new(&built.chunks);
+
+    let mut volumes = sink.into_volumes();
+    let main = volumes.remove("archive.dz").unwrap();
+    let metadata = ArchiveMetadata::load(&mut DzipReader::new(Cursor::new(main))).unwrap();
+    let written: u64 = metadata
+        .chunks
+        .iter()
+        .map(|c| c.compressed_length as u64)
+        .sum();
+    assert_eq!(report.total_compressed, written);
+    assert_eq!(report.total_raw, 3 * text.len() as u64 + 3);
+    assert_eq!(report.chunk_count, 4);
+    assert_eq!(report.per_method.len(), 3);
+    assert_eq!(report.per_method[&CompressionMethod::Copy], (3, 3));
+    assert_eq!(
+        report.per_method[&CompressionMethod::Zlib].0,
+        2 * text.len() as u64
+    );
+    let method_total: u64 = report.per_method.values().map(|&(_, c)| c).sum();
+    assert_eq!(method_total, written);
+    assert!(report.ratio().unwrap() < 1.0);
+}
+
+#[test]
+fn test_pack_with_and_without_comment() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+    let metadata = ArchiveMetadata::load(&mut DzipReader::new(Cursor::new(main))).unwrap();
+    assert_eq!(metadata.comment(), None);
+
+    // The comment counts towards the size of the main file.
+    let comment = "Repacked with new textures. ".repeat(8);
+    let mut sink = MemoryPackSink::new();
+    let built = PackBuilder::new(&mut sink)
+        .add_file("a.bin", vec![1u8; 300], CompressionMethod::Copy)
+        .add_file("b.bin", vec![2u8; 300], CompressionMethod::Copy)
+        .comment(comment.clone())
+        .volume_limit(700)
+        .build()
+        .unwrap();
+    assert_eq!(built.comment(), Some(comment.as_str()));
+
+    let mut volumes = sink.into_volumes();
+    let main = volumes.remove("archive.dz").unwrap();
+    assert!(main.len() <= 700);
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.comment(), Some(comment.as_str()));
+
+    let mut volume_manager = MemoryVolumeManager::new(metadata.volume_files.clone(), volumes);
+    let b = metadata
+        .extract_one(&mut reader, &mut volume_manager, "b.bin")
+        .unwrap();
+    assert_eq!(b, vec![2u8; 300]);
+}
+
+#[test]
+fn test_directory_ids_follow_path_order() {
+    let files = [
+        ("sound/b.wav", b"bravo".to_vec()),
+        ("img/icons/c.png", b"charlie".to_vec()),
+        ("img/a.png", b"alpha".to_vec()),
+        ("d.txt", b"delta".to_vec()),
+    ];
+    let pack = |order: &[usize]| {
+        let mut sink = MemoryPackSink::new();
+        let mut builder = PackBuilder::new(&mut sink);
+        for &i in order {
+            builder = builder.add_file(files[i].0, files[i].1.clone(), CompressionMethod::Copy);
+        }
+        builder.build().unwrap()
+    };
+
+    let forward = pack(&[0, 1, 2, 3]);
+    let backward = pack(&[3, 2, 1, 0]);
+    assert_eq!(forward.directories, ["img", "img\\icons", "sound"]);
+    assert_eq!(backward.directories, forward.directories);
+    for metadata in [&forward, &backward] {
+        for i in 0..files.len() {
+            let path = metadata.resolve_path(i).unwrap();
+            assert!(files.iter().any(|(name, _)| *name == path), "{}", path);
+        }
+    }
+}
+
+#[test]
+fn test_empty_files_are_zero_chunks() {
+    let methods = [
+        CompressionMethod::Zlib,
+        CompressionMethod::Bzip,
+        CompressionMethod::Lzma,
+        CompressionMethod::Copy,
+    ];
+    let mut sink = MemoryPackSink::new();
+    let mut builder = PackBuilder::new(&mut sink);
+    for (i, method) in methods.into_iter().enumerate() {
+        builder = builder.add_file(&format!("empty{}.bin", i), Vec::new(), method);
+    }
+    let built = builder.build().unwrap();
+    for chunk in &built.chunks {
+        assert_eq!(chunk.flags, CHUNK_ZERO);
+        assert_eq!(chunk.compressed_length, 0);
+        assert_eq!(chunk.decompressed_length, 0);
+    }
+
+    let main = sink.into_volumes().remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let mut metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    let mut volume_manager = MemoryVolumeManager::new(Vec::new(), Default::default());
+    metadata.correct_chunk_sizes(&mut volume_manager).unwrap();
+    for i in 0..methods.len() {
+        let data = metadata
+            .extract_one(&mut reader, &mut volume_manager, &format!("empty{}.bin", i))
+            .unwrap();
+        assert!(data.is_empty());
+    }
+}
+
+#[test]
+fn test_pack_progress_counts_every_file() {
+    let events = Mutex::new(Vec::new());
+    let on_progress = |event| events.lock().unwrap().push(event);
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .on_progress(&on_progress)
+        .dedup(true)
+        .add_file("a.bin", vec![1; 100], CompressionMethod::Zlib)
+        .add_file("b.bin", vec![1; 100], CompressionMethod::Zlib)
+        .add_file("c.bin", Vec::new(), CompressionMethod::Copy)
+        .no_chunks()
+        .build()
+        .unwrap();
+    // Duplicates and files without chunks write nothing but still count
+    let events = events.into_inner().unwrap();
+    assert_eq!(
+        events,
+        [
+            ProgressEvent::Start(3),
+            ProgressEvent::Inc(1),
+            ProgressEvent::Inc(1),
+            ProgressEvent::Inc(1),
+            ProgressEvent::Finish,
+        ]
+    );
+}
+
+/// Sets the cancel flag as soon as a file is opened.
+struct CancellingSource<'a> {
+    cancel: &'a AtomicBool,
+    opened: AtomicUsize,
+}
+
+impl PackSource for CancellingSource<'_> {
+    fn exists(&self, _path: &Path) -> bool {
+        true
+    }
+
+    fn open_file(&self, path: &Path) -> dzip_core::Result<Box<dyn Read + Send + '_>> {
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        self.cancel.store(true, Ordering::Relaxed);
+        let i: u8 = path.to_str().unwrap().parse().unwrap();
+        Ok(Box::new(std::io::Cursor::new(vec![i; 100])))
+    }
+}
+
+#[test]
+fn test_pack_cancel_after_first_file() {
+    let cancel = AtomicBool::new(false);
+    let source = CancellingSource {
+        cancel: &cancel,
+        opened: AtomicUsize::new(0),
+    };
+    let mut sink = MemoryPackSink::new();
+    // On a single thread no other file is in flight when the flag is set
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .unwrap();
+    let result = pool.install(|| {
+        let mut builder = PackBuilder::new(&mut sink).cancel_flag(&cancel);
+        for i in 0..64 {
+            builder = builder.add_source_file(
+                &format!("{}.bin", i),
+                &source,
+                Path::new(&i.to_string()),
+                CompressionMethod::Zlib,
+            );
+        }
+        builder.build()
+    });
+    assert!(matches!(result, Err(DzipError::Cancelled)));
+    assert_eq!(source.opened.load(Ordering::Relaxed), 1);
+    assert!(sink.into_volumes().is_empty());
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_encrypted_roundtrip() {
+    let key = [7u8; 32];
+    let secret = b"top secret level data".repeat(20);
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("plain.txt", secret.clone(), CompressionMethod::Copy)
+        .add_file("packed.bin", secret.clone(), CompressionMethod::Zlib)
+        .add_file("zero.bin", vec![0u8; 64], CompressionMethod::Zero)
+        .volume_limit(600)
+        .encryption_key(key)
+        .build()
+        .unwrap();
+    let mut volumes = sink.into_volumes();
+    assert!(volumes.len() > 1);
+    assert!(
+        volumes
+            .values()
+            .all(|v| !v.windows(secret.len()).any(|w| w == secret))
+    );
+
+    let main = volumes.remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(main));
+    let metadata = ArchiveMetadata::load(&mut reader).unwrap();
+    assert!(metadata.is_encrypted());
+    let mut volume_manager = MemoryVolumeManager::new(metadata.volume_files.clone(), volumes);
+
+    // Without a key, encrypted chunks cannot be read
+    assert!(matches!(
+        metadata.extract_one(&mut reader, &mut volume_manager, "plain.txt"),
+        Err(DzipError::Encryption(_))
+    ));
+    // A wrong key is rejected before any chunk is read
+    assert!(matches!(
+        metadata.cipher(&[8u8; 32]),
+        Err(DzipError::Encryption(_))
+    ));
+
+    reader.set_cipher(metadata.cipher(&key).unwrap());
+    for path in ["plain.txt", "packed.bin"] {
+        let data = metadata
+            .extract_one(&mut reader, &mut volume_manager, path)
+            .unwrap();
+        assert_eq!(data, secret, "{}", path);
+    }
+    let zero = metadata
+        .extract_one(&mut reader, &mut volume_manager, "zero.bin")
+        .unwrap();
+    assert_eq!(zero, vec![0u8; 64]);
+}
+
+#[cfg(not(feature = "encryption"))]
+#[test]
+fn test_encryption_requires_feature() {
+    let mut sink = MemoryPackSink::new();
+    let result = PackBuilder::new(&mut sink)
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Copy)
+        .encryption_key([7u8; 32])
+        .build();
+    assert!(matches!(result, Err(DzipError::Unsupported(_))));
+}
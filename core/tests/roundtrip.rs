@@ -96,3 +96,363 @@ fn test_roundtrip() {
     let read_global_settings = reader.read_global_settings().unwrap();
     assert_eq!(global_settings, read_global_settings);
 }
+
+#[test]
+fn test_trailer_roundtrip() {
+    let mut buffer = Vec::new();
+    let trailer = ArchiveTrailer {
+        chunk_crcs: Some(vec![0xDEADBEEF, 0x12345678]),
+        file_mtimes: Some(vec![1_700_000_000, 0, 42]),
+        comment: Some("Packed for the 1.2 patch".to_string()),
+        encryption: Some(EncryptionParams {
+            salt: [1; 16],
+            key_check: [2; 16],
+        }),
+        header_crc: Some(0xCAFEF00D),
+        file_modes: Some(vec![0o755, 0o644, 0]),
+    };
+    {
+        let mut writer = DzipWriter::new(Cursor::new(&mut buffer));
+        writer.write_trailer(&trailer).unwrap();
+    }
+    // Pretend there is some chunk data before the trailer
+    let mut data = vec![0xAAu8; 32];
+    data.extend_from_slice(&buffer);
+
+    let mut reader = DzipReader::new(Cursor::new(&data));
+    let (read_trailer, start) = reader.read_trailer().unwrap().unwrap();
+    assert_eq!(read_trailer, trailer);
+    assert_eq!(start, 32);
+
+    // No trailer present
+    let mut reader = DzipReader::new(Cursor::new(vec![0xAAu8; 32]));
+    assert!(reader.read_trailer().unwrap().is_none());
+
+    // Chunk data ending like a trailer, without sections filling it
+    let mut data = vec![0xAAu8; 32];
+    data.extend_from_slice(&12u32.to_le_bytes());
+    data.extend_from_slice(&TRAILER_MAGIC.to_le_bytes());
+    let mut reader = DzipReader::new(Cursor::new(&data));
+    assert!(reader.read_trailer().unwrap().is_none());
+    // A section table leaving bytes over
+    let mut data = vec![0xAAu8; 32];
+    data.extend_from_slice(&TRAILER_TAG_COMMENT.to_le_bytes());
+    data.extend_from_slice(&2u32.to_le_bytes());
+    data.extend_from_slice(b"hi!");
+    data.extend_from_slice(&11u32.to_le_bytes());
+    data.extend_from_slice(&TRAILER_MAGIC.to_le_bytes());
+    let mut reader = DzipReader::new(Cursor::new(&data));
+    assert!(reader.read_trailer().unwrap().is_none());
+}
+
+#[test]
+fn test_wide_roundtrip() {
+    let num_files = 70_000u32;
+    let mut metadata = dzip_core::ArchiveMetadata {
+        settings: ArchiveSettings {
+            header: 0x5A525444,
+            num_user_files: num_files,
+            num_directories: 1,
+            version: VERSION_WIDE,
+        },
+        user_files: (0..num_files).map(|i| format!("f{}", i)).collect(),
+        directories: Vec::new(),
+        string_layout: StringLayout::FilesFirst,
+        file_map: (0..num_files).map(|i| (0, vec![i])).collect(),
+        chunk_settings: ChunkSettings {
+            num_archive_files: 1,
+            num_chunks: num_files,
+        },
+        chunks: vec![
+            Chunk {
+                offset: 0,
+                compressed_length: 0,
+                decompressed_length: 4,
+                flags: CHUNK_ZERO,
+                file: 0,
+            };
+            num_files as usize
+        ],
+        volume_files: Vec::new(),
+        range_settings: None,
+        trailer: None,
+        main_data_end: 0,
+    };
+
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    metadata.write_header(&mut writer).unwrap();
+    let buffer = writer.into_inner().into_inner();
+
+    let mut reader = DzipReader::new(Cursor::new(&buffer));
+    let loaded = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(loaded.settings, metadata.settings);
+    assert_eq!(loaded.chunk_settings, metadata.chunk_settings);
+    assert_eq!(loaded.file_map, metadata.file_map);
+    assert_eq!(loaded.file_path(69_999).unwrap(), "f69999");
+
+    // The legacy format cannot hold this many files
+    metadata.settings.version = VERSION_LEGACY;
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    assert!(matches!(
+        metadata.write_header(&mut writer),
+        Err(dzip_core::DzipError::Config(_))
+    ));
+}
+
+#[test]
+fn test_write_archive() {
+    use dzip_core::writer::{CompressionMethod, checksum, compress_data};
+
+    let contents: [&[u8]; 3] = [b"first file", &[0u8; 64], b"second file, in a directory"];
+    let mut bodies = Vec::new();
+    let mut chunks = Vec::new();
+    for (data, method) in contents.iter().zip([
+        CompressionMethod::Zlib,
+        CompressionMethod::Zero,
+        CompressionMethod::Copy,
+    ]) {
+        let (flags, body) = compress_data(data, method).unwrap();
+        chunks.push(Chunk {
+            offset: 0,
+            compressed_length: 0,
+            decompressed_length: data.len() as u32,
+            flags,
+            file: 0,
+        });
+        bodies.push(body);
+    }
+    let mut metadata = dzip_core::ArchiveMetadata {
+        settings: ArchiveSettings {
+            header: ARCHIVE_MAGIC,
+            num_user_files: 2,
+            num_directories: 2,
+            version: VERSION_LEGACY,
+        },
+        user_files: vec!["a.txt".to_string(), "b.txt".to_string()],
+        directories: vec!["dir".to_string()],
+        string_layout: StringLayout::FilesFirst,
+        file_map: vec![(0, vec![0, 1]), (1, vec![2])],
+        chunk_settings: ChunkSettings {
+            num_archive_files: 2,
+            num_chunks: 3,
+        },
+        chunks,
+        // Dropped, as everything goes into the main file
+        volume_files: vec!["stale.d01".to_string()],
+        range_settings: None,
+        trailer: Some(ArchiveTrailer {
+            header_crc: Some(0),
+            ..Default::default()
+        }),
+        main_data_end: 0,
+    };
+
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    writer.write_archive(&mut metadata, &bodies).unwrap();
+    let buffer = writer.into_inner().into_inner();
+
+    let mut reader = DzipReader::new(Cursor::new(buffer));
+    let loaded = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert!(loaded.volume_files.is_empty());
+    assert_eq!(loaded.chunks, metadata.chunks);
+    assert_eq!(loaded.main_data_end, metadata.main_data_end);
+    let header_crc = loaded.trailer.as_ref().unwrap().header_crc.unwrap();
+    assert_eq!(
+        header_crc,
+        checksum(&loaded.encode_header(Default::default()).unwrap())
+    );
+    assert_eq!(
+        loaded.check_header_crc(&mut reader).unwrap(),
+        Some((header_crc, header_crc))
+    );
+
+    let mut volumes = dzip_core::volume::MemoryVolumeManager::new(Vec::new(), Default::default());
+    let files = loaded
+        .extract_all_to_memory(&mut reader, &mut volumes)
+        .unwrap();
+    assert_eq!(
+        files[0],
+        ("a.txt".to_string(), [contents[0], contents[1]].concat())
+    );
+    assert_eq!(files[1], ("dir/b.txt".to_string(), contents[2].to_vec()));
+
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    assert!(matches!(
+        writer.write_archive(&mut metadata, &bodies[1..]),
+        Err(dzip_core::DzipError::Config(_))
+    ));
+}
+
+#[test]
+fn test_offset_overflow() {
+    use dzip_core::DzipError;
+    use dzip_core::writer::{chunk_length, chunk_offset};
+
+    // A volume that has grown past 4 GiB, as the packer would see it before the next chunk
+    let last = u32::MAX as u64;
+    assert_eq!(chunk_offset(last, 2).unwrap(), u32::MAX);
+    assert!(matches!(
+        chunk_offset(last + 1, 2),
+        Err(DzipError::VolumeTooLarge(2))
+    ));
+
+    assert_eq!(chunk_length(1234).unwrap(), 1234);
+    if let Ok(len) = usize::try_from(last + 1) {
+        assert!(matches!(
+            chunk_length(len),
+            Err(DzipError::ChunkTooLarge(size)) if size == last + 1
+        ));
+    }
+}
+
+#[test]
+fn test_big_endian_header() {
+    // One Copy chunk holding "hello", with every header number stored big-endian
+    let header_len = 9 + 6 + 6 + 4 + 16;
+    let mut archive = Vec::new();
+    archive.extend(ARCHIVE_MAGIC.to_be_bytes());
+    archive.extend(1u16.to_be_bytes()); // Files
+    archive.extend(1u16.to_be_bytes()); // Directories
+    archive.push(VERSION_LEGACY);
+    archive.extend(b"a.txt\0");
+    for id in [0u16, 0, 0xFFFF] {
+        archive.extend(id.to_be_bytes());
+    }
+    archive.extend(1u16.to_be_bytes()); // Volumes
+    archive.extend(1u16.to_be_bytes()); // Chunks
+    archive.extend((header_len as u32).to_be_bytes());
+    archive.extend(5u32.to_be_bytes());
+    archive.extend(5u32.to_be_bytes());
+    archive.extend(CHUNK_COPYCOMP.to_be_bytes());
+    archive.extend(0u16.to_be_bytes());
+    assert_eq!(archive.len(), header_len);
+    archive.extend(b"hello");
+
+    let mut reader = DzipReader::new(Cursor::new(archive.clone()));
+    reader.set_endian(Endian::Big);
+    let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.settings.header, ARCHIVE_MAGIC);
+    assert_eq!(metadata.file_path(0).unwrap(), "a.txt");
+    assert_eq!(metadata.chunks[0].offset as usize, header_len);
+    assert_eq!(
+        reader.read_chunk_data(0, &metadata.chunks[0]).unwrap(),
+        b"hello"
+    );
+
+    // The byte order is also detected from the magic number
+    let mut reader = DzipReader::new(Cursor::new(archive.clone()));
+    let detected = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(reader.endian(), Endian::Big);
+    assert_eq!(detected.chunks, metadata.chunks);
+
+    // Writing big-endian reproduces the header
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    writer.set_endian(Endian::Big);
+    metadata.write_header(&mut writer).unwrap();
+    assert_eq!(writer.into_inner().into_inner(), archive[..header_len]);
+}
+
+#[test]
+fn test_utf16_names() {
+    use dzip_core::volume::MemoryPackSink;
+    use dzip_core::{CompressionMethod, NameEncoding, PackBuilder};
+
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .name_encoding(NameEncoding::Utf16Le)
+        .add_file(
+            "テクスチャ/画像.bmp",
+            b"bmp".to_vec(),
+            CompressionMethod::Copy,
+        )
+        .add_file("a.txt", b"alpha".to_vec(), CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let archive = sink.into_volumes().remove("archive.dz").unwrap();
+    // ASCII names have a zero high byte in every unit, the terminator is a zero unit
+    let needle = b"a\0.\0t\0x\0t\0\0\0";
+    assert!(archive.windows(needle.len()).any(|w| w == needle));
+
+    let mut reader = DzipReader::new(Cursor::new(archive.clone()));
+    reader.set_name_encoding(NameEncoding::Utf16Le);
+    let metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.user_files, ["画像.bmp", "a.txt"]);
+    assert_eq!(metadata.directories, ["テクスチャ"]);
+    assert_eq!(metadata.file_path(0).unwrap(), "テクスチャ\\画像.bmp");
+    assert_eq!(
+        reader.read_chunk_data(1, &metadata.chunks[1]).unwrap(),
+        b"alpha"
+    );
+
+    // Writing with the same encoding reproduces the header
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    writer.set_name_encoding(NameEncoding::Utf16Le);
+    metadata.write_header(&mut writer).unwrap();
+    let header = writer.into_inner().into_inner();
+    assert_eq!(header, archive[..header.len()]);
+}
+
+#[test]
+fn test_directories_first_string_table() {
+    use dzip_core::volume::MemoryPackSink;
+    use dzip_core::{CompressionMethod, PackBuilder};
+
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .add_file("tex/ui/a.png", b"png".to_vec(), CompressionMethod::Copy)
+        .add_file("tex/b.dds", b"dds".to_vec(), CompressionMethod::Copy)
+        .add_file("readme.txt", b"hello".to_vec(), CompressionMethod::Copy)
+        .build()
+        .unwrap();
+    let mut archive = sink.into_volumes().remove("archive.dz").unwrap();
+    let mut reader = DzipReader::new(Cursor::new(archive.clone()));
+    let mut metadata = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(metadata.string_layout, StringLayout::FilesFirst);
+
+    // Same header with the directory names listed before the file names
+    metadata.string_layout = StringLayout::DirectoriesFirst;
+    let mut writer = DzipWriter::new(Cursor::new(Vec::new()));
+    metadata.write_header(&mut writer).unwrap();
+    let header = writer.into_inner().into_inner();
+    assert_ne!(header, archive[..header.len()]);
+    archive[..header.len()].copy_from_slice(&header);
+
+    let mut reader = DzipReader::new(Cursor::new(archive.clone()));
+    let detected = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(detected.string_layout, StringLayout::DirectoriesFirst);
+    assert_eq!(detected.user_files, metadata.user_files);
+    assert_eq!(detected.directories, metadata.directories);
+    let i = (0..3)
+        .find(|&i| detected.resolve_path(i).unwrap() == "readme.txt")
+        .unwrap();
+    let id = detected.file_map[i].1[0];
+    let chunk = &detected.chunks[id as usize];
+    assert_eq!(reader.read_chunk_data(id, chunk).unwrap(), b"hello");
+
+    // Forcing the usual layout reads the names in the wrong places
+    let mut reader = DzipReader::new(Cursor::new(archive));
+    reader.set_string_layout(StringLayout::FilesFirst);
+    let forced = dzip_core::ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(reader.string_layout(), StringLayout::FilesFirst);
+    assert_ne!(forced.user_files, metadata.user_files);
+}
+
+#[test]
+fn test_string_layout_detection() {
+    let strings = |names: &[&str]| names.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    // Files without an extension before a directory with one
+    let table = strings(&["LICENSE", "x", "v1.0"]);
+    assert_eq!(StringLayout::detect(&table, 2), StringLayout::FilesFirst);
+    // Top-level directories first cannot be told apart, so files first is kept
+    let table = strings(&["data", "a.txt", "b.txt"]);
+    assert_eq!(StringLayout::detect(&table, 2), StringLayout::FilesFirst);
+    // A nested directory in the file positions rules out files first
+    let table = strings(&["data", "data\\sub", "a.txt"]);
+    assert_eq!(StringLayout::detect(&table, 1), StringLayout::FilesFirst);
+    let table = strings(&["data\\sub", "data", "a.txt"]);
+    assert_eq!(
+        StringLayout::detect(&table, 1),
+        StringLayout::DirectoriesFirst
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::read_all;
+use dzip_core::format::{CHUNK_COPYCOMP, CHUNK_ZLIB};
+use dzip_core::reader::DzipReader;
+use dzip_core::volume::MemoryPackSink;
+use dzip_core::{ArchiveMetadata, CompressionMethod, PackBuilder, transcode};
+use std::fs::File;
+
+#[test]
+fn test_transcode_copy_to_zlib() {
+    let mut sink = MemoryPackSink::new();
+    PackBuilder::new(&mut sink)
+        .archive_names(vec!["copy.dz".to_string()])
+        .header_checksum(true)
+        .add_file("tex/a.bin", vec![1u8; 3000], CompressionMethod::Copy)
+        .add_file("tex/b.bin", vec![2u8; 3000], CompressionMethod::Copy)
+        .add_file(
+            "readme.txt",
+            b"left as stored".to_vec(),
+            CompressionMethod::Copy,
+        )
+        .volume_limit(4000)
+        .build()
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    for (name, data) in sink.into_volumes() {
+        std::fs::write(dir.path().join(name), data).unwrap();
+    }
+    let source = dir.path().join("copy.dz");
+    let original = read_all(&source);
+
+    let output = dir.path().join("zlib.dz");
+    let metadata = transcode(&source, &output, CompressionMethod::Zlib, &|path| {
+        path.starts_with("tex/")
+    })
+    .unwrap();
+    assert!(metadata.volume_files.is_empty());
+    let flags: Vec<u16> = metadata.chunks.iter().map(|c| c.flags).collect();
+    assert_eq!(flags, [CHUNK_ZLIB, CHUNK_ZLIB, CHUNK_COPYCOMP]);
+    assert!(std::fs::metadata(&output).unwrap().len() < 1000);
+
+    assert_eq!(read_all(&output), original);
+    let mut reader = DzipReader::new(File::open(&output).unwrap());
+    let loaded = ArchiveMetadata::load(&mut reader).unwrap();
+    assert_eq!(loaded.chunks, metadata.chunks);
+    let (stored, actual) = loaded.check_header_crc(&mut reader).unwrap().unwrap();
+    assert_eq!(stored, actual);
+    assert!(!dir.path().join("zlib.dz.tmp").exists());
+}